@@ -0,0 +1,5 @@
+pragma circom 2.2.2;
+
+include "transactions.circom";
+
+component main {public [max_transfer, max_balance]} = ActionQueue(50);
@@ -2,6 +2,7 @@ pragma circom 2.2.2;
 
 include "circomlib/bitify.circom";
 include "poseidon2/poseidon2.circom";
+include "widths.circom";
 
 template Commit1() {
     signal input value;
@@ -18,7 +19,7 @@ template CheckAmount() {
     signal input amount_r;
     signal output amount_c;
 
-    var NUM_AMOUNT_BITS = 80;
+    var NUM_AMOUNT_BITS = NumAmountBits();
 
     var bits[NUM_AMOUNT_BITS] = Num2Bits(NUM_AMOUNT_BITS)(amount);
     var commitment = Commit1()(amount, amount_r);
@@ -70,7 +71,7 @@ template WithdrawInner() {
     signal output old_c;
     signal output new_c;
 
-    var NUM_WITHDRAW_NEW_BITS = 100;
+    var NUM_WITHDRAW_NEW_BITS = NumBalanceBits();
 
     signal new_balance <== old_balance - amount;
     var bits[NUM_WITHDRAW_NEW_BITS] = Num2Bits(NUM_WITHDRAW_NEW_BITS)(new_balance);
@@ -171,3 +172,58 @@ template TransactionBatched(N) {
         amount_commitment[i] <== transactions[i].amount_c;
     }
 }
+
+// Checks the configurable policy limits of a transaction, the same as check_policy of the Noir circuits
+template CheckPolicy() {
+    signal input amount;
+    signal input receiver_new_balance;
+    signal input max_transfer;
+    signal input max_balance;
+
+    var NUM_AMOUNT_BITS = NumAmountBits();
+    var NUM_BALANCE_BITS = NumBalanceBits();
+
+    var transfer_bits[NUM_AMOUNT_BITS] = Num2Bits(NUM_AMOUNT_BITS)(max_transfer - amount);
+    var balance_bits[NUM_BALANCE_BITS] = Num2Bits(NUM_BALANCE_BITS)(max_balance - receiver_new_balance);
+}
+
+// The statement of processMPC, i.e., the circom version of private_transaction_batched. Deposits, withdraws, and dummies are transactions with public inputs. The commitments are ordered per transaction and the limits are public inputs, thus they follow the commitments and the public inputs have the same layout as the ones of the Noir circuit.
+template ActionQueue(N) {
+    signal input sender_old_balance[N];
+    signal input sender_old_r[N];
+    signal input receiver_old_balance[N];
+    signal input receiver_old_r[N];
+    signal input amount[N];
+    signal input amount_r[N];
+    signal input sender_new_r[N];
+    signal input receiver_new_r[N];
+    signal input max_transfer;
+    signal input max_balance;
+    signal output commitments[N][5];
+
+    component transactions[N];
+    component policies[N];
+    for (var i=0; i<N; i++) {
+        transactions[i] = Transaction();
+        transactions[i].sender_old_balance <== sender_old_balance[i];
+        transactions[i].sender_old_r <== sender_old_r[i];
+        transactions[i].receiver_old_balance <== receiver_old_balance[i];
+        transactions[i].receiver_old_r <== receiver_old_r[i];
+        transactions[i].amount <== amount[i];
+        transactions[i].amount_r <== amount_r[i];
+        transactions[i].sender_new_r <== sender_new_r[i];
+        transactions[i].receiver_new_r <== receiver_new_r[i];
+
+        commitments[i][0] <== transactions[i].sender_old_c;
+        commitments[i][1] <== transactions[i].sender_new_c;
+        commitments[i][2] <== transactions[i].receiver_old_c;
+        commitments[i][3] <== transactions[i].receiver_new_c;
+        commitments[i][4] <== transactions[i].amount_c;
+
+        policies[i] = CheckPolicy();
+        policies[i].amount <== amount[i];
+        policies[i].receiver_new_balance <== receiver_old_balance[i] + amount[i];
+        policies[i].max_transfer <== max_transfer;
+        policies[i].max_balance <== max_balance;
+    }
+}
@@ -0,0 +1,12 @@
+pragma circom 2.2.2;
+
+// Generated by create_circuits.sh, see there for the constraints on the widths
+// The bit size of transaction amounts
+function NumAmountBits() {
+    return 80;
+}
+
+// The bit size of balances, i.e., the range check for withdraws and of the balance limit
+function NumBalanceBits() {
+    return 100;
+}
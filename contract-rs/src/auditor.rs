@@ -0,0 +1,374 @@
+use crate::{F, backend::ConfTokenBackend};
+use alloy::primitives::Address;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{UniformRand, Zero};
+use mpc_core::gadgets::poseidon2::Poseidon2;
+use rand::{CryptoRng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+// From the Noir circuits
+const DOMAIN_SEPARATOR: u64 = 0xDEADBEEF;
+
+// commit(value, blinding), the same as the commitments stored on chain
+pub(crate) fn commit(value: F, blinding: F) -> F {
+    let hasher = Poseidon2::<F, 2, 5>::default();
+    let permuted = hasher.permutation(&[value + F::from(DOMAIN_SEPARATOR), blinding]);
+    permuted[0] + value
+}
+
+// What is disclosed to the auditor. The action index is the one in the queue of the contract.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Disclosure {
+    // The new balance and its blinding of the sender after the action
+    SenderBalance {
+        action_index: usize,
+        user: Address,
+    },
+    // The new balance and its blinding of the receiver after the action
+    ReceiverBalance {
+        action_index: usize,
+        user: Address,
+    },
+    // The amount and its blinding of a transfer (or private withdraw), together with the public amount commitment of the action
+    TransferAmount {
+        action_index: usize,
+        #[serde(
+            serialize_with = "mpc_core::serde_compat::ark_se",
+            deserialize_with = "mpc_core::serde_compat::ark_de"
+        )]
+        commitment: F,
+    },
+}
+
+impl Disclosure {
+    // The nonce of the encryption. An action index is processed only once by a contract, thus each nonce is only used once for the key of a party and the auditor, as long as the disclosures are only encrypted for batches which were processed on chain. A failed batch which is retried is not encrypted.
+    fn nonce(&self, contract_address: Address) -> F {
+        let (action_index, slot) = match self {
+            Self::SenderBalance { action_index, .. } => (action_index, 0u64),
+            Self::ReceiverBalance { action_index, .. } => (action_index, 1),
+            Self::TransferAmount { action_index, .. } => (action_index, 2),
+        };
+        // The address has 160 bits, thus the nonce is unique for each contract
+        (crate::address_to_field(contract_address) * F::from(1u128 << 64)
+            + F::from(*action_index as u64))
+            * F::from(4u64)
+            + F::from(slot)
+    }
+
+    fn user(&self) -> Option<Address> {
+        match self {
+            Self::SenderBalance { user, .. } | Self::ReceiverBalance { user, .. } => Some(*user),
+            Self::TransferAmount { .. } => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptedDisclosure {
+    pub disclosure: Disclosure,
+    #[serde(
+        serialize_with = "mpc_core::serde_compat::ark_se",
+        deserialize_with = "mpc_core::serde_compat::ark_de"
+    )]
+    pub ciphertext: [F; 2],
+}
+
+// The disclosures of one MPC party for one batch which was processed on chain. Each party encrypts its additive shares with the key derived from its MPC key and the auditor key, such that the auditor needs the disclosures of all three parties, while no party learns anything new.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditBatch {
+    pub contract_address: Address,
+    pub party: usize,
+    pub disclosures: Vec<EncryptedDisclosure>,
+}
+
+impl AuditBatch {
+    // The shares are the additive shares (i.e., share.a of a Rep3 share) of the value and the blinding
+    pub fn encrypt(
+        contract_address: Address,
+        party: usize,
+        party_sk: &ark_babyjubjub::Fr,
+        auditor_pk: ark_babyjubjub::EdwardsAffine,
+        shares: Vec<(Disclosure, [F; 2])>,
+    ) -> eyre::Result<Self> {
+        if party >= 3 {
+            eyre::bail!("invalid party index for audit disclosure");
+        }
+        let mut seen = HashSet::with_capacity(shares.len());
+        if !shares
+            .iter()
+            .all(|(disclosure, _)| seen.insert(*disclosure))
+        {
+            eyre::bail!("a disclosure appears twice in the batch, which would reuse its nonce");
+        }
+        let key = crate::ae::dh_key_derivation(party_sk, auditor_pk);
+        let disclosures = shares
+            .into_iter()
+            .map(|(disclosure, share)| EncryptedDisclosure {
+                disclosure,
+                ciphertext: crate::ae::sym_encrypt(key, share, disclosure.nonce(contract_address)),
+            })
+            .collect();
+
+        Ok(Self {
+            contract_address,
+            party,
+            disclosures,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisclosedValue {
+    pub disclosure: Disclosure,
+    pub amount: F,
+    pub blinding: F,
+}
+
+pub struct Auditor {
+    sk: ark_babyjubjub::Fr,
+    mpc_pk: [ark_babyjubjub::EdwardsAffine; 3],
+}
+
+impl Auditor {
+    pub fn new(sk: ark_babyjubjub::Fr, mpc_pk: [ark_babyjubjub::EdwardsAffine; 3]) -> Self {
+        Self { sk, mpc_pk }
+    }
+
+    // Reads the MPC keys from the smart contract
+    pub async fn from_contract(
+        sk: ark_babyjubjub::Fr,
+        contract: &crate::conf_token::ConfidentialTokenContract,
+    ) -> eyre::Result<Self> {
+        Ok(Self::new(sk, contract.get_mpc_keys().await?))
+    }
+
+    pub fn random<R: Rng + CryptoRng>(
+        mpc_pk: [ark_babyjubjub::EdwardsAffine; 3],
+        rng: &mut R,
+    ) -> Self {
+        Self::new(ark_babyjubjub::Fr::rand(rng), mpc_pk)
+    }
+
+    pub fn public_key(&self) -> ark_babyjubjub::EdwardsAffine {
+        (ark_babyjubjub::EdwardsAffine::generator() * self.sk).into_affine()
+    }
+
+    // Decrypts the disclosures of all three parties for a batch and combines the shares
+    pub fn decrypt(&self, batches: [AuditBatch; 3]) -> eyre::Result<Vec<DisclosedValue>> {
+        let contract_address = batches[0].contract_address;
+        let len = batches[0].disclosures.len();
+        for (party, audit_batch) in batches.iter().enumerate() {
+            if audit_batch.party != party {
+                eyre::bail!("audit batches are not ordered by party");
+            }
+            if audit_batch.contract_address != contract_address
+                || audit_batch.disclosures.len() != len
+            {
+                eyre::bail!("audit batches of the parties do not match");
+            }
+        }
+
+        let keys = self
+            .mpc_pk
+            .map(|pk| crate::ae::dh_key_derivation(&self.sk, pk));
+        let mut values = Vec::with_capacity(len);
+        for index in 0..len {
+            let disclosure = batches[0].disclosures[index].disclosure;
+            let mut amount = F::zero();
+            let mut blinding = F::zero();
+            for (key, audit_batch) in keys.iter().zip(batches.iter()) {
+                let encrypted = &audit_batch.disclosures[index];
+                if encrypted.disclosure != disclosure {
+                    eyre::bail!("parties disclosed different values at index {index}");
+                }
+                let [amount_share, blinding_share] = crate::ae::sym_decrypt(
+                    *key,
+                    encrypted.ciphertext,
+                    disclosure.nonce(contract_address),
+                );
+                amount += amount_share;
+                blinding += blinding_share;
+            }
+            values.push(DisclosedValue {
+                disclosure,
+                amount,
+                blinding,
+            });
+        }
+        Ok(values)
+    }
+
+    // Returns the disclosures which do not open the corresponding commitment. Balances are compared against the given on-chain commitments, where only the last disclosure per user counts, since a user can appear multiple times in a batch.
+    pub fn check(
+        values: &[DisclosedValue],
+        balance_commitments: &HashMap<Address, F>,
+    ) -> Vec<Disclosure> {
+        let mut last_balance = HashMap::new();
+        let mut mismatches = Vec::new();
+        for value in values {
+            match value.disclosure {
+                Disclosure::SenderBalance { user, .. }
+                | Disclosure::ReceiverBalance { user, .. } => {
+                    last_balance.insert(user, value);
+                }
+                Disclosure::TransferAmount { commitment, .. } => {
+                    if commit(value.amount, value.blinding) != commitment {
+                        mismatches.push(value.disclosure);
+                    }
+                }
+            }
+        }
+        for (user, value) in last_balance {
+            let expected = balance_commitments.get(&user).copied();
+            if expected != Some(commit(value.amount, value.blinding)) {
+                mismatches.push(value.disclosure);
+            }
+        }
+        mismatches
+    }
+
+    // Same as check, but reads the balance commitments from the smart contract. Should be called right after the batch was processed on chain.
+    pub async fn reconcile<B: ConfTokenBackend>(
+        contract: &B,
+        values: &[DisclosedValue],
+    ) -> eyre::Result<Vec<Disclosure>> {
+        let mut balance_commitments = HashMap::new();
+        for user in values.iter().filter_map(|value| value.disclosure.user()) {
+            if balance_commitments.contains_key(&user) {
+                continue;
+            }
+            let commitment = contract.get_balance_commitment(user).await?;
+            balance_commitments.insert(user, commitment);
+        }
+        Ok(Self::check(values, &balance_commitments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpc_core::protocols::rep3;
+
+    #[test]
+    fn auditor_test() {
+        let mut rng = rand::thread_rng();
+        let mpc_sk: [ark_babyjubjub::Fr; 3] = std::array::from_fn(|_| UniformRand::rand(&mut rng));
+        let mpc_pk =
+            mpc_sk.map(|sk| (ark_babyjubjub::EdwardsAffine::generator() * sk).into_affine());
+        let auditor = Auditor::random(mpc_pk, &mut rng);
+
+        // The balances and the amount of a transfer from alice to bob
+        let contract = Address::from(rng.r#gen::<[u8; 20]>());
+        let alice = Address::from(rng.r#gen::<[u8; 20]>());
+        let bob = Address::from(rng.r#gen::<[u8; 20]>());
+        let alice_balance = [F::from(100u64), F::rand(&mut rng)];
+        let bob_balance = [F::from(50u64), F::rand(&mut rng)];
+        let amount = [F::from(50u64), F::rand(&mut rng)];
+        let plain = [
+            (
+                Disclosure::SenderBalance {
+                    action_index: 7,
+                    user: alice,
+                },
+                alice_balance,
+            ),
+            (
+                Disclosure::ReceiverBalance {
+                    action_index: 7,
+                    user: bob,
+                },
+                bob_balance,
+            ),
+            (
+                Disclosure::TransferAmount {
+                    action_index: 7,
+                    commitment: commit(amount[0], amount[1]),
+                },
+                amount,
+            ),
+        ];
+
+        // The nonces differ for each disclosure, action and contract
+        let nonces = plain.map(|(disclosure, _)| disclosure.nonce(contract));
+        assert_ne!(nonces[0], nonces[1]);
+        assert_ne!(nonces[1], nonces[2]);
+        assert_ne!(nonces[0], nonces[2]);
+        let next = Disclosure::SenderBalance {
+            action_index: 8,
+            user: alice,
+        };
+        assert!(!nonces.contains(&next.nonce(contract)));
+        assert_ne!(
+            plain[0].0.nonce(contract),
+            plain[0].0.nonce(Address::from(rng.r#gen::<[u8; 20]>()))
+        );
+
+        // Every party encrypts its additive shares
+        let mut shares: [Vec<(Disclosure, [F; 2])>; 3] = Default::default();
+        for (disclosure, [value, blinding]) in plain {
+            let value = rep3::share_field_element(value, &mut rng);
+            let blinding = rep3::share_field_element(blinding, &mut rng);
+            for (party, shares) in shares.iter_mut().enumerate() {
+                shares.push((disclosure, [value[party].a, blinding[party].a]));
+            }
+        }
+        // A disclosure cannot be encrypted twice in a batch
+        let mut repeated = shares[0].clone();
+        repeated.push(repeated[0]);
+        assert!(
+            AuditBatch::encrypt(contract, 0, &mpc_sk[0], auditor.public_key(), repeated).is_err()
+        );
+
+        let mut party = 0;
+        let batches = shares.clone().map(|shares| {
+            let batch = AuditBatch::encrypt(
+                contract,
+                party,
+                &mpc_sk[party],
+                auditor.public_key(),
+                shares,
+            )
+            .unwrap();
+            party += 1;
+            batch
+        });
+
+        let values = auditor.decrypt(batches.clone()).unwrap();
+        for (value, (disclosure, [amount, blinding])) in values.iter().zip(plain) {
+            assert_eq!(value.disclosure, disclosure);
+            assert_eq!(value.amount, amount);
+            assert_eq!(value.blinding, blinding);
+        }
+
+        // Reconcile against the on-chain commitments
+        let mut onchain = HashMap::from([
+            (alice, commit(alice_balance[0], alice_balance[1])),
+            (bob, commit(bob_balance[0], bob_balance[1])),
+        ]);
+        assert!(Auditor::check(&values, &onchain).is_empty());
+        onchain.insert(bob, commit(bob_balance[0] + F::from(1u64), bob_balance[1]));
+        assert_eq!(Auditor::check(&values, &onchain), vec![plain[1].0]);
+
+        // The batch of a single party only contains its additive shares
+        let key = crate::ae::dh_key_derivation(&auditor.sk, mpc_pk[0]);
+        for (encrypted, (_, share)) in batches[0].disclosures.iter().zip(&shares[0]) {
+            let decrypted = crate::ae::sym_decrypt(
+                key,
+                encrypted.ciphertext,
+                encrypted.disclosure.nonce(contract),
+            );
+            assert_eq!(&decrypted, share);
+        }
+        assert_ne!(shares[0][0].1[0], alice_balance[0]);
+
+        // A different auditor key does not decrypt
+        let other = Auditor::random(mpc_pk, &mut rng);
+        let values = other.decrypt(batches.clone()).unwrap();
+        assert_ne!(values[0].amount, alice_balance[0]);
+
+        // The batches of all parties are required
+        let [batch0, batch1, _] = batches;
+        assert!(auditor.decrypt([batch0.clone(), batch1, batch0]).is_err());
+    }
+}
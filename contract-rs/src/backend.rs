@@ -0,0 +1,128 @@
+use crate::{
+    F,
+    conf_token::{
+        ConfTokenError,
+        ConfidentialToken::{ActionQuery, Ciphertext, Groth16Proof, TransactionInput},
+        ConfidentialTokenContract,
+    },
+};
+use alloy::primitives::Address;
+
+// The queue and commitment logic of the ConfidentialToken contract, which is implemented by the contract on chain and by the in-memory MockConfidentialToken. Actions are sent from the account of the instance, the returned indices are the ones of the actions in the queue.
+pub trait ConfTokenBackend: Send + Sync {
+    fn get_balance_commitment(
+        &self,
+        user: Address,
+    ) -> impl Future<Output = Result<F, ConfTokenError>> + Send;
+
+    fn get_balance_root(&self) -> impl Future<Output = Result<F, ConfTokenError>> + Send;
+
+    fn get_policy_limits(&self) -> impl Future<Output = Result<(F, F), ConfTokenError>> + Send;
+
+    fn get_action_at_index(
+        &self,
+        index: usize,
+    ) -> impl Future<Output = Result<ActionQuery, ConfTokenError>> + Send;
+
+    // Includes the dummy action at index 0
+    fn get_action_queue_size(&self) -> impl Future<Output = Result<usize, ConfTokenError>> + Send;
+
+    fn read_queue(
+        &self,
+        num_items: usize,
+    ) -> impl Future<
+        Output = Result<(Vec<usize>, Vec<ActionQuery>, Vec<Ciphertext>), ConfTokenError>,
+    > + Send;
+
+    fn deposit(&self, amount: F) -> impl Future<Output = Result<usize, ConfTokenError>> + Send;
+
+    fn withdraw(&self, amount: F) -> impl Future<Output = Result<usize, ConfTokenError>> + Send;
+
+    fn withdraw_private(
+        &self,
+        amount_commitment: F,
+        ciphertext: Ciphertext,
+    ) -> impl Future<Output = Result<usize, ConfTokenError>> + Send;
+
+    fn transfer(
+        &self,
+        to: Address,
+        amount: F,
+        ciphertext: Ciphertext,
+    ) -> impl Future<Output = Result<usize, ConfTokenError>> + Send;
+
+    fn process_mpc(
+        &self,
+        inputs: TransactionInput,
+        proof: Groth16Proof,
+    ) -> impl Future<Output = Result<(), ConfTokenError>> + Send;
+}
+
+impl ConfTokenBackend for ConfidentialTokenContract {
+    async fn get_balance_commitment(&self, user: Address) -> Result<F, ConfTokenError> {
+        ConfidentialTokenContract::get_balance_commitment(self, user).await
+    }
+
+    async fn get_balance_root(&self) -> Result<F, ConfTokenError> {
+        ConfidentialTokenContract::get_balance_root(self).await
+    }
+
+    async fn get_policy_limits(&self) -> Result<(F, F), ConfTokenError> {
+        ConfidentialTokenContract::get_policy_limits(self).await
+    }
+
+    async fn get_action_at_index(&self, index: usize) -> Result<ActionQuery, ConfTokenError> {
+        ConfidentialTokenContract::get_action_at_index(self, index).await
+    }
+
+    async fn get_action_queue_size(&self) -> Result<usize, ConfTokenError> {
+        ConfidentialTokenContract::get_action_queue_size(self).await
+    }
+
+    async fn read_queue(
+        &self,
+        num_items: usize,
+    ) -> Result<(Vec<usize>, Vec<ActionQuery>, Vec<Ciphertext>), ConfTokenError> {
+        ConfidentialTokenContract::read_queue(self, num_items).await
+    }
+
+    async fn deposit(&self, amount: F) -> Result<usize, ConfTokenError> {
+        let (index, _) = ConfidentialTokenContract::deposit(self, amount).await?;
+        Ok(index)
+    }
+
+    async fn withdraw(&self, amount: F) -> Result<usize, ConfTokenError> {
+        let (index, _) = ConfidentialTokenContract::withdraw(self, amount).await?;
+        Ok(index)
+    }
+
+    async fn withdraw_private(
+        &self,
+        amount_commitment: F,
+        ciphertext: Ciphertext,
+    ) -> Result<usize, ConfTokenError> {
+        let (index, _) =
+            ConfidentialTokenContract::withdraw_private(self, amount_commitment, ciphertext)
+                .await?;
+        Ok(index)
+    }
+
+    async fn transfer(
+        &self,
+        to: Address,
+        amount: F,
+        ciphertext: Ciphertext,
+    ) -> Result<usize, ConfTokenError> {
+        let (index, _) = ConfidentialTokenContract::transfer(self, to, amount, ciphertext).await?;
+        Ok(index)
+    }
+
+    async fn process_mpc(
+        &self,
+        inputs: TransactionInput,
+        proof: Groth16Proof,
+    ) -> Result<(), ConfTokenError> {
+        ConfidentialTokenContract::process_mpc(self, inputs, proof).await?;
+        Ok(())
+    }
+}
@@ -1,20 +1,85 @@
 use crate::{
     F,
     conf_token::ConfidentialToken::{
-        ActionQuery, BabyJubJubElement, Ciphertext, Groth16Proof, TransactionInput,
+        ActionQuery, BabyJubJubElement, Ciphertext, Groth16Proof, SignedTransfer, TransactionInput,
+        TransferAuthorization,
     },
+    eddsa::{self, TransferMessage},
+    simulation::ContractRevert,
+    token::USDCTokenContract,
+    tx_manager::{TxConfig, TxManager},
 };
 use alloy::{
-    network::EthereumWallet,
-    primitives::{Address, U256},
+    network::{Ethereum, EthereumWallet, NetworkWallet},
+    primitives::{Address, Bytes, U256, keccak256},
     providers::{DynProvider, Provider as _, ProviderBuilder, WsConnect},
     rpc::types::TransactionReceipt,
+    signers::{SignerSync, local::PrivateKeySigner},
     sol,
+    sol_types::SolValue,
 };
 use ark_ec::{AffineRepr, CurveGroup};
 use ark_ff::{UniformRand, Zero};
 use eyre::Context;
 use rand::{CryptoRng, Rng};
+use std::collections::{HashMap, hash_map::Entry};
+
+// The failures of the interaction with the smart contract. A reverted transaction or an unauthorized transfer is caused by the action itself, while an Rpc failure might succeed when retried.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfTokenError {
+    #[error("RPC failure: {0:#}")]
+    Rpc(eyre::Report),
+    #[error("The transaction {} reverted", .0.transaction_hash)]
+    Reverted(Box<TransactionReceipt>),
+    // The decoded revert, e.g., of the MockConfidentialToken which has no receipts
+    #[error("The contract rejected the call with {0:?}")]
+    Rejected(ContractRevert),
+    #[error("No {0} event found in the transaction receipt logs")]
+    MissingEvent(&'static str),
+    // The signed transfer of the sender with the nonce cannot be authorized
+    #[error("Unauthorized transfer of {sender} with nonce {nonce}: {reason:#}")]
+    Unauthorized {
+        sender: Address,
+        nonce: U256,
+        reason: eyre::Report,
+    },
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+    #[error("Invalid response of the contract: {0}")]
+    InvalidResponse(String),
+    // The action index at the position of a batch, an event, or the queue does not fit into a usize
+    #[error("Invalid action index {index} at position {position}")]
+    InvalidActionIndex { position: usize, index: U256 },
+    // A value of the action at the index of the queue is not a field element
+    #[error("The {value} of the action at index {action_index} is not a field element")]
+    InvalidActionValue {
+        action_index: usize,
+        value: &'static str,
+    },
+    #[error("Signing failed: {0:#}")]
+    Signing(eyre::Report),
+}
+
+// A value read from the contract which has to be a field element
+pub(crate) fn response_field(value: U256, what: &str) -> Result<F, ConfTokenError> {
+    crate::u256_to_field(value)
+        .map_err(|_| ConfTokenError::InvalidResponse(format!("{what} is not a field element")))
+}
+
+pub(crate) fn decode_action_index(position: usize, index: U256) -> Result<usize, ConfTokenError> {
+    crate::u256_to_usize(index).map_err(|_| ConfTokenError::InvalidActionIndex { position, index })
+}
+
+fn decode_action_indices(indices: &[U256]) -> Result<Vec<usize>, ConfTokenError> {
+    indices
+        .iter()
+        .enumerate()
+        .map(|(position, index)| decode_action_index(position, *index))
+        .collect()
+}
+
+// The index of Action.Deposit in the solidity enum
+const ACTION_DEPOSIT: u8 = 1;
 
 // Codegen from ABI file to interact with the contract.
 sol!(
@@ -26,36 +91,54 @@ sol!(
 pub struct ConfidentialTokenContract {
     pub(crate) contract_address: Address,
     pub(crate) provider: DynProvider,
+    pub(crate) tx_manager: TxManager,
 }
 
 impl ConfidentialTokenContract {
-    pub fn new(contract_address: Address, provider: DynProvider) -> Self {
+    // The sender is the account of the wallet of the provider, transactions with an explicit sender (e.g., deposit_with_sender) are sent from that one instead
+    pub fn new(contract_address: Address, provider: DynProvider, sender: Address) -> Self {
         Self {
             contract_address,
+            tx_manager: TxManager::new(provider.clone(), sender, TxConfig::default()),
             provider,
         }
     }
 
+    pub fn with_tx_config(self, config: TxConfig) -> Self {
+        let sender = self.tx_manager.default_from();
+        Self {
+            tx_manager: TxManager::new(self.provider.clone(), sender, config),
+            ..self
+        }
+    }
+
     // Returns additive shares of the decrypted amount and randomness for the given party index
     pub fn decrypt_share(
         ciphertext: Ciphertext,
         my_sk: ark_babyjubjub::Fr,
         my_index: usize,
-    ) -> eyre::Result<[F; 2]> {
+    ) -> Result<[F; 2], ConfTokenError> {
         if my_index >= 3 {
-            eyre::bail!("invalid party index for decryption share");
+            return Err(ConfTokenError::InvalidInput(
+                "invalid party index for decryption share".to_owned(),
+            ));
         }
+        let field = |value| {
+            crate::u256_to_field(value).map_err(|_| {
+                ConfTokenError::InvalidInput("ciphertext is not a field element".to_owned())
+            })
+        };
         let sender_pk = ark_babyjubjub::EdwardsAffine::new(
-            crate::u256_to_field(ciphertext.sender_pk.x)?,
-            crate::u256_to_field(ciphertext.sender_pk.y)?,
+            field(ciphertext.sender_pk.x)?,
+            field(ciphertext.sender_pk.y)?,
         );
         let dh_key = crate::ae::dh_key_derivation(&my_sk, sender_pk);
 
         let decrypted = crate::ae::sym_decrypt(
             dh_key,
             [
-                crate::u256_to_field(ciphertext.amount[my_index])?,
-                crate::u256_to_field(ciphertext.r[my_index])?,
+                field(ciphertext.amount[my_index])?,
+                field(ciphertext.r[my_index])?,
             ],
             F::zero(),
         );
@@ -95,63 +178,65 @@ impl ConfidentialTokenContract {
         rpc_url: &str,
         contract_address: Address,
         wallet: EthereumWallet,
-    ) -> eyre::Result<Self> {
+    ) -> Result<Self, ConfTokenError> {
+        let sender = NetworkWallet::<Ethereum>::default_signer_address(&wallet);
+
         // Create the provider.
         let ws = WsConnect::new(rpc_url); // rpc-url of anvil
         let provider = ProviderBuilder::new()
             .wallet(wallet)
             .connect_ws(ws)
             .await
-            .context("while connecting to RPC")?;
+            .context("while connecting to RPC")
+            .map_err(ConfTokenError::Rpc)?;
 
-        Ok(Self {
-            contract_address,
-            provider: provider.erased(),
-        })
+        Ok(Self::new(contract_address, provider.erased(), sender))
     }
 
-    pub async fn get_balance_commitment(&self, user: F) -> eyre::Result<F> {
+    pub async fn get_balance_commitment(&self, user: Address) -> Result<F, ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
         let commitment = contract
-            .getBalanceCommitment(crate::field_to_address(user)?)
+            .getBalanceCommitment(user)
             .call()
             .await
-            .context("while calling get_balance_commitment")?;
+            .context("while calling get_balance_commitment")
+            .map_err(ConfTokenError::Rpc)?;
 
-        crate::u256_to_field(commitment)
+        response_field(commitment, "balance commitment")
     }
 
-    pub async fn get_action_at_index(&self, index: usize) -> eyre::Result<ActionQuery> {
+    pub async fn get_action_at_index(&self, index: usize) -> Result<ActionQuery, ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
         contract
             .getActionAtIndex(crate::usize_to_u256(index))
             .call()
             .await
             .context("while calling get_action_at_index")
+            .map_err(ConfTokenError::Rpc)
     }
 
-    pub async fn get_action_queue_size(&self) -> eyre::Result<usize> {
+    pub async fn get_action_queue_size(&self) -> Result<usize, ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
         let size = contract
             .getActionQueueSize()
             .call()
             .await
-            .context("while calling get_action_queue_size")?;
+            .context("while calling get_action_queue_size")
+            .map_err(ConfTokenError::Rpc)?;
 
-        crate::u256_to_usize(size)
+        crate::u256_to_usize(size).map_err(|_| {
+            ConfTokenError::InvalidResponse("queue size does not fit into a usize".to_owned())
+        })
     }
 
-    pub async fn retrieve_funds(&self, receiver: Address) -> eyre::Result<TransactionReceipt> {
+    pub async fn retrieve_funds(
+        &self,
+        receiver: Address,
+    ) -> Result<TransactionReceipt, ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
 
-        let receipt = contract
-            .retrieveFunds(receiver)
-            .send()
-            .await
-            .context("while broadcasting to network")?
-            .get_receipt()
-            .await
-            .context("while receiving receipt for transaction")?;
+        let tx = contract.retrieveFunds(receiver).into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
 
         if receipt.status() {
             tracing::info!(
@@ -159,23 +244,19 @@ impl ConfidentialTokenContract {
                 receipt.transaction_hash
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         Ok(receipt)
     }
 
-    pub async fn deposit(&self, amount: F) -> eyre::Result<(usize, TransactionReceipt)> {
+    pub async fn deposit(&self, amount: F) -> Result<(usize, TransactionReceipt), ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
 
-        let receipt = contract
+        let tx = contract
             .deposit(crate::field_to_u256(amount))
-            .send()
-            .await
-            .context("while broadcasting to network")?
-            .get_receipt()
-            .await
-            .context("while receiving receipt for transaction")?;
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
 
         if receipt.status() {
             tracing::info!(
@@ -183,13 +264,13 @@ impl ConfidentialTokenContract {
                 receipt.transaction_hash
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         let result = receipt
             .decoded_log::<ConfidentialToken::Deposit>()
-            .ok_or_else(|| eyre::eyre!("no Deposit event found in transaction receipt logs"))?;
-        let action_index = crate::u256_to_usize(result.action_index)?;
+            .ok_or(ConfTokenError::MissingEvent("Deposit"))?;
+        let action_index = decode_action_index(0, result.action_index)?;
 
         Ok((action_index, receipt))
     }
@@ -198,18 +279,14 @@ impl ConfidentialTokenContract {
         &self,
         from: Address,
         amount: F,
-    ) -> eyre::Result<(usize, TransactionReceipt)> {
+    ) -> Result<(usize, TransactionReceipt), ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
 
-        let receipt = contract
+        let tx = contract
             .deposit(crate::field_to_u256(amount))
             .from(from)
-            .send()
-            .await
-            .context("while broadcasting to network")?
-            .get_receipt()
-            .await
-            .context("while receiving receipt for transaction")?;
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
 
         if receipt.status() {
             tracing::info!(
@@ -217,28 +294,24 @@ impl ConfidentialTokenContract {
                 receipt.transaction_hash
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         let result = receipt
             .decoded_log::<ConfidentialToken::Deposit>()
-            .ok_or_else(|| eyre::eyre!("no Deposit event found in transaction receipt logs"))?;
-        let action_index = crate::u256_to_usize(result.action_index)?;
+            .ok_or(ConfTokenError::MissingEvent("Deposit"))?;
+        let action_index = decode_action_index(0, result.action_index)?;
 
         Ok((action_index, receipt))
     }
 
-    pub async fn withdraw(&self, amount: F) -> eyre::Result<(usize, TransactionReceipt)> {
+    pub async fn withdraw(&self, amount: F) -> Result<(usize, TransactionReceipt), ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
 
-        let receipt = contract
+        let tx = contract
             .withdraw(crate::field_to_u256(amount))
-            .send()
-            .await
-            .context("while broadcasting to network")?
-            .get_receipt()
-            .await
-            .context("while receiving receipt for transaction")?;
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
 
         if receipt.status() {
             tracing::info!(
@@ -246,46 +319,79 @@ impl ConfidentialTokenContract {
                 receipt.transaction_hash
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         let result = receipt
             .decoded_log::<ConfidentialToken::Withdraw>()
-            .ok_or_else(|| eyre::eyre!("no Withdraw event found in transaction receipt logs"))?;
-        let action_index = crate::u256_to_usize(result.action_index)?;
+            .ok_or(ConfTokenError::MissingEvent("Withdraw"))?;
+        let action_index = decode_action_index(0, result.action_index)?;
 
         Ok((action_index, receipt))
     }
 
-    pub async fn get_mpc_keys(&self) -> eyre::Result<[ark_babyjubjub::EdwardsAffine; 3]> {
+    // The amount is only revealed by the MPC network at payout. The ciphertext contains the shares of the amount and the blinding of amount_commitment, see encrypt_shares.
+    pub async fn withdraw_private(
+        &self,
+        amount_commitment: F,
+        ciphertext: Ciphertext,
+    ) -> Result<(usize, TransactionReceipt), ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+
+        let tx = contract
+            .withdrawPrivate(crate::field_to_u256(amount_commitment), ciphertext)
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
+
+        if receipt.status() {
+            tracing::info!(
+                "private withdraw done with transaction hash: {}",
+                receipt.transaction_hash
+            );
+        } else {
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
+        }
+
+        let result = receipt
+            .decoded_log::<ConfidentialToken::Withdraw>()
+            .ok_or(ConfTokenError::MissingEvent("Withdraw"))?;
+        let action_index = decode_action_index(0, result.action_index)?;
+
+        Ok((action_index, receipt))
+    }
+
+    pub async fn get_mpc_keys(&self) -> Result<[ark_babyjubjub::EdwardsAffine; 3], ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
         let key1 = contract
             .mpc_pk1()
             .call()
             .await
-            .context("while calling get_balance_commitment")?;
+            .context("while calling get_balance_commitment")
+            .map_err(ConfTokenError::Rpc)?;
         let key2 = contract
             .mpc_pk2()
             .call()
             .await
-            .context("while calling get_balance_commitment")?;
+            .context("while calling get_balance_commitment")
+            .map_err(ConfTokenError::Rpc)?;
         let key3 = contract
             .mpc_pk3()
             .call()
             .await
-            .context("while calling get_balance_commitment")?;
+            .context("while calling get_balance_commitment")
+            .map_err(ConfTokenError::Rpc)?;
 
         let key1 = ark_babyjubjub::EdwardsAffine::new(
-            crate::u256_to_field(key1.x)?,
-            crate::u256_to_field(key1.y)?,
+            response_field(key1.x, "mpc_pk1")?,
+            response_field(key1.y, "mpc_pk1")?,
         );
         let key2 = ark_babyjubjub::EdwardsAffine::new(
-            crate::u256_to_field(key2.x)?,
-            crate::u256_to_field(key2.y)?,
+            response_field(key2.x, "mpc_pk2")?,
+            response_field(key2.y, "mpc_pk2")?,
         );
         let key3 = ark_babyjubjub::EdwardsAffine::new(
-            crate::u256_to_field(key3.x)?,
-            crate::u256_to_field(key3.y)?,
+            response_field(key3.x, "mpc_pk3")?,
+            response_field(key3.y, "mpc_pk3")?,
         );
 
         Ok([key1, key2, key3])
@@ -297,18 +403,14 @@ impl ConfidentialTokenContract {
         from: Address,
         amount: F,
         ciphertext: Ciphertext,
-    ) -> eyre::Result<(usize, TransactionReceipt)> {
+    ) -> Result<(usize, TransactionReceipt), ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
 
-        let receipt = contract
+        let tx = contract
             .transfer(to, crate::field_to_u256(amount), ciphertext)
             .from(from)
-            .send()
-            .await
-            .context("while broadcasting to network")?
-            .get_receipt()
-            .await
-            .context("while receiving receipt for transaction")?;
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
 
         if receipt.status() {
             tracing::info!(
@@ -317,13 +419,13 @@ impl ConfidentialTokenContract {
                 receipt.gas_used
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         let result = receipt
             .decoded_log::<ConfidentialToken::Transfer>()
-            .ok_or_else(|| eyre::eyre!("no Transfer event found in transaction receipt logs"))?;
-        let action_index = crate::u256_to_usize(result.action_index)?;
+            .ok_or(ConfTokenError::MissingEvent("Transfer"))?;
+        let action_index = decode_action_index(0, result.action_index)?;
 
         Ok((action_index, receipt))
     }
@@ -333,17 +435,13 @@ impl ConfidentialTokenContract {
         to: Address,
         amount: F,
         ciphertext: Ciphertext,
-    ) -> eyre::Result<(usize, TransactionReceipt)> {
+    ) -> Result<(usize, TransactionReceipt), ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
 
-        let receipt = contract
+        let tx = contract
             .transfer(to, crate::field_to_u256(amount), ciphertext)
-            .send()
-            .await
-            .context("while broadcasting to network")?
-            .get_receipt()
-            .await
-            .context("while receiving receipt for transaction")?;
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
 
         if receipt.status() {
             tracing::debug!(
@@ -351,13 +449,13 @@ impl ConfidentialTokenContract {
                 receipt.transaction_hash
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         let result = receipt
             .decoded_log::<ConfidentialToken::Transfer>()
-            .ok_or_else(|| eyre::eyre!("no Transfer event found in transaction receipt logs"))?;
-        let action_index = crate::u256_to_usize(result.action_index)?;
+            .ok_or(ConfTokenError::MissingEvent("Transfer"))?;
+        let action_index = decode_action_index(0, result.action_index)?;
 
         Ok((action_index, receipt))
     }
@@ -368,26 +466,21 @@ impl ConfidentialTokenContract {
         to: &[Address],
         amount: &[F],
         // ciphertext: &[Ciphertext],
-    ) -> eyre::Result<(Vec<usize>, TransactionReceipt)> {
+    ) -> Result<(Vec<usize>, TransactionReceipt), ConfTokenError> {
         assert_eq!(from.len(), to.len());
         assert_eq!(from.len(), amount.len());
         // assert_eq!(from.len(), ciphertext.len());
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
 
-        let receipt = contract
+        let tx = contract
             .transferBatch(
                 from.to_vec(),
                 to.to_vec(),
                 amount.iter().map(|x| crate::field_to_u256(*x)).collect(),
                 // ciphertext.to_vec(),
             )
-            .gas(5_000_000)
-            .send()
-            .await
-            .context("while broadcasting to network")?
-            .get_receipt()
-            .await
-            .context("while receiving receipt for transaction")?;
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
 
         if receipt.status() {
             tracing::info!(
@@ -396,61 +489,480 @@ impl ConfidentialTokenContract {
                 receipt.gas_used
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         let result = receipt
             .decoded_log::<ConfidentialToken::TransferBatch>()
-            .ok_or_else(|| {
-                eyre::eyre!("no TransferBatch event found in transaction receipt logs")
-            })?;
+            .ok_or(ConfTokenError::MissingEvent("TransferBatch"))?;
 
-        let action_indices = result
-            .action_indices
-            .iter()
-            .cloned()
-            .map(crate::u256_to_usize)
-            .collect::<eyre::Result<Vec<usize>>>()?;
+        let action_indices = decode_action_indices(&result.action_indices)?;
 
         Ok((action_indices, receipt))
     }
 
-    pub async fn remove_action_at_index(&self, index: usize) -> eyre::Result<TransactionReceipt> {
+    pub async fn get_auth_key(
+        &self,
+        user: Address,
+    ) -> Result<Option<ark_babyjubjub::EdwardsAffine>, ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        let key = contract
+            .authKeys(user)
+            .call()
+            .await
+            .context("while calling get_auth_key")
+            .map_err(ConfTokenError::Rpc)?;
 
-        let receipt = contract
-            .removeActionAtIndex(crate::usize_to_u256(index))
-            .send()
+        // The contract does not allow keys with x = 0, thus this is the marker for no registered key
+        if key.x.is_zero() {
+            return Ok(None);
+        }
+        let key =
+            eddsa::element_to_point(&BabyJubJubElement { x: key.x, y: key.y }).map_err(|err| {
+                ConfTokenError::InvalidResponse(format!("auth key of {user}: {err:#}"))
+            })?;
+        Ok(Some(key))
+    }
+
+    pub async fn get_auth_nonce(&self, user: Address) -> Result<u64, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        let nonce = contract
+            .authNonces(user)
+            .call()
             .await
-            .context("while broadcasting to network")?
-            .get_receipt()
+            .context("while calling get_auth_nonce")
+            .map_err(ConfTokenError::Rpc)?;
+
+        nonce.try_into().map_err(|_| {
+            ConfTokenError::InvalidResponse("nonce does not fit into a u64".to_owned())
+        })
+    }
+
+    pub async fn get_authorization_at_index(
+        &self,
+        index: usize,
+    ) -> Result<TransferAuthorization, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        contract
+            .getAuthorizationAtIndex(crate::usize_to_u256(index))
+            .call()
             .await
-            .context("while receiving receipt for transaction")?;
+            .context("while calling get_authorization_at_index")
+            .map_err(ConfTokenError::Rpc)
+    }
+
+    pub async fn register_auth_key(
+        &self,
+        pk: &ark_babyjubjub::EdwardsAffine,
+    ) -> Result<TransactionReceipt, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+
+        let tx = contract
+            .registerAuthKey(eddsa::point_to_element(pk))
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
 
         if receipt.status() {
             tracing::info!(
-                "remove action done with transaction hash: {}",
+                "register auth key done with transaction hash: {}",
+                receipt.transaction_hash
+            );
+        } else {
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
+        }
+
+        Ok(receipt)
+    }
+
+    // Signs the registration of an auth key with the Ethereum key of the user, such that a relayer can submit it with register_auth_key_for
+    pub fn sign_auth_key_registration(
+        &self,
+        signer: &PrivateKeySigner,
+        pk: &ark_babyjubjub::EdwardsAffine,
+        nonce: u64,
+    ) -> Result<Bytes, ConfTokenError> {
+        // abi.encodePacked(address(this), user, pk.x, pk.y, nonce)
+        let mut packed = Vec::with_capacity(2 * 20 + 3 * 32);
+        packed.extend_from_slice(self.contract_address.as_slice());
+        packed.extend_from_slice(signer.address().as_slice());
+        packed.extend_from_slice(&crate::field_to_u256(pk.x).to_be_bytes::<32>());
+        packed.extend_from_slice(&crate::field_to_u256(pk.y).to_be_bytes::<32>());
+        packed.extend_from_slice(&U256::from(nonce).to_be_bytes::<32>());
+
+        // Adds the "\x19Ethereum Signed Message:\n32" prefix
+        let signature = signer
+            .sign_message_sync(keccak256(packed).as_slice())
+            .context("while signing auth key registration")
+            .map_err(ConfTokenError::Signing)?;
+        Ok(Bytes::copy_from_slice(&signature.as_bytes()))
+    }
+
+    pub async fn register_auth_key_for(
+        &self,
+        user: Address,
+        pk: &ark_babyjubjub::EdwardsAffine,
+        signature: Bytes,
+    ) -> Result<TransactionReceipt, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+
+        let tx = contract
+            .registerAuthKeyFor(user, eddsa::point_to_element(pk), signature)
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
+
+        if receipt.status() {
+            tracing::info!(
+                "register auth key done with transaction hash: {}",
                 receipt.transaction_hash
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         Ok(receipt)
     }
 
-    pub async fn remove_all_open_actions(&self) -> eyre::Result<TransactionReceipt> {
+    // Creates a transfer which is signed by the sender and can be submitted by anyone using transfer_signed. The nonce has to be the next nonce of the sender which is not used by another pending transfer, see get_auth_nonce. It is only consumed when the MPC network processes the transfer.
+    pub fn sign_transfer<R: Rng + CryptoRng>(
+        &self,
+        sk: &ark_babyjubjub::Fr,
+        transfer: TransferMessage,
+        ciphertext: Ciphertext,
+        rng: &mut R,
+    ) -> Result<SignedTransfer, ConfTokenError> {
+        let ciphertext_hash = eddsa::ciphertext_hash(&ciphertext)
+            .map_err(|err| ConfTokenError::InvalidInput(format!("{err:#}")))?;
+        if transfer.ciphertext_hash != ciphertext_hash {
+            return Err(ConfTokenError::InvalidInput(
+                "the transfer message does not bind the ciphertext".to_owned(),
+            ));
+        }
+        let signature = eddsa::sign(sk, transfer.message(self.contract_address), rng);
+        Ok(SignedTransfer {
+            sender: transfer.sender,
+            receiver: transfer.receiver,
+            amount_commitment: crate::field_to_u256(transfer.amount_commitment),
+            nonce: U256::from(transfer.nonce),
+            ciphertext,
+            signature,
+        })
+    }
+
+    // Checks the signature of a signed transfer against the registered key of the sender
+    pub async fn verify_signed_transfer(
+        &self,
+        transfer: &SignedTransfer,
+    ) -> Result<(), ConfTokenError> {
+        let unauthorized = |reason| ConfTokenError::Unauthorized {
+            sender: transfer.sender,
+            nonce: transfer.nonce,
+            reason,
+        };
+        let pk = self
+            .get_auth_key(transfer.sender)
+            .await?
+            .ok_or_else(|| unauthorized(eyre::eyre!("no auth key registered")))?;
+        let message = TransferMessage {
+            sender: transfer.sender,
+            receiver: transfer.receiver,
+            amount_commitment: crate::u256_to_field(transfer.amount_commitment)
+                .map_err(unauthorized)?,
+            ciphertext_hash: eddsa::ciphertext_hash(&transfer.ciphertext).map_err(unauthorized)?,
+            nonce: transfer
+                .nonce
+                .try_into()
+                .map_err(|_| unauthorized(eyre::eyre!("nonce does not fit into a u64")))?,
+        };
+        eddsa::verify(
+            &pk,
+            message.message(self.contract_address),
+            &transfer.signature,
+        )
+        .map_err(unauthorized)
+    }
+
+    // Submits signed transfers of (potentially) different senders in one transaction. The signatures are checked before, since the contract does not verify them.
+    pub async fn transfer_signed(
+        &self,
+        transfers: Vec<SignedTransfer>,
+    ) -> Result<(Vec<usize>, TransactionReceipt), ConfTokenError> {
+        for transfer in transfers.iter() {
+            self.verify_signed_transfer(transfer).await?;
+        }
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
 
-        let receipt = contract
-            .removeAllOpenActions()
-            .gas(20_000_000)
-            .send()
+        let tx = contract
+            .transferSigned(transfers)
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
+
+        if receipt.status() {
+            tracing::info!(
+                "transferSigned done with transaction hash: {}, gas_used: {}",
+                receipt.transaction_hash,
+                receipt.gas_used
+            );
+        } else {
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
+        }
+
+        let result = receipt
+            .decoded_log::<ConfidentialToken::TransferBatch>()
+            .ok_or(ConfTokenError::MissingEvent("TransferBatch"))?;
+
+        let action_indices = decode_action_indices(&result.action_indices)?;
+
+        Ok((action_indices, receipt))
+    }
+
+    // Used by the MPC network before including actions of the queue into a batch. Returns the indices of relayed transfers with an invalid signature or a nonce which is not the next one of the sender, which have to be removed from the queue. processMPC consumes the nonces of the remaining relayed transfers in queue order, thus the indices have to be in the order of the batch. Transfers which were not relayed are authorized by the sender of the transaction and are thus always valid.
+    pub async fn find_unauthorized_transfers(
+        &self,
+        indices: &[usize],
+        actions: &[ActionQuery],
+    ) -> Result<Vec<usize>, ConfTokenError> {
+        if indices.len() != actions.len() {
+            return Err(ConfTokenError::InvalidInput(
+                "mismatched lengths of indices and actions".to_owned(),
+            ));
+        }
+
+        // The next nonce of each sender, advanced by the accepted transfers
+        let mut nonces = HashMap::new();
+        let mut unauthorized = Vec::new();
+        for (index, action) in indices.iter().zip(actions) {
+            if action.action != eddsa::ACTION_TRANSFER {
+                continue;
+            }
+            let authorization = self.get_authorization_at_index(*index).await?;
+            if !authorization.relayed {
+                continue;
+            }
+            let nonce = match nonces.entry(action.sender) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(self.get_auth_nonce(action.sender).await?),
+            };
+            let valid = U256::from(*nonce) == authorization.nonce
+                && match self.get_auth_key(action.sender).await? {
+                    Some(pk) => {
+                        let ciphertext = self.get_ciphertext_at_index(*index).await?;
+                        eddsa::verify_transfer(
+                            self.contract_address,
+                            action,
+                            &ciphertext,
+                            &authorization,
+                            &pk,
+                        )
+                        .is_ok()
+                    }
+                    None => false,
+                };
+            if valid {
+                *nonce += 1;
+            } else {
+                tracing::warn!("invalid authorization for action at index {index}");
+                unauthorized.push(*index);
+            }
+        }
+
+        Ok(unauthorized)
+    }
+
+    pub async fn get_auditor_key(
+        &self,
+    ) -> Result<Option<ark_babyjubjub::EdwardsAffine>, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        let key = contract
+            .auditor_pk()
+            .call()
+            .await
+            .context("while calling get_auditor_key")
+            .map_err(ConfTokenError::Rpc)?;
+
+        // (0, 0) marks that there is no auditor
+        if key.x.is_zero() && key.y.is_zero() {
+            return Ok(None);
+        }
+        let key = eddsa::element_to_point(&BabyJubJubElement { x: key.x, y: key.y })
+            .map_err(|err| ConfTokenError::InvalidResponse(format!("auditor key: {err:#}")))?;
+        Ok(Some(key))
+    }
+
+    // None removes the auditor
+    pub async fn set_auditor_key(
+        &self,
+        pk: Option<&ark_babyjubjub::EdwardsAffine>,
+    ) -> Result<TransactionReceipt, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        let pk = match pk {
+            Some(pk) => {
+                if pk.is_zero() {
+                    return Err(ConfTokenError::InvalidInput(
+                        "the identity is not a valid auditor key".to_owned(),
+                    ));
+                }
+                eddsa::point_to_element(pk)
+            }
+            None => BabyJubJubElement {
+                x: U256::ZERO,
+                y: U256::ZERO,
+            },
+        };
+
+        let tx = contract.setAuditorKey(pk).into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
+
+        if receipt.status() {
+            tracing::info!(
+                "set auditor key done with transaction hash: {}",
+                receipt.transaction_hash
+            );
+        } else {
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
+        }
+
+        Ok(receipt)
+    }
+
+    // Rotates the keys of the MPC network, e.g., after resharing to a new committee
+    pub async fn set_mpc_keys(
+        &self,
+        mpc_pk: &[ark_babyjubjub::EdwardsAffine; 3],
+    ) -> Result<TransactionReceipt, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        let [pk1, pk2, pk3] = mpc_pk.each_ref().map(eddsa::point_to_element);
+
+        let tx = contract
+            .setMpcKeys(pk1, pk2, pk3)
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
+
+        if receipt.status() {
+            tracing::info!(
+                "set mpc keys done with transaction hash: {}",
+                receipt.transaction_hash
+            );
+        } else {
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
+        }
+
+        Ok(receipt)
+    }
+
+    // The root of the Merkle tree over all balance commitments, posted with the last processed batch
+    pub async fn get_balance_root(&self) -> Result<F, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        let root = contract
+            .balanceRoot()
+            .call()
             .await
-            .context("while broadcasting to network")?
-            .get_receipt()
+            .context("while calling get_balance_root")
+            .map_err(ConfTokenError::Rpc)?;
+        response_field(root, "balance root")
+    }
+
+    // Returns (max_transfer, max_balance)
+    pub async fn get_policy_limits(&self) -> Result<(F, F), ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        let max_transfer = contract
+            .maxTransfer()
+            .call()
             .await
-            .context("while receiving receipt for transaction")?;
+            .context("while calling max_transfer")
+            .map_err(ConfTokenError::Rpc)?;
+        let max_balance = contract
+            .maxBalance()
+            .call()
+            .await
+            .context("while calling max_balance")
+            .map_err(ConfTokenError::Rpc)?;
+        Ok((
+            response_field(max_transfer, "max transfer")?,
+            response_field(max_balance, "max balance")?,
+        ))
+    }
+
+    pub async fn set_policy_limits(
+        &self,
+        max_transfer: F,
+        max_balance: F,
+    ) -> Result<TransactionReceipt, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+
+        let tx = contract
+            .setPolicyLimits(
+                crate::field_to_u256(max_transfer),
+                crate::field_to_u256(max_balance),
+            )
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
+
+        if receipt.status() {
+            tracing::info!(
+                "set policy limits done with transaction hash: {}",
+                receipt.transaction_hash
+            );
+        } else {
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
+        }
+
+        Ok(receipt)
+    }
+
+    // Removes the actions the MPC network rejected because of the policy limits, deposits are refunded
+    pub async fn reject_actions(
+        &self,
+        indices: &[usize],
+    ) -> Result<TransactionReceipt, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        let indices = indices
+            .iter()
+            .map(|index| crate::usize_to_u256(*index))
+            .collect::<Vec<_>>();
+
+        let tx = contract.rejectActions(indices).into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
+
+        if receipt.status() {
+            tracing::info!(
+                "reject actions done with transaction hash: {}",
+                receipt.transaction_hash
+            );
+        } else {
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
+        }
+
+        Ok(receipt)
+    }
+
+    pub async fn remove_action_at_index(
+        &self,
+        index: usize,
+    ) -> Result<TransactionReceipt, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+
+        let tx = contract
+            .removeActionAtIndex(crate::usize_to_u256(index))
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
+
+        if receipt.status() {
+            tracing::info!(
+                "remove action done with transaction hash: {}",
+                receipt.transaction_hash
+            );
+        } else {
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
+        }
+
+        Ok(receipt)
+    }
+
+    pub async fn remove_all_open_actions(&self) -> Result<TransactionReceipt, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+
+        let tx = contract.removeAllOpenActions().into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
 
         if receipt.status() {
             tracing::info!(
@@ -458,28 +970,25 @@ impl ConfidentialTokenContract {
                 receipt.transaction_hash
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         Ok(receipt)
     }
 
+    // Submitting the same batch again, e.g., with a new proof after a timeout, waits for or replaces the earlier submission instead of sending a second transaction
     pub async fn process_mpc(
         &self,
         inputs: TransactionInput,
         proof: Groth16Proof,
-    ) -> eyre::Result<TransactionReceipt> {
+    ) -> Result<TransactionReceipt, ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
 
-        let receipt = contract
+        let batch = keccak256(inputs.abi_encode());
+        let tx = contract
             .processMPC(inputs, proof)
-            .gas(10_000_000)
-            .send()
-            .await
-            .context("while broadcasting to network")?
-            .get_receipt()
-            .await
-            .context("while receiving receipt for transaction")?;
+            .into_transaction_request();
+        let receipt = self.tx_manager.send_idempotent(batch, tx).await?;
 
         if receipt.status() {
             tracing::info!(
@@ -488,7 +997,7 @@ impl ConfidentialTokenContract {
                 receipt.gas_used
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         Ok(receipt)
@@ -497,17 +1006,13 @@ impl ConfidentialTokenContract {
     pub async fn withelist_addresses_for_demo(
         &self,
         addresses: Vec<Address>,
-    ) -> eyre::Result<TransactionReceipt> {
+    ) -> Result<TransactionReceipt, ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
 
-        let receipt = contract
+        let tx = contract
             .whitelistForDemo(addresses)
-            .send()
-            .await
-            .context("while broadcasting to network")?
-            .get_receipt()
-            .await
-            .context("while registering watcher for whitelist")?;
+            .into_transaction_request();
+        let receipt = self.tx_manager.send(tx).await?;
 
         if receipt.status() {
             tracing::info!(
@@ -515,7 +1020,7 @@ impl ConfidentialTokenContract {
                 receipt.transaction_hash
             );
         } else {
-            eyre::bail!("cannot finish transaction: {receipt:?}");
+            return Err(ConfTokenError::Reverted(Box::new(receipt)));
         }
 
         Ok(receipt)
@@ -524,45 +1029,71 @@ impl ConfidentialTokenContract {
     pub async fn read_queue(
         &self,
         num_items: usize,
-    ) -> eyre::Result<(Vec<usize>, Vec<ActionQuery>, Vec<Ciphertext>)> {
+    ) -> Result<(Vec<usize>, Vec<ActionQuery>, Vec<Ciphertext>), ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
         let res = contract
             .read_queue(crate::usize_to_u256(num_items))
             .call()
             .await
-            .context("while calling read_queue")?;
+            .context("while calling read_queue")
+            .map_err(ConfTokenError::Rpc)?;
 
         if res._0.len() != res._1.len() {
-            eyre::bail!("mismatched lengths in read_queue");
+            return Err(ConfTokenError::InvalidResponse(
+                "mismatched lengths in read_queue".to_owned(),
+            ));
         }
         if res._0.len() != res._2.len() {
-            eyre::bail!("mismatched lengths in read_queue");
+            return Err(ConfTokenError::InvalidResponse(
+                "mismatched lengths in read_queue".to_owned(),
+            ));
         }
 
-        let indices = res
-            ._0
-            .into_iter()
-            .map(crate::u256_to_usize)
-            .collect::<eyre::Result<Vec<usize>>>()?;
+        let indices = decode_action_indices(&res._0)?;
 
         Ok((indices, res._1, res._2))
     }
 
-    pub async fn get_ciphertext_at_index(&self, index: usize) -> eyre::Result<Ciphertext> {
+    pub async fn get_ciphertext_at_index(
+        &self,
+        index: usize,
+    ) -> Result<Ciphertext, ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
         contract
             .getCiphertextAtIndex(crate::usize_to_u256(index))
             .call()
             .await
             .context("while calling get_ciphertext_at_index")
+            .map_err(ConfTokenError::Rpc)
+    }
+
+    // The amount of tokens backing the processed private balances, i.e., the token balance of the contract minus the deposits which are still in the queue. Compare with the total of a solvency proof.
+    pub async fn get_solvency_reserve(&self) -> Result<U256, ConfTokenError> {
+        let token = USDCTokenContract::new(self.get_token_address().await?, self.provider.clone());
+        let balance = token
+            .balance_of(self.contract_address)
+            .await
+            .map_err(ConfTokenError::Rpc)?;
+
+        let queue_size = self.get_action_queue_size().await?;
+        let (_, actions, _) = self.read_queue(queue_size).await?;
+        let pending = actions
+            .iter()
+            .filter(|action| action.action == ACTION_DEPOSIT)
+            .fold(U256::ZERO, |acc, action| acc + action.amount);
+
+        balance.checked_sub(pending).ok_or_else(|| {
+            ConfTokenError::InvalidResponse("pending deposits exceed the token balance".to_owned())
+        })
     }
 
-    pub async fn get_token_address(&self) -> eyre::Result<Address> {
+    pub async fn get_token_address(&self) -> Result<Address, ConfTokenError> {
         let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
         contract
             .token()
             .call()
             .await
             .context("while calling get_token_address")
+            .map_err(ConfTokenError::Rpc)
     }
 }
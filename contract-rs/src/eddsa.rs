@@ -0,0 +1,290 @@
+use crate::conf_token::ConfidentialToken::{
+    ActionQuery, BabyJubJubElement, Ciphertext, EdDSASignature, TransferAuthorization,
+};
+use alloy::primitives::{Address, U256};
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{BigInteger, PrimeField, UniformRand};
+use mpc_core::gadgets::poseidon2::Poseidon2;
+use rand::{CryptoRng, Rng};
+
+type Fq = ark_bn254::Fr;
+type Fr = ark_babyjubjub::Fr;
+type Point = ark_babyjubjub::EdwardsAffine;
+
+// The index of Action.Transfer in the solidity enum
+pub(crate) const ACTION_TRANSFER: u8 = 3;
+
+// Absorb 2, squeeze 1, domainsep = 0x4544 ("ED")
+// [0x80000002, 0x00000001, 0x4544]
+const SPONGE_DS: u128 = 0x80000002000000014544;
+// Separates the transfer message from the challenge hash
+const TRANSFER_DS: u64 = 0x5452;
+// Separates the ciphertext hash from the transfer message
+const CIPHERTEXT_DS: u64 = 0x4354;
+
+// Poseidon2 sponge with state size 3, absorbing two elements per permutation
+fn hash(inputs: &[Fq]) -> Fq {
+    let poseidon2_3 = Poseidon2::<_, 3, 5>::default();
+    let mut state = [Fq::from(SPONGE_DS), Fq::from(0u64), Fq::from(0u64)];
+    for chunk in inputs.chunks(2) {
+        state[1] += chunk[0];
+        if let Some(second) = chunk.get(1) {
+            state[2] += second;
+        }
+        state = poseidon2_3.permutation(&state);
+    }
+    state[1]
+}
+
+fn challenge(r: &Point, pk: &Point, msg: Fq) -> Fr {
+    let c = hash(&[r.x, r.y, pk.x, pk.y, msg]);
+    Fr::from_le_bytes_mod_order(&c.into_bigint().to_bytes_le())
+}
+
+pub fn public_key(sk: &Fr) -> Point {
+    (Point::generator() * sk).into_affine()
+}
+
+pub fn random_key<R: Rng + CryptoRng>(rng: &mut R) -> (Fr, Point) {
+    let sk = Fr::rand(rng);
+    (sk, public_key(&sk))
+}
+
+pub fn point_to_element(point: &Point) -> BabyJubJubElement {
+    BabyJubJubElement {
+        x: crate::field_to_u256(point.x),
+        y: crate::field_to_u256(point.y),
+    }
+}
+
+// Returns an error if the point is not on the curve, of small order (including the identity), or not in the prime order subgroup
+pub fn element_to_point(element: &BabyJubJubElement) -> eyre::Result<Point> {
+    let point = Point::new_unchecked(
+        crate::u256_to_field(element.x)?,
+        crate::u256_to_field(element.y)?,
+    );
+    if !point.is_on_curve() {
+        eyre::bail!("point is not on the BabyJubJub curve");
+    }
+    if point.mul_by_cofactor().is_zero() {
+        eyre::bail!("point has small order");
+    }
+    if !point.is_in_correct_subgroup_assuming_on_curve() {
+        eyre::bail!("point is not in the prime order subgroup");
+    }
+    Ok(point)
+}
+
+// Binds the ciphertext of the shares to the transfer message, such that a relayer cannot swap it
+pub fn ciphertext_hash(ciphertext: &Ciphertext) -> eyre::Result<Fq> {
+    let mut inputs = Vec::with_capacity(9);
+    inputs.push(Fq::from(CIPHERTEXT_DS));
+    for value in ciphertext.amount.iter().chain(ciphertext.r.iter()) {
+        inputs.push(crate::u256_to_field(*value)?);
+    }
+    inputs.push(crate::u256_to_field(ciphertext.sender_pk.x)?);
+    inputs.push(crate::u256_to_field(ciphertext.sender_pk.y)?);
+    Ok(hash(&inputs))
+}
+
+// The message a sender signs to authorize a relayed transfer. The contract address prevents replays on other deployments, the nonce replays on the same one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferMessage {
+    pub sender: Address,
+    pub receiver: Address,
+    pub amount_commitment: Fq,
+    pub ciphertext_hash: Fq, // See ciphertext_hash
+    pub nonce: u64,
+}
+
+impl TransferMessage {
+    pub fn message(&self, contract_address: Address) -> Fq {
+        hash(&[
+            Fq::from(TRANSFER_DS),
+            crate::address_to_field(contract_address),
+            crate::address_to_field(self.sender),
+            crate::address_to_field(self.receiver),
+            self.amount_commitment,
+            self.ciphertext_hash,
+            Fq::from(self.nonce),
+        ])
+    }
+}
+
+// Signature: R = r * G, s = r + H(R, pk, msg) * sk
+pub fn sign<R: Rng + CryptoRng>(sk: &Fr, msg: Fq, rng: &mut R) -> EdDSASignature {
+    let pk = public_key(sk);
+    let nonce = Fr::rand(rng);
+    let r = public_key(&nonce);
+    let s = nonce + challenge(&r, &pk, msg) * sk;
+
+    EdDSASignature {
+        r: point_to_element(&r),
+        s: U256::from_limbs(s.into_bigint().0),
+    }
+}
+
+// Checks s * G == R + H(R, pk, msg) * pk
+pub fn verify(pk: &Point, msg: Fq, signature: &EdDSASignature) -> eyre::Result<()> {
+    if pk.mul_by_cofactor().is_zero() {
+        eyre::bail!("public key has small order");
+    }
+    let r = element_to_point(&signature.r)?;
+    let s = Fr::from_bigint(<Fr as PrimeField>::BigInt::new(signature.s.into_limbs()))
+        .ok_or_else(|| eyre::eyre!("signature scalar is out of range"))?;
+
+    let lhs = Point::generator() * s;
+    let rhs = *pk * challenge(&r, pk, msg) + r;
+    if lhs != rhs {
+        eyre::bail!("invalid signature");
+    }
+    Ok(())
+}
+
+// Checks the authorization of a relayed transfer in the action queue against the registered key of the sender
+pub fn verify_transfer(
+    contract_address: Address,
+    action: &ActionQuery,
+    ciphertext: &Ciphertext,
+    authorization: &TransferAuthorization,
+    pk: &Point,
+) -> eyre::Result<()> {
+    if action.action != ACTION_TRANSFER {
+        eyre::bail!("only transfers carry an authorization");
+    }
+    if !authorization.relayed {
+        eyre::bail!("the transfer was not relayed");
+    }
+    let transfer = TransferMessage {
+        sender: action.sender,
+        receiver: action.receiver,
+        amount_commitment: crate::u256_to_field(action.amount)?,
+        ciphertext_hash: ciphertext_hash(ciphertext)?,
+        nonce: authorization
+            .nonce
+            .try_into()
+            .map_err(|_| eyre::eyre!("nonce does not fit into a u64"))?,
+    };
+    verify(
+        pk,
+        transfer.message(contract_address),
+        &authorization.signature,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_address<R: Rng>(rng: &mut R) -> Address {
+        Address::from(rng.r#gen::<[u8; 20]>())
+    }
+
+    fn random_ciphertext<R: Rng + CryptoRng>(rng: &mut R) -> Ciphertext {
+        Ciphertext {
+            amount: std::array::from_fn(|_| crate::field_to_u256(Fq::rand(rng))),
+            r: std::array::from_fn(|_| crate::field_to_u256(Fq::rand(rng))),
+            sender_pk: point_to_element(&random_key(rng).1),
+        }
+    }
+
+    #[test]
+    fn eddsa_test() {
+        let mut rng = rand::thread_rng();
+        let contract = random_address(&mut rng);
+        let (sk, pk) = random_key(&mut rng);
+        let (_, other_pk) = random_key(&mut rng);
+        let ciphertext = random_ciphertext(&mut rng);
+
+        let transfer = TransferMessage {
+            sender: random_address(&mut rng),
+            receiver: random_address(&mut rng),
+            amount_commitment: Fq::rand(&mut rng),
+            ciphertext_hash: ciphertext_hash(&ciphertext).unwrap(),
+            nonce: rng.r#gen::<u32>().into(),
+        };
+        let msg = transfer.message(contract);
+        let signature = sign(&sk, msg, &mut rng);
+        verify(&pk, msg, &signature).unwrap();
+
+        // Wrong key
+        assert!(verify(&other_pk, msg, &signature).is_err());
+
+        // Every field is bound by the signature
+        let mut modified = transfer;
+        modified.nonce += 1;
+        assert!(verify(&pk, modified.message(contract), &signature).is_err());
+        let mut modified = transfer;
+        modified.receiver = random_address(&mut rng);
+        assert!(verify(&pk, modified.message(contract), &signature).is_err());
+        let mut modified = transfer;
+        modified.amount_commitment += Fq::from(1u64);
+        assert!(verify(&pk, modified.message(contract), &signature).is_err());
+        let mut modified = transfer;
+        modified.ciphertext_hash = ciphertext_hash(&random_ciphertext(&mut rng)).unwrap();
+        assert!(verify(&pk, modified.message(contract), &signature).is_err());
+        assert!(verify(&pk, transfer.message(random_address(&mut rng)), &signature).is_err());
+
+        // The MPC network checks the action read from the queue
+        let action = ActionQuery {
+            action: ACTION_TRANSFER,
+            sender: transfer.sender,
+            receiver: transfer.receiver,
+            amount: crate::field_to_u256(transfer.amount_commitment),
+        };
+        let authorization = TransferAuthorization {
+            relayed: true,
+            nonce: U256::from(transfer.nonce),
+            signature: signature.clone(),
+        };
+        verify_transfer(contract, &action, &ciphertext, &authorization, &pk).unwrap();
+        assert!(
+            verify_transfer(contract, &action, &ciphertext, &authorization, &other_pk).is_err()
+        );
+
+        // A relayer cannot swap the ciphertext
+        let swapped = random_ciphertext(&mut rng);
+        assert!(verify_transfer(contract, &action, &swapped, &authorization, &pk).is_err());
+
+        // Transfers which were not relayed carry no authorization
+        let mut not_relayed = authorization.clone();
+        not_relayed.relayed = false;
+        assert!(verify_transfer(contract, &action, &ciphertext, &not_relayed, &pk).is_err());
+
+        // Tampered signature
+        let mut tampered = signature.clone();
+        tampered.s += U256::from(1u64);
+        assert!(verify(&pk, msg, &tampered).is_err());
+    }
+
+    // R = (sqrt(1/a), 0) has order 4, with s = 0 it would pass the check for every key that also has small order
+    #[test]
+    fn eddsa_small_order_test() {
+        let mut rng = rand::thread_rng();
+        let small_order = BabyJubJubElement {
+            x: U256::from_str_radix(
+                "2957874849018779266517920829765869116077630550401372566248359756137677864698",
+                10,
+            )
+            .unwrap(),
+            y: U256::ZERO,
+        };
+        let point = Point::new_unchecked(
+            crate::u256_to_field(small_order.x).unwrap(),
+            crate::u256_to_field(small_order.y).unwrap(),
+        );
+        assert!(point.is_on_curve());
+        assert!(element_to_point(&small_order).is_err());
+        assert!(element_to_point(&point_to_element(&Point::zero())).is_err());
+
+        let (_, pk) = random_key(&mut rng);
+        let msg = Fq::rand(&mut rng);
+        let forged = EdDSASignature {
+            r: small_order,
+            s: U256::ZERO,
+        };
+        assert!(verify(&pk, msg, &forged).is_err());
+        assert!(verify(&point, msg, &forged).is_err());
+        assert!(verify(&Point::zero(), msg, &forged).is_err());
+    }
+}
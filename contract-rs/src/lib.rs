@@ -1,13 +1,20 @@
 pub mod ae;
+pub mod auditor;
+pub mod backend;
 pub mod conf_token;
+pub mod eddsa;
+pub mod mock;
+pub mod simulation;
 pub mod token;
+pub mod tx_manager;
+pub mod widths;
 
 use std::array;
 
 use alloy::primitives::{Address, U256};
 use ark_bn254::Bn254;
 use ark_ec::AffineRepr;
-use ark_ff::PrimeField;
+use ark_ff::{PrimeField, Zero};
 use ark_groth16::Proof;
 use serde::{Deserialize, Serialize};
 
@@ -44,12 +51,17 @@ pub fn u256_to_field(value: U256) -> eyre::Result<F> {
     F::from_bigint(bigint).ok_or_else(|| eyre::eyre!("U256 value is out of field range"))
 }
 
+// An address is the uint160 in the low 20 bytes of the big-endian word, i.e., uint160(value) in Solidity
 pub fn u256_to_address(value: U256) -> eyre::Result<Address> {
-    let bytes: [_; 32] = value.to_le_bytes();
-    if bytes.iter().skip(20).any(|&b| b != 0) {
+    let bytes: [_; 32] = value.to_be_bytes();
+    if bytes[..12].iter().any(|&b| b != 0) {
         eyre::bail!("U256 value is too large to fit into an Address");
     }
-    Ok(Address::from_slice(&bytes[..20]))
+    Ok(Address::from_slice(&bytes[12..]))
+}
+
+pub fn address_to_u256(address: Address) -> U256 {
+    U256::from_be_slice(address.as_slice())
 }
 
 pub fn field_to_address(field: F) -> eyre::Result<Address> {
@@ -57,6 +69,11 @@ pub fn field_to_address(field: F) -> eyre::Result<Address> {
     u256_to_address(u256)
 }
 
+// An address always fits into the field. This is the same encoding as the MapKey of the private_deposit crate.
+pub fn address_to_field(address: Address) -> F {
+    u256_to_field(address_to_u256(address)).expect("An address fits into the field")
+}
+
 impl From<Proof<Curve>> for Groth16Proof {
     fn from(proof: Proof<Curve>) -> Self {
         // Extract the proof
@@ -88,6 +105,50 @@ impl From<Proof<Curve>> for Groth16Proof {
     }
 }
 
+impl TryFrom<Groth16Proof> for Proof<Curve> {
+    type Error = eyre::Error;
+
+    // Points which are not in the respective group are rejected, the same way as by the verifier contract
+    fn try_from(proof: Groth16Proof) -> eyre::Result<Self> {
+        let fq = |value: U256| {
+            let bigint = <ark_bn254::Fq as PrimeField>::BigInt::new(value.into_limbs());
+            ark_bn254::Fq::from_bigint(bigint)
+                .ok_or_else(|| eyre::eyre!("U256 value is out of field range"))
+        };
+        let g1 = |[x, y]: [U256; 2]| -> eyre::Result<ark_bn254::G1Affine> {
+            let (x, y) = (fq(x)?, fq(y)?);
+            // The identity is encoded as (0, 0)
+            if x.is_zero() && y.is_zero() {
+                return Ok(ark_bn254::G1Affine::identity());
+            }
+            let point = ark_bn254::G1Affine::new_unchecked(x, y);
+            if !point.is_on_curve() || !point.is_in_correct_subgroup_assuming_on_curve() {
+                eyre::bail!("Point is not in G1");
+            }
+            Ok(point)
+        };
+        let g2 = |[x, y]: [[U256; 2]; 2]| -> eyre::Result<ark_bn254::G2Affine> {
+            // c1 and then c0, see above
+            let x = ark_bn254::Fq2::new(fq(x[1])?, fq(x[0])?);
+            let y = ark_bn254::Fq2::new(fq(y[1])?, fq(y[0])?);
+            if x.is_zero() && y.is_zero() {
+                return Ok(ark_bn254::G2Affine::identity());
+            }
+            let point = ark_bn254::G2Affine::new_unchecked(x, y);
+            if !point.is_on_curve() || !point.is_in_correct_subgroup_assuming_on_curve() {
+                eyre::bail!("Point is not in G2");
+            }
+            Ok(point)
+        };
+
+        Ok(Self {
+            a: g1(proof.pA)?,
+            b: g2(proof.pB)?,
+            c: g1(proof.pC)?,
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TransactionInputRust {
     pub action_index: Vec<usize>,
@@ -96,6 +157,12 @@ pub struct TransactionInputRust {
         deserialize_with = "mpc_core::serde_compat::ark_de"
     )]
     pub commitment: Vec<F>,
+    // The root of the Merkle tree over all balance commitments after the batch
+    #[serde(
+        serialize_with = "mpc_core::serde_compat::ark_se",
+        deserialize_with = "mpc_core::serde_compat::ark_de"
+    )]
+    pub balance_root: F,
 }
 
 impl TryFrom<TransactionInputRust> for TransactionInput {
@@ -115,6 +182,79 @@ impl TryFrom<TransactionInputRust> for TransactionInput {
         Ok(Self {
             action_index,
             commitments,
+            balance_root: field_to_u256(input.balance_root),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_ff::UniformRand;
+    use proptest::prelude::*;
+
+    const BATCH_SIZE: usize = 50;
+
+    fn field_strategy() -> impl Strategy<Value = F> {
+        prop_oneof![
+            Just(F::from(0u64)),
+            Just(-F::from(1u64)),
+            any::<[u8; 32]>().prop_map(|bytes| F::from_le_bytes_mod_order(&bytes)),
+        ]
+    }
+
+    #[test]
+    fn groth16_proof_conversion() {
+        let mut rng = rand::thread_rng();
+        let proof = Proof::<Curve> {
+            a: ark_bn254::G1Affine::rand(&mut rng),
+            b: ark_bn254::G2Affine::rand(&mut rng),
+            c: ark_bn254::G1Affine::rand(&mut rng),
+        };
+        let converted = Groth16Proof::from(proof.clone());
+        assert_eq!(Proof::<Curve>::try_from(converted.clone()).unwrap(), proof);
+
+        let mut modified = converted;
+        modified.pA[1] += U256::from(1u64);
+        assert!(Proof::<Curve>::try_from(modified).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn address_conversion(bytes in any::<[u8; 20]>(), high in 1u64..) {
+            let address = Address::from(bytes);
+            let value = address_to_u256(address);
+            prop_assert_eq!(value.to_be_bytes::<32>()[12..].to_vec(), bytes.to_vec());
+            prop_assert_eq!(u256_to_address(value).unwrap(), address);
+            prop_assert_eq!(field_to_address(address_to_field(address)).unwrap(), address);
+            prop_assert!(u256_to_address(value + (U256::from(high) << 160)).is_err());
+        }
+
+        #[test]
+        fn transaction_input_conversion(
+            action_index in prop::collection::vec(any::<usize>(), 0..=BATCH_SIZE + 5),
+            commitment in prop::collection::vec(field_strategy(), BATCH_SIZE * 2 - 5..=BATCH_SIZE * 2 + 5),
+            balance_root in field_strategy(),
+        ) {
+            let valid = action_index.len() <= BATCH_SIZE && commitment.len() == BATCH_SIZE * 2;
+            let result = TransactionInput::try_from(TransactionInputRust {
+                action_index: action_index.clone(),
+                commitment: commitment.clone(),
+                balance_root,
+            });
+            prop_assert_eq!(result.is_ok(), valid);
+
+            if let Ok(input) = result {
+                // Missing action indices are padded with the dummy index 0
+                for (i, index) in input.action_index.into_iter().enumerate() {
+                    let expected = action_index.get(i).copied().unwrap_or_default();
+                    prop_assert_eq!(u256_to_usize(index).unwrap(), expected);
+                }
+                for (converted, expected) in input.commitments.into_iter().zip(commitment) {
+                    prop_assert_eq!(u256_to_field(converted).unwrap(), expected);
+                }
+                prop_assert_eq!(u256_to_field(input.balance_root).unwrap(), balance_root);
+            }
+        }
+    }
+}
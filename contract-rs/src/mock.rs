@@ -0,0 +1,645 @@
+use crate::{
+    Curve, F,
+    auditor::commit,
+    backend::ConfTokenBackend,
+    conf_token::{
+        ConfTokenError,
+        ConfidentialToken::{
+            ActionQuery, BabyJubJubElement, Ciphertext, Groth16Proof, TransactionInput,
+        },
+        response_field,
+    },
+    simulation::{
+        self, ACTION_DEPOSIT, ACTION_DUMMY, ACTION_INVALID, ACTION_PRIVATE_WITHDRAW,
+        ACTION_TRANSFER, ACTION_WITHDRAW, ContractRevert, NUM_ACTION_INPUTS, SimulationIssue,
+    },
+    widths::{AMOUNT_BITS, BALANCE_BITS},
+};
+use alloy::primitives::{Address, U256};
+use ark_ff::Zero;
+use ark_groth16::{Groth16, PreparedVerifyingKey, Proof, VerifyingKey};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+// The mock has no token contract, failed token transfers report this address
+const MOCK_TOKEN: Address = Address::ZERO;
+
+fn max_value(num_bits: usize) -> U256 {
+    (U256::from(1u64) << num_bits) - U256::from(1u64)
+}
+
+fn empty_ciphertext() -> Ciphertext {
+    Ciphertext {
+        amount: [U256::ZERO; 3],
+        r: [U256::ZERO; 3],
+        sender_pk: BabyJubJubElement {
+            x: U256::ZERO,
+            y: U256::ZERO,
+        },
+    }
+}
+
+fn check_ciphertext(ciphertext: &Ciphertext) -> Result<(), ContractRevert> {
+    let on_curve = match (
+        crate::u256_to_field(ciphertext.sender_pk.x),
+        crate::u256_to_field(ciphertext.sender_pk.y),
+    ) {
+        (Ok(x), Ok(y)) => ark_babyjubjub::EdwardsAffine::new_unchecked(x, y).is_on_curve(),
+        _ => false,
+    };
+    if !on_curve {
+        return Err(ContractRevert::NotOnCurve);
+    }
+    if ciphertext
+        .amount
+        .iter()
+        .chain(ciphertext.r.iter())
+        .any(|value| crate::u256_to_field(*value).is_err())
+    {
+        return Err(ContractRevert::NotInPrimeField);
+    }
+    Ok(())
+}
+
+struct MockState {
+    mpc: Address,
+    vk: PreparedVerifyingKey<Curve>,
+    amount_bits: usize,
+    balance_bits: usize,
+    // The actions by index, without the dummy action at index 0
+    queue: BTreeMap<usize, ActionQuery>,
+    ciphertexts: HashMap<usize, Ciphertext>,
+    next_index: usize,
+    // The raw balanceCommitments, i.e., users without a balance are missing
+    commitments: HashMap<Address, U256>,
+    balance_root: F,
+    max_transfer: F,
+    max_balance: F,
+    // The token balances of the users and of the contract
+    tokens: HashMap<Address, U256>,
+    reserve: U256,
+}
+
+impl MockState {
+    fn push(&mut self, action: ActionQuery, ciphertext: Option<Ciphertext>) -> usize {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.queue.insert(index, action);
+        if let Some(ciphertext) = ciphertext {
+            self.ciphertexts.insert(index, ciphertext);
+        }
+        index
+    }
+
+    fn action_at(&self, index: usize) -> ActionQuery {
+        if let Some(action) = self.queue.get(&index) {
+            return action.clone();
+        }
+        // Like the queue of the contract, index 0 is the dummy action and missing actions are Invalid
+        ActionQuery {
+            action: if index == 0 {
+                ACTION_DUMMY
+            } else {
+                ACTION_INVALID
+            },
+            sender: Address::ZERO,
+            receiver: Address::ZERO,
+            amount: U256::ZERO,
+        }
+    }
+
+    fn check_amount(&self, amount: U256) -> Result<(), ContractRevert> {
+        if amount.is_zero() || amount > max_value(self.amount_bits) {
+            return Err(ContractRevert::InvalidAmount);
+        }
+        Ok(())
+    }
+
+    fn deposit(&mut self, sender: Address, amount: U256) -> Result<usize, ContractRevert> {
+        self.check_amount(amount)?;
+        let balance = self.tokens.entry(sender).or_default();
+        if *balance < amount {
+            return Err(ContractRevert::TokenTransferFailed(MOCK_TOKEN));
+        }
+        *balance -= amount;
+        self.reserve += amount;
+
+        let action = ActionQuery {
+            action: ACTION_DEPOSIT,
+            sender: Address::ZERO,
+            receiver: sender,
+            amount,
+        };
+        Ok(self.push(action, None))
+    }
+
+    fn withdraw(&mut self, sender: Address, amount: U256) -> Result<usize, ContractRevert> {
+        self.check_amount(amount)?;
+        // Like the contract, we do not check the balance, since it might be topped up by an action in the queue
+        let action = ActionQuery {
+            action: ACTION_WITHDRAW,
+            sender,
+            receiver: Address::ZERO,
+            amount,
+        };
+        Ok(self.push(action, None))
+    }
+
+    fn withdraw_private(
+        &mut self,
+        sender: Address,
+        amount_commitment: F,
+        ciphertext: Ciphertext,
+    ) -> Result<usize, ContractRevert> {
+        check_ciphertext(&ciphertext)?;
+        let action = ActionQuery {
+            action: ACTION_PRIVATE_WITHDRAW,
+            sender,
+            receiver: Address::ZERO,
+            amount: crate::field_to_u256(amount_commitment),
+        };
+        Ok(self.push(action, Some(ciphertext)))
+    }
+
+    fn transfer(
+        &mut self,
+        sender: Address,
+        receiver: Address,
+        amount: F,
+        ciphertext: Ciphertext,
+    ) -> Result<usize, ContractRevert> {
+        if sender == receiver {
+            return Err(ContractRevert::InvalidTransfer);
+        }
+        check_ciphertext(&ciphertext)?;
+        let action = ActionQuery {
+            action: ACTION_TRANSFER,
+            sender,
+            receiver,
+            amount: crate::field_to_u256(amount),
+        };
+        Ok(self.push(action, Some(ciphertext)))
+    }
+
+    fn set_policy_limits(
+        &mut self,
+        sender: Address,
+        max_transfer: F,
+        max_balance: F,
+    ) -> Result<(), ContractRevert> {
+        if sender != self.mpc {
+            return Err(ContractRevert::Unauthorized);
+        }
+        let (transfer, balance) = (
+            crate::field_to_u256(max_transfer),
+            crate::field_to_u256(max_balance),
+        );
+        if transfer > max_value(self.amount_bits)
+            || balance > max_value(self.balance_bits)
+            || transfer > balance
+        {
+            return Err(ContractRevert::InvalidParameters);
+        }
+        self.max_transfer = max_transfer;
+        self.max_balance = max_balance;
+        Ok(())
+    }
+
+    // The state is only changed if the batch is accepted, like a reverted transaction does not change the contract
+    fn process_mpc(
+        &mut self,
+        sender: Address,
+        inputs: &TransactionInput,
+        proof: Groth16Proof,
+    ) -> Result<(), ContractRevert> {
+        if sender != self.mpc {
+            return Err(ContractRevert::Unauthorized);
+        }
+
+        let mut actions = HashMap::new();
+        for index in inputs.action_index.iter() {
+            // An index which does not fit into a usize is not in the queue
+            if let Ok(index) = crate::u256_to_usize(*index) {
+                actions.insert(index, self.action_at(index));
+            }
+        }
+        let mut commitments = self.commitments.clone();
+        let mut issues = Vec::new();
+        let batch = simulation::assemble_batch(inputs, &actions, &mut commitments, &mut issues)
+            .map_err(|_| ContractRevert::InvalidMpcAction)?;
+
+        // The contract reverts at the first action which is rejected
+        let mut reverts = issues
+            .iter()
+            .filter_map(|issue| match issue {
+                SimulationIssue::InvalidAction { position, .. } => {
+                    Some((*position, ContractRevert::InvalidMpcAction))
+                }
+                SimulationIssue::InvalidCommitment { position, .. } => {
+                    Some((*position, ContractRevert::InvalidCommitment))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut reserve = self.reserve;
+        for (position, _, amount) in batch.payouts.iter() {
+            if *amount > max_value(self.amount_bits) {
+                reverts.push((*position, ContractRevert::InvalidAmount));
+                break;
+            }
+            match reserve.checked_sub(*amount) {
+                Some(remaining) => reserve = remaining,
+                None => {
+                    reverts.push((*position, ContractRevert::TokenTransferFailed(MOCK_TOKEN)));
+                    break;
+                }
+            }
+        }
+        if let Some((_, revert)) = reverts.into_iter().min_by_key(|(position, _)| *position) {
+            return Err(revert);
+        }
+
+        // The verifier rejects public inputs which are not field elements
+        let mut public_inputs =
+            Vec::with_capacity(batch.action_inputs.len() * NUM_ACTION_INPUTS + 2);
+        for assembled in batch.action_inputs {
+            public_inputs.extend(assembled.ok_or(ContractRevert::InvalidProof)?);
+        }
+        public_inputs.extend([self.max_transfer, self.max_balance]);
+        let proof = Proof::<Curve>::try_from(proof).map_err(|_| ContractRevert::InvalidProof)?;
+        if !matches!(
+            Groth16::<Curve>::verify_proof(&self.vk, &proof, &public_inputs),
+            Ok(true)
+        ) {
+            return Err(ContractRevert::InvalidProof);
+        }
+        let balance_root = crate::u256_to_field(inputs.balance_root)
+            .map_err(|_| ContractRevert::NotInPrimeField)?;
+
+        self.commitments = commitments;
+        for index in batch.removed {
+            self.queue.remove(&index);
+        }
+        for (_, receiver, amount) in batch.payouts {
+            self.reserve -= amount;
+            *self.tokens.entry(receiver).or_default() += amount;
+        }
+        self.balance_root = balance_root;
+        Ok(())
+    }
+}
+
+// An in-memory implementation of the queue and commitment logic of the ConfidentialToken contract, such that the MPC network can be tested without a chain. The proofs of processMPC are verified with the given verifying key, like the verifier contract does. The token is a plain balance per account, without allowances, and the demo whitelist is not enforced. Clones share the state, as_user returns an instance sending from another account.
+#[derive(Clone)]
+pub struct MockConfidentialToken {
+    sender: Address,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockConfidentialToken {
+    // The instance sends from the account of the MPC network
+    pub fn new(mpc: Address, vk: &VerifyingKey<Curve>) -> Self {
+        Self::with_widths(mpc, vk, AMOUNT_BITS, BALANCE_BITS)
+    }
+
+    // The widths have to be the ones of the circuit of the verifying key, see create_circuits.sh
+    pub fn with_widths(
+        mpc: Address,
+        vk: &VerifyingKey<Curve>,
+        amount_bits: usize,
+        balance_bits: usize,
+    ) -> Self {
+        let max_transfer = crate::u256_to_field(max_value(amount_bits))
+            .expect("The widths are below the field size");
+        let max_balance = crate::u256_to_field(max_value(balance_bits))
+            .expect("The widths are below the field size");
+        let state = MockState {
+            mpc,
+            vk: ark_groth16::prepare_verifying_key(vk),
+            amount_bits,
+            balance_bits,
+            queue: BTreeMap::new(),
+            ciphertexts: HashMap::new(),
+            // Index 0 is the dummy action
+            next_index: 1,
+            commitments: HashMap::new(),
+            balance_root: F::zero(),
+            max_transfer,
+            max_balance,
+            tokens: HashMap::new(),
+            reserve: U256::ZERO,
+        };
+        Self {
+            sender: mpc,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn as_user(&self, user: Address) -> Self {
+        Self {
+            sender: user,
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    pub fn sender(&self) -> Address {
+        self.sender
+    }
+
+    // A panic while holding the lock cannot leave the state half updated, since all checks happen before the first change
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn mint(&self, user: Address, amount: U256) {
+        *self.state().tokens.entry(user).or_default() += amount;
+    }
+
+    pub fn token_balance(&self, user: Address) -> U256 {
+        self.state().tokens.get(&user).copied().unwrap_or_default()
+    }
+
+    // The token balance of the contract, i.e., the tokens of all deposits which were not withdrawn yet
+    pub fn reserve(&self) -> U256 {
+        self.state().reserve
+    }
+
+    pub fn get_ciphertext_at_index(&self, index: usize) -> Ciphertext {
+        self.state()
+            .ciphertexts
+            .get(&index)
+            .cloned()
+            .unwrap_or_else(empty_ciphertext)
+    }
+
+    pub fn set_policy_limits(&self, max_transfer: F, max_balance: F) -> Result<(), ConfTokenError> {
+        self.state()
+            .set_policy_limits(self.sender, max_transfer, max_balance)
+            .map_err(ConfTokenError::Rejected)
+    }
+}
+
+impl ConfTokenBackend for MockConfidentialToken {
+    async fn get_balance_commitment(&self, user: Address) -> Result<F, ConfTokenError> {
+        let commitment = self.state().commitments.get(&user).copied();
+        match commitment {
+            Some(commitment) => response_field(commitment, "balance commitment"),
+            None => Ok(commit(F::zero(), F::zero())),
+        }
+    }
+
+    async fn get_balance_root(&self) -> Result<F, ConfTokenError> {
+        Ok(self.state().balance_root)
+    }
+
+    async fn get_policy_limits(&self) -> Result<(F, F), ConfTokenError> {
+        let state = self.state();
+        Ok((state.max_transfer, state.max_balance))
+    }
+
+    async fn get_action_at_index(&self, index: usize) -> Result<ActionQuery, ConfTokenError> {
+        Ok(self.state().action_at(index))
+    }
+
+    async fn get_action_queue_size(&self) -> Result<usize, ConfTokenError> {
+        Ok(self.state().queue.len() + 1)
+    }
+
+    async fn read_queue(
+        &self,
+        num_items: usize,
+    ) -> Result<(Vec<usize>, Vec<ActionQuery>, Vec<Ciphertext>), ConfTokenError> {
+        let state = self.state();
+        let mut indices = Vec::new();
+        let mut actions = Vec::new();
+        let mut ciphertexts = Vec::new();
+        for (index, action) in state.queue.iter().take(num_items) {
+            indices.push(*index);
+            actions.push(action.clone());
+            ciphertexts.push(
+                state
+                    .ciphertexts
+                    .get(index)
+                    .cloned()
+                    .unwrap_or_else(empty_ciphertext),
+            );
+        }
+        Ok((indices, actions, ciphertexts))
+    }
+
+    async fn deposit(&self, amount: F) -> Result<usize, ConfTokenError> {
+        self.state()
+            .deposit(self.sender, crate::field_to_u256(amount))
+            .map_err(ConfTokenError::Rejected)
+    }
+
+    async fn withdraw(&self, amount: F) -> Result<usize, ConfTokenError> {
+        self.state()
+            .withdraw(self.sender, crate::field_to_u256(amount))
+            .map_err(ConfTokenError::Rejected)
+    }
+
+    async fn withdraw_private(
+        &self,
+        amount_commitment: F,
+        ciphertext: Ciphertext,
+    ) -> Result<usize, ConfTokenError> {
+        self.state()
+            .withdraw_private(self.sender, amount_commitment, ciphertext)
+            .map_err(ConfTokenError::Rejected)
+    }
+
+    async fn transfer(
+        &self,
+        to: Address,
+        amount: F,
+        ciphertext: Ciphertext,
+    ) -> Result<usize, ConfTokenError> {
+        self.state()
+            .transfer(self.sender, to, amount, ciphertext)
+            .map_err(ConfTokenError::Rejected)
+    }
+
+    async fn process_mpc(
+        &self,
+        inputs: TransactionInput,
+        proof: Groth16Proof,
+    ) -> Result<(), ConfTokenError> {
+        self.state()
+            .process_mpc(self.sender, &inputs, proof)
+            .map_err(ConfTokenError::Rejected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_ff::UniformRand;
+    use ark_groth16::ProvingKey;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+    use rand::Rng;
+
+    const BATCH_SIZE: usize = 50;
+
+    // A circuit without constraints, i.e., its proofs only bind the public inputs
+    struct PublicInputsCircuit(Vec<F>);
+
+    impl ConstraintSynthesizer<F> for PublicInputsCircuit {
+        fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+            for input in self.0 {
+                cs.new_input_variable(|| Ok(input))?;
+            }
+            Ok(())
+        }
+    }
+
+    fn prove(pk: &ProvingKey<Curve>, public_inputs: Vec<F>) -> Groth16Proof {
+        let mut rng = rand::thread_rng();
+        Groth16::<Curve>::create_random_proof_with_reduction(
+            PublicInputsCircuit(public_inputs),
+            pk,
+            &mut rng,
+        )
+        .unwrap()
+        .into()
+    }
+
+    // The public inputs of a batch with one action, padded with dummies and followed by the policy limits
+    fn public_inputs(action: [F; NUM_ACTION_INPUTS], limits: (F, F)) -> Vec<F> {
+        let mut inputs = action.to_vec();
+        inputs.resize(BATCH_SIZE * NUM_ACTION_INPUTS, commit(F::zero(), F::zero()));
+        inputs.extend([limits.0, limits.1]);
+        inputs
+    }
+
+    // A batch with one action and its (sender, receiver) commitments, padded with dummies
+    fn batch(index: usize, commitments: (F, F), balance_root: F) -> TransactionInput {
+        let mut action_index = [U256::ZERO; BATCH_SIZE];
+        let mut new_commitments = [U256::ZERO; BATCH_SIZE * 2];
+        action_index[0] = crate::usize_to_u256(index);
+        new_commitments[0] = crate::field_to_u256(commitments.0);
+        new_commitments[1] = crate::field_to_u256(commitments.1);
+        TransactionInput {
+            action_index,
+            commitments: new_commitments,
+            balance_root: crate::field_to_u256(balance_root),
+        }
+    }
+
+    fn revert<T: std::fmt::Debug>(result: Result<T, ConfTokenError>) -> ContractRevert {
+        match result {
+            Err(ConfTokenError::Rejected(revert)) => revert,
+            other => panic!("expected a revert, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn mock_process_mpc_test() {
+        let mut rng = rand::thread_rng();
+        let num_inputs = BATCH_SIZE * NUM_ACTION_INPUTS + 2;
+        let pk = Groth16::<Curve>::generate_random_parameters_with_reduction(
+            PublicInputsCircuit(vec![F::zero(); num_inputs]),
+            &mut rng,
+        )
+        .unwrap();
+
+        let mpc = Address::from(rng.r#gen::<[u8; 20]>());
+        let user = Address::from(rng.r#gen::<[u8; 20]>());
+        let contract = MockConfidentialToken::new(mpc, &pk.vk);
+        let alice = contract.as_user(user);
+        contract.mint(user, U256::from(100u64));
+        let limits = contract.get_policy_limits().await.unwrap();
+        let zero = commit(F::zero(), F::zero());
+
+        // Deposit
+        assert_eq!(
+            revert(alice.deposit(F::zero()).await),
+            ContractRevert::InvalidAmount
+        );
+        assert_eq!(
+            revert(alice.deposit(F::from(101u64)).await),
+            ContractRevert::TokenTransferFailed(MOCK_TOKEN)
+        );
+        let index = alice.deposit(F::from(60u64)).await.unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(contract.token_balance(user), U256::from(40u64));
+        assert_eq!(contract.reserve(), U256::from(60u64));
+        let (indices, actions, _) = contract.read_queue(10).await.unwrap();
+        assert_eq!(indices, [index]);
+        assert_eq!(actions[0].action, ACTION_DEPOSIT);
+
+        let amount = commit(F::from(60u64), F::zero());
+        let balance = commit(F::from(60u64), F::rand(&mut rng));
+        let proof = prove(
+            &pk,
+            public_inputs([amount, zero, zero, balance, amount], limits),
+        );
+        let inputs = batch(index, (F::zero(), balance), F::from(1u64));
+
+        assert_eq!(
+            revert(alice.process_mpc(inputs.clone(), proof.clone()).await),
+            ContractRevert::Unauthorized
+        );
+        let other_proof = prove(
+            &pk,
+            public_inputs(
+                [amount, zero, zero, balance + F::from(1u64), amount],
+                limits,
+            ),
+        );
+        assert_eq!(
+            revert(contract.process_mpc(inputs.clone(), other_proof).await),
+            ContractRevert::InvalidProof
+        );
+        // A deposit has no sender commitment
+        assert_eq!(
+            revert(
+                contract
+                    .process_mpc(batch(index, (zero, balance), F::from(1u64)), proof.clone())
+                    .await
+            ),
+            ContractRevert::InvalidCommitment
+        );
+        // Rejected batches do not change the state
+        assert_eq!(contract.get_action_queue_size().await.unwrap(), 2);
+        assert_eq!(contract.get_balance_commitment(user).await.unwrap(), zero);
+
+        contract
+            .process_mpc(inputs.clone(), proof.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            contract.get_balance_commitment(user).await.unwrap(),
+            balance
+        );
+        assert_eq!(contract.get_balance_root().await.unwrap(), F::from(1u64));
+        assert_eq!(contract.get_action_queue_size().await.unwrap(), 1);
+        // The action was removed from the queue
+        assert_eq!(
+            revert(contract.process_mpc(inputs, proof).await),
+            ContractRevert::InvalidMpcAction
+        );
+
+        // Withdraw
+        let index = alice.withdraw(F::from(10u64)).await.unwrap();
+        assert_eq!(index, 2);
+        let amount = commit(F::from(10u64), F::zero());
+        let new_balance = commit(F::from(50u64), F::rand(&mut rng));
+        let proof = prove(
+            &pk,
+            public_inputs([balance, new_balance, zero, amount, amount], limits),
+        );
+        contract
+            .process_mpc(batch(index, (new_balance, F::zero()), F::from(2u64)), proof)
+            .await
+            .unwrap();
+        assert_eq!(
+            contract.get_balance_commitment(user).await.unwrap(),
+            new_balance
+        );
+        assert_eq!(contract.token_balance(user), U256::from(50u64));
+        assert_eq!(contract.reserve(), U256::from(50u64));
+    }
+}
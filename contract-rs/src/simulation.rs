@@ -0,0 +1,746 @@
+use crate::{
+    Curve, F,
+    auditor::commit,
+    conf_token::{
+        ConfTokenError, ConfidentialToken,
+        ConfidentialToken::{ActionQuery, ConfidentialTokenErrors, Groth16Proof, TransactionInput},
+        ConfidentialTokenContract, decode_action_index,
+    },
+};
+use alloy::primitives::{Address, U256};
+use ark_ff::Zero;
+use ark_groth16::{Groth16, Proof, VerifyingKey};
+use eyre::Context;
+use std::collections::HashMap;
+
+// The indices of the solidity enum Action
+pub const ACTION_INVALID: u8 = 0;
+pub const ACTION_DEPOSIT: u8 = 1;
+pub const ACTION_WITHDRAW: u8 = 2;
+pub const ACTION_TRANSFER: u8 = 3;
+pub const ACTION_DUMMY: u8 = 4;
+pub const ACTION_PRIVATE_WITHDRAW: u8 = 5;
+
+// The number of public inputs the contract assembles for each action of a batch
+pub const NUM_ACTION_INPUTS: usize = 5;
+
+// The custom errors of the contract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContractRevert {
+    Unauthorized,
+    InvalidProof,
+    InvalidMpcAction,
+    NotInPrimeField,
+    InvalidAmount,
+    InvalidTransfer,
+    CannotRemoveDummyAction,
+    InvalidCommitment,
+    NotOnCurve,
+    InvalidParameters,
+    InvalidNonce,
+    InvalidSignature,
+    SmallOrder,
+    TokenTransferFailed(Address), // SafeERC20FailedOperation, i.e., the contract does not hold enough tokens for a payout
+    InvalidEcdsaSignature,
+}
+
+impl From<ConfidentialTokenErrors> for ContractRevert {
+    fn from(error: ConfidentialTokenErrors) -> Self {
+        match error {
+            ConfidentialTokenErrors::Unauthorized(_) => Self::Unauthorized,
+            ConfidentialTokenErrors::InvalidProof(_) => Self::InvalidProof,
+            ConfidentialTokenErrors::InvalidMpcAction(_) => Self::InvalidMpcAction,
+            ConfidentialTokenErrors::NotInPrimeField(_) => Self::NotInPrimeField,
+            ConfidentialTokenErrors::InvalidAmount(_) => Self::InvalidAmount,
+            ConfidentialTokenErrors::InvalidTransfer(_) => Self::InvalidTransfer,
+            ConfidentialTokenErrors::CannotRemoveDummyAction(_) => Self::CannotRemoveDummyAction,
+            ConfidentialTokenErrors::InvalidCommitment(_) => Self::InvalidCommitment,
+            ConfidentialTokenErrors::NotOnCurve(_) => Self::NotOnCurve,
+            ConfidentialTokenErrors::InvalidParameters(_) => Self::InvalidParameters,
+            ConfidentialTokenErrors::InvalidNonce(_) => Self::InvalidNonce,
+            ConfidentialTokenErrors::InvalidSignature(_) => Self::InvalidSignature,
+            ConfidentialTokenErrors::SmallOrder(_) => Self::SmallOrder,
+            ConfidentialTokenErrors::SafeERC20FailedOperation(error) => {
+                Self::TokenTransferFailed(error.token)
+            }
+            ConfidentialTokenErrors::ECDSAInvalidSignature(_)
+            | ConfidentialTokenErrors::ECDSAInvalidSignatureLength(_)
+            | ConfidentialTokenErrors::ECDSAInvalidSignatureS(_) => Self::InvalidEcdsaSignature,
+        }
+    }
+}
+
+// A reason why processMPC would fail for a batch. The position is the one of the action in the batch, the action index the one in the queue of the contract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimulationIssue {
+    // The action is not in the queue (anymore)
+    InvalidAction {
+        position: usize,
+        action_index: usize,
+    },
+    // The posted commitments are rejected by the contract, e.g., a non-zero sender commitment for a deposit
+    InvalidCommitment {
+        position: usize,
+        action_index: usize,
+    },
+    // A posted commitment or a commitment stored on chain is not a field element
+    NotInPrimeField {
+        position: usize,
+        action_index: usize,
+    },
+    // The public inputs the contract assembles for the action differ from the ones of the proof
+    InputMismatch {
+        position: usize,
+        action_index: usize,
+        contract: Vec<F>,
+        proof: Vec<F>,
+    },
+    // The policy limits of the contract differ from the ones of the proof
+    PolicyMismatch {
+        contract: [F; 2],
+        proof: Vec<F>,
+    },
+    // The proof does not verify with the public inputs of the contract
+    InvalidProof,
+    // The eth_call of processMPC reverted
+    Reverted(ContractRevert),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessMpcSimulation {
+    // The public inputs the contract assembles from the queue, the posted commitments, and its state
+    pub public_inputs: Vec<F>,
+    pub issues: Vec<SimulationIssue>,
+}
+
+impl ProcessMpcSimulation {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    // The action indices of the actions which caused an issue, e.g., to remove them from the next batch
+    pub fn action_indices(&self) -> Vec<usize> {
+        self.issues
+            .iter()
+            .filter_map(|issue| match issue {
+                SimulationIssue::InvalidAction { action_index, .. }
+                | SimulationIssue::InvalidCommitment { action_index, .. }
+                | SimulationIssue::NotInPrimeField { action_index, .. }
+                | SimulationIssue::InputMismatch { action_index, .. } => Some(*action_index),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// The result of processMPC for the actions of a batch, apart from the proof verification
+pub(crate) struct AssembledBatch {
+    // The public inputs of each action, None if one of them is not a field element
+    pub(crate) action_inputs: Vec<Option<Vec<F>>>,
+    // The token transfers of withdraws as (position, receiver, amount), in the order of the batch
+    pub(crate) payouts: Vec<(usize, Address, U256)>,
+    // The actions which are removed from the queue
+    pub(crate) removed: Vec<usize>,
+}
+
+// The users whose balance commitment processMPC reads for an action
+pub(crate) fn touched_users(action: &ActionQuery) -> Vec<Address> {
+    match action.action {
+        ACTION_DEPOSIT => vec![action.receiver],
+        ACTION_WITHDRAW | ACTION_PRIVATE_WITHDRAW => vec![action.sender],
+        ACTION_TRANSFER => vec![action.sender, action.receiver],
+        _ => Vec::new(),
+    }
+}
+
+// Assembles the public inputs of processMPC for the actions of a batch the same way the contract does. `actions` holds the actions at the indices of the batch, missing ones are Invalid. `commitments` holds the raw balanceCommitments of the users the batch touches, missing users have none. The new commitments of the batch are written to it. An action can only be processed once per batch, since the contract removes it from the queue.
+pub(crate) fn assemble_batch(
+    inputs: &TransactionInput,
+    actions: &HashMap<usize, ActionQuery>,
+    commitments: &mut HashMap<Address, U256>,
+    issues: &mut Vec<SimulationIssue>,
+) -> Result<AssembledBatch, ConfTokenError> {
+    let zero_commitment = crate::field_to_u256(commit(F::zero(), F::zero()));
+    let or_zero_commitment = |commitment: U256| {
+        if commitment.is_zero() {
+            zero_commitment
+        } else {
+            commitment
+        }
+    };
+    let stored = |commitments: &HashMap<Address, U256>, user: Address| {
+        commitments.get(&user).copied().unwrap_or_default()
+    };
+
+    let invalid = ActionQuery {
+        action: ACTION_INVALID,
+        sender: Address::ZERO,
+        receiver: Address::ZERO,
+        amount: U256::ZERO,
+    };
+
+    let mut batch = AssembledBatch {
+        action_inputs: Vec::with_capacity(inputs.action_index.len()),
+        payouts: Vec::new(),
+        removed: Vec::new(),
+    };
+    for (position, (action_index, new_commitments)) in inputs
+        .action_index
+        .iter()
+        .zip(inputs.commitments.chunks_exact(2))
+        .enumerate()
+    {
+        let action_index = decode_action_index(position, *action_index)?;
+        // Like the queue of the contract, a missing or already removed action is Invalid
+        let action = match actions.get(&action_index) {
+            Some(action) if !batch.removed.contains(&action_index) => action,
+            _ => &invalid,
+        };
+        let (sender_new, receiver_new) = (new_commitments[0], new_commitments[1]);
+        let amount = || {
+            crate::u256_to_field(action.amount).map_err(|_| ConfTokenError::InvalidActionValue {
+                action_index,
+                value: "amount",
+            })
+        };
+
+        let assembled = match action.action {
+            ACTION_DEPOSIT => {
+                if !sender_new.is_zero() {
+                    issues.push(SimulationIssue::InvalidCommitment {
+                        position,
+                        action_index,
+                    });
+                }
+                let amount_commitment = commit(amount()?, F::zero());
+                let amount_commitment = crate::field_to_u256(amount_commitment);
+                let receiver_old = stored(commitments, action.receiver);
+                commitments.insert(action.receiver, receiver_new);
+                batch.removed.push(action_index);
+                [
+                    amount_commitment,
+                    zero_commitment,
+                    or_zero_commitment(receiver_old),
+                    receiver_new,
+                    amount_commitment,
+                ]
+            }
+            ACTION_WITHDRAW => {
+                if !receiver_new.is_zero() {
+                    issues.push(SimulationIssue::InvalidCommitment {
+                        position,
+                        action_index,
+                    });
+                }
+                let amount_commitment = commit(amount()?, F::zero());
+                let amount_commitment = crate::field_to_u256(amount_commitment);
+                let sender_old = stored(commitments, action.sender);
+                commitments.insert(action.sender, sender_new);
+                batch.payouts.push((position, action.sender, action.amount));
+                batch.removed.push(action_index);
+                [
+                    sender_old,
+                    sender_new,
+                    zero_commitment,
+                    amount_commitment,
+                    amount_commitment,
+                ]
+            }
+            ACTION_TRANSFER => {
+                let sender_old = stored(commitments, action.sender);
+                let receiver_old = stored(commitments, action.receiver);
+                commitments.insert(action.sender, sender_new);
+                commitments.insert(action.receiver, receiver_new);
+                batch.removed.push(action_index);
+                [
+                    sender_old,
+                    sender_new,
+                    or_zero_commitment(receiver_old),
+                    receiver_new,
+                    action.amount,
+                ]
+            }
+            ACTION_PRIVATE_WITHDRAW => {
+                // The MPC network posts the opened amount instead of the receiver commitment
+                let payout = match crate::u256_to_field(receiver_new) {
+                    Ok(payout) => crate::field_to_u256(commit(payout, F::zero())),
+                    Err(_) => receiver_new,
+                };
+                let sender_old = stored(commitments, action.sender);
+                commitments.insert(action.sender, sender_new);
+                batch.payouts.push((position, action.sender, receiver_new));
+                batch.removed.push(action_index);
+                [
+                    sender_old,
+                    sender_new,
+                    zero_commitment,
+                    payout,
+                    action.amount,
+                ]
+            }
+            ACTION_DUMMY => {
+                if !sender_new.is_zero() || !receiver_new.is_zero() {
+                    issues.push(SimulationIssue::InvalidCommitment {
+                        position,
+                        action_index,
+                    });
+                }
+                [zero_commitment; NUM_ACTION_INPUTS]
+            }
+            _ => {
+                issues.push(SimulationIssue::InvalidAction {
+                    position,
+                    action_index,
+                });
+                [zero_commitment; NUM_ACTION_INPUTS]
+            }
+        };
+
+        match assembled
+            .into_iter()
+            .map(crate::u256_to_field)
+            .collect::<eyre::Result<Vec<_>>>()
+        {
+            Ok(assembled) => batch.action_inputs.push(Some(assembled)),
+            Err(_) => {
+                issues.push(SimulationIssue::NotInPrimeField {
+                    position,
+                    action_index,
+                });
+                batch.action_inputs.push(None);
+            }
+        }
+    }
+    Ok(batch)
+}
+
+// Compares the public inputs the contract assembles for a batch with the ones of the proof. Actions whose inputs are not field elements are already reported by assemble_batch and contribute zeros. Returns the public inputs of the contract, i.e., the ones of the actions followed by the policy limits.
+pub(crate) fn compare_public_inputs(
+    inputs: &TransactionInput,
+    action_inputs: Vec<Option<Vec<F>>>,
+    limits: [F; 2],
+    proof_public_inputs: &[F],
+    issues: &mut Vec<SimulationIssue>,
+) -> Result<Vec<F>, ConfTokenError> {
+    let mut public_inputs = Vec::with_capacity(action_inputs.len() * NUM_ACTION_INPUTS + 2);
+    for (position, (action_index, assembled)) in
+        inputs.action_index.iter().zip(action_inputs).enumerate()
+    {
+        let Some(assembled) = assembled else {
+            public_inputs.extend([F::zero(); NUM_ACTION_INPUTS]);
+            continue;
+        };
+        let start = position * NUM_ACTION_INPUTS;
+        let proof_inputs = proof_public_inputs
+            .get(start..start + NUM_ACTION_INPUTS)
+            .unwrap_or_default();
+        if assembled != proof_inputs {
+            issues.push(SimulationIssue::InputMismatch {
+                position,
+                action_index: decode_action_index(position, *action_index)?,
+                contract: assembled.clone(),
+                proof: proof_inputs.to_vec(),
+            });
+        }
+        public_inputs.extend(assembled);
+    }
+
+    let proof_limits = proof_public_inputs
+        .get(public_inputs.len()..)
+        .unwrap_or_default();
+    if proof_limits != limits {
+        issues.push(SimulationIssue::PolicyMismatch {
+            contract: limits,
+            proof: proof_limits.to_vec(),
+        });
+    }
+    public_inputs.extend(limits);
+    Ok(public_inputs)
+}
+
+impl ConfidentialTokenContract {
+    // The raw value of balanceCommitments, which is zero for users without a balance
+    async fn stored_commitment(&self, user: Address) -> Result<U256, ConfTokenError> {
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        let commitment = contract
+            .balanceCommitments(user)
+            .call()
+            .await
+            .context("while calling balance_commitments")
+            .map_err(ConfTokenError::Rpc)?;
+        Ok(commitment)
+    }
+
+    // Checks a batch before broadcasting processMPC: The public inputs are rebuilt the same way the contract assembles them from the queue, compared to the public inputs of the proof, and used to verify the proof locally. Additionally, processMPC is executed with eth_call, whose revert is decoded. Since the queue can change until the transaction is included, this does not guarantee that processMPC succeeds.
+    pub async fn simulate_process_mpc(
+        &self,
+        inputs: &TransactionInput,
+        proof: &Proof<Curve>,
+        proof_public_inputs: &[F],
+        vk: &VerifyingKey<Curve>,
+    ) -> Result<ProcessMpcSimulation, ConfTokenError> {
+        let mut actions = HashMap::new();
+        let mut commitments = HashMap::new();
+        for (position, action_index) in inputs.action_index.iter().enumerate() {
+            let action_index = decode_action_index(position, *action_index)?;
+            if actions.contains_key(&action_index) {
+                continue;
+            }
+            let action = self.get_action_at_index(action_index).await?;
+            for user in touched_users(&action) {
+                if !commitments.contains_key(&user) {
+                    commitments.insert(user, self.stored_commitment(user).await?);
+                }
+            }
+            actions.insert(action_index, action);
+        }
+
+        let mut issues = Vec::new();
+        let batch = assemble_batch(inputs, &actions, &mut commitments, &mut issues)?;
+
+        let (max_transfer, max_balance) = self.get_policy_limits().await?;
+        let public_inputs = compare_public_inputs(
+            inputs,
+            batch.action_inputs,
+            [max_transfer, max_balance],
+            proof_public_inputs,
+            &mut issues,
+        )?;
+
+        let pvk = ark_groth16::prepare_verifying_key(vk);
+        if !matches!(
+            Groth16::<Curve>::verify_proof(&pvk, proof, &public_inputs),
+            Ok(true)
+        ) {
+            issues.push(SimulationIssue::InvalidProof);
+        }
+
+        let contract = ConfidentialToken::new(self.contract_address, self.provider.clone());
+        let result = contract
+            .processMPC(inputs.to_owned(), Groth16Proof::from(proof.to_owned()))
+            .from(self.tx_manager.default_from())
+            .call()
+            .await;
+        if let Err(error) = result {
+            match error.as_decoded_interface_error::<ConfidentialTokenErrors>() {
+                Some(revert) => issues.push(SimulationIssue::Reverted(revert.into())),
+                None => {
+                    return Err(ConfTokenError::Rpc(
+                        eyre::Report::new(error).wrap_err("while simulating processMPC"),
+                    ));
+                }
+            }
+        }
+
+        Ok(ProcessMpcSimulation {
+            public_inputs,
+            issues,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BATCH_SIZE: usize = 50;
+
+    fn action(action: u8, sender: Address, receiver: Address, amount: U256) -> ActionQuery {
+        ActionQuery {
+            action,
+            sender,
+            receiver,
+            amount,
+        }
+    }
+
+    fn dummy() -> ActionQuery {
+        action(ACTION_DUMMY, Address::ZERO, Address::ZERO, U256::ZERO)
+    }
+
+    // A batch of the given (action index, sender commitment, receiver commitment), padded with the dummy action at index 0
+    fn batch(actions: &[(usize, U256, U256)]) -> TransactionInput {
+        let mut action_index = [U256::ZERO; BATCH_SIZE];
+        let mut commitments = [U256::ZERO; BATCH_SIZE * 2];
+        for (position, (index, sender_new, receiver_new)) in actions.iter().enumerate() {
+            action_index[position] = crate::usize_to_u256(*index);
+            commitments[position * 2] = *sender_new;
+            commitments[position * 2 + 1] = *receiver_new;
+        }
+        TransactionInput {
+            action_index,
+            commitments,
+            balance_root: U256::ZERO,
+        }
+    }
+
+    fn commitment(value: u64) -> U256 {
+        crate::field_to_u256(F::from(value))
+    }
+
+    #[test]
+    fn zero_commitment_test() {
+        // ZERO_COMMITMENT of the contract
+        let zero_commitment: U256 =
+            "0x087f763a403ee4109adc79d4a7638af3cb8cb6a33f5b027bd1476ffa97361acb"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            crate::field_to_u256(commit(F::zero(), F::zero())),
+            zero_commitment
+        );
+    }
+
+    #[test]
+    fn assemble_batch_test() {
+        let alice = Address::repeat_byte(1);
+        let bob = Address::repeat_byte(2);
+        let amount_commitment = commitment(42);
+        let actions = HashMap::from([
+            (0, dummy()),
+            (
+                3,
+                action(ACTION_DEPOSIT, Address::ZERO, alice, U256::from(100)),
+            ),
+            (4, action(ACTION_TRANSFER, alice, bob, amount_commitment)),
+            (
+                5,
+                action(ACTION_WITHDRAW, bob, Address::ZERO, U256::from(10)),
+            ),
+        ]);
+        // Index 3 is processed twice and index 7 is not in the queue
+        let inputs = batch(&[
+            (3, U256::ZERO, commitment(11)),
+            (4, commitment(12), commitment(13)),
+            (5, commitment(14), U256::ZERO),
+            (3, U256::ZERO, commitment(15)),
+            (7, U256::ZERO, U256::ZERO),
+        ]);
+        let mut commitments = HashMap::new();
+        let mut issues = Vec::new();
+        let batch = assemble_batch(&inputs, &actions, &mut commitments, &mut issues).unwrap();
+
+        let zero = commit(F::zero(), F::zero());
+        let deposit = commit(F::from(100u64), F::zero());
+        let withdraw = commit(F::from(10u64), F::zero());
+        let expected = [
+            // The receiver has no commitment yet
+            [deposit, zero, zero, F::from(11u64), deposit],
+            // The commitment of the deposit before is used
+            [
+                F::from(11u64),
+                F::from(12u64),
+                zero,
+                F::from(13u64),
+                F::from(42u64),
+            ],
+            [F::from(13u64), F::from(14u64), zero, withdraw, withdraw],
+            [zero; NUM_ACTION_INPUTS],
+            [zero; NUM_ACTION_INPUTS],
+        ];
+        let padding = [zero; NUM_ACTION_INPUTS];
+        assert_eq!(batch.action_inputs.len(), BATCH_SIZE);
+        for (position, assembled) in batch.action_inputs.iter().enumerate() {
+            let expected = expected.get(position).unwrap_or(&padding);
+            assert_eq!(
+                assembled.as_deref(),
+                Some(&expected[..]),
+                "position {position}"
+            );
+        }
+        assert_eq!(
+            issues,
+            [
+                SimulationIssue::InvalidAction {
+                    position: 3,
+                    action_index: 3,
+                },
+                SimulationIssue::InvalidAction {
+                    position: 4,
+                    action_index: 7,
+                },
+            ]
+        );
+        assert_eq!(batch.payouts, [(2, bob, U256::from(10))]);
+        assert_eq!(batch.removed, [3, 4, 5]);
+        assert_eq!(
+            commitments,
+            HashMap::from([(alice, commitment(12)), (bob, commitment(14))])
+        );
+    }
+
+    #[test]
+    fn assemble_batch_issues_test() {
+        let alice = Address::repeat_byte(1);
+        let bob = Address::repeat_byte(2);
+        let actions = HashMap::from([
+            (0, dummy()),
+            (
+                1,
+                action(ACTION_DEPOSIT, Address::ZERO, alice, U256::from(100)),
+            ),
+            (2, action(ACTION_TRANSFER, alice, bob, commitment(42))),
+        ]);
+        let inputs = batch(&[
+            // A deposit has no sender commitment
+            (1, commitment(1), commitment(11)),
+            // A dummy has no commitments
+            (0, U256::ZERO, commitment(2)),
+            // Not a field element
+            (2, U256::MAX, commitment(13)),
+        ]);
+        let mut commitments = HashMap::new();
+        let mut issues = Vec::new();
+        let batch = assemble_batch(&inputs, &actions, &mut commitments, &mut issues).unwrap();
+
+        let expected = [
+            SimulationIssue::InvalidCommitment {
+                position: 0,
+                action_index: 1,
+            },
+            SimulationIssue::InvalidCommitment {
+                position: 1,
+                action_index: 0,
+            },
+            SimulationIssue::NotInPrimeField {
+                position: 2,
+                action_index: 2,
+            },
+        ];
+        assert_eq!(issues, expected);
+        assert!(batch.action_inputs[0].is_some());
+        assert!(batch.action_inputs[2].is_none());
+
+        let simulation = ProcessMpcSimulation {
+            public_inputs: Vec::new(),
+            issues,
+        };
+        assert!(!simulation.is_ok());
+        assert_eq!(simulation.action_indices(), [1, 0, 2]);
+    }
+
+    // The failures of a single action carry its position or index
+    #[test]
+    fn assemble_batch_errors_test() {
+        let alice = Address::repeat_byte(1);
+        let actions = HashMap::from([(0, dummy())]);
+        let mut inputs = batch(&[(0, U256::ZERO, U256::ZERO), (1, U256::ZERO, commitment(1))]);
+        inputs.action_index[0] = U256::MAX;
+        let result = assemble_batch(&inputs, &actions, &mut HashMap::new(), &mut Vec::new());
+        assert!(matches!(
+            result,
+            Err(ConfTokenError::InvalidActionIndex { position: 0, index }) if index == U256::MAX
+        ));
+        let actions = HashMap::from([(1, action(ACTION_DEPOSIT, Address::ZERO, alice, U256::MAX))]);
+        let inputs = batch(&[(1, U256::ZERO, commitment(1))]);
+        let result = assemble_batch(&inputs, &actions, &mut HashMap::new(), &mut Vec::new());
+        assert!(matches!(
+            result,
+            Err(ConfTokenError::InvalidActionValue {
+                action_index: 1,
+                value: "amount"
+            })
+        ));
+    }
+
+    #[test]
+    fn compare_public_inputs_test() {
+        let alice = Address::repeat_byte(1);
+        let actions = HashMap::from([
+            (0, dummy()),
+            (
+                1,
+                action(ACTION_DEPOSIT, Address::ZERO, alice, U256::from(100)),
+            ),
+            (2, action(ACTION_TRANSFER, alice, Address::ZERO, U256::MAX)),
+        ]);
+        let inputs = batch(&[
+            (0, U256::ZERO, U256::ZERO),
+            (1, U256::ZERO, commitment(11)),
+            (2, commitment(12), commitment(13)),
+        ]);
+        let limits = [F::from(1000u64), F::from(2000u64)];
+        let mut issues = Vec::new();
+        let batch = assemble_batch(&inputs, &actions, &mut HashMap::new(), &mut issues).unwrap();
+        // The amount commitment of the transfer is not a field element
+        assert_eq!(
+            issues,
+            [SimulationIssue::NotInPrimeField {
+                position: 2,
+                action_index: 2,
+            }]
+        );
+        let contract_inputs = batch
+            .action_inputs
+            .iter()
+            .flat_map(|assembled| {
+                assembled
+                    .clone()
+                    .unwrap_or_else(|| vec![F::zero(); NUM_ACTION_INPUTS])
+            })
+            .chain(limits)
+            .collect::<Vec<_>>();
+
+        // Matching inputs add no issue
+        let mut matching = Vec::new();
+        let public_inputs = compare_public_inputs(
+            &inputs,
+            batch.action_inputs.clone(),
+            limits,
+            &contract_inputs,
+            &mut matching,
+        )
+        .unwrap();
+        assert_eq!(public_inputs, contract_inputs);
+        assert!(matching.is_empty());
+
+        // The proof has a different receiver commitment for the deposit and other limits
+        let mut proof_inputs = contract_inputs.clone();
+        proof_inputs[NUM_ACTION_INPUTS + 3] = F::from(99u64);
+        let num_inputs = proof_inputs.len();
+        proof_inputs[num_inputs - 1] = F::from(3000u64);
+        let mut mismatches = Vec::new();
+        let public_inputs = compare_public_inputs(
+            &inputs,
+            batch.action_inputs.clone(),
+            limits,
+            &proof_inputs,
+            &mut mismatches,
+        )
+        .unwrap();
+        assert_eq!(public_inputs, contract_inputs);
+        assert_eq!(
+            mismatches,
+            [
+                SimulationIssue::InputMismatch {
+                    position: 1,
+                    action_index: 1,
+                    contract: contract_inputs[NUM_ACTION_INPUTS..2 * NUM_ACTION_INPUTS].to_vec(),
+                    proof: proof_inputs[NUM_ACTION_INPUTS..2 * NUM_ACTION_INPUTS].to_vec(),
+                },
+                SimulationIssue::PolicyMismatch {
+                    contract: limits,
+                    proof: vec![F::from(1000u64), F::from(3000u64)],
+                },
+            ]
+        );
+
+        // A proof without the limits
+        let mut mismatches = Vec::new();
+        compare_public_inputs(
+            &inputs,
+            batch.action_inputs,
+            limits,
+            &contract_inputs[..num_inputs - 2],
+            &mut mismatches,
+        )
+        .unwrap();
+        assert_eq!(
+            mismatches,
+            [SimulationIssue::PolicyMismatch {
+                contract: limits,
+                proof: Vec::new(),
+            }]
+        );
+    }
+}
@@ -137,4 +137,13 @@ impl USDCTokenContract {
 
         Ok(receipt)
     }
+
+    pub async fn balance_of(&self, account: Address) -> eyre::Result<U256> {
+        let contract = USDCToken::new(self.contract_address, self.provider.clone());
+        contract
+            .balanceOf(account)
+            .call()
+            .await
+            .context("while calling balance_of")
+    }
 }
@@ -0,0 +1,587 @@
+use crate::conf_token::ConfTokenError;
+use alloy::{
+    eips::eip1559::Eip1559Estimation,
+    primitives::{Address, B256, TxHash},
+    providers::{DynProvider, Provider as _},
+    rpc::types::{TransactionReceipt, TransactionRequest},
+};
+use eyre::Context;
+use std::{collections::HashMap, time::Duration};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone)]
+pub struct TxConfig {
+    // The gas limit is the estimate plus this margin, since the state can change between estimation and inclusion
+    pub gas_margin_percent: u64,
+    // The fees are bumped by this much for each resubmission. Nodes only accept a replacement with the same nonce if the fees are at least 10% higher.
+    pub fee_bump_percent: u128,
+    // The number of resubmissions after the first attempt
+    pub max_retries: usize,
+    // How long to wait for the receipt of an attempt before resubmitting with higher fees
+    pub receipt_timeout: Duration,
+    // How long to wait before resubmitting after the node rejected an attempt
+    pub retry_delay: Duration,
+}
+
+impl Default for TxConfig {
+    fn default() -> Self {
+        Self {
+            gas_margin_percent: 20,
+            fee_bump_percent: 15,
+            max_retries: 3,
+            receipt_timeout: Duration::from_secs(60),
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+// The failure of broadcasting a transaction and waiting for its receipt
+#[derive(Debug)]
+pub enum BroadcastError {
+    // The node did not accept the transaction
+    Broadcast(eyre::Report),
+    // The transaction with the hash was accepted, but no receipt arrived in time
+    Receipt(TxHash, eyre::Report),
+}
+
+// The calls to the node the TxManager needs, implemented by the provider and by a mocked node in the tests
+pub trait TxProvider: Send + Sync {
+    // The nonce of the sender including its pending transactions
+    fn fetch_nonce(&self, from: Address) -> impl Future<Output = eyre::Result<u64>> + Send;
+
+    fn fetch_fees(&self) -> impl Future<Output = eyre::Result<Eip1559Estimation>> + Send;
+
+    fn fetch_gas_estimate(
+        &self,
+        tx: TransactionRequest,
+    ) -> impl Future<Output = eyre::Result<u64>> + Send;
+
+    fn fetch_receipt(
+        &self,
+        hash: TxHash,
+    ) -> impl Future<Output = eyre::Result<Option<TransactionReceipt>>> + Send;
+
+    fn broadcast(
+        &self,
+        tx: TransactionRequest,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<TransactionReceipt, BroadcastError>> + Send;
+}
+
+impl TxProvider for DynProvider {
+    async fn fetch_nonce(&self, from: Address) -> eyre::Result<u64> {
+        self.get_transaction_count(from)
+            .pending()
+            .await
+            .context("while fetching nonce")
+    }
+
+    async fn fetch_fees(&self) -> eyre::Result<Eip1559Estimation> {
+        self.estimate_eip1559_fees()
+            .await
+            .context("while estimating fees")
+    }
+
+    async fn fetch_gas_estimate(&self, tx: TransactionRequest) -> eyre::Result<u64> {
+        self.estimate_gas(tx).await.context("while estimating gas")
+    }
+
+    async fn fetch_receipt(&self, hash: TxHash) -> eyre::Result<Option<TransactionReceipt>> {
+        self.get_transaction_receipt(hash)
+            .await
+            .context("while fetching receipt")
+    }
+
+    async fn broadcast(
+        &self,
+        tx: TransactionRequest,
+        timeout: Duration,
+    ) -> Result<TransactionReceipt, BroadcastError> {
+        let pending = self.send_transaction(tx).await.map_err(|error| {
+            BroadcastError::Broadcast(
+                eyre::Report::new(error).wrap_err("while broadcasting to network"),
+            )
+        })?;
+        let hash = *pending.tx_hash();
+        pending
+            .with_timeout(Some(timeout))
+            .get_receipt()
+            .await
+            .map_err(|error| {
+                BroadcastError::Receipt(
+                    hash,
+                    eyre::Report::new(error).wrap_err("while receiving receipt"),
+                )
+            })
+    }
+}
+
+// The attempts of sending one transaction. All of them use the same nonce, thus at most one of them is mined.
+#[derive(Debug, Clone)]
+struct Submission {
+    nonce: u64,
+    max_fee_per_gas: u128,
+    max_priority_fee_per_gas: u128,
+    hashes: Vec<TxHash>,
+}
+
+// Sends transactions with estimated gas limits and EIP-1559 fees. Nonces are assigned locally, such that concurrent submissions of the same sender do not collide. If no receipt arrives in time, the transaction is resubmitted with the same nonce and bumped fees, which replaces the pending one.
+pub struct TxManager<P = DynProvider> {
+    provider: P,
+    default_from: Address,
+    config: TxConfig,
+    nonces: Mutex<HashMap<Address, u64>>,
+    submissions: Mutex<HashMap<B256, Submission>>,
+}
+
+impl<P: TxProvider> TxManager<P> {
+    pub fn new(provider: P, default_from: Address, config: TxConfig) -> Self {
+        Self {
+            provider,
+            default_from,
+            config,
+            nonces: Mutex::new(HashMap::new()),
+            submissions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn default_from(&self) -> Address {
+        self.default_from
+    }
+
+    pub fn config(&self) -> &TxConfig {
+        &self.config
+    }
+
+    pub async fn send(&self, tx: TransactionRequest) -> Result<TransactionReceipt, ConfTokenError> {
+        self.send_inner(None, tx).await
+    }
+
+    // Same as send, but sending the same operation (e.g., processMPC for the same batch) again does not submit a second transaction: If an earlier attempt was mined, its receipt is returned, otherwise the earlier nonce is reused. This only holds for this instance, the contract has to reject a repeated operation after a restart.
+    pub async fn send_idempotent(
+        &self,
+        key: B256,
+        tx: TransactionRequest,
+    ) -> Result<TransactionReceipt, ConfTokenError> {
+        self.send_inner(Some(key), tx).await
+    }
+
+    // Forgets the local nonce of the sender, e.g., after a transaction of the same sender was sent elsewhere. The next transaction reads the nonce from the node again.
+    pub async fn reset_nonce(&self, from: Address) {
+        self.nonces.lock().await.remove(&from);
+    }
+
+    async fn next_nonce(&self, from: Address) -> Result<u64, ConfTokenError> {
+        let mut nonces = self.nonces.lock().await;
+        let nonce = match nonces.get(&from) {
+            Some(nonce) => *nonce,
+            None => self
+                .provider
+                .fetch_nonce(from)
+                .await
+                .map_err(ConfTokenError::Rpc)?,
+        };
+        nonces.insert(from, nonce + 1);
+        Ok(nonce)
+    }
+
+    async fn new_submission(&self, from: Address) -> Result<Submission, ConfTokenError> {
+        let fees = self
+            .provider
+            .fetch_fees()
+            .await
+            .map_err(ConfTokenError::Rpc)?;
+        Ok(Submission {
+            nonce: self.next_nonce(from).await?,
+            max_fee_per_gas: fees.max_fee_per_gas,
+            max_priority_fee_per_gas: fees.max_priority_fee_per_gas,
+            hashes: Vec::new(),
+        })
+    }
+
+    // The receipt of an attempt which was mined, if any
+    async fn find_receipt(
+        &self,
+        hashes: &[TxHash],
+    ) -> Result<Option<TransactionReceipt>, ConfTokenError> {
+        for hash in hashes {
+            let receipt = self
+                .provider
+                .fetch_receipt(*hash)
+                .await
+                .map_err(ConfTokenError::Rpc)?;
+            if receipt.is_some() {
+                return Ok(receipt);
+            }
+        }
+        Ok(None)
+    }
+
+    async fn send_inner(
+        &self,
+        key: Option<B256>,
+        mut tx: TransactionRequest,
+    ) -> Result<TransactionReceipt, ConfTokenError> {
+        let from = *tx.from.get_or_insert(self.default_from);
+
+        let known = match key {
+            Some(key) => self.submissions.lock().await.remove(&key),
+            None => None,
+        };
+        let mut submission = match known {
+            Some(submission) => {
+                if let Some(receipt) = self.find_receipt(&submission.hashes).await? {
+                    tracing::info!(
+                        "transaction {key:?} was already mined with hash {}",
+                        receipt.transaction_hash
+                    );
+                    return Ok(receipt);
+                }
+                submission
+            }
+            None => self.new_submission(from).await?,
+        };
+
+        let result = self.submit(&mut submission, tx).await;
+        if result.is_err() {
+            match key {
+                // Keep the nonce and the attempts for the next call with the same key, if an attempt was broadcast and might still be mined
+                Some(key) if !submission.hashes.is_empty() => {
+                    self.submissions.lock().await.insert(key, submission);
+                }
+                // The nonce might not be used (e.g., estimating the gas reverted before anything was broadcast), thus the following transactions would be stuck behind it
+                _ => self.reset_nonce(from).await,
+            }
+        }
+        result
+    }
+
+    async fn submit(
+        &self,
+        submission: &mut Submission,
+        mut tx: TransactionRequest,
+    ) -> Result<TransactionReceipt, ConfTokenError> {
+        let gas = self
+            .provider
+            .fetch_gas_estimate(tx.clone())
+            .await
+            .map_err(ConfTokenError::Rpc)?;
+        tx.gas = Some(with_margin(gas, self.config.gas_margin_percent));
+        tx.nonce = Some(submission.nonce);
+
+        let mut last_error = None;
+        for attempt in 0..=self.config.max_retries {
+            // A replacement of an earlier attempt needs higher fees
+            if attempt > 0 || !submission.hashes.is_empty() {
+                submission.max_fee_per_gas =
+                    bump_fee(submission.max_fee_per_gas, self.config.fee_bump_percent);
+                submission.max_priority_fee_per_gas = bump_fee(
+                    submission.max_priority_fee_per_gas,
+                    self.config.fee_bump_percent,
+                );
+            }
+            tx.max_fee_per_gas = Some(submission.max_fee_per_gas);
+            tx.max_priority_fee_per_gas = Some(submission.max_priority_fee_per_gas);
+
+            let error = match self
+                .provider
+                .broadcast(tx.clone(), self.config.receipt_timeout)
+                .await
+            {
+                Ok(receipt) => return Ok(receipt),
+                Err(BroadcastError::Receipt(hash, error)) => {
+                    submission.hashes.push(hash);
+                    error
+                }
+                Err(BroadcastError::Broadcast(error)) => {
+                    tokio::time::sleep(self.config.retry_delay).await;
+                    error
+                }
+            };
+
+            // An earlier attempt might have been mined in the meantime
+            if let Ok(Some(receipt)) = self.find_receipt(&submission.hashes).await {
+                return Ok(receipt);
+            }
+            tracing::warn!(
+                "attempt {attempt} with nonce {} failed: {error:#}",
+                submission.nonce
+            );
+            last_error = Some(error);
+        }
+
+        let error = last_error.expect("at least one attempt");
+        Err(ConfTokenError::Rpc(error.wrap_err(format!(
+            "no receipt after {} attempts",
+            self.config.max_retries + 1
+        ))))
+    }
+}
+
+fn with_margin(gas: u64, margin_percent: u64) -> u64 {
+    gas.saturating_add(gas.saturating_mul(margin_percent) / 100)
+}
+
+// Rounds up, such that small fees are also bumped
+fn bump_fee(fee: u128, bump_percent: u128) -> u128 {
+    fee.saturating_add(fee.saturating_mul(bump_percent).div_ceil(100))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex as SyncMutex};
+
+    const FEES: Eip1559Estimation = Eip1559Estimation {
+        max_fee_per_gas: 100,
+        max_priority_fee_per_gas: 10,
+    };
+
+    #[derive(Default)]
+    struct NodeState {
+        // The pending nonce the node reports for every sender
+        nonce: u64,
+        // The number of broadcasts whose receipt times out before one is mined
+        stalled: usize,
+        fail_gas_estimate: bool,
+        nonce_requests: usize,
+        sent: Vec<TransactionRequest>,
+        mined: HashMap<TxHash, TransactionReceipt>,
+    }
+
+    // A node which mines every broadcast transaction, apart from the stalled ones
+    #[derive(Default)]
+    struct MockNode(SyncMutex<NodeState>);
+
+    impl MockNode {
+        fn state(&self) -> std::sync::MutexGuard<'_, NodeState> {
+            self.0.lock().unwrap()
+        }
+    }
+
+    fn receipt(hash: TxHash, from: Address) -> TransactionReceipt {
+        serde_json::from_value(serde_json::json!({
+            "type": "0x2",
+            "status": "0x1",
+            "cumulativeGasUsed": "0x5208",
+            "logs": [],
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "transactionHash": hash,
+            "transactionIndex": "0x0",
+            "blockHash": B256::ZERO,
+            "blockNumber": "0x1",
+            "gasUsed": "0x5208",
+            "effectiveGasPrice": "0x64",
+            "from": from,
+            "to": Address::ZERO,
+            "contractAddress": null,
+        }))
+        .unwrap()
+    }
+
+    impl TxProvider for MockNode {
+        async fn fetch_nonce(&self, _from: Address) -> eyre::Result<u64> {
+            // Such that concurrent sends interleave
+            tokio::task::yield_now().await;
+            let mut state = self.state();
+            state.nonce_requests += 1;
+            Ok(state.nonce)
+        }
+
+        async fn fetch_fees(&self) -> eyre::Result<Eip1559Estimation> {
+            tokio::task::yield_now().await;
+            Ok(FEES)
+        }
+
+        async fn fetch_gas_estimate(&self, _tx: TransactionRequest) -> eyre::Result<u64> {
+            if self.state().fail_gas_estimate {
+                eyre::bail!("execution reverted");
+            }
+            Ok(21_000)
+        }
+
+        async fn fetch_receipt(&self, hash: TxHash) -> eyre::Result<Option<TransactionReceipt>> {
+            Ok(self.state().mined.get(&hash).cloned())
+        }
+
+        async fn broadcast(
+            &self,
+            tx: TransactionRequest,
+            _timeout: Duration,
+        ) -> Result<TransactionReceipt, BroadcastError> {
+            tokio::task::yield_now().await;
+            let mut state = self.state();
+            let hash = B256::with_last_byte(state.sent.len() as u8 + 1);
+            let from = tx.from.unwrap_or_default();
+            state.sent.push(tx);
+            if state.stalled > 0 {
+                state.stalled -= 1;
+                return Err(BroadcastError::Receipt(hash, eyre::eyre!("timeout")));
+            }
+            let receipt = receipt(hash, from);
+            state.mined.insert(hash, receipt.clone());
+            Ok(receipt)
+        }
+    }
+
+    fn tx_manager(node: MockNode) -> TxManager<MockNode> {
+        let config = TxConfig {
+            retry_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        TxManager::new(node, Address::repeat_byte(1), config)
+    }
+
+    fn sent_nonces(sent: &[TransactionRequest]) -> Vec<u64> {
+        sent.iter().map(|tx| tx.nonce.unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn concurrent_nonce_test() {
+        let manager = Arc::new(tx_manager(MockNode(SyncMutex::new(NodeState {
+            nonce: 5,
+            ..Default::default()
+        }))));
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..10 {
+            let manager = Arc::clone(&manager);
+            tasks.spawn(async move { manager.send(TransactionRequest::default()).await });
+        }
+        while let Some(result) = tasks.join_next().await {
+            assert!(result.unwrap().unwrap().status());
+        }
+
+        // The nonce is only fetched once, afterwards it is assigned locally
+        let state = manager.provider.state();
+        assert_eq!(state.nonce_requests, 1);
+        let mut nonces = sent_nonces(&state.sent);
+        nonces.sort();
+        assert_eq!(nonces, (5..15).collect::<Vec<_>>());
+        drop(state);
+
+        // After a reset, the nonce is read from the node again
+        manager.provider.state().nonce = 20;
+        manager.reset_nonce(Address::repeat_byte(1)).await;
+        manager.send(TransactionRequest::default()).await.unwrap();
+        let state = manager.provider.state();
+        assert_eq!(state.nonce_requests, 2);
+        assert_eq!(state.sent.last().unwrap().nonce, Some(20));
+    }
+
+    #[tokio::test]
+    async fn resubmission_test() {
+        let manager = tx_manager(MockNode(SyncMutex::new(NodeState {
+            stalled: 2,
+            ..Default::default()
+        })));
+        let bump = manager.config().fee_bump_percent;
+
+        // The first two attempts time out, the third one with the same nonce and bumped fees is mined
+        let receipt = manager.send(TransactionRequest::default()).await.unwrap();
+        let state = manager.provider.state();
+        assert_eq!(sent_nonces(&state.sent), [0, 0, 0]);
+        let mut max_fee = FEES.max_fee_per_gas;
+        let mut priority_fee = FEES.max_priority_fee_per_gas;
+        for tx in state.sent.iter() {
+            assert_eq!(tx.max_fee_per_gas, Some(max_fee));
+            assert_eq!(tx.max_priority_fee_per_gas, Some(priority_fee));
+            assert_eq!(tx.gas, Some(with_margin(21_000, 20)));
+            max_fee = bump_fee(max_fee, bump);
+            priority_fee = bump_fee(priority_fee, bump);
+        }
+        assert_eq!(receipt.transaction_hash, B256::with_last_byte(3));
+        drop(state);
+
+        // Without a receipt after all attempts, the broadcast nonce is released as well
+        manager.provider.state().stalled = usize::MAX;
+        assert!(matches!(
+            manager.send(TransactionRequest::default()).await,
+            Err(ConfTokenError::Rpc(_))
+        ));
+        let num_sent = manager.provider.state().sent.len();
+        assert_eq!(num_sent, 3 + manager.config().max_retries + 1);
+        assert_eq!(manager.provider.state().sent[num_sent - 1].nonce, Some(1));
+        manager.provider.state().stalled = 0;
+        manager.provider.state().nonce = 1;
+        manager.send(TransactionRequest::default()).await.unwrap();
+        assert_eq!(manager.provider.state().sent.last().unwrap().nonce, Some(1));
+
+        // A nonce which was never broadcast is released, such that the next transaction is not stuck behind it
+        manager.provider.state().fail_gas_estimate = true;
+        assert!(manager.send(TransactionRequest::default()).await.is_err());
+        manager.provider.state().fail_gas_estimate = false;
+        manager.provider.state().nonce = 2;
+        manager.send(TransactionRequest::default()).await.unwrap();
+        assert_eq!(manager.provider.state().sent.last().unwrap().nonce, Some(2));
+    }
+
+    #[tokio::test]
+    async fn idempotent_test() {
+        let manager = tx_manager(MockNode(SyncMutex::new(NodeState {
+            stalled: usize::MAX,
+            ..Default::default()
+        })));
+        let key = B256::repeat_byte(7);
+        let attempts = manager.config().max_retries + 1;
+
+        // No attempt is mined in time
+        assert!(
+            manager
+                .send_idempotent(key, TransactionRequest::default())
+                .await
+                .is_err()
+        );
+        assert_eq!(manager.provider.state().sent.len(), attempts);
+
+        // The first attempt is mined afterwards, resending returns its receipt without a new transaction
+        let first = B256::with_last_byte(1);
+        manager
+            .provider
+            .state()
+            .mined
+            .insert(first, receipt(first, Address::repeat_byte(1)));
+        let receipt = manager
+            .send_idempotent(key, TransactionRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(receipt.transaction_hash, first);
+        assert_eq!(manager.provider.state().sent.len(), attempts);
+
+        // A pending submission is replaced with the same nonce and higher fees
+        let other = B256::repeat_byte(8);
+        assert!(
+            manager
+                .send_idempotent(other, TransactionRequest::default())
+                .await
+                .is_err()
+        );
+        manager.provider.state().stalled = 0;
+        manager
+            .send_idempotent(other, TransactionRequest::default())
+            .await
+            .unwrap();
+        let state = manager.provider.state();
+        let sent = &state.sent[attempts..];
+        assert_eq!(sent.len(), attempts + 1);
+        assert!(sent.iter().all(|tx| tx.nonce == Some(1)));
+        assert!(
+            sent.windows(2)
+                .all(|txs| txs[1].max_fee_per_gas > txs[0].max_fee_per_gas)
+        );
+    }
+
+    #[test]
+    fn fee_test() {
+        assert_eq!(with_margin(100_000, 20), 120_000);
+        assert_eq!(with_margin(u64::MAX, 20), u64::MAX);
+
+        // A replacement needs at least 10% higher fees
+        let config = TxConfig::default();
+        for fee in [1, 7, 10, 1_000_000_007] {
+            let bumped = bump_fee(fee, config.fee_bump_percent);
+            assert!(bumped * 10 >= fee * 11);
+        }
+        assert_eq!(bump_fee(u128::MAX, 15), u128::MAX);
+    }
+}
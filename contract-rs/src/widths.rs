@@ -0,0 +1,5 @@
+// Generated by create_circuits.sh, see there for the constraints on the widths
+// The bit size of transaction amounts
+pub const AMOUNT_BITS: usize = 80;
+// The bit size of balances, i.e., the range check for withdraws and of the balance limit
+pub const BALANCE_BITS: usize = 100;
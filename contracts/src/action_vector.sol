@@ -6,7 +6,8 @@ enum Action {
     Deposit,
     Withdraw,
     Transfer,
-    Dummy // Placeholder to fill batches
+    Dummy, // Placeholder to fill batches
+    PrivateWithdraw // Withdraw of an amount which is only revealed at payout
 }
 
 struct ActionQuery {
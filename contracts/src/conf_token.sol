@@ -4,15 +4,18 @@ pragma solidity ^0.8.20;
 // import "forge-std/console.sol";
 // import {Action, ActionQuery, QueryMap, QueryMapLib, Iterator} from "./action_queue.sol";
 import {Action, ActionQuery, QueryMap, QueryMapLib} from "./action_vector.sol";
+import {AMOUNT_BITS, BALANCE_BITS} from "./widths.sol";
 import {IERC20} from "@openzeppelin/contracts/token/ERC20/IERC20.sol";
 import {SafeERC20} from "@openzeppelin/contracts/token/ERC20/utils/SafeERC20.sol";
+import {ECDSA} from "@openzeppelin/contracts/utils/cryptography/ECDSA.sol";
+import {MessageHashUtils} from "@openzeppelin/contracts/utils/cryptography/MessageHashUtils.sol";
 
 interface IGroth16Verifier {
     function verifyProof(
         uint256[2] calldata _pA,
         uint256[2][2] calldata _pB,
         uint256[2] calldata _pC,
-        uint256[250] calldata _pubSignals
+        uint256[252] calldata _pubSignals
     ) external view returns (bool);
 }
 
@@ -38,9 +41,17 @@ contract ConfidentialToken {
     BabyJubJubElement public mpc_pk1;
     BabyJubJubElement public mpc_pk2;
     BabyJubJubElement public mpc_pk3;
+    // Optional BabyJubJub key of an auditor, to which the MPC network discloses balances and transfer amounts. (0, 0) if there is no auditor.
+    BabyJubJubElement public auditor_pk;
+    // The policy limits the MPC network enforces for every processed action. They are public inputs of the proof, the defaults are the largest values the circuit supports.
+    // The MPC network can additionally limit the volume an account spends per epoch. That limit is a trusted-MPC policy: it is neither stored here nor part of the proof, thus this contract cannot verify it.
+    uint256 public maxTransfer = 2 ** AMOUNT_BITS - 1;
+    uint256 public maxBalance = 2 ** BALANCE_BITS - 1;
 
     // Stores the commitments to the balances of users
     mapping(address => uint256) public balanceCommitments;
+    // The root of the Poseidon2 Merkle tree over (user, commitment) which the MPC network maintains, such that light clients can check balance commitments with inclusion proofs. The root is attested by the MPC network and not proven: the proof of processMPC only covers the commitments, thus light clients trust the MPC network for the root. Anyone with access to the contract state can recompute it, since the leaves are the values of balanceCommitments.
+    uint256 public balanceRoot;
 
     // Stores the actions which are not yet processed
     QueryMap public action_queue;
@@ -48,6 +59,13 @@ contract ConfidentialToken {
     // Stores the secret shares of the amount and randomness for a transfer
     mapping(uint256 => Ciphertext) private shares;
 
+    // BabyJubJub keys of users which authorize relayed transfers (EdDSA signatures)
+    mapping(address => BabyJubJubElement) public authKeys;
+    // The next nonce of a user, which is used by a key registration or a processed relayed transfer
+    mapping(address => uint256) public authNonces;
+    // Stores the authorization of a relayed transfer, such that the MPC network can check the signature before processing it
+    mapping(uint256 => TransferAuthorization) private authorizations;
+
     // For the demo we only allow a list of certain addresses to interact with the contract. We can however also instantiate it to allow everyone using the allow_all flag.
     mapping(address => bool) public demo_whitelist;
     bool allow_all;
@@ -70,6 +88,12 @@ contract ConfidentialToken {
     event Withdraw(uint256 action_index);
     event Transfer(uint256 action_index);
     event TransferBatch(uint256[] action_indices);
+    event AuthKeyRegistered(address user);
+    event AuditorKeySet(uint256 x, uint256 y);
+    event PolicyLimitsSet(uint256 max_transfer, uint256 max_balance);
+    event BalanceRootUpdated(uint256 root);
+    event ActionRejected(uint256 action_index);
+    event MpcKeysSet();
 
     // The error codes
     error Unauthorized();
@@ -82,6 +106,9 @@ contract ConfidentialToken {
     error InvalidCommitment();
     error NotOnCurve();
     error InvalidParameters();
+    error InvalidNonce();
+    error InvalidSignature();
+    error SmallOrder();
 
     modifier onlyMPC() {
         if (msg.sender != mpcAdress) revert Unauthorized();
@@ -89,11 +116,7 @@ contract ConfidentialToken {
     }
 
     modifier demoWhitelist() {
-        if (!allow_all) {
-            if (!demo_whitelist[msg.sender]) {
-                revert Unauthorized();
-            }
-        }
+        checkWhitelist(msg.sender);
         _;
     }
 
@@ -143,6 +166,29 @@ contract ConfidentialToken {
         BabyJubJubElement sender_pk;
     }
 
+    // EdDSA signature over BabyJubJub, verified off-chain by the MPC network
+    struct EdDSASignature {
+        BabyJubJubElement r;
+        uint256 s;
+    }
+
+    // relayed is false for transfers which are authorized by the sender of the transaction
+    struct TransferAuthorization {
+        bool relayed;
+        uint256 nonce;
+        EdDSASignature signature;
+    }
+
+    // A transfer signed off-chain by the sender, which can be submitted by anyone (e.g., a relayer paying the gas)
+    struct SignedTransfer {
+        address sender;
+        address receiver;
+        uint256 amount_commitment;
+        uint256 nonce;
+        Ciphertext ciphertext;
+        EdDSASignature signature;
+    }
+
     struct Groth16Proof {
         uint256[2] pA;
         uint256[2][2] pB;
@@ -152,6 +198,7 @@ contract ConfidentialToken {
     struct TransactionInput {
         uint256[BATCH_SIZE] action_index;
         uint256[BATCH_SIZE * 2] commitments; // Consists of new_commitments of sender/receiver balances, remaining commitments are read from smart contract
+        uint256 balance_root; // The root of the Merkle tree over all balance commitments after the batch
     }
 
     function whitelistForDemo(address[] calldata addresses) public onlyMPC {
@@ -160,6 +207,27 @@ contract ConfidentialToken {
         }
     }
 
+    function checkWhitelist(address user) internal view {
+        if (!allow_all) {
+            if (!demo_whitelist[user]) {
+                revert Unauthorized();
+            }
+        }
+    }
+
+    function checkCiphertext(Ciphertext calldata ciphertext) internal pure {
+        if (!isOnBabyJubJubCurve(ciphertext.sender_pk.x, ciphertext.sender_pk.y)) {
+            revert NotOnCurve();
+        }
+
+        if (ciphertext.amount[0] >= PRIME) revert NotInPrimeField();
+        if (ciphertext.amount[1] >= PRIME) revert NotInPrimeField();
+        if (ciphertext.amount[2] >= PRIME) revert NotInPrimeField();
+        if (ciphertext.r[0] >= PRIME) revert NotInPrimeField();
+        if (ciphertext.r[1] >= PRIME) revert NotInPrimeField();
+        if (ciphertext.r[2] >= PRIME) revert NotInPrimeField();
+    }
+
     function getBalanceCommitment(address user) public view returns (uint256) {
         uint256 commitment = balanceCommitments[user];
         if (commitment == 0) {
@@ -181,6 +249,11 @@ contract ConfidentialToken {
         return shares[index];
     }
 
+    // Not relayed (and zero) for transfers which are authorized by the sender of the transaction
+    function getAuthorizationAtIndex(uint256 index) public view returns (TransferAuthorization memory) {
+        return authorizations[index];
+    }
+
     function commit(uint256 input, uint256 randomness) public view returns (uint256) {
         if (input >= PRIME) {
             revert NotInPrimeField();
@@ -191,6 +264,53 @@ contract ConfidentialToken {
         return poseidon2.compress([input, randomness], DS);
     }
 
+    // Sets the key of the auditor. The MPC network only encrypts disclosures to it, the auditor cannot do anything on chain. (0, 0) removes the auditor.
+    function setAuditorKey(BabyJubJubElement calldata pk) public onlyMPC {
+        if (pk.x != 0 || pk.y != 0) {
+            // The identity is not a valid key
+            if (pk.x == 0 || !isOnBabyJubJubCurve(pk.x, pk.y)) {
+                revert NotOnCurve();
+            }
+        }
+        auditor_pk = pk;
+        emit AuditorKeySet(pk.x, pk.y);
+    }
+
+    // Rotates the keys of the MPC network, e.g., after resharing to a new committee. Ciphertexts queued before are still encrypted to the old keys, thus the old committee has to reshare them as well.
+    function setMpcKeys(
+        BabyJubJubElement calldata pk1,
+        BabyJubJubElement calldata pk2,
+        BabyJubJubElement calldata pk3
+    ) public onlyMPC {
+        checkMpcKey(pk1);
+        checkMpcKey(pk2);
+        checkMpcKey(pk3);
+        mpc_pk1 = pk1;
+        mpc_pk2 = pk2;
+        mpc_pk3 = pk3;
+        emit MpcKeysSet();
+    }
+
+    // Users encrypt to the MPC keys, thus a small order key would leak the plaintexts
+    function checkMpcKey(BabyJubJubElement calldata pk) internal pure {
+        if (!isOnBabyJubJubCurve(pk.x, pk.y)) {
+            revert NotOnCurve();
+        }
+        if (isSmallOrder(pk.x, pk.y)) {
+            revert SmallOrder();
+        }
+    }
+
+    // Sets the policy limits. Actions violating them are rejected by the MPC network, the proof of a batch is only valid for the limits stored here.
+    function setPolicyLimits(uint256 max_transfer, uint256 max_balance) public onlyMPC {
+        if (max_transfer > 2 ** AMOUNT_BITS - 1 || max_balance > 2 ** BALANCE_BITS - 1 || max_transfer > max_balance) {
+            revert InvalidParameters();
+        }
+        maxTransfer = max_transfer;
+        maxBalance = max_balance;
+        emit PolicyLimitsSet(max_transfer, max_balance);
+    }
+
     // TODO the following is just for a demo to be able to retrieve funds after it is done
     // Remove for a real deployment
     function retrieveFunds(address receiver) public onlyMPC {
@@ -199,8 +319,8 @@ contract ConfidentialToken {
 
     function deposit(uint256 amount) public demoWhitelist returns (uint256) {
         address receiver = msg.sender;
-        // This is at most 2^80 / 10^18 = 1_208_925.8 ETH
-        if (amount > 0xFFFFFFFFFFFFFFFFFFFF) {
+        // With the default of 80 bits, this is at most 2^80 / 10^18 = 1_208_925.8 ETH
+        if (amount > 2 ** AMOUNT_BITS - 1) {
             revert InvalidAmount();
         }
         if (amount == 0) {
@@ -218,8 +338,8 @@ contract ConfidentialToken {
 
     function withdraw(uint256 amount) public demoWhitelist returns (uint256) {
         address sender = msg.sender;
-        // This is at most 2^80 / 10^18 = 1_208_925.8 ETH
-        if (amount > 0xFFFFFFFFFFFFFFFFFFFF) {
+        // With the default of 80 bits, this is at most 2^80 / 10^18 = 1_208_925.8 ETH
+        if (amount > 2 ** AMOUNT_BITS - 1) {
             revert InvalidAmount();
         }
         if (amount == 0) {
@@ -234,6 +354,28 @@ contract ConfidentialToken {
         return index;
     }
 
+    // Withdraws an amount which stays hidden until the payout. The amount is given as a commitment and its shares are encrypted to the MPC network, the same way as for transfers.
+    function withdrawPrivate(uint256 amount_commitment, Ciphertext calldata ciphertext)
+        public
+        demoWhitelist
+        returns (uint256)
+    {
+        address sender = msg.sender;
+        if (amount_commitment >= PRIME) {
+            revert NotInPrimeField();
+        }
+        // We do not check if the sender has a balance here, because it might be topped up by an action in the queue
+
+        checkCiphertext(ciphertext);
+
+        ActionQuery memory aq = ActionQuery(Action.PrivateWithdraw, sender, address(0), amount_commitment);
+        action_queue.push(aq);
+        uint256 index = action_queue.highest_key();
+        shares[index] = ciphertext;
+        emit Withdraw(index);
+        return index;
+    }
+
     function transfer(address receiver, uint256 amount, Ciphertext calldata ciphertext)
         public
         demoWhitelist
@@ -249,16 +391,7 @@ contract ConfidentialToken {
         }
         // We do not check if the sender has a balance here, because it might be topped up by an action in the queue
 
-        if (!isOnBabyJubJubCurve(ciphertext.sender_pk.x, ciphertext.sender_pk.y)) {
-            revert NotOnCurve();
-        }
-
-        if (ciphertext.amount[0] >= PRIME) revert NotInPrimeField();
-        if (ciphertext.amount[1] >= PRIME) revert NotInPrimeField();
-        if (ciphertext.amount[2] >= PRIME) revert NotInPrimeField();
-        if (ciphertext.r[0] >= PRIME) revert NotInPrimeField();
-        if (ciphertext.r[1] >= PRIME) revert NotInPrimeField();
-        if (ciphertext.r[2] >= PRIME) revert NotInPrimeField();
+        checkCiphertext(ciphertext);
 
         ActionQuery memory aq = ActionQuery(Action.Transfer, sender, receiver, amount);
 
@@ -269,7 +402,80 @@ contract ConfidentialToken {
         return index;
     }
 
-    // TODO This function is only used for demo to make it easier to register transactions (we do not need to fund a lot of different wallets to pay the gas costs). It does not check a signature of the sender_pk and the ciphertexts are assumed to be given to the MPC network off-chain.
+    function registerAuthKey(BabyJubJubElement calldata pk) public demoWhitelist {
+        storeAuthKey(msg.sender, pk);
+    }
+
+    // Allows a relayer to register the key of a user who does not hold ETH. The user signs (contract, user, pk, nonce) with their Ethereum key.
+    function registerAuthKeyFor(address user, BabyJubJubElement calldata pk, bytes calldata signature) public {
+        checkWhitelist(user);
+        bytes32 hash = MessageHashUtils.toEthSignedMessageHash(
+            keccak256(abi.encodePacked(address(this), user, pk.x, pk.y, authNonces[user]))
+        );
+        if (ECDSA.recover(hash, signature) != user) {
+            revert InvalidSignature();
+        }
+        storeAuthKey(user, pk);
+    }
+
+    function storeAuthKey(address user, BabyJubJubElement calldata pk) internal {
+        // We do not allow the identity (or other points with x = 0) as key
+        if (pk.x == 0 || !isOnBabyJubJubCurve(pk.x, pk.y)) {
+            revert NotOnCurve();
+        }
+        if (isSmallOrder(pk.x, pk.y)) {
+            revert SmallOrder();
+        }
+        authKeys[user] = pk;
+        // Invalidates all signatures for the old key
+        authNonces[user]++;
+        emit AuthKeyRegistered(user);
+    }
+
+    // Registers transfers which are signed by the sender with their registered BabyJubJub key. Can be called by anyone, e.g., a relayer paying the gas. Verifying EdDSA on-chain is too expensive, thus the MPC network verifies the signature and removes unauthorized transfers before processing. The nonce is only consumed in processMPC, such that a transfer with an invalid signature cannot burn the nonce of the sender.
+    function transferSigned(SignedTransfer[] calldata transfers) public returns (uint256[] memory) {
+        uint256[] memory indices = new uint256[](transfers.length);
+        for (uint256 i = 0; i < transfers.length; i++) {
+            SignedTransfer calldata st = transfers[i];
+            checkWhitelist(st.sender);
+            // Amount is just a commitment here
+            if (st.amount_commitment >= PRIME) {
+                revert NotInPrimeField();
+            }
+            if (st.sender == st.receiver) {
+                revert InvalidTransfer();
+            }
+            if (authKeys[st.sender].x == 0) {
+                revert Unauthorized();
+            }
+            if (st.nonce < authNonces[st.sender]) {
+                revert InvalidNonce();
+            }
+            // We do not check if the sender has a balance here, because it might be topped up by an action in the queue
+
+            checkCiphertext(st.ciphertext);
+            if (!isOnBabyJubJubCurve(st.signature.r.x, st.signature.r.y)) {
+                revert NotOnCurve();
+            }
+            if (isSmallOrder(st.signature.r.x, st.signature.r.y)) {
+                revert SmallOrder();
+            }
+            if (st.signature.s >= PRIME) {
+                revert NotInPrimeField();
+            }
+
+            ActionQuery memory aq = ActionQuery(Action.Transfer, st.sender, st.receiver, st.amount_commitment);
+            action_queue.push(aq);
+            uint256 index = action_queue.highest_key();
+            shares[index] = st.ciphertext;
+            authorizations[index] = TransferAuthorization(true, st.nonce, st.signature);
+            indices[i] = index;
+        }
+        emit TransferBatch(indices);
+        return indices;
+    }
+
+    // TODO This function is only used for demo to make it easier to register transactions (we do not need to fund a lot of different wallets to pay the gas costs). It does not check a signature of the sender_pk and the ciphertexts are assumed to be given to the MPC network off-chain. Relayed transfers of users should use transferSigned instead.
     function transferBatch(
         address[] calldata senders,
         address[] calldata receivers,
@@ -324,6 +530,22 @@ contract ConfidentialToken {
         action_queue.remove(index);
     }
 
+    // Removes actions which the MPC network rejected because they violate the policy limits or its velocity limit. The check itself is done obliviously by the MPC network, only the result is public. Rejected deposits are refunded.
+    function rejectActions(uint256[] calldata indices) public onlyMPC {
+        for (uint256 i = 0; i < indices.length; i++) {
+            uint256 index = indices[i];
+            // We are not allowed to remove 0
+            if (index == 0) revert CannotRemoveDummyAction();
+            ActionQuery memory aq = action_queue.get(index);
+            if (aq.action == Action.Invalid) revert InvalidMpcAction();
+            action_queue.remove(index);
+            if (aq.action == Action.Deposit) {
+                token.safeTransfer(aq.receiver, aq.amount);
+            }
+            emit ActionRejected(index);
+        }
+    }
+
     // TODO This function is only for demo purposes to be able to clear the action queue in case something goes wrong. In a real deployment this function should not be included.
     function removeAllOpenActions() public onlyMPC {
         uint256 num_items = action_queue.size - 1; // Exclude dummy
@@ -338,7 +560,10 @@ contract ConfidentialToken {
     // and removes the actions from the queue.
     // Deposit and Withdraw are rewritten to be transfers
     function processMPC(TransactionInput calldata inputs, Groth16Proof calldata proof) public onlyMPC {
-        uint256[BATCH_SIZE * 5] memory commitments;
+        // The commitments of the transactions followed by the policy limits
+        uint256[BATCH_SIZE * 5 + 2] memory commitments;
+        commitments[BATCH_SIZE * 5] = maxTransfer;
+        commitments[BATCH_SIZE * 5 + 1] = maxBalance;
 
         for (uint256 i = 0; i < BATCH_SIZE; i++) {
             uint256 index = inputs.action_index[i];
@@ -396,6 +621,14 @@ contract ConfidentialToken {
                 uint256 sender_old_commitment = balanceCommitments[aq.sender];
                 uint256 receiver_old_commitment = getBalanceCommitment(aq.receiver);
 
+                // Relayed transfers consume the nonce of the sender, the MPC network only includes the ones with the next nonce and a valid signature
+                if (authorizations[index].relayed) {
+                    if (authorizations[index].nonce != authNonces[aq.sender]) {
+                        revert InvalidNonce();
+                    }
+                    authNonces[aq.sender]++;
+                }
+
                 // Update the commitments on-chain
                 balanceCommitments[aq.sender] = inputs.commitments[i * 2];
                 balanceCommitments[aq.receiver] = inputs.commitments[i * 2 + 1];
@@ -410,6 +643,29 @@ contract ConfidentialToken {
                 // Remove the action from the queue
                 action_queue.remove(index);
                 // delete shares[index]; // Actually costs more gas
+            } else if (aq.action == Action.PrivateWithdraw) {
+                // The MPC network reveals the amount in place of the receiver commitment. The proof shows that it matches the amount commitment and that it is deducted from the balance.
+                uint256 payout = inputs.commitments[i * 2 + 1];
+                if (payout > 2 ** AMOUNT_BITS - 1) {
+                    revert InvalidAmount();
+                }
+                uint256 sender_old_commitment = balanceCommitments[aq.sender];
+
+                // Update the commitments on-chain
+                balanceCommitments[aq.sender] = inputs.commitments[i * 2];
+
+                // Fill the commitments array for ZK proof verification
+                commitments[i * 5] = sender_old_commitment;
+                commitments[i * 5 + 1] = inputs.commitments[i * 2]; // sender_new_commitment
+                commitments[i * 5 + 2] = ZERO_COMMITMENT; // receiver_old_commitment
+                commitments[i * 5 + 3] = poseidon2.compress([payout, 0], DS); // receiver_new_commitment
+                commitments[i * 5 + 4] = amount; // Is already a commitment
+
+                // Send the actual tokens
+                token.safeTransfer(aq.sender, payout);
+
+                // Remove the action from the queue
+                action_queue.remove(index);
             } else if (aq.action == Action.Dummy) {
                 // Do nothing, just add zeros to the commitments
                 if (inputs.commitments[i * 2] != 0) {
@@ -433,6 +689,13 @@ contract ConfidentialToken {
         if (!verifier.verifyProof(proof.pA, proof.pB, proof.pC, commitments)) {
             revert InvalidProof();
         }
+
+        // The root is not part of the proof, it is attested by the MPC network, see balanceRoot
+        if (inputs.balance_root >= PRIME) {
+            revert NotInPrimeField();
+        }
+        balanceRoot = inputs.balance_root;
+        emit BalanceRootUpdated(inputs.balance_root);
     }
 
     function read_queue(uint256 num_items)
@@ -452,7 +715,7 @@ contract ConfidentialToken {
         for (uint256 i = 0; i < num_items; i++) {
             keys[i] = it;
             actions[i] = action_queue.get(it);
-            if (actions[i].action == Action.Transfer) {
+            if (actions[i].action == Action.Transfer || actions[i].action == Action.PrivateWithdraw) {
                 cts[i] = shares[it];
             }
             (it,) = action_queue.next_key(it);
@@ -472,4 +735,22 @@ contract ConfidentialToken {
 
         return addmod(axx, yy, PRIME) == addmod(1, dxxyy, PRIME);
     }
+
+    // Checks if a point on the curve is in the small subgroup, i.e., cofactor * P is the identity. Uses projective doubling, such that no inversions are required.
+    function isSmallOrder(uint256 x, uint256 y) public pure returns (bool) {
+        uint256 z = 1;
+        for (uint256 i = 0; i < 3; i++) {
+            uint256 b = mulmod(addmod(x, y, PRIME), addmod(x, y, PRIME), PRIME);
+            uint256 c = mulmod(x, x, PRIME);
+            uint256 d = mulmod(y, y, PRIME);
+            uint256 e = mulmod(A, c, PRIME);
+            uint256 f = addmod(e, d, PRIME);
+            uint256 j = addmod(f, PRIME - mulmod(2, mulmod(z, z, PRIME), PRIME), PRIME);
+            x = mulmod(addmod(b, PRIME - addmod(c, d, PRIME), PRIME), j, PRIME);
+            y = mulmod(f, addmod(e, PRIME - d, PRIME), PRIME);
+            z = mulmod(f, j, PRIME);
+        }
+        // The identity is (0, z, z)
+        return x == 0 && y == z;
+    }
 }
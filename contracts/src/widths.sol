@@ -0,0 +1,8 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.20;
+
+// Generated by create_circuits.sh, see there for the constraints on the widths. The verifiers have to be generated from circuits of the same widths.
+// The bit size of transaction amounts
+uint256 constant AMOUNT_BITS = 80;
+// The bit size of balances, i.e., the range check for withdraws and of the balance limit
+uint256 constant BALANCE_BITS = 100;
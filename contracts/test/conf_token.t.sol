@@ -4,6 +4,7 @@ pragma solidity ^0.8.20;
 import {Test, console} from "forge-std/Test.sol";
 import {ConfidentialToken} from "../src/conf_token.sol";
 import {Action, ActionQuery} from "../src/action_vector.sol";
+import {AMOUNT_BITS, BALANCE_BITS} from "../src/widths.sol";
 import {Groth16Verifier} from "../src/groth16_verifier.sol";
 import {Poseidon2T2_BN254} from "../src/poseidon2.sol";
 import {USDCToken} from "../src/token.sol";
@@ -129,6 +130,207 @@ contract ConfidentialTokenTest is Test {
         assertEq(query.amount, commit);
     }
 
+    function testWithdrawPrivate() public {
+        uint256 commit = conf_token.commit(1 ether, 123);
+        uint256 index = conf_token.withdrawPrivate(commit, ciphertext);
+
+        ConfidentialToken.Ciphertext memory cipher = conf_token.getCiphertextAtIndex(index);
+        assertNotEq(cipher.amount[0], 0);
+
+        ActionQuery memory query = conf_token.getActionAtIndex(index);
+        assertEq(uint256(query.action), uint256(Action.PrivateWithdraw));
+        assertEq(query.sender, address(this));
+        assertEq(query.receiver, address(0));
+        assertEq(query.amount, commit);
+    }
+
+    function testTransferSigned() public {
+        // Alice registers her key via a relayer (this contract) using an ECDSA signature
+        (address signer, uint256 signer_key) = makeAddrAndKey("signer");
+        bytes32 hash = keccak256(abi.encodePacked(address(conf_token), signer, sender_key.x, sender_key.y, uint256(0)));
+        (uint8 v, bytes32 r, bytes32 s) =
+            vm.sign(signer_key, keccak256(abi.encodePacked("\x19Ethereum Signed Message:\n32", hash)));
+        conf_token.registerAuthKeyFor(signer, sender_key, abi.encodePacked(r, s, v));
+        assertEq(conf_token.authNonces(signer), 1);
+
+        // Replaying the registration fails, since the nonce changed
+        vm.expectRevert(ConfidentialToken.InvalidSignature.selector);
+        conf_token.registerAuthKeyFor(signer, sender_key, abi.encodePacked(r, s, v));
+
+        // The EdDSA signature is only checked by the MPC network
+        uint256 commit = conf_token.commit(1 ether, 123);
+        ConfidentialToken.SignedTransfer[] memory transfers = new ConfidentialToken.SignedTransfer[](1);
+        transfers[0] = ConfidentialToken.SignedTransfer(
+            signer, bob, commit, 1, ciphertext, ConfidentialToken.EdDSASignature(sender_key, 42)
+        );
+        uint256[] memory indices = conf_token.transferSigned(transfers);
+
+        ActionQuery memory query = conf_token.getActionAtIndex(indices[0]);
+        assertEq(uint256(query.action), uint256(Action.Transfer));
+        assertEq(query.sender, signer);
+        assertEq(query.receiver, bob);
+        assertEq(query.amount, commit);
+        ConfidentialToken.TransferAuthorization memory auth = conf_token.getAuthorizationAtIndex(indices[0]);
+        assertTrue(auth.relayed);
+        assertEq(auth.nonce, 1);
+        assertEq(auth.signature.s, 42);
+
+        // The nonce is only consumed in processMPC, thus queueing a transfer with a garbage signature does not burn it
+        assertEq(conf_token.authNonces(signer), 1);
+        conf_token.transferSigned(transfers);
+        assertEq(conf_token.authNonces(signer), 1);
+
+        // Stale nonces are rejected
+        transfers[0].nonce = 0;
+        vm.expectRevert(ConfidentialToken.InvalidNonce.selector);
+        conf_token.transferSigned(transfers);
+        transfers[0].nonce = 1;
+
+        // R of small order is rejected, (sqrt(1/a), 0) has order 4
+        transfers[0].signature.r = ConfidentialToken.BabyJubJubElement(
+            2957874849018779266517920829765869116077630550401372566248359756137677864698, 0
+        );
+        vm.expectRevert(ConfidentialToken.SmallOrder.selector);
+        conf_token.transferSigned(transfers);
+        transfers[0].signature.r = sender_key;
+
+        // Transfers of the sender of the transaction are not relayed
+        uint256 index = conf_token.transfer(bob, commit, ciphertext);
+        assertFalse(conf_token.getAuthorizationAtIndex(index).relayed);
+
+        // Senders without a registered key cannot be relayed
+        transfers[0].sender = alice;
+        transfers[0].nonce = 0;
+        vm.expectRevert(ConfidentialToken.Unauthorized.selector);
+        conf_token.transferSigned(transfers);
+    }
+
+    function testSmallOrderAuthKey() public {
+        assertTrue(conf_token.isSmallOrder(0, 1));
+        assertTrue(
+            conf_token.isSmallOrder(2957874849018779266517920829765869116077630550401372566248359756137677864698, 0)
+        );
+        assertFalse(conf_token.isSmallOrder(sender_key.x, sender_key.y));
+
+        vm.expectRevert(ConfidentialToken.SmallOrder.selector);
+        conf_token.registerAuthKey(
+            ConfidentialToken.BabyJubJubElement(
+                2957874849018779266517920829765869116077630550401372566248359756137677864698, 0
+            )
+        );
+    }
+
+    function testSetAuditorKey() public {
+        // Only the MPC network can set the auditor
+        vm.expectRevert(ConfidentialToken.Unauthorized.selector);
+        conf_token.setAuditorKey(sender_key);
+
+        vm.startPrank(mpcAdress);
+        conf_token.setAuditorKey(sender_key);
+        (uint256 x, uint256 y) = conf_token.auditor_pk();
+        assertEq(x, sender_key.x);
+        assertEq(y, sender_key.y);
+
+        vm.expectRevert(ConfidentialToken.NotOnCurve.selector);
+        conf_token.setAuditorKey(ConfidentialToken.BabyJubJubElement(1, 2));
+        vm.expectRevert(ConfidentialToken.NotOnCurve.selector);
+        conf_token.setAuditorKey(ConfidentialToken.BabyJubJubElement(0, 1));
+
+        // (0, 0) removes the auditor
+        conf_token.setAuditorKey(ConfidentialToken.BabyJubJubElement(0, 0));
+        (x, y) = conf_token.auditor_pk();
+        assertEq(x, 0);
+        assertEq(y, 0);
+        vm.stopPrank();
+    }
+
+    function testSetMpcKeys() public {
+        // Only the MPC network can rotate its keys
+        vm.expectRevert(ConfidentialToken.Unauthorized.selector);
+        conf_token.setMpcKeys(mpc_pk2, mpc_pk3, mpc_pk1);
+
+        vm.startPrank(mpcAdress);
+        conf_token.setMpcKeys(mpc_pk2, mpc_pk3, mpc_pk1);
+        (uint256 x, uint256 y) = conf_token.mpc_pk1();
+        assertEq(x, mpc_pk2.x);
+        assertEq(y, mpc_pk2.y);
+        (x, y) = conf_token.mpc_pk2();
+        assertEq(x, mpc_pk3.x);
+        assertEq(y, mpc_pk3.y);
+        (x, y) = conf_token.mpc_pk3();
+        assertEq(x, mpc_pk1.x);
+        assertEq(y, mpc_pk1.y);
+
+        vm.expectRevert(ConfidentialToken.NotOnCurve.selector);
+        conf_token.setMpcKeys(mpc_pk1, mpc_pk2, ConfidentialToken.BabyJubJubElement(1, 2));
+
+        // The identity and other small order points are rejected for each key
+        ConfidentialToken.BabyJubJubElement memory small = ConfidentialToken.BabyJubJubElement(
+            2957874849018779266517920829765869116077630550401372566248359756137677864698, 0
+        );
+        vm.expectRevert(ConfidentialToken.SmallOrder.selector);
+        conf_token.setMpcKeys(ConfidentialToken.BabyJubJubElement(0, 1), mpc_pk2, mpc_pk3);
+        vm.expectRevert(ConfidentialToken.SmallOrder.selector);
+        conf_token.setMpcKeys(mpc_pk1, small, mpc_pk3);
+        vm.expectRevert(ConfidentialToken.SmallOrder.selector);
+        conf_token.setMpcKeys(mpc_pk1, mpc_pk2, small);
+        vm.stopPrank();
+    }
+
+    function testSetPolicyLimits() public {
+        assertEq(conf_token.maxTransfer(), 2 ** AMOUNT_BITS - 1);
+        assertEq(conf_token.maxBalance(), 2 ** BALANCE_BITS - 1);
+
+        // Only the MPC network can set the limits
+        vm.expectRevert(ConfidentialToken.Unauthorized.selector);
+        conf_token.setPolicyLimits(1 ether, 10 ether);
+
+        vm.startPrank(mpcAdress);
+        conf_token.setPolicyLimits(1 ether, 10 ether);
+        assertEq(conf_token.maxTransfer(), 1 ether);
+        assertEq(conf_token.maxBalance(), 10 ether);
+
+        vm.expectRevert(ConfidentialToken.InvalidParameters.selector);
+        conf_token.setPolicyLimits(10 ether, 1 ether);
+        vm.expectRevert(ConfidentialToken.InvalidParameters.selector);
+        conf_token.setPolicyLimits(2 ** AMOUNT_BITS, 2 ** BALANCE_BITS - 1);
+        vm.expectRevert(ConfidentialToken.InvalidParameters.selector);
+        conf_token.setPolicyLimits(1 ether, 2 ** BALANCE_BITS);
+        vm.stopPrank();
+    }
+
+    function testRejectActions() public {
+        deal_tokens(address(this), 10 ether);
+        uint256 balance = token.balanceOf(address(this));
+        uint256 deposit_index = conf_token.deposit(1 ether);
+        uint256 withdraw_index = conf_token.withdraw(1 ether);
+        assertEq(token.balanceOf(address(this)), balance - 1 ether);
+
+        uint256[] memory indices = new uint256[](2);
+        indices[0] = deposit_index;
+        indices[1] = withdraw_index;
+
+        vm.expectRevert(ConfidentialToken.Unauthorized.selector);
+        conf_token.rejectActions(indices);
+
+        vm.startPrank(mpcAdress);
+        conf_token.rejectActions(indices);
+
+        // Already removed
+        vm.expectRevert(ConfidentialToken.InvalidMpcAction.selector);
+        conf_token.rejectActions(indices);
+
+        uint256[] memory dummy = new uint256[](1);
+        vm.expectRevert(ConfidentialToken.CannotRemoveDummyAction.selector);
+        conf_token.rejectActions(dummy);
+        vm.stopPrank();
+
+        // The deposit is refunded
+        assertEq(token.balanceOf(address(this)), balance);
+        assertEq(token.balanceOf(address(conf_token)), 0);
+        assertEq(conf_token.getActionQueueSize(), 1);
+    }
+
     function testRemoveAction() public {
         uint256 index = conf_token.withdraw(1 ether);
         console.log("Withdraw action added at index:", index);
@@ -234,6 +436,9 @@ contract ConfidentialTokenTest is Test {
             // inputs.commitments[i * 2 + 1] = 0;
         }
 
+        // The root of the balance tree is only posted, not checked
+        inputs.balance_root = 42;
+
         vm.startPrank(mpcAdress);
         conf_token.processMPC(inputs, proof);
         vm.stopPrank();
@@ -243,6 +448,7 @@ contract ConfidentialTokenTest is Test {
         assertEq(token.balanceOf(bob), amount);
 
         assertEq(conf_token.getActionQueueSize(), 1);
+        assertEq(conf_token.balanceRoot(), 42);
 
         cipher = conf_token.getCiphertextAtIndex(index);
         // assertEq(cipher.amount[0], 0); // We don't remove anymore, since it costs more gas
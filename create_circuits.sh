@@ -1,6 +1,72 @@
 #!/usr/bin/env bash
 
-CIRCUITS=("private_deposit" "private_withdraw" "private_transaction" "private_transaction_batched")
+# The bit sizes of amounts and balances, e.g., AMOUNT_BITS=128 BALANCE_BITS=160 ./create_circuits.sh for 18-decimal tokens.
+# Amounts have to fit into balances, and balances have to stay below 2^252, such that a negative balance (p - x) cannot pass the range check.
+AMOUNT_BITS=${AMOUNT_BITS:-80}
+BALANCE_BITS=${BALANCE_BITS:-100}
+
+if ! [[ "$AMOUNT_BITS" =~ ^[0-9]+$ && "$BALANCE_BITS" =~ ^[0-9]+$ ]] || ((AMOUNT_BITS == 0 || AMOUNT_BITS > BALANCE_BITS || BALANCE_BITS > 252)); then
+  echo "Invalid widths: AMOUNT_BITS=$AMOUNT_BITS, BALANCE_BITS=$BALANCE_BITS"
+  exit 1
+fi
+echo "Using $AMOUNT_BITS bit amounts and $BALANCE_BITS bit balances"
+
+HEADER="Generated by create_circuits.sh, see there for the constraints on the widths"
+AMOUNT_COMMENT="The bit size of transaction amounts"
+BALANCE_COMMENT="The bit size of balances, i.e., the range check for withdraws and of the balance limit"
+
+cat > noir/private_deposit_common/src/widths.nr << EOF
+// $HEADER
+// $AMOUNT_COMMENT
+pub global AMOUNT_BITS: u32 = $AMOUNT_BITS;
+// $BALANCE_COMMENT
+pub global BALANCE_BITS: u32 = $BALANCE_BITS;
+EOF
+
+cat > circom/widths.circom << EOF
+pragma circom 2.2.2;
+
+// $HEADER
+// $AMOUNT_COMMENT
+function NumAmountBits() {
+    return $AMOUNT_BITS;
+}
+
+// $BALANCE_COMMENT
+function NumBalanceBits() {
+    return $BALANCE_BITS;
+}
+EOF
+
+cat > contracts/src/widths.sol << EOF
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.20;
+
+// $HEADER. The verifiers have to be generated from circuits of the same widths.
+// $AMOUNT_COMMENT
+uint256 constant AMOUNT_BITS = $AMOUNT_BITS;
+// $BALANCE_COMMENT
+uint256 constant BALANCE_BITS = $BALANCE_BITS;
+EOF
+
+cat > private_deposit/src/proof/widths.rs << EOF
+// $HEADER
+// $AMOUNT_COMMENT
+pub const NUM_AMOUNT_BITS: usize = $AMOUNT_BITS;
+// $BALANCE_COMMENT
+pub const NUM_WITHDRAW_NEW_BITS: usize = $BALANCE_BITS;
+EOF
+
+# The MockConfidentialToken of contract-rs mirrors widths.sol
+cat > contract-rs/src/widths.rs << EOF
+// $HEADER
+// $AMOUNT_COMMENT
+pub const AMOUNT_BITS: usize = $AMOUNT_BITS;
+// $BALANCE_COMMENT
+pub const BALANCE_BITS: usize = $BALANCE_BITS;
+EOF
+
+CIRCUITS=("private_deposit" "private_withdraw" "private_transaction" "private_transaction_batched" "private_balance_threshold" "private_solvency")
 
 for CIRCUIT in "${CIRCUITS[@]}"; do
   echo "Creating circuit: $CIRCUIT"
@@ -11,7 +77,7 @@ for CIRCUIT in "${CIRCUITS[@]}"; do
   cd ../..
 done
 
-CIRCOM_CIRCUITS=("deposit" "withdraw" "transaction" "transaction_batched")
+CIRCOM_CIRCUITS=("deposit" "withdraw" "transaction" "transaction_batched" "action_queue")
 
 cd circom/main
 for CIRCUIT in "${CIRCOM_CIRCUITS[@]}"; do
@@ -19,3 +85,7 @@ for CIRCUIT in "${CIRCOM_CIRCUITS[@]}"; do
     circom -l .. --O2 --r1cs $CIRCUIT.circom
 done
 cd ../..
+
+# The verifiers depend on the circuits and their public inputs, thus they are regenerated together. create_solidity prints the fixture of testProcessMPC in contracts/test/conf_token.t.sol.
+echo "Creating the Solidity verifiers"
+cargo run --release --bin create_solidity
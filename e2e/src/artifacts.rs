@@ -0,0 +1,202 @@
+use alloy::{
+    network::TransactionBuilder,
+    primitives::{Address, Bytes, U256},
+    providers::{DynProvider, Provider as _},
+    rpc::types::TransactionRequest,
+    sol_types::SolValue,
+};
+use eyre::Context;
+use rust_contract::field_to_u256;
+use std::{collections::HashMap, path::Path};
+
+const ROOT: &str = std::env!("CARGO_MANIFEST_DIR");
+// The output directory of `forge build` in the contracts directory
+const OUT_PATH: &str = "/../contracts/out";
+
+// The bytecode of a contract compiled by forge, together with the positions of the library addresses it has to be linked with
+pub struct Artifact {
+    name: String,
+    // Without the 0x prefix. Unlinked libraries are placeholders, which are not valid hex.
+    bytecode: String,
+    // The source file and name of each library, with the byte offsets of its address in the bytecode
+    link_references: Vec<(String, String, Vec<usize>)>,
+}
+
+impl Artifact {
+    // Loads out/<file>/<name>.json, i.e., file is the name of the source file, e.g., conf_token.sol
+    pub fn load(file: &str, name: &str) -> eyre::Result<Self> {
+        let path = format!("{ROOT}{OUT_PATH}/{file}/{name}.json");
+        let json = std::fs::read_to_string(&path).with_context(|| {
+            format!("while reading {path}, run forge build in the contracts directory first")
+        })?;
+        let json: serde_json::Value =
+            serde_json::from_str(&json).with_context(|| format!("while parsing {path}"))?;
+
+        let bytecode = &json["bytecode"];
+        let object = bytecode["object"]
+            .as_str()
+            .ok_or_else(|| eyre::eyre!("{path} contains no bytecode"))?;
+
+        let mut link_references = Vec::new();
+        if let Some(files) = bytecode["linkReferences"].as_object() {
+            for (file, libraries) in files {
+                let libraries = libraries
+                    .as_object()
+                    .ok_or_else(|| eyre::eyre!("invalid link references in {path}"))?;
+                for (library, references) in libraries {
+                    let offsets = references
+                        .as_array()
+                        .ok_or_else(|| eyre::eyre!("invalid link references in {path}"))?
+                        .iter()
+                        .map(|reference| {
+                            reference["start"]
+                                .as_u64()
+                                .map(|start| start as usize)
+                                .ok_or_else(|| eyre::eyre!("invalid link reference in {path}"))
+                        })
+                        .collect::<eyre::Result<Vec<_>>>()?;
+                    link_references.push((file.to_owned(), library.to_owned(), offsets));
+                }
+            }
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            bytecode: object.trim_start_matches("0x").to_owned(),
+            link_references,
+        })
+    }
+
+    // The source file and name of each library the contract has to be linked with
+    pub fn libraries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.link_references
+            .iter()
+            .map(|(file, library, _)| (file.as_str(), library.as_str()))
+    }
+
+    // Replaces the placeholders with the addresses of the deployed libraries, which are keyed by source file and name
+    pub fn link(&self, libraries: &HashMap<(String, String), Address>) -> eyre::Result<Bytes> {
+        let mut bytecode = self.bytecode.clone();
+        for (file, library, offsets) in &self.link_references {
+            let address = libraries
+                .get(&(file.to_owned(), library.to_owned()))
+                .ok_or_else(|| eyre::eyre!("library {file}:{library} is not deployed"))?;
+            let address = alloy::hex::encode(address);
+            for offset in offsets {
+                // The offsets are in bytes, while the bytecode is hex encoded
+                let range = 2 * offset..2 * (offset + Address::len_bytes());
+                if range.end > bytecode.len() {
+                    eyre::bail!("link reference of {library} is out of range");
+                }
+                bytecode.replace_range(range, &address);
+            }
+        }
+        let bytecode = alloy::hex::decode(bytecode)
+            .with_context(|| format!("while decoding the bytecode of {}", self.name))?;
+        Ok(bytecode.into())
+    }
+}
+
+// Deploys contracts from the account of the provider. The libraries a contract links against are deployed first and reused for later contracts.
+pub struct Deployer {
+    provider: DynProvider,
+    libraries: HashMap<(String, String), Address>,
+}
+
+impl Deployer {
+    pub fn new(provider: DynProvider) -> Self {
+        Self {
+            provider,
+            libraries: HashMap::new(),
+        }
+    }
+
+    // The constructor arguments are ABI encoded and appended to the bytecode
+    pub async fn deploy(&mut self, file: &str, name: &str, args: &[u8]) -> eyre::Result<Address> {
+        let artifact = Artifact::load(file, name)?;
+        for (library_file, library) in artifact.libraries() {
+            let key = (library_file.to_owned(), library.to_owned());
+            if self.libraries.contains_key(&key) {
+                continue;
+            }
+            // The artifacts are named after the source file without its directory. Libraries which link other libraries are not supported.
+            let artifact_file = Path::new(library_file)
+                .file_name()
+                .and_then(|file| file.to_str())
+                .ok_or_else(|| eyre::eyre!("invalid source file {library_file}"))?;
+            let code = Artifact::load(artifact_file, library)?.link(&HashMap::new())?;
+            let address = self.send_deployment(library, code, &[]).await?;
+            self.libraries.insert(key, address);
+        }
+
+        let code = artifact.link(&self.libraries)?;
+        self.send_deployment(name, code, args).await
+    }
+
+    async fn send_deployment(&self, name: &str, code: Bytes, args: &[u8]) -> eyre::Result<Address> {
+        let mut code = code.to_vec();
+        code.extend_from_slice(args);
+        let tx = TransactionRequest::default().with_deploy_code(code);
+
+        let receipt = self
+            .provider
+            .send_transaction(tx)
+            .await
+            .context("while broadcasting to network")?
+            .get_receipt()
+            .await
+            .context("while registering watcher for transaction")?;
+        if !receipt.status() {
+            eyre::bail!("cannot deploy {name}: {receipt:?}");
+        }
+        let address = receipt
+            .contract_address
+            .ok_or_else(|| eyre::eyre!("no contract address in the receipt of {name}"))?;
+        tracing::info!("deployed {name} at {address}");
+        Ok(address)
+    }
+}
+
+// The addresses of the deployed contracts
+#[derive(Debug, Clone, Copy)]
+pub struct Deployment {
+    pub token: Address,
+    pub poseidon2: Address,
+    pub verifier: Address,
+    pub conf_token: Address,
+}
+
+impl Deployment {
+    // Deploys the token, Poseidon2, the Groth16 verifier and ConfidentialToken from the account of the provider, which becomes the owner of the token. The token starts without supply. Only `mpc` can process the queue and the users have to be whitelisted by it.
+    pub async fn deploy(
+        provider: DynProvider,
+        mpc: Address,
+        mpc_pks: &[ark_babyjubjub::EdwardsAffine; 3],
+    ) -> eyre::Result<Self> {
+        let mut deployer = Deployer::new(provider);
+
+        let token = deployer
+            .deploy("token.sol", "USDCToken", &U256::ZERO.abi_encode())
+            .await?;
+        let poseidon2 = deployer
+            .deploy("poseidon2.sol", "Poseidon2T2_BN254", &[])
+            .await?;
+        let verifier = deployer
+            .deploy("groth16_verifier.sol", "Groth16Verifier", &[])
+            .await?;
+
+        // The BabyJubJubElement structs are encoded as tuples
+        let [pk1, pk2, pk3] = mpc_pks.map(|pk| (field_to_u256(pk.x), field_to_u256(pk.y)));
+        let args = (verifier, poseidon2, token, mpc, pk1, pk2, pk3, false).abi_encode_params();
+        let conf_token = deployer
+            .deploy("conf_token.sol", "ConfidentialToken", &args)
+            .await?;
+
+        Ok(Self {
+            token,
+            poseidon2,
+            verifier,
+            conf_token,
+        })
+    }
+}
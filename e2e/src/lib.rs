@@ -0,0 +1,39 @@
+pub mod artifacts;
+pub mod mpc;
+
+use alloy::{
+    network::EthereumWallet,
+    node_bindings::AnvilInstance,
+    primitives::Address,
+    providers::{DynProvider, Provider as _, ProviderBuilder, WsConnect},
+    signers::local::PrivateKeySigner,
+};
+use eyre::Context;
+
+// The mock contract mirrors the widths of the circuits, create_circuits.sh generates both
+const _: () = assert!(
+    rust_contract::widths::AMOUNT_BITS == private_deposit::proof::widths::NUM_AMOUNT_BITS
+        && rust_contract::widths::BALANCE_BITS
+            == private_deposit::proof::widths::NUM_WITHDRAW_NEW_BITS
+);
+
+// Connects to anvil with the wallet of the given prefunded account
+pub async fn connect(
+    anvil: &AnvilInstance,
+    account: usize,
+) -> eyre::Result<(Address, DynProvider)> {
+    let key = anvil
+        .keys()
+        .get(account)
+        .ok_or_else(|| eyre::eyre!("anvil has no account {account}"))?;
+    let signer = PrivateKeySigner::from(key.to_owned());
+    let address = signer.address();
+
+    let provider = ProviderBuilder::new()
+        .wallet(EthereumWallet::from(signer))
+        .connect_ws(WsConnect::new(anvil.ws_endpoint()))
+        .await
+        .context("while connecting to RPC")?;
+
+    Ok((address, provider.erased()))
+}
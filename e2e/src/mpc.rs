@@ -0,0 +1,394 @@
+use alloy::primitives::Address;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::UniformRand;
+use co_circom::{ConstraintMatrices, ProvingKey};
+use co_noir_to_r1cs::{noir::r1cs, r1cs::noir_proof_schema::NoirProofScheme};
+use mpc_core::protocols::rep3::Rep3PrimeFieldShare;
+use private_deposit::{
+    data_structure::{DepositValuePlain, DepositValueShare, PrivateDeposit},
+    key::MapKey,
+    merkle_tree::{CommitmentTree, MerkleProof},
+    proof::{
+        NUM_BATCHED_TRANSACTIONS, TestConfig,
+        actionquery::{Action, AuditShare, public_inputs_to_contract_commitments},
+        policy::PolicyLimits,
+    },
+    reshare::{PendingAdditiveShare, PendingShare},
+    three_party::ThreeParty,
+};
+use rust_contract::{
+    TransactionInputRust,
+    auditor::{AuditBatch, Disclosure},
+    backend::ConfTokenBackend,
+    conf_token::{
+        ConfidentialToken::{ActionQuery, Ciphertext},
+        ConfidentialTokenContract,
+    },
+    mock::MockConfidentialToken,
+    simulation::{ACTION_DEPOSIT, ACTION_PRIVATE_WITHDRAW, ACTION_TRANSFER, ACTION_WITHDRAW},
+    u256_to_field,
+};
+use std::collections::HashMap;
+
+type F = ark_bn254::Fr;
+type Curve = ark_bn254::Bn254;
+
+// The seed of create_solidity, such that the proving key matches contracts/src/groth16_verifier.sol
+const SEED: &str = "SOLIDITY_DEPOSIT";
+
+// The three parties of the MPC network, simulated locally. Each party holds its shares of the balances and its key for decrypting the transfer amounts, the commitment tree is public.
+pub struct LocalMpc {
+    parties: ThreeParty,
+    proof_schema: NoirProofScheme<F>,
+    cs: ConstraintMatrices<F>,
+    pk: ProvingKey<Curve>,
+    sks: [ark_babyjubjub::Fr; 3],
+    maps: [PrivateDeposit<Address, DepositValueShare<F>>; 3],
+    // The shares of the transfers which were queued before a resharing, by action index. The current committee cannot decrypt their ciphertexts.
+    pending: [HashMap<usize, PendingShare>; 3],
+    // The index of the last processed action
+    action_index: usize,
+    tree: CommitmentTree,
+    // The contract address and the key of the auditor, see with_auditor
+    auditor: Option<(Address, ark_babyjubjub::EdwardsAffine)>,
+    audit_batches: Vec<[AuditBatch; 3]>,
+}
+
+// Converts the audit shares of a party into disclosures, the positions in the batch are replaced by the action indices of the queue. The amount commitments of transfers are read from the actions.
+fn disclosures(
+    indices: &[usize],
+    actions: &[ActionQuery],
+    shares: Vec<AuditShare<Address>>,
+) -> eyre::Result<Vec<(Disclosure, [F; 2])>> {
+    let action_index = |position: usize| {
+        indices
+            .get(position)
+            .copied()
+            .ok_or_else(|| eyre::eyre!("no action at position {position} of the batch"))
+    };
+    shares
+        .into_iter()
+        .map(|share| {
+            Ok(match share {
+                AuditShare::SenderBalance(position, user, amount, blinding) => (
+                    Disclosure::SenderBalance {
+                        action_index: action_index(position)?,
+                        user,
+                    },
+                    [amount, blinding],
+                ),
+                AuditShare::ReceiverBalance(position, user, amount, blinding) => (
+                    Disclosure::ReceiverBalance {
+                        action_index: action_index(position)?,
+                        user,
+                    },
+                    [amount, blinding],
+                ),
+                AuditShare::TransferAmount(position, amount, blinding) => (
+                    Disclosure::TransferAmount {
+                        action_index: action_index(position)?,
+                        commitment: u256_to_field(actions[position].amount)?,
+                    },
+                    [amount, blinding],
+                ),
+            })
+        })
+        .collect()
+}
+
+impl LocalMpc {
+    pub fn new() -> eyre::Result<Self> {
+        let mut seed = [0u8; 32];
+        seed[..SEED.len()].copy_from_slice(SEED.as_bytes());
+        let mut parties = ThreeParty::from_seed(NUM_BATCHED_TRANSACTIONS * 2, seed);
+
+        // Has to be the first use of the rng, the same as in create_solidity
+        let pa = TestConfig::get_transaction_batched_program_artifact()?;
+        let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, parties.rng())?;
+
+        let sks = std::array::from_fn(|_| ark_babyjubjub::Fr::rand(parties.rng()));
+
+        Ok(Self {
+            parties,
+            proof_schema,
+            cs,
+            pk,
+            sks,
+            maps: std::array::from_fn(|_| PrivateDeposit::new()),
+            pending: Default::default(),
+            action_index: 0,
+            tree: CommitmentTree::new(),
+            auditor: None,
+            audit_batches: Vec::new(),
+        })
+    }
+
+    // Each processed batch is disclosed to the auditor, i.e., the key of auditor_pk in the contract
+    pub fn with_auditor(
+        mut self,
+        contract_address: Address,
+        auditor_pk: ark_babyjubjub::EdwardsAffine,
+    ) -> Self {
+        self.auditor = Some((contract_address, auditor_pk));
+        self
+    }
+
+    // The disclosures of the parties for each batch which was processed on chain
+    pub fn audit_batches(&self) -> &[[AuditBatch; 3]] {
+        &self.audit_batches
+    }
+
+    // An in-memory contract whose verifier accepts the proofs of the parties, the MPC network sends from mpc_address
+    pub fn mock_contract(&self, mpc_address: Address) -> MockConfidentialToken {
+        MockConfidentialToken::new(mpc_address, &self.pk.vk)
+    }
+
+    pub fn public_keys(&self) -> [ark_babyjubjub::EdwardsAffine; 3] {
+        self.sks
+            .map(|sk| (ark_babyjubjub::EdwardsAffine::generator() * sk).into_affine())
+    }
+
+    // The plain balances, reconstructed from the shares of the parties
+    pub fn balances(&self) -> eyre::Result<PrivateDeposit<Address, DepositValuePlain<F>>> {
+        Ok(PrivateDeposit::reconstruct(self.maps.clone())?)
+    }
+
+    pub fn balance_root(&self) -> F {
+        self.tree.root()
+    }
+
+    // The inclusion proof of the commitment of a user against the balance root
+    pub fn balance_proof(&self, user: Address) -> eyre::Result<MerkleProof> {
+        self.tree.proof(user.to_field())
+    }
+
+    // The contract does not prove the balance root, it stores what the MPC network posts. Checks that the posted root is the one of the commitment tree and that the inclusion proofs of the users show their commitments in the balanceCommitments mapping, users without a commitment have none in the tree.
+    pub async fn check_balance_root<B: ConfTokenBackend>(
+        &self,
+        contract: &B,
+        users: &[Address],
+    ) -> eyre::Result<()> {
+        let root = contract.get_balance_root().await?;
+        if root != self.tree.root() {
+            eyre::bail!(
+                "The contract stores the balance root {root}, but the tree has {}",
+                self.tree.root()
+            );
+        }
+        for user in users {
+            let proof = self.balance_proof(*user)?;
+            if !proof.verify(root) {
+                eyre::bail!(
+                    "The inclusion proof of {user} does not verify against the balance root"
+                );
+            }
+            let commitment = contract.get_balance_commitment(*user).await?;
+            if proof.commitment.unwrap_or_default() != commitment {
+                eyre::bail!(
+                    "The tree has the commitment {:?} for {user}, but the contract has {commitment}",
+                    proof.commitment
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // The additive shares of each party of the amount and blinding of a transfer. If the transfer was queued before a resharing, they are the reshared shares of the action index, otherwise each party decrypts its part of the ciphertext.
+    fn additive_shares(
+        &self,
+        action_index: usize,
+        ciphertext: &Ciphertext,
+    ) -> eyre::Result<[[F; 2]; 3]> {
+        let mut additive = [[F::default(); 2]; 3];
+        for (i, (shares, sk)) in additive.iter_mut().zip(self.sks).enumerate() {
+            *shares = match self.pending[i].get(&action_index) {
+                Some(pending) => [pending.amount.a, pending.amount_blinding.a],
+                None => ConfidentialTokenContract::decrypt_share(ciphertext.to_owned(), sk, i)?,
+            };
+        }
+        Ok(additive)
+    }
+
+    // The replicated shares of a party consist of its own and the previous additive share, which the parties would exchange over the network
+    fn decrypt_shares(
+        &self,
+        action_index: usize,
+        ciphertext: &Ciphertext,
+    ) -> eyre::Result<[(Rep3PrimeFieldShare<F>, Rep3PrimeFieldShare<F>); 3]> {
+        let additive = self.additive_shares(action_index, ciphertext)?;
+        Ok(std::array::from_fn(|i| {
+            let [amount, blinding] = additive[i];
+            let [amount_prev, blinding_prev] = additive[(i + 2) % 3];
+            (
+                Rep3PrimeFieldShare::new(amount, amount_prev),
+                Rep3PrimeFieldShare::new(blinding, blinding_prev),
+            )
+        }))
+    }
+
+    // Processes the oldest actions of the queue: the parties prove the batch under the policy limits of the contract and post the new commitments together with the new root of the commitment tree. Returns the number of processed actions. Proving blocks the thread, thus this needs the multi-threaded tokio runtime. If the contract rejects the batch, the local state is not rolled back.
+    pub async fn process_batch<B: ConfTokenBackend>(
+        &mut self,
+        contract: &B,
+    ) -> eyre::Result<usize> {
+        let (indices, actions, ciphertexts) = contract.read_queue(NUM_BATCHED_TRANSACTIONS).await?;
+        let (max_transfer, max_balance) = contract.get_policy_limits().await?;
+        let limits = PolicyLimits::new(max_transfer, max_balance)?;
+
+        let mut queues: [Vec<Action<Address>>; 3] = Default::default();
+        for ((index, action), ciphertext) in indices.iter().zip(&actions).zip(&ciphertexts) {
+            match action.action {
+                ACTION_DEPOSIT => {
+                    let amount = u256_to_field(action.amount)?;
+                    for queue in queues.iter_mut() {
+                        queue.push(Action::Deposit(action.receiver, amount));
+                    }
+                }
+                ACTION_WITHDRAW => {
+                    let amount = u256_to_field(action.amount)?;
+                    for queue in queues.iter_mut() {
+                        queue.push(Action::Withdraw(action.sender, amount));
+                    }
+                }
+                ACTION_TRANSFER => {
+                    let shares = self.decrypt_shares(*index, ciphertext)?;
+                    for (queue, (amount, blinding)) in queues.iter_mut().zip(shares) {
+                        queue.push(Action::Transfer(
+                            action.sender,
+                            action.receiver,
+                            amount,
+                            blinding,
+                        ));
+                    }
+                }
+                ACTION_PRIVATE_WITHDRAW => {
+                    let shares = self.decrypt_shares(*index, ciphertext)?;
+                    for (queue, (amount, blinding)) in queues.iter_mut().zip(shares) {
+                        queue.push(Action::PrivateWithdraw(action.sender, amount, blinding));
+                    }
+                }
+                action => eyre::bail!("Unexpected action {action} in the queue"),
+            }
+        }
+        for queue in queues.iter_mut() {
+            queue.resize(NUM_BATCHED_TRANSACTIONS, Action::Dummy);
+        }
+        let queue = queues[0].clone();
+
+        // Do the MPC work
+        let [queue0, queue1, queue2] = queues;
+        let [map0, map1, map2] = self.maps.each_mut();
+        let (proof_schema, cs, pk) = (&self.proof_schema, &self.cs, &self.pk);
+        let parties = &mut self.parties;
+        let outputs = tokio::task::block_in_place(|| {
+            parties.run(
+                NUM_BATCHED_TRANSACTIONS,
+                [(map0, queue0), (map1, queue1), (map2, queue2)],
+                |(map, queue), nets, rep3_states| {
+                    let (sender_new, receiver_new, proof, public_inputs, _proof_duration) = map
+                        .process_queue_with_groth16_proof(
+                            queue.clone(),
+                            &limits,
+                            proof_schema,
+                            cs,
+                            pk,
+                            nets.try_into()?,
+                            rep3_states.try_into()?,
+                        )?;
+                    let payouts = PrivateDeposit::open_payouts(&queue, &receiver_new, &nets[0])?;
+                    let audit_shares =
+                        PrivateDeposit::audit_shares(&queue, &sender_new, &receiver_new)?;
+                    Ok(((proof, public_inputs, payouts), audit_shares))
+                },
+            )
+        })?;
+        let [(public0, audit0), (public1, audit1), (public2, audit2)] = outputs;
+        if public0 != public1 || public0 != public2 {
+            eyre::bail!("Parties disagree on the proof, the public inputs or the payouts");
+        }
+        let (proof, public_inputs, payouts) = public0;
+
+        let commitment = public_inputs_to_contract_commitments(&queue, &public_inputs, &payouts)?;
+        let balance_root = self.tree.apply_batch(&queue, &public_inputs)?;
+        let inputs = TransactionInputRust {
+            action_index: indices.clone(),
+            commitment,
+            balance_root,
+        };
+        contract
+            .process_mpc(inputs.try_into()?, proof.into())
+            .await?;
+        for pending in self.pending.iter_mut() {
+            pending.retain(|index, _| !indices.contains(index));
+        }
+        if let Some(last) = indices.last() {
+            self.action_index = *last;
+        }
+
+        // Only batches which were processed on chain are disclosed, such that each action index is encrypted once
+        if let Some((contract_address, auditor_pk)) = self.auditor {
+            let batches: [AuditBatch; 3] = [audit0, audit1, audit2]
+                .into_iter()
+                .zip(self.sks)
+                .enumerate()
+                .map(|(party, (shares, sk))| {
+                    AuditBatch::encrypt(
+                        contract_address,
+                        party,
+                        &sk,
+                        auditor_pk,
+                        disclosures(&indices, &actions, shares)?,
+                    )
+                })
+                .collect::<eyre::Result<Vec<_>>>()?
+                .try_into()
+                .expect("three parties");
+            self.audit_batches.push(batches);
+        }
+
+        Ok(indices.len())
+    }
+
+    // Moves the shares to a new committee with fresh keys, see private_deposit::reshare. The old committee decrypts its shares of the transfers which are still in the queue and reshares them together with the balances. Rotating the keys in the contract (setMpcKeys) is up to the caller, see public_keys.
+    pub async fn reshare<B: ConfTokenBackend>(&mut self, contract: &B) -> eyre::Result<()> {
+        let queue_size = contract.get_action_queue_size().await?;
+        let (indices, actions, ciphertexts) = contract.read_queue(queue_size).await?;
+
+        let mut pending: [Vec<PendingAdditiveShare>; 3] = Default::default();
+        for ((index, action), ciphertext) in indices.iter().zip(&actions).zip(&ciphertexts) {
+            if !matches!(action.action, ACTION_TRANSFER | ACTION_PRIVATE_WITHDRAW) {
+                continue;
+            }
+            let additive = self.additive_shares(*index, ciphertext)?;
+            for (pending, [amount, amount_blinding]) in pending.iter_mut().zip(additive) {
+                pending.push(PendingAdditiveShare {
+                    action_index: *index,
+                    amount,
+                    amount_blinding,
+                });
+            }
+        }
+
+        // Each old party sends one message to each new party
+        let rng = self.parties.rng();
+        let mut messages: [Vec<_>; 3] = Default::default();
+        for (party, (map, pending)) in self.maps.iter().zip(&pending).enumerate() {
+            let sent = map.reshare(party, self.action_index as u64, pending, rng)?;
+            for (messages, message) in messages.iter_mut().zip(sent) {
+                messages.push(message);
+            }
+        }
+
+        for (party, messages) in messages.into_iter().enumerate() {
+            let messages = messages.try_into().expect("three old parties");
+            let (map, pending, _) = PrivateDeposit::from_reshare(party, messages)?;
+            self.maps[party] = map;
+            self.pending[party] = pending
+                .into_iter()
+                .map(|pending| (pending.action_index, pending))
+                .collect();
+        }
+        self.sks = std::array::from_fn(|_| ark_babyjubjub::Fr::rand(rng));
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use alloy::{
+    node_bindings::Anvil,
+    primitives::{Address, U256},
+};
+use ark_ff::UniformRand;
+use e2e::{artifacts::Deployment, mpc::LocalMpc};
+use mpc_core::protocols::rep3;
+use private_deposit::proof::plain_commitment;
+use rust_contract::{conf_token::ConfidentialTokenContract, token::USDCTokenContract};
+
+type F = ark_bn254::Fr;
+
+// Compares the balance of a user in the shares of the MPC network with the expected amount and the commitment on chain
+async fn assert_balance(
+    mpc: &LocalMpc,
+    contract: &ConfidentialTokenContract,
+    user: Address,
+    amount: u64,
+) -> eyre::Result<()> {
+    let balances = mpc.balances()?;
+    let balance = balances
+        .get(&user)
+        .ok_or_else(|| eyre::eyre!("the MPC network has no balance of {user}"))?;
+    assert_eq!(balance.amount, F::from(amount));
+    assert_eq!(
+        contract.get_balance_commitment(user).await?,
+        plain_commitment(balance.amount, balance.blinding)
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "This test needs anvil and the artifacts of forge build"]
+async fn deposit_transfer_withdraw_test() -> eyre::Result<()> {
+    let mut rng = rand::thread_rng();
+    let anvil = Anvil::new()
+        .args(["--disable-code-size-limit", "--disable-block-gas-limit"])
+        .try_spawn()?;
+    let mut mpc = LocalMpc::new()?;
+    let mpc_pks = mpc.public_keys();
+
+    let (_, deployer_provider) = e2e::connect(&anvil, 0).await?;
+    let (mpc_address, mpc_provider) = e2e::connect(&anvil, 1).await?;
+    let (alice, alice_provider) = e2e::connect(&anvil, 2).await?;
+    let (bob, bob_provider) = e2e::connect(&anvil, 3).await?;
+
+    let deployment = Deployment::deploy(deployer_provider.clone(), mpc_address, &mpc_pks).await?;
+    let mpc_contract =
+        ConfidentialTokenContract::new(deployment.conf_token, mpc_provider, mpc_address);
+    let alice_contract =
+        ConfidentialTokenContract::new(deployment.conf_token, alice_provider.clone(), alice);
+    let bob_contract = ConfidentialTokenContract::new(deployment.conf_token, bob_provider, bob);
+    let token = USDCTokenContract::new(deployment.token, deployer_provider);
+
+    // Register the users
+    mpc_contract
+        .withelist_addresses_for_demo(vec![alice, bob])
+        .await?;
+    token.mint(alice, U256::from(1000)).await?;
+    USDCTokenContract::new(deployment.token, alice_provider)
+        .approve(deployment.conf_token, U256::MAX)
+        .await?;
+
+    // Deposit
+    alice_contract.deposit(F::from(100u64)).await?;
+    assert_eq!(mpc.process_batch(&mpc_contract).await?, 1);
+    assert_balance(&mpc, &mpc_contract, alice, 100).await?;
+    assert_eq!(token.balance_of(alice).await?, U256::from(900));
+    assert_eq!(
+        token.balance_of(deployment.conf_token).await?,
+        U256::from(100)
+    );
+
+    // Transfer, the amount is only known to alice, bob and the MPC network
+    let amount = F::from(40u64);
+    let blinding = F::rand(&mut rng);
+    let amount_shares = rep3::share_field_element(amount, &mut rng).map(|share| share.a);
+    let blinding_shares = rep3::share_field_element(blinding, &mut rng).map(|share| share.a);
+    let ciphertext = ConfidentialTokenContract::encrypt_shares(
+        amount_shares,
+        blinding_shares,
+        &mpc_pks,
+        &mut rng,
+    );
+    alice_contract
+        .transfer(bob, plain_commitment(amount, blinding), ciphertext)
+        .await?;
+    assert_eq!(mpc.process_batch(&mpc_contract).await?, 1);
+    assert_balance(&mpc, &mpc_contract, alice, 60).await?;
+    assert_balance(&mpc, &mpc_contract, bob, 40).await?;
+    assert_eq!(
+        token.balance_of(deployment.conf_token).await?,
+        U256::from(100)
+    );
+
+    // Withdraw
+    bob_contract.withdraw(F::from(25u64)).await?;
+    assert_eq!(mpc.process_batch(&mpc_contract).await?, 1);
+    assert_balance(&mpc, &mpc_contract, alice, 60).await?;
+    assert_balance(&mpc, &mpc_contract, bob, 15).await?;
+    assert_eq!(token.balance_of(bob).await?, U256::from(25));
+    assert_eq!(
+        token.balance_of(deployment.conf_token).await?,
+        U256::from(75)
+    );
+
+    // Only the dummy action is left and the contract stores the root of the commitments of the MPC network
+    assert_eq!(mpc_contract.get_action_queue_size().await?, 1);
+    mpc.check_balance_root(&mpc_contract, &[alice, bob]).await?;
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use alloy::primitives::{Address, U256};
+use ark_ff::UniformRand;
+use e2e::mpc::LocalMpc;
+use mpc_core::protocols::rep3;
+use private_deposit::proof::plain_commitment;
+use rand::Rng;
+use rust_contract::{
+    auditor::{Auditor, DisclosedValue, Disclosure},
+    backend::ConfTokenBackend,
+    conf_token::ConfidentialTokenContract,
+    mock::MockConfidentialToken,
+};
+
+type F = ark_bn254::Fr;
+
+// Decrypts the disclosures of the last processed batch and checks them against the commitments of the contract
+async fn audit_last_batch(
+    mpc: &LocalMpc,
+    auditor: &Auditor,
+    contract: &MockConfidentialToken,
+) -> eyre::Result<Vec<DisclosedValue>> {
+    let batches = mpc
+        .audit_batches()
+        .last()
+        .ok_or_else(|| eyre::eyre!("no batch was disclosed"))?;
+    let values = auditor.decrypt(batches.clone())?;
+    assert!(Auditor::reconcile(contract, &values).await?.is_empty());
+    Ok(values)
+}
+
+// The parties disclose every processed batch to the auditor, who decrypts the balances and the transfer amounts of the actions and checks them against the contract
+#[tokio::test(flavor = "multi_thread")]
+async fn auditor_test() -> eyre::Result<()> {
+    let mut rng = rand::thread_rng();
+    let mpc_address = Address::from(rng.r#gen::<[u8; 20]>());
+    let contract_address = Address::from(rng.r#gen::<[u8; 20]>());
+    let alice = Address::from(rng.r#gen::<[u8; 20]>());
+    let bob = Address::from(rng.r#gen::<[u8; 20]>());
+
+    let mpc = LocalMpc::new()?;
+    let mpc_pks = mpc.public_keys();
+    let auditor = Auditor::random(mpc_pks, &mut rng);
+    let mut mpc = mpc.with_auditor(contract_address, auditor.public_key());
+    let contract = mpc.mock_contract(mpc_address);
+    let alice_contract = contract.as_user(alice);
+    contract.mint(alice, U256::from(1000));
+
+    // Deposit
+    let deposit_index = alice_contract.deposit(F::from(100u64)).await?;
+    assert_eq!(mpc.process_batch(&contract).await?, 1);
+    let values = audit_last_batch(&mpc, &auditor, &contract).await?;
+    assert_eq!(values.len(), 1);
+    assert_eq!(
+        values[0].disclosure,
+        Disclosure::ReceiverBalance {
+            action_index: deposit_index,
+            user: alice
+        }
+    );
+    assert_eq!(values[0].amount, F::from(100u64));
+
+    // Transfer
+    let amount = F::from(40u64);
+    let blinding = F::rand(&mut rng);
+    let amount_shares = rep3::share_field_element(amount, &mut rng).map(|share| share.a);
+    let blinding_shares = rep3::share_field_element(blinding, &mut rng).map(|share| share.a);
+    let ciphertext = ConfidentialTokenContract::encrypt_shares(
+        amount_shares,
+        blinding_shares,
+        &mpc_pks,
+        &mut rng,
+    );
+    let commitment = plain_commitment(amount, blinding);
+    let transfer_index = alice_contract.transfer(bob, commitment, ciphertext).await?;
+    assert_eq!(mpc.process_batch(&contract).await?, 1);
+    let values = audit_last_batch(&mpc, &auditor, &contract).await?;
+    let disclosed = values
+        .iter()
+        .map(|value| (value.disclosure, value.amount, value.blinding))
+        .collect::<Vec<_>>();
+    let balances = mpc.balances()?;
+    assert_eq!(
+        disclosed,
+        [
+            (
+                Disclosure::SenderBalance {
+                    action_index: transfer_index,
+                    user: alice
+                },
+                F::from(60u64),
+                balances.get(&alice).unwrap().blinding
+            ),
+            (
+                Disclosure::ReceiverBalance {
+                    action_index: transfer_index,
+                    user: bob
+                },
+                F::from(40u64),
+                balances.get(&bob).unwrap().blinding
+            ),
+            (
+                Disclosure::TransferAmount {
+                    action_index: transfer_index,
+                    commitment
+                },
+                amount,
+                blinding
+            ),
+        ]
+    );
+
+    // Every processed batch was disclosed, nothing else
+    assert_eq!(mpc.audit_batches().len(), 2);
+    assert_eq!(contract.get_action_queue_size().await?, 1);
+
+    Ok(())
+}
+
+// A transfer queued before the resharing is encrypted to the keys of the old committee, the new committee processes it with the reshared shares
+#[tokio::test(flavor = "multi_thread")]
+async fn reshare_pending_test() -> eyre::Result<()> {
+    let mut rng = rand::thread_rng();
+    let mpc_address = Address::from(rng.r#gen::<[u8; 20]>());
+    let alice = Address::from(rng.r#gen::<[u8; 20]>());
+    let bob = Address::from(rng.r#gen::<[u8; 20]>());
+
+    let mut mpc = LocalMpc::new()?;
+    let contract = mpc.mock_contract(mpc_address);
+    let alice_contract = contract.as_user(alice);
+    contract.mint(alice, U256::from(1000));
+
+    alice_contract.deposit(F::from(100u64)).await?;
+    assert_eq!(mpc.process_batch(&contract).await?, 1);
+
+    // Transfer to the old committee
+    let old_pks = mpc.public_keys();
+    let amount = F::from(40u64);
+    let blinding = F::rand(&mut rng);
+    let amount_shares = rep3::share_field_element(amount, &mut rng).map(|share| share.a);
+    let blinding_shares = rep3::share_field_element(blinding, &mut rng).map(|share| share.a);
+    let ciphertext = ConfidentialTokenContract::encrypt_shares(
+        amount_shares,
+        blinding_shares,
+        &old_pks,
+        &mut rng,
+    );
+    alice_contract
+        .transfer(bob, plain_commitment(amount, blinding), ciphertext)
+        .await?;
+
+    mpc.reshare(&contract).await?;
+    assert_ne!(mpc.public_keys(), old_pks);
+
+    assert_eq!(mpc.process_batch(&contract).await?, 1);
+    let balances = mpc.balances()?;
+    assert_eq!(balances.get(&alice).unwrap().amount, F::from(60u64));
+    assert_eq!(balances.get(&bob).unwrap().amount, F::from(40u64));
+    assert_eq!(
+        contract.get_balance_commitment(bob).await?,
+        plain_commitment(F::from(40u64), balances.get(&bob).unwrap().blinding)
+    );
+
+    // Deposits after the resharing are processed by the new committee as usual
+    alice_contract.deposit(F::from(5u64)).await?;
+    assert_eq!(mpc.process_batch(&contract).await?, 1);
+    assert_eq!(mpc.balances()?.get(&alice).unwrap().amount, F::from(65u64));
+
+    // The posted root matches the commitments of the contract, also for a user without a balance
+    let carol = Address::from(rng.r#gen::<[u8; 20]>());
+    mpc.check_balance_root(&contract, &[alice, bob, carol])
+        .await?;
+
+    Ok(())
+}
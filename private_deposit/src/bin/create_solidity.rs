@@ -3,24 +3,36 @@ use ark_ff::{UniformRand, Zero};
 use co_circom::{ConstraintMatrices, ProvingKey};
 use co_noir::Bn254;
 use co_noir_to_r1cs::{
-    circom::solidity_verifier, noir::r1cs, r1cs::noir_proof_schema::NoirProofScheme,
+    circom::solidity_verifier,
+    noir::{r1cs, ultrahonk},
+    r1cs::noir_proof_schema::NoirProofScheme,
 };
+use eyre::Context;
 use itertools::izip;
-use mpc_core::{
-    gadgets::poseidon2::Poseidon2,
-    protocols::rep3::{self, Rep3State, conversion::A2BType},
-};
-use mpc_net::local::LocalNetwork;
+use mpc_core::{gadgets::poseidon2::Poseidon2, protocols::rep3};
 use private_deposit::{
     data_structure::{DepositValue, PrivateDeposit},
-    proof::{NUM_BATCHED_TRANSACTIONS, TestConfig, actionquery::Action},
+    proof::{
+        NUM_BATCHED_TRANSACTIONS, TestConfig,
+        actionquery::Action,
+        policy::{NUM_POLICY_INPUTS, PolicyLimits},
+        threshold,
+        transaction_batched::NUM_COMMITMENTS,
+    },
+    three_party::ThreeParty,
 };
 use rand::{CryptoRng, Rng};
-use rand_chacha::{ChaCha12Rng, rand_core::SeedableRng};
-use std::{fs::File, io::Write, process::ExitCode, thread};
+use std::{
+    fs::File,
+    io::Write,
+    process::{Command, ExitCode},
+};
 
 const ROOT: &str = std::env!("CARGO_MANIFEST_DIR");
 const PATH: &str = "/../contracts/src/groth16_verifier.sol";
+const CONF_TOKEN_PATH: &str = "/../contracts/src/conf_token.sol";
+const THRESHOLD_PATH: &str = "/../contracts/src/balance_threshold_verifier.sol";
+const ULTRAHONK_PATH: &str = "/../contracts/src/ultrahonk_verifier.sol";
 const SEED: &str = "SOLIDITY_DEPOSIT";
 
 type F = ark_bn254::Fr;
@@ -65,135 +77,71 @@ fn create_action_queues<R: Rng + CryptoRng>(
     println!();
 
     // Create the action queue
-    let mut action_queue_0: Vec<
-        Action<ark_ff::Fp<ark_ff::MontBackend<ark_bn254::FrConfig, 4>, 4>>,
-    > = Vec::with_capacity(NUM_BATCHED_TRANSACTIONS);
-    let mut action_queue_1 = Vec::with_capacity(NUM_BATCHED_TRANSACTIONS);
-    let mut action_queue_2 = Vec::with_capacity(NUM_BATCHED_TRANSACTIONS);
-
-    // Deposit to key1
-    action_queue_0.push(Action::Deposit(key1, amount));
-    action_queue_1.push(Action::Deposit(key1, amount));
-    action_queue_2.push(Action::Deposit(key1, amount));
-
-    // Transfer from key1 to key2
-    action_queue_0.push(Action::Transfer(
-        key1,
-        key2,
-        amount_share[0],
-        amount_blinding_share[0],
-    ));
-    action_queue_1.push(Action::Transfer(
-        key1,
-        key2,
-        amount_share[1],
-        amount_blinding_share[1],
-    ));
-    action_queue_2.push(Action::Transfer(
-        key1,
-        key2,
-        amount_share[2],
-        amount_blinding_share[2],
-    ));
-
-    // Withdraw from key2
-    action_queue_0.push(Action::Withdraw(key2, amount));
-    action_queue_1.push(Action::Withdraw(key2, amount));
-    action_queue_2.push(Action::Withdraw(key2, amount));
-
-    // Batch queues
-    debug_assert_eq!(action_queue_0.len(), action_queue_1.len());
-    debug_assert_eq!(action_queue_0.len(), action_queue_2.len());
-    for _ in action_queue_0.len()..NUM_BATCHED_TRANSACTIONS {
-        action_queue_0.push(Action::Dummy);
-        action_queue_1.push(Action::Dummy);
-        action_queue_2.push(Action::Dummy);
+    let mut action_queues: [Vec<Action<F>>; 3] = Default::default();
+    for (queue, amount_share, amount_blinding_share) in
+        izip!(&mut action_queues, amount_share, amount_blinding_share)
+    {
+        // Deposit to key1
+        queue.push(Action::Deposit(key1, amount));
+        // Transfer from key1 to key2
+        queue.push(Action::Transfer(
+            key1,
+            key2,
+            amount_share,
+            amount_blinding_share,
+        ));
+        // Withdraw from key2
+        queue.push(Action::Withdraw(key2, amount));
+        // Batch queues
+        queue.resize(NUM_BATCHED_TRANSACTIONS, Action::Dummy);
     }
 
-    [action_queue_0, action_queue_1, action_queue_2]
+    action_queues
 }
 
 // This function creates inputs to the solidity verifier test
 #[expect(clippy::identity_op)]
-fn test_process_mpc<R: Rng + CryptoRng>(
+fn test_process_mpc(
     proof_schema: &NoirProofScheme<F>,
     cs: &ConstraintMatrices<F>,
     pk: &ProvingKey<Curve>,
     mpc_keys: [ark_babyjubjub::EdwardsAffine; 3],
-    rng: &mut R,
+    parties: &mut ThreeParty,
 ) -> eyre::Result<ExitCode> {
     let alice = F::from(1u64);
     let bob = F::from(2u64);
     let amount = F::from(1000000000000000000u64); // 1 ETH
-    let amount_blinding = F::rand(rng);
+    let amount_blinding = F::rand(parties.rng());
 
     // prepare the map
     let plain_map: PrivateDeposit<F, DepositValue<F>> = PrivateDeposit::new();
 
-    // Init networks
-    let mut test_networks0 = Vec::with_capacity(NUM_BATCHED_TRANSACTIONS * 2);
-    let mut test_networks1 = Vec::with_capacity(NUM_BATCHED_TRANSACTIONS * 2);
-    let mut test_networks2 = Vec::with_capacity(NUM_BATCHED_TRANSACTIONS);
-    for _ in 0..(NUM_BATCHED_TRANSACTIONS * 2) {
-        let [net0, net1, net2] = LocalNetwork::new(3).try_into().unwrap();
-        test_networks0.push(net0);
-        test_networks1.push(net1);
-        test_networks2.push(net2);
-    }
-
     // Create shares
-    let mut map_shares = plain_map.share(rng);
+    let [map0, map1, map2] = parties.share_map(&plain_map);
 
     // Get action queues
-    let action_queues = create_action_queues(alice, bob, amount, amount_blinding, mpc_keys, rng);
+    let [queue0, queue1, queue2] =
+        create_action_queues(alice, bob, amount, amount_blinding, mpc_keys, parties.rng());
 
     // Do the MPC work
-    let result = thread::scope(|scope| {
-        let mut handles = Vec::with_capacity(3);
-        for (nets, map, transaction) in izip!(
-            [
-                &mut test_networks0,
-                &mut test_networks1,
-                &mut test_networks2
-            ],
-            &mut map_shares,
-            action_queues
-        ) {
-            let handle = scope.spawn(move || {
-                let mut rep3_states = Vec::with_capacity(nets.len() / 2);
-                for net in nets.iter().take(nets.len() / 2) {
-                    rep3_states.push(Rep3State::new(net, A2BType::default())?);
-                }
-
-                let (_sender_read, _receiver_read, proof, public_inputs, _proof_time) = map
-                    .process_queue_with_groth16_proof(
-                        transaction,
-                        proof_schema,
-                        cs,
-                        pk,
-                        nets.as_slice().try_into().unwrap(),
-                        rep3_states.as_mut_slice().try_into().unwrap(),
-                    )?;
-
-                Result::<_, eyre::Report>::Ok((proof, public_inputs))
-            });
-            handles.push(handle);
-        }
-
-        let (proof0, public_inputs0) = handles
-            .remove(0)
-            .join()
-            .map_err(|_| eyre::eyre!("A thread panicked while processing a transaction"))??;
-        for handle in handles {
-            let (proof, public_inputs) = handle
-                .join()
-                .map_err(|_| eyre::eyre!("A thread panicked while processing a transaction"))??;
-            assert_eq!(proof, proof0);
-            assert_eq!(public_inputs, public_inputs0);
-        }
-        Result::<_, eyre::Report>::Ok((proof0, public_inputs0))
-    });
-    let (proof, public_inputs) = result?;
+    let (proof, public_inputs) = parties.run_public(
+        NUM_BATCHED_TRANSACTIONS,
+        [(map0, queue0), (map1, queue1), (map2, queue2)],
+        |(mut map, queue), nets, rep3_states| {
+            let (_sender_read, _receiver_read, proof, public_inputs, _proof_time) = map
+                .process_queue_with_groth16_proof(
+                    queue,
+                    // The default limits of the smart contract
+                    &PolicyLimits::default(),
+                    proof_schema,
+                    cs,
+                    pk,
+                    nets.try_into()?,
+                    rep3_states.try_into()?,
+                )?;
+            Ok((proof, public_inputs))
+        },
+    )?;
 
     // Verifiy the results
     if !r1cs::verify(&pk.vk, &proof, &public_inputs)? {
@@ -237,6 +185,63 @@ fn test_process_mpc<R: Rng + CryptoRng>(
     Ok(ExitCode::SUCCESS)
 }
 
+// This function creates inputs to the test of the balance threshold verifier, a proof that the balance of alice lies in [min, max]
+fn test_balance_threshold(
+    proof_schema: &NoirProofScheme<F>,
+    cs: &ConstraintMatrices<F>,
+    pk: &ProvingKey<Curve>,
+    parties: &mut ThreeParty,
+) -> eyre::Result<()> {
+    let alice = F::from(1u64);
+    let amount = F::from(1000000000000000000u64); // 1 ETH
+    let amount_blinding = F::rand(parties.rng());
+    let min = F::from(1000000000000000u64); // 0.001 ETH
+    let max = F::from(10000000000000000000u64); // 10 ETH
+
+    let mut plain_map: PrivateDeposit<F, DepositValue<F>> = PrivateDeposit::new();
+    plain_map.insert(alice, DepositValue::new(amount, amount_blinding));
+    let [map0, map1, map2] = parties.share_map(&plain_map);
+
+    let (proof, public_inputs) =
+        parties.run_public(1, [map0, map1, map2], |map, nets, rep3_states| {
+            map.balance_threshold_with_groth16_proof(
+                &alice,
+                min,
+                max,
+                proof_schema,
+                cs,
+                pk,
+                &nets[0],
+                &nets[1],
+                &mut rep3_states[0],
+            )
+        })?;
+
+    let commitment = public_inputs[2];
+    if !threshold::verify_balance_threshold(&pk.vk, &proof, commitment, min, max)? {
+        return Err(eyre::eyre!("Balance threshold proof verification failed"));
+    }
+
+    let (ax, ay) = proof.a.xy().unwrap_or_default();
+    let (bx, by) = proof.b.xy().unwrap_or_default();
+    let (cx, cy) = proof.c.xy().unwrap_or_default();
+
+    println!("// The balance threshold proof");
+    println!(
+        "uint256[3] memory thresholdSignals = [uint256({}), {}, {}];",
+        min, max, commitment
+    );
+    println!("uint256[2] memory thresholdA = [uint256({}), {}];", ax, ay);
+    println!(
+        "uint256[2][2] memory thresholdB = [[uint256({}), {}], [uint256({}), {}]];",
+        bx.c1, bx.c0, by.c1, by.c0
+    );
+    println!("uint256[2] memory thresholdC = [uint256({}), {}];", cx, cy);
+    println!();
+
+    Ok(())
+}
+
 fn gen_public_keys<R: Rng + CryptoRng>(rng: &mut R) -> [ark_babyjubjub::EdwardsAffine; 3] {
     let sk1 = ark_babyjubjub::Fr::rand(rng);
     let sk2 = ark_babyjubjub::Fr::rand(rng);
@@ -302,18 +307,60 @@ fn sym_decrypt(key: F, mut ciphertext: [F; 2], nonce: F) -> [F; 2] {
     ciphertext
 }
 
-fn main() -> eyre::Result<ExitCode> {
-    type R = ChaCha12Rng;
+// The contract passes the commitments and the policy limits to the verifier through IGroth16Verifier. A verifier rendered from a stale circuit has a different number of public inputs, and its verifyProof has a different selector, thus every processMPC call would revert.
+fn check_num_public_inputs(vk: &ark_groth16::VerifyingKey<Curve>) -> eyre::Result<()> {
+    let num_public_inputs = vk.gamma_abc_g1.len() - 1;
+    if num_public_inputs != NUM_COMMITMENTS + NUM_POLICY_INPUTS {
+        eyre::bail!(
+            "The circuit has {num_public_inputs} public inputs, but {} are expected, run create_circuits.sh first",
+            NUM_COMMITMENTS + NUM_POLICY_INPUTS
+        );
+    }
+    let path = format!("{}{}", ROOT, CONF_TOKEN_PATH);
+    let conf_token = std::fs::read_to_string(&path).context("while reading conf_token.sol")?;
+    let interface = format!("uint256[{num_public_inputs}] calldata _pubSignals");
+    if !conf_token.contains(&interface) {
+        eyre::bail!(
+            "IGroth16Verifier in conf_token.sol does not take {num_public_inputs} public inputs"
+        );
+    }
+    Ok(())
+}
 
+// The UltraHonk verifier for the action queue. It needs no trusted setup, the verification key only depends on the circuit and the universal CRS. The Solidity code is rendered from the verification key by barretenberg, thus this requires bb and is only done if --ultrahonk is given.
+fn export_ultrahonk_verifier() -> eyre::Result<()> {
+    let pa = TestConfig::get_transaction_batched_program_artifact()?;
+    let constraint_system = ultrahonk::get_constraint_system_from_artifact(&pa);
+    let prover_crs = TestConfig::get_prover_crs(&constraint_system)?;
+    let vk = ultrahonk::generate_vk_barretenberg(&constraint_system, prover_crs)?;
+
+    let vk_path = std::env::temp_dir().join("private_deposit_ultrahonk_vk");
+    std::fs::write(&vk_path, vk.to_buffer()).context("while writing verification key")?;
+    let status = Command::new("bb")
+        .arg("write_solidity_verifier")
+        .arg("-k")
+        .arg(&vk_path)
+        .arg("-o")
+        .arg(format!("{}{}", ROOT, ULTRAHONK_PATH))
+        .status()
+        .context("while running bb write_solidity_verifier")?;
+    if !status.success() {
+        eyre::bail!("bb failed to write the UltraHonk verifier: {status}");
+    }
+    Ok(())
+}
+
+fn main() -> eyre::Result<ExitCode> {
     let mut seed = [0u8; 32];
     if SEED.len() > 32 {
         panic!("Seed too long");
     }
     seed[0..SEED.len()].copy_from_slice(SEED.as_bytes());
-    let mut rng = R::from_seed(seed);
+    let mut parties = ThreeParty::from_seed(NUM_BATCHED_TRANSACTIONS * 2, seed);
 
     let pa = TestConfig::get_transaction_batched_program_artifact()?;
-    let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, &mut rng)?;
+    let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, parties.rng())?;
+    check_num_public_inputs(&pk.vk)?;
     let mut result = Vec::new();
     solidity_verifier::export_solidity_verifier(&pk.vk, &mut result)?;
 
@@ -321,10 +368,36 @@ fn main() -> eyre::Result<ExitCode> {
     let mut file = File::create(path)?;
     file.write_all(&result)?;
 
-    let keys = gen_public_keys(&mut rng);
+    let keys = gen_public_keys(parties.rng());
 
     // Print inputs for the testcase of the solidity verifier
-    test_process_mpc(&proof_schema, &cs, &pk, keys, &mut rng)?;
+    test_process_mpc(&proof_schema, &cs, &pk, keys, &mut parties)?;
+
+    // The verifier for balance threshold proofs, renamed to not clash with the transaction verifier. The keys come from their own seed, such that the MPC nodes can recreate the proving key with balance_threshold_setup.
+    let pa = TestConfig::get_balance_threshold_program_artifact()?;
+    let (threshold_schema, threshold_pk, threshold_cs) = threshold::balance_threshold_setup(pa)?;
+    let mut result = Vec::new();
+    solidity_verifier::export_solidity_verifier(&threshold_pk.vk, &mut result)?;
+    let result = String::from_utf8(result)?.replace(
+        "contract Groth16Verifier",
+        "contract BalanceThresholdVerifier",
+    );
+
+    let path = format!("{}{}", ROOT, THRESHOLD_PATH);
+    let mut file = File::create(path)?;
+    file.write_all(result.as_bytes())?;
+
+    // Print inputs for the testcase of the balance threshold verifier
+    test_balance_threshold(
+        &threshold_schema,
+        &threshold_cs,
+        &threshold_pk,
+        &mut parties,
+    )?;
+
+    if std::env::args().any(|arg| arg == "--ultrahonk") {
+        export_ultrahonk_verifier()?;
+    }
 
     Ok(ExitCode::SUCCESS)
 }
@@ -17,7 +17,10 @@ use mpc_net::{
 };
 use private_deposit::{
     data_structure::{DepositValuePlain, PrivateDeposit},
-    proof::{NUM_BATCHED_TRANSACTIONS, TestConfig, transaction_batched::TransactionInput},
+    proof::{
+        NUM_BATCHED_TRANSACTIONS, TestConfig, actionquery::Action, policy::PolicyLimits,
+        transaction_batched::TransactionInput,
+    },
 };
 use rand::{CryptoRng, Rng};
 use rand_chacha::{ChaCha12Rng, rand_core::SeedableRng};
@@ -217,6 +220,10 @@ fn transactions_benchmarks<R: Rng + CryptoRng>(
     transactions_with_r1cs_witext(map, config, &proof_schema, nets, rng)?;
     transactions_groth16_proof(map, config, &proof_schema, &cs, &pk, nets, rng)?;
 
+    // Compares processing each action of a queue in its own thread with the packed mode
+    queue_with_r1cs_witext(map, config, &proof_schema, nets, rng)?;
+    queue_packed_with_r1cs_witext(map, config, &proof_schema, &nets[0], &nets[1], rng)?;
+
     let circom = TestConfig::get_transaction_batched_circom()?;
     let circom_proof_schema = TestConfig::get_transaction_batched_proof_schema(rng)?;
 
@@ -505,6 +512,104 @@ fn transactions_with_r1cs_witext<R: Rng + CryptoRng>(
     Ok(ExitCode::SUCCESS)
 }
 
+fn get_transfer_queue<R: Rng + CryptoRng>(
+    map: &ShareMap<F>,
+    id: usize,
+    rng: &mut R,
+) -> eyre::Result<Vec<Action<F>>> {
+    let inputs = get_transaction_inputs(map, id, rng)?;
+    Ok(inputs
+        .into_iter()
+        .map(|input| {
+            Action::Transfer(
+                input.sender_key,
+                input.receiver_key,
+                input.amount,
+                input.amount_blinding,
+            )
+        })
+        .collect())
+}
+
+fn queue_with_r1cs_witext<R: Rng + CryptoRng>(
+    map: &ShareMap<F>,
+    config: &Config,
+    proof_schema: &NoirProofScheme<F>,
+    nets: &[TcpNetwork; NUM_BATCHED_TRANSACTIONS * 2],
+    rng: &mut R,
+) -> eyre::Result<ExitCode> {
+    tracing::info!("Starting queue_with_r1cs_witext benchmarks");
+    let queue = get_transfer_queue(map, config.network.my_id, rng)?;
+    let limits = PolicyLimits::default();
+
+    // init MPC protocol
+    let mut rep3_states = Vec::with_capacity(nets.len() / 2);
+    for net in nets.iter().take(nets.len() / 2) {
+        rep3_states.push(Rep3State::new(net, A2BType::default())?);
+    }
+
+    benchmark_blueprint!(
+        config,
+        &format!(
+            "queue + witext (batch={}, n={})",
+            NUM_BATCHED_TRANSACTIONS, config.num_items
+        ),
+        PrivateDeposit::process_queue_with_r1cs_witness,
+        map,
+        &nets[0],
+        rep3_states[0].id.prev() as usize,
+        rep3_states[0].id.next() as usize,
+        (
+            queue.to_owned(),
+            &limits,
+            proof_schema,
+            nets,
+            rep3_states.as_mut_slice().try_into().unwrap()
+        )
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn queue_packed_with_r1cs_witext<R: Rng + CryptoRng>(
+    map: &ShareMap<F>,
+    config: &Config,
+    proof_schema: &NoirProofScheme<F>,
+    net0: &TcpNetwork,
+    net1: &TcpNetwork,
+    rng: &mut R,
+) -> eyre::Result<ExitCode> {
+    tracing::info!("Starting queue_packed_with_r1cs_witext benchmarks");
+    let queue = get_transfer_queue(map, config.network.my_id, rng)?;
+    let limits = PolicyLimits::default();
+
+    let mut rep3_state = Rep3State::new(net0, A2BType::default())?;
+
+    benchmark_blueprint!(
+        config,
+        &format!(
+            "queue packed + witext (batch={}, n={})",
+            NUM_BATCHED_TRANSACTIONS, config.num_items
+        ),
+        PrivateDeposit::process_queue_packed_with_r1cs_witness,
+        map,
+        net0,
+        rep3_state.id.prev() as usize,
+        rep3_state.id.next() as usize,
+        (
+            queue.to_owned(),
+            &limits,
+            proof_schema,
+            None,
+            net0,
+            net1,
+            &mut rep3_state
+        )
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
 fn transactions_cocircom_witext<R: Rng + CryptoRng>(
     map: &ShareMap<F>,
     config: &Config,
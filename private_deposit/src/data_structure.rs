@@ -8,6 +8,17 @@ use std::collections::{BTreeMap, HashMap};
 pub type DepositValuePlain<F> = DepositValue<F>;
 pub type DepositValueShare<F> = DepositValue<Rep3PrimeFieldShare<F>>;
 
+// Errors of the operations on a PrivateDeposit, they carry the affected key
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DataStructureError<K> {
+    #[error("The key {0:?} has no balance")]
+    KeyNotFound(K),
+    #[error("The shares have a different number of keys")]
+    ShareLengthMismatch,
+    #[error("The key {0:?} is not in all shares")]
+    KeyNotInAllShares(K),
+}
+
 #[derive(Clone, Debug, CanonicalSerialize, CanonicalDeserialize, Serialize, Deserialize)]
 pub struct DepositValue<V: CanonicalDeserialize + CanonicalSerialize + Clone> {
     #[serde(
@@ -173,7 +184,7 @@ where
         key: K,
         amount: Rep3PrimeFieldShare<F>,
         rep3_state: &mut Rep3State,
-    ) -> eyre::Result<(DepositValueShare<F>, DepositValueShare<F>)> {
+    ) -> Result<(DepositValueShare<F>, DepositValueShare<F>), DataStructureError<K>> {
         let new_blinding = rep3::arithmetic::rand(rep3_state);
         self.withdraw_with_blinding(key, amount, new_blinding)
     }
@@ -186,12 +197,15 @@ where
         receiver: K,
         amount: Rep3PrimeFieldShare<F>,
         rep3_state: &mut Rep3State,
-    ) -> eyre::Result<(
-        DepositValueShare<F>,
-        DepositValueShare<F>,
-        Option<DepositValueShare<F>>,
-        DepositValueShare<F>,
-    )> {
+    ) -> Result<
+        (
+            DepositValueShare<F>,
+            DepositValueShare<F>,
+            Option<DepositValueShare<F>>,
+            DepositValueShare<F>,
+        ),
+        DataStructureError<K>,
+    > {
         let sender_new_blinding = rep3::arithmetic::rand(rep3_state);
         let receiver_new_blinding = rep3::arithmetic::rand(rep3_state);
         self.transaction_with_blinding(
@@ -202,6 +216,28 @@ where
             receiver_new_blinding,
         )
     }
+
+    // Combines the shares of all three parties into the plain map
+    pub fn reconstruct(
+        shares: [Self; 3],
+    ) -> Result<PrivateDeposit<K, DepositValuePlain<F>>, DataStructureError<K>> {
+        let [shares0, mut shares1, mut shares2] = shares;
+        if shares0.len() != shares1.len() || shares0.len() != shares2.len() {
+            return Err(DataStructureError::ShareLengthMismatch);
+        }
+
+        let mut map = PrivateDeposit::with_capacity(shares0.len());
+        for (key, value0) in shares0 {
+            let (Some(value1), Some(value2)) = (shares1.remove(&key), shares2.remove(&key)) else {
+                return Err(DataStructureError::KeyNotInAllShares(key));
+            };
+            let amount = rep3::combine_field_element(value0.amount, value1.amount, value2.amount);
+            let blinding =
+                rep3::combine_field_element(value0.blinding, value1.blinding, value2.blinding);
+            map.insert(key, DepositValue::new(amount, blinding));
+        }
+        Ok(map)
+    }
 }
 
 impl<K, V> PrivateDeposit<K, DepositValue<V>>
@@ -242,12 +278,10 @@ where
         key: K,
         amount: V,
         new_blinding: V,
-    ) -> eyre::Result<(DepositValue<V>, DepositValue<V>)> {
-        let old = self.get(&key);
-        if old.is_none() {
-            return Err(eyre::eyre!("Key not found in HashMap"));
-        }
-        let old = old.unwrap().clone();
+    ) -> Result<(DepositValue<V>, DepositValue<V>), DataStructureError<K>> {
+        let Some(old) = self.get(&key).cloned() else {
+            return Err(DataStructureError::KeyNotFound(key));
+        };
         let new_amount = old.amount.to_owned() - amount;
         let new = DepositValue::new(new_amount, new_blinding);
         self.insert(key, new.clone());
@@ -263,12 +297,15 @@ where
         amount: V,
         sender_new_blinding: V,
         receiver_new_blinding: V,
-    ) -> eyre::Result<(
-        DepositValue<V>,
-        DepositValue<V>,
-        Option<DepositValue<V>>,
-        DepositValue<V>,
-    )> {
+    ) -> Result<
+        (
+            DepositValue<V>,
+            DepositValue<V>,
+            Option<DepositValue<V>>,
+            DepositValue<V>,
+        ),
+        DataStructureError<K>,
+    > {
         let (sender_old, sender_new) =
             self.withdraw_with_blinding(sender, amount.to_owned(), sender_new_blinding)?;
         let (receiver_old, receiver_new) =
@@ -0,0 +1,324 @@
+use mpc_net::{ConnectionStats, Network};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+// Every message is framed with a tag and a per-peer sequence number, such that lost messages and aborts of other parties can be detected
+const TAG_DATA: u8 = 0;
+const TAG_ABORT: u8 = 1;
+const HEADER_SIZE: usize = 9;
+const NUM_PARTIES: usize = 3;
+
+// The message index counts all messages a party sends over all of its wrapped networks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    // Every message is delayed
+    Delay(Duration),
+    // A byte of the payload of the message with the given index is flipped. Messages without payload are sent unchanged.
+    Corrupt(usize),
+    // The message with the given index is never sent. The receiver notices the gap at the next message from the same peer, otherwise its receive times out.
+    Drop(usize),
+    // The party crashes after sending the given number of messages
+    Disconnect(usize),
+}
+
+// Shared between all networks of one party
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    fault: Option<Fault>,
+    sent: AtomicUsize,
+    disconnected: AtomicBool,
+}
+
+impl FaultInjector {
+    pub fn new(fault: Fault) -> Arc<Self> {
+        Arc::new(Self {
+            fault: Some(fault),
+            ..Default::default()
+        })
+    }
+
+    pub fn healthy() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+}
+
+// Wraps a network (e.g., LocalNetwork or TcpNetwork) and injects the fault of its FaultInjector. All parties have to use the wrapper, since it changes the wire format. If a party encounters an error, it sends an abort message to the other parties, such that they do not wait forever for messages which will never arrive.
+pub struct FaultyNetwork<'a, N: Network> {
+    inner: &'a N,
+    injector: Arc<FaultInjector>,
+    send_seq: [AtomicU64; NUM_PARTIES],
+    recv_seq: [AtomicU64; NUM_PARTIES],
+    aborted: AtomicBool,
+}
+
+impl<'a, N: Network> FaultyNetwork<'a, N> {
+    pub fn new(inner: &'a N, injector: Arc<FaultInjector>) -> Self {
+        Self {
+            inner,
+            injector,
+            send_seq: Default::default(),
+            recv_seq: Default::default(),
+            aborted: AtomicBool::new(false),
+        }
+    }
+
+    pub fn wrap_all(nets: &'a [N], injector: Arc<FaultInjector>) -> Vec<Self> {
+        nets.iter()
+            .map(|net| Self::new(net, injector.clone()))
+            .collect()
+    }
+
+    // Notifies the other parties that this party stops participating. Is done at most once per network.
+    pub fn abort(&self) {
+        if self.aborted.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let id = self.inner.id();
+        for peer in (0..NUM_PARTIES).filter(|peer| *peer != id) {
+            let frame = Self::frame(TAG_ABORT, 0, &[]);
+            // The peer might be gone already, which is fine
+            let _ = self.inner.send(peer, &frame);
+        }
+    }
+
+    pub fn abort_all(nets: &[Self]) {
+        for net in nets {
+            net.abort();
+        }
+    }
+
+    fn frame(tag: u8, seq: u64, data: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(HEADER_SIZE + data.len());
+        frame.push(tag);
+        frame.extend_from_slice(&seq.to_le_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn fail(&self, error: eyre::Report) -> eyre::Report {
+        self.abort();
+        error
+    }
+}
+
+impl<N: Network> Network for FaultyNetwork<'_, N> {
+    fn id(&self) -> usize {
+        self.inner.id()
+    }
+
+    fn send(&self, to: usize, data: &[u8]) -> eyre::Result<()> {
+        if self.injector.is_disconnected() {
+            return Err(self.fail(eyre::eyre!("Party {} is disconnected", self.id())));
+        }
+        let index = self.injector.sent.fetch_add(1, Ordering::SeqCst);
+        let seq = self.send_seq[to].fetch_add(1, Ordering::SeqCst);
+
+        let mut frame = Self::frame(TAG_DATA, seq, data);
+        match self.injector.fault {
+            Some(Fault::Delay(delay)) => std::thread::sleep(delay),
+            Some(Fault::Corrupt(i)) if i == index => {
+                if let Some(byte) = frame
+                    .get_mut(HEADER_SIZE..)
+                    .and_then(|data| data.last_mut())
+                {
+                    *byte ^= 1;
+                }
+            }
+            Some(Fault::Drop(i)) if i == index => return Ok(()),
+            Some(Fault::Disconnect(i)) if i <= index => {
+                self.injector.disconnected.store(true, Ordering::SeqCst);
+                return Err(self.fail(eyre::eyre!("Party {} is disconnected", self.id())));
+            }
+            _ => {}
+        }
+
+        self.inner
+            .send(to, &frame)
+            .map_err(|error| self.fail(error))
+    }
+
+    fn recv(&self, from: usize) -> eyre::Result<Vec<u8>> {
+        if self.injector.is_disconnected() {
+            return Err(self.fail(eyre::eyre!("Party {} is disconnected", self.id())));
+        }
+
+        // Relies on the receive timeout of the transport if a message never arrives
+        let frame = self.inner.recv(from).map_err(|error| {
+            self.fail(error.wrap_err(format!("while receiving from party {from}")))
+        })?;
+        if frame.len() < HEADER_SIZE {
+            return Err(self.fail(eyre::eyre!("Invalid frame from party {from}")));
+        }
+        let seq = u64::from_le_bytes(frame[1..HEADER_SIZE].try_into().expect("Size is checked"));
+        match frame[0] {
+            TAG_DATA => {}
+            TAG_ABORT => return Err(self.fail(eyre::eyre!("Party {from} aborted"))),
+            tag => return Err(self.fail(eyre::eyre!("Unknown tag {tag} from party {from}"))),
+        }
+
+        let expected = self.recv_seq[from].fetch_add(1, Ordering::SeqCst);
+        if seq != expected {
+            return Err(self.fail(eyre::eyre!(
+                "Expected message {expected} from party {from}, got {seq}"
+            )));
+        }
+        Ok(frame[HEADER_SIZE..].to_vec())
+    }
+
+    fn get_connection_stats(&self) -> ConnectionStats {
+        self.inner.get_connection_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{proof::TestConfig, three_party::ThreeParty};
+    use figment::{
+        Figment,
+        providers::{Format, Toml},
+    };
+    use mpc_net::tcp::{NetworkConfig, TcpNetwork};
+    use std::thread;
+
+    const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+    // The fault of party 0 and the error it causes at one of the parties
+    fn faults() -> [(Option<Fault>, Option<&'static str>); 6] {
+        [
+            (None, None),
+            (Some(Fault::Delay(Duration::from_millis(10))), None),
+            (Some(Fault::Corrupt(1)), Some("corrupted")),
+            // The next message to party 2 reveals the gap
+            (
+                Some(Fault::Drop(1)),
+                Some("Expected message 0 from party 0, got 1"),
+            ),
+            // The last message to party 2 never arrives
+            (Some(Fault::Drop(3)), Some("while receiving from party 0")),
+            (Some(Fault::Disconnect(1)), Some("is disconnected")),
+        ]
+    }
+
+    // Party 0 sends two messages to each of the other parties
+    fn exchange<N: Network>(net: FaultyNetwork<N>) -> Result<Vec<Vec<u8>>, String> {
+        let result = (|| {
+            if net.id() == 0 {
+                for _ in 0..2 {
+                    net.send(1, &[1, 2, 3])?;
+                    net.send(2, &[1, 2, 3])?;
+                }
+                Ok(vec![])
+            } else {
+                let mut received = Vec::new();
+                for _ in 0..2 {
+                    let msg = net.recv(0)?;
+                    if msg != [1, 2, 3] {
+                        eyre::bail!("Received corrupted message");
+                    }
+                    received.push(msg);
+                }
+                Ok(received)
+            }
+        })();
+        result.map_err(|error| format!("{error:#}"))
+    }
+
+    fn check_results(results: &[Result<Vec<Vec<u8>>, String>], error: Option<&str>) {
+        match error {
+            None => assert!(results.iter().all(|result| result.is_ok())),
+            Some(error) => assert!(
+                results
+                    .iter()
+                    .any(|result| result.as_ref().is_err_and(|e| e.contains(error))),
+                "expected {error:?}, got {results:?}"
+            ),
+        }
+    }
+
+    fn injectors(fault: Option<Fault>) -> [Arc<FaultInjector>; 3] {
+        [
+            fault.map_or_else(FaultInjector::healthy, FaultInjector::new),
+            FaultInjector::healthy(),
+            FaultInjector::healthy(),
+        ]
+    }
+
+    #[test]
+    fn faulty_network_test() {
+        let mut parties = ThreeParty::new(1, TestConfig::SEED).with_recv_timeout(RECV_TIMEOUT);
+        for (fault, error) in faults() {
+            let results = parties
+                .run(0, injectors(fault), |injector, nets, _| {
+                    Ok(exchange(FaultyNetwork::new(&nets[0], injector)))
+                })
+                .unwrap();
+            check_results(&results, error);
+
+            // Stale frames of the faulty run must not leak into the next one
+            parties.reconnect();
+        }
+    }
+
+    // Three TCP networks on localhost, configured like the parties in bin/configs. Connecting blocks until all parties are up.
+    fn tcp_networks(port: u16) -> Vec<TcpNetwork> {
+        thread::scope(|scope| {
+            let handles = (0..NUM_PARTIES)
+                .map(|id| {
+                    let mut config = format!(
+                        "my_id = {id}\nbind_addr = \"127.0.0.1:{}\"\ntimeout = \"{}s\"\n",
+                        port + id as u16,
+                        RECV_TIMEOUT.as_secs()
+                    );
+                    for party in 0..NUM_PARTIES {
+                        config.push_str(&format!(
+                            "[[parties]]\nid = {party}\ndns_name = \"127.0.0.1:{}\"\n",
+                            port + party as u16
+                        ));
+                    }
+                    scope.spawn(move || {
+                        let config: NetworkConfig =
+                            Figment::new().merge(Toml::string(&config)).extract()?;
+                        let [net] = TcpNetwork::networks(config)?;
+                        eyre::Ok(net)
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("party panicked").unwrap())
+                .collect()
+        })
+    }
+
+    // The same faults over a transport where a lost message really never arrives
+    #[test]
+    fn faulty_tcp_network_test() {
+        for (port, (fault, error)) in (31_000..).step_by(NUM_PARTIES).zip(faults()) {
+            let nets = tcp_networks(port);
+            let results = thread::scope(|scope| {
+                let handles = nets
+                    .iter()
+                    .zip(injectors(fault))
+                    .map(|(net, injector)| {
+                        scope.spawn(move || exchange(FaultyNetwork::new(net, injector)))
+                    })
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("party panicked"))
+                    .collect::<Vec<_>>()
+            });
+            check_results(&results, error);
+        }
+    }
+}
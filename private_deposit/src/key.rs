@@ -0,0 +1,81 @@
+use alloy::primitives::Address;
+use ark_ff::{BigInteger, PrimeField};
+
+type F = ark_bn254::Fr;
+
+const ADDRESS_SIZE: usize = 20;
+
+// The keys of a PrivateDeposit, with an injective encoding as a field element
+// for hashes and circuits
+pub trait MapKey:
+    std::hash::Hash + Eq + Clone + Ord + Send + Sync + std::fmt::Debug + 'static
+{
+    fn to_field(&self) -> F;
+
+    fn from_field(field: F) -> eyre::Result<Self>;
+}
+
+// Field elements are their own encoding, as in the benchmarks
+impl MapKey for F {
+    fn to_field(&self) -> F {
+        *self
+    }
+
+    fn from_field(field: F) -> eyre::Result<Self> {
+        Ok(field)
+    }
+}
+
+// An address is encoded as uint256(uint160(address)) like in Solidity, i.e.,
+// its 20 bytes read as a big-endian integer
+impl MapKey for Address {
+    fn to_field(&self) -> F {
+        F::from_be_bytes_mod_order(self.as_slice())
+    }
+
+    fn from_field(field: F) -> eyre::Result<Self> {
+        let bytes = field.into_bigint().to_bytes_be();
+        let (high, low) = bytes.split_at(bytes.len() - ADDRESS_SIZE);
+        if high.iter().any(|byte| *byte != 0) {
+            eyre::bail!("Field element does not fit into an address");
+        }
+        Ok(Address::from_slice(low))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_ff::{Field, One, UniformRand, Zero};
+    use rand::Rng;
+
+    #[test]
+    fn address_key_test() {
+        let mut rng = rand::thread_rng();
+
+        assert_eq!(Address::ZERO.to_field(), F::zero());
+        let mut one = [0u8; 20];
+        one[19] = 1;
+        assert_eq!(Address::from(one).to_field(), F::one());
+        // The first byte is the most significant one
+        let mut high = [0u8; 20];
+        high[0] = 0xff;
+        assert_eq!(
+            Address::from(high).to_field(),
+            F::from(0xffu64) * F::from(2u64).pow([152])
+        );
+
+        for _ in 0..100 {
+            let address = Address::from(rng.r#gen::<[u8; 20]>());
+            let field = address.to_field();
+            assert_eq!(Address::from_field(field).unwrap(), address);
+        }
+
+        // Everything above 160 bits is not an address
+        assert!(Address::from_field(F::from(2u64).pow([160])).is_err());
+        assert!(Address::from_field(-F::one()).is_err());
+
+        let field = F::rand(&mut rng);
+        assert_eq!(F::from_field(field).unwrap().to_field(), field);
+    }
+}
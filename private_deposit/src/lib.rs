@@ -1,2 +1,8 @@
 pub mod data_structure;
+pub mod faulty_network;
+pub mod key;
+pub mod merkle_tree;
 pub mod proof;
+pub mod reshare;
+pub mod snapshot;
+pub mod three_party;
@@ -0,0 +1,274 @@
+use crate::key::MapKey;
+use crate::proof::actionquery::Action;
+use crate::proof::transaction::NUM_TRANSACTION_COMMITMENTS;
+use crate::proof::transaction_batched::{NUM_COMMITMENTS, NUM_TRANSACTIONS};
+use ark_ff::{BigInteger, PrimeField, Zero};
+use mpc_core::gadgets::poseidon2::Poseidon2;
+use mpc_core::serde_compat::{ark_de, ark_se};
+use std::collections::HashMap;
+
+type F = ark_bn254::Fr;
+type Index = <F as PrimeField>::BigInt;
+
+// Keys are addresses, see the MapKey encoding
+pub const TREE_DEPTH: usize = 160;
+
+// Distinct from the domain separator of the commitments
+const LEAF_DOMAIN_SEPARATOR: u64 = 0x4C454146; // "LEAF"
+const NODE_DOMAIN_SEPARATOR: u64 = 0x4E4F4445; // "NODE"
+
+// The same as `poseidon2.compress(inputs, domain_separator)` of the smart contract
+fn compress(left: F, right: F, domain_separator: u64) -> F {
+    let hasher = Poseidon2::<F, 2, 5>::default();
+    let permuted = hasher.permutation(&[left + F::from(domain_separator), right]);
+    permuted[0] + left
+}
+
+pub fn leaf_hash(key: F, commitment: F) -> F {
+    compress(key, commitment, LEAF_DOMAIN_SEPARATOR)
+}
+
+fn node_hash(left: F, right: F) -> F {
+    compress(left, right, NODE_DOMAIN_SEPARATOR)
+}
+
+fn check_key(key: F) -> eyre::Result<Index> {
+    let index = key.into_bigint();
+    if index.num_bits() as usize > TREE_DEPTH {
+        eyre::bail!("Key does not fit into the Merkle tree");
+    }
+    Ok(index)
+}
+
+// Proves that the leaf of a key is hash(key, commitment), the siblings are
+// ordered from the leaves to the root. Keys without a commitment have the empty
+// leaf 0.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MerkleProof {
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    pub key: F,
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    pub commitment: Option<F>,
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    pub siblings: Vec<F>,
+}
+
+impl MerkleProof {
+    pub fn verify(&self, root: F) -> bool {
+        let Ok(index) = check_key(self.key) else {
+            return false;
+        };
+        if self.siblings.len() != TREE_DEPTH {
+            return false;
+        }
+        let mut node = self
+            .commitment
+            .map_or_else(F::zero, |commitment| leaf_hash(self.key, commitment));
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            node = if index.get_bit(level) {
+                node_hash(*sibling, node)
+            } else {
+                node_hash(node, *sibling)
+            };
+        }
+        node == root
+    }
+}
+
+// A sparse Poseidon2 Merkle tree over the `balanceCommitments` of the smart
+// contract. The MPC network posts its root with processMPC, the root is attested
+// by the MPC network and not covered by the proof of the batch.
+#[derive(Debug, Clone)]
+pub struct CommitmentTree {
+    // Only non-empty nodes, indexed by (level, index in level), level 0 are the leaves
+    nodes: HashMap<(usize, Index), F>,
+    commitments: HashMap<Index, F>,
+    // The roots of empty subtrees for each level
+    empty: Vec<F>,
+}
+
+impl Default for CommitmentTree {
+    fn default() -> Self {
+        let mut empty = Vec::with_capacity(TREE_DEPTH + 1);
+        empty.push(F::zero());
+        for level in 0..TREE_DEPTH {
+            empty.push(node_hash(empty[level], empty[level]));
+        }
+        Self {
+            nodes: HashMap::new(),
+            commitments: HashMap::new(),
+            empty,
+        }
+    }
+}
+
+impl CommitmentTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.commitments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commitments.is_empty()
+    }
+
+    pub fn root(&self) -> F {
+        self.node(TREE_DEPTH, Index::from(0u64))
+    }
+
+    pub fn get(&self, key: F) -> eyre::Result<Option<F>> {
+        Ok(self.commitments.get(&check_key(key)?).copied())
+    }
+
+    fn node(&self, level: usize, index: Index) -> F {
+        self.nodes
+            .get(&(level, index))
+            .copied()
+            .unwrap_or(self.empty[level])
+    }
+
+    // Sets the commitment of a key and updates the path to the root
+    pub fn insert(&mut self, key: F, commitment: F) -> eyre::Result<()> {
+        let mut index = check_key(key)?;
+        self.commitments.insert(index, commitment);
+
+        let mut node = leaf_hash(key, commitment);
+        for level in 0..TREE_DEPTH {
+            self.nodes.insert((level, index), node);
+            let mut sibling = index;
+            if index.get_bit(0) {
+                sibling.sub_with_borrow(&Index::from(1u64));
+                node = node_hash(self.node(level, sibling), node);
+            } else {
+                sibling.add_with_carry(&Index::from(1u64));
+                node = node_hash(node, self.node(level, sibling));
+            }
+            index.div2();
+        }
+        self.nodes.insert((TREE_DEPTH, index), node);
+        Ok(())
+    }
+
+    pub fn proof(&self, key: F) -> eyre::Result<MerkleProof> {
+        let mut index = check_key(key)?;
+        let commitment = self.commitments.get(&index).copied();
+
+        let mut siblings = Vec::with_capacity(TREE_DEPTH);
+        for level in 0..TREE_DEPTH {
+            let mut sibling = index;
+            if index.get_bit(0) {
+                sibling.sub_with_borrow(&Index::from(1u64));
+            } else {
+                sibling.add_with_carry(&Index::from(1u64));
+            }
+            siblings.push(self.node(level, sibling));
+            index.div2();
+        }
+
+        Ok(MerkleProof {
+            key,
+            commitment,
+            siblings,
+        })
+    }
+
+    // Applies the new commitments of a processed batch in the order of processMPC
+    // and returns the new root. The public inputs are the ones of the batched proof.
+    pub fn apply_batch<K: MapKey>(
+        &mut self,
+        queue: &[Action<K>],
+        public_inputs: &[F],
+    ) -> eyre::Result<F> {
+        if queue.len() != NUM_TRANSACTIONS || public_inputs.len() < NUM_COMMITMENTS {
+            eyre::bail!("Invalid queue or public input length");
+        }
+
+        for (action, public_inputs) in queue
+            .iter()
+            .zip(public_inputs[..NUM_COMMITMENTS].chunks_exact(NUM_TRANSACTION_COMMITMENTS))
+        {
+            match action {
+                Action::Deposit(receiver, _) => {
+                    self.insert(receiver.to_field(), public_inputs[3])?
+                }
+                Action::Withdraw(sender, _) | Action::PrivateWithdraw(sender, _, _) => {
+                    self.insert(sender.to_field(), public_inputs[1])?
+                }
+                Action::Transfer(sender, receiver, _, _) => {
+                    self.insert(sender.to_field(), public_inputs[1])?;
+                    self.insert(receiver.to_field(), public_inputs[3])?;
+                }
+                Action::Dummy => {}
+                Action::Invalid => eyre::bail!("Invalid action in queue"),
+            }
+        }
+        Ok(self.root())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof::TestConfig;
+    use ark_ff::{One, UniformRand};
+    use rand::Rng;
+    use rand_chacha::{ChaCha12Rng, rand_core::SeedableRng};
+
+    #[test]
+    fn commitment_tree_test() {
+        let mut rng = ChaCha12Rng::seed_from_u64(TestConfig::SEED);
+        let mut tree = CommitmentTree::new();
+        let empty_root = tree.root();
+
+        let keys = (0..20)
+            .map(|_| F::from(rng.r#gen::<u128>()) * F::from(1u64 << 32))
+            .chain([F::zero(), F::one(), F::from(2u64)])
+            .collect::<Vec<_>>();
+        for key in keys.iter() {
+            tree.insert(*key, F::rand(&mut rng)).unwrap();
+        }
+        assert_eq!(tree.len(), keys.len());
+
+        // Update a commitment
+        let commitment = F::rand(&mut rng);
+        tree.insert(keys[0], commitment).unwrap();
+        assert_eq!(tree.len(), keys.len());
+        assert_eq!(tree.get(keys[0]).unwrap(), Some(commitment));
+
+        let root = tree.root();
+        assert_ne!(root, empty_root);
+        for key in keys.iter() {
+            let proof = tree.proof(*key).unwrap();
+            assert_eq!(proof.commitment, tree.get(*key).unwrap());
+            assert!(proof.verify(root));
+        }
+
+        // Keys without a commitment have the empty leaf
+        let proof = tree.proof(F::from(3u64)).unwrap();
+        assert_eq!(proof.commitment, None);
+        assert!(proof.verify(root));
+
+        // Wrong commitments are rejected
+        let mut proof = tree.proof(keys[0]).unwrap();
+        proof.commitment = Some(commitment + F::one());
+        assert!(!proof.verify(root));
+        let mut proof = tree.proof(keys[1]).unwrap();
+        proof.key = keys[2];
+        assert!(!proof.verify(root));
+
+        // The root does not depend on the insertion order
+        let mut reversed = CommitmentTree::new();
+        for key in keys.iter().rev() {
+            reversed
+                .insert(*key, tree.get(*key).unwrap().unwrap())
+                .unwrap();
+        }
+        assert_eq!(reversed.root(), root);
+
+        // Keys have to be addresses
+        assert!(tree.insert(-F::one(), F::zero()).is_err());
+    }
+}
@@ -1,26 +1,35 @@
-use crate::data_structure::{DepositValueShare, PrivateDeposit};
-use crate::proof::NUM_WITHDRAW_NEW_BITS;
+use crate::data_structure::{DataStructureError, DepositValueShare, PrivateDeposit};
+use crate::proof::ProofError;
+use crate::proof::bitdecomp::BitDecomps;
+use crate::proof::policy::{NUM_POLICY_INPUTS, PolicyLimits};
+use crate::proof::precompute::PrecomputationPool;
 use crate::proof::transaction::NUM_TRANSACTION_COMMITMENTS;
-use crate::proof::transaction_batched::{NUM_COMMITMENTS, NUM_TRANSACTIONS};
+use crate::proof::transaction_batched::{NUM_COMMITMENTS, NUM_TRANSACTIONS, batched_range_checks};
+use crate::proof::witness_input::{TRANSACTION_INPUTS, TransactionWitnessInput};
 use ark_ff::Zero;
 use ark_groth16::Proof;
 use co_circom::{ConstraintMatrices, ProvingKey, Rep3SharedWitness};
-use co_noir::Rep3AcvmType;
+use co_noir::{AcirFormat, HonkProof, Rep3AcvmType, VerifyingKeyBarretenberg};
+use co_noir_common::crs::ProverCrs;
 use co_noir_common::utils::Utils;
-use co_noir_to_r1cs::{noir::r1cs, r1cs::noir_proof_schema::NoirProofScheme};
+use co_noir_to_r1cs::{
+    noir::{r1cs, ultrahonk},
+    r1cs::noir_proof_schema::NoirProofScheme,
+};
 use eyre::Context;
 use itertools::izip;
 use mpc_core::protocols::rep3::id::PartyID;
 use mpc_core::protocols::rep3::{self, Rep3PrimeFieldShare, Rep3State};
-use mpc_core::protocols::rep3_ring::ring::bit::Bit;
-use mpc_core::protocols::rep3_ring::{self, Rep3RingShare};
 use mpc_core::serde_compat::{ark_de, ark_se};
 use mpc_net::Network;
+use noir_types::U256;
+use noirc_artifacts::program::ProgramArtifact;
 use std::thread;
 use std::time::{Duration, Instant};
 
 use super::Curve;
 use super::F;
+use super::{NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS};
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
 pub enum Action<K> {
@@ -35,11 +44,132 @@ pub enum Action<K> {
     ), // Sender, amount
     Transfer(K, K, Rep3PrimeFieldShare<F>, Rep3PrimeFieldShare<F>), // Sender, Receiver, amount, amount_blinding
     Dummy,
+    PrivateWithdraw(K, Rep3PrimeFieldShare<F>, Rep3PrimeFieldShare<F>), // Sender, amount, amount_blinding
+}
+
+// The additive share (i.e., `share.a`) of a value and its blinding which is disclosed to an auditor. Summing the shares of all three parties gives the value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditShare<K> {
+    SenderBalance(usize, K, F, F), // Position in the batch, sender, new balance, blinding
+    ReceiverBalance(usize, K, F, F), // Position in the batch, receiver, new balance, blinding
+    TransferAmount(usize, F, F),   // Position in the batch, amount, amount_blinding
+}
+
+impl<K> AuditShare<K> {
+    fn sender(index: usize, key: K, value: &DepositValueShare<F>) -> Self {
+        Self::SenderBalance(index, key, value.amount.a, value.blinding.a)
+    }
+
+    fn receiver(index: usize, key: K, value: &DepositValueShare<F>) -> Self {
+        Self::ReceiverBalance(index, key, value.amount.a, value.blinding.a)
+    }
+}
+
+// What the node should do after processing a queue failed. The map is rolled back in all cases, see process_queue_with_r1cs_witness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    // The MPC protocols failed, e.g., because of the network. The same batch can be processed again.
+    Retry,
+    // The action at the index cannot be processed, e.g., a withdraw from a key without balance. The batch can be processed without it.
+    DropAction(usize),
+    // The circuit or the code is broken, processing again does not help
+    Halt,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ActionQueueError<K> {
+    #[error("The queue has {0} actions instead of {expected}", expected = NUM_TRANSACTIONS)]
+    InvalidLength(usize),
+    #[error("The action at index {index} is not supported in a queue")]
+    UnsupportedAction { index: usize },
+    #[error("The action at index {index} failed: {error}")]
+    DataStructure {
+        index: usize,
+        error: DataStructureError<K>,
+    },
+    #[error(transparent)]
+    Proof(#[from] ProofError),
+}
+
+// Such that the MPC protocols can be called with ?
+impl<K> From<eyre::Report> for ActionQueueError<K> {
+    fn from(err: eyre::Report) -> Self {
+        Self::Proof(ProofError::Mpc(err))
+    }
+}
+
+impl<K> ActionQueueError<K> {
+    pub(super) fn at(index: usize) -> impl FnOnce(DataStructureError<K>) -> Self {
+        move |error| Self::DataStructure { index, error }
+    }
+
+    // The index of the action which caused the failure, if a single action caused it
+    pub fn action_index(&self) -> Option<usize> {
+        match self {
+            Self::UnsupportedAction { index } | Self::DataStructure { index, .. } => Some(*index),
+            Self::InvalidLength(_) | Self::Proof(_) => None,
+        }
+    }
+
+    // The MPC failures cannot be told apart from each other, thus all of them are retried. The node should bound the number of retries.
+    pub fn recovery(&self) -> Recovery {
+        match self {
+            Self::UnsupportedAction { index } | Self::DataStructure { index, .. } => {
+                Recovery::DropAction(*index)
+            }
+            Self::Proof(ProofError::Mpc(_))
+            | Self::Proof(ProofError::WitnessExtension(_))
+            | Self::Proof(ProofError::Prover(_)) => Recovery::Retry,
+            Self::InvalidLength(_)
+            | Self::Proof(ProofError::InvalidCircuit(_))
+            | Self::Proof(ProofError::Panicked)
+            | Self::Proof(ProofError::Diverged) => Recovery::Halt,
+        }
+    }
+}
+
+// Extracts the new sender/receiver commitments from the public inputs of the batched proof in the layout of `TransactionInput.commitments` in the smart contract. The trailing policy limits are not posted, the contract appends the limits it stores. The contract recomputes the remaining commitments itself and expects zeros for the ones it does not read. For private withdraws, the contract expects the opened amount (see `open_payouts`) instead of the receiver commitment.
+pub fn public_inputs_to_contract_commitments<K>(
+    queue: &[Action<K>],
+    public_inputs: &[F],
+    payouts: &[F],
+) -> eyre::Result<Vec<F>> {
+    if queue.len() != NUM_TRANSACTIONS || public_inputs.len() != NUM_COMMITMENTS + NUM_POLICY_INPUTS
+    {
+        eyre::bail!("Invalid queue or public input length");
+    }
+
+    let mut payouts = payouts.iter();
+    let mut commitments = Vec::with_capacity(NUM_TRANSACTIONS * 2);
+    for (action, public_inputs) in queue
+        .iter()
+        .zip(public_inputs[..NUM_COMMITMENTS].chunks_exact(NUM_TRANSACTION_COMMITMENTS))
+    {
+        let (sender_new, receiver_new) = match action {
+            Action::Deposit(_, _) => (F::zero(), public_inputs[3]),
+            Action::Withdraw(_, _) => (public_inputs[1], F::zero()),
+            Action::Transfer(_, _, _, _) => (public_inputs[1], public_inputs[3]),
+            Action::PrivateWithdraw(_, _, _) => {
+                let payout = payouts
+                    .next()
+                    .ok_or_else(|| eyre::eyre!("Missing payout for private withdraw"))?;
+                (public_inputs[1], *payout)
+            }
+            Action::Dummy => (F::zero(), F::zero()),
+            Action::Invalid => eyre::bail!("Invalid action in queue"),
+        };
+        commitments.push(sender_new);
+        commitments.push(receiver_new);
+    }
+    if payouts.next().is_some() {
+        eyre::bail!("More payouts than private withdraws");
+    }
+    Ok(commitments)
 }
 
 impl<K> PrivateDeposit<K, DepositValueShare<F>>
 where
-    K: std::hash::Hash + Eq + Clone + Send + Sync,
+    K: std::hash::Hash + Eq + Clone + Send + Sync + std::fmt::Debug + 'static,
 {
     pub fn zero_commitment() -> F {
         Utils::field_from_hex_string(
@@ -56,6 +186,7 @@ where
         receiver_new: DepositValueShare<F>,
         amount: Rep3PrimeFieldShare<F>,
         amount_blinding: Rep3PrimeFieldShare<F>,
+        limits: &PolicyLimits,
         net0: &N,
         net1: &N,
         rep3_state: &mut Rep3State,
@@ -64,8 +195,7 @@ where
         DepositValueShare<F>,
         Vec<Rep3AcvmType<F>>,
         Vec<Vec<Rep3AcvmType<F>>>,
-        Vec<Rep3PrimeFieldShare<F>>,
-        Vec<Rep3PrimeFieldShare<F>>,
+        BitDecomps<Vec<Rep3PrimeFieldShare<F>>>,
     )> {
         let (inputs, reciever_old_amount, reciever_old_blinding) = Self::get_transaction_input(
             sender_old.to_owned(),
@@ -94,23 +224,25 @@ where
             rep3_state,
         )?;
 
-        // The bit decompositions
-        let (decomp_amount, decomp_sender) = super::decompose_compose_for_transaction(
-            amount,
-            sender_new.amount,
-            net0,
-            net1,
-            rep3_state,
-        )?;
+        // The bit decompositions, the ones of the policy checks follow the ones of the transaction in the circuit
+        let mut decomps = BitDecomps::new();
+        decomps.push(NUM_AMOUNT_BITS, amount);
+        decomps.push(NUM_WITHDRAW_NEW_BITS, sender_new.amount);
+        decomps.push(
+            NUM_AMOUNT_BITS,
+            rep3::arithmetic::sub_public_by_shared(limits.max_transfer(), amount, rep3_state.id),
+        );
+        decomps.push(
+            NUM_WITHDRAW_NEW_BITS,
+            rep3::arithmetic::sub_public_by_shared(
+                limits.max_balance(),
+                receiver_new.amount,
+                rep3_state.id,
+            ),
+        );
+        let decomps = decomps.decompose_compose(net1, rep3_state)?;
 
-        Ok((
-            sender_new,
-            receiver_new,
-            inputs,
-            traces,
-            decomp_amount,
-            decomp_sender,
-        ))
+        Ok((sender_new, receiver_new, inputs, traces, decomps))
     }
 
     fn get_deposit_input_public_amount(
@@ -123,23 +255,34 @@ where
         Rep3PrimeFieldShare<F>,
         Rep3PrimeFieldShare<F>,
     ) {
-        let mut inputs = Vec::with_capacity(8);
-        inputs.push(Rep3AcvmType::from(amount));
-        inputs.push(Rep3AcvmType::from(amount_blinding));
-        let (old_amount, old_blinding) = if let Some(old) = receiver_old {
-            inputs.push(Rep3AcvmType::from(old.amount));
-            inputs.push(Rep3AcvmType::from(old.blinding));
-            (old.amount, old.blinding)
-        } else {
-            inputs.push(Rep3AcvmType::from(F::zero()));
-            inputs.push(Rep3AcvmType::from(F::zero()));
-            (Rep3PrimeFieldShare::zero(), Rep3PrimeFieldShare::zero())
+        let (receiver_old_balance, receiver_old_r, old_amount, old_blinding) =
+            if let Some(old) = receiver_old {
+                (
+                    old.amount.into(),
+                    old.blinding.into(),
+                    old.amount,
+                    old.blinding,
+                )
+            } else {
+                (
+                    Rep3AcvmType::from(F::zero()),
+                    Rep3AcvmType::from(F::zero()),
+                    Rep3PrimeFieldShare::zero(),
+                    Rep3PrimeFieldShare::zero(),
+                )
+            };
+        // The sender is a public balance of amount which is deposited entirely
+        let inputs = TransactionWitnessInput {
+            sender_old_balance: amount.into(),
+            sender_old_r: amount_blinding.into(),
+            receiver_old_balance,
+            receiver_old_r,
+            amount: amount.into(),
+            amount_r: amount_blinding.into(),
+            sender_new_r: F::zero().into(),
+            receiver_new_r: receiver_new_blinding.into(),
         };
-        inputs.push(Rep3AcvmType::from(amount));
-        inputs.push(Rep3AcvmType::from(amount_blinding));
-        inputs.push(Rep3AcvmType::from(F::zero()));
-        inputs.push(Rep3AcvmType::from(receiver_new_blinding));
-        (inputs, old_amount, old_blinding)
+        (inputs.into_noir(), old_amount, old_blinding)
     }
 
     #[expect(clippy::type_complexity)]
@@ -147,6 +290,7 @@ where
         receiver_old: Option<DepositValueShare<F>>,
         receiver_new: DepositValueShare<F>,
         amount: F,
+        limits: &PolicyLimits,
         net0: &N,
         rep3_state: &mut Rep3State,
     ) -> eyre::Result<(
@@ -154,8 +298,7 @@ where
         DepositValueShare<F>,
         Vec<Rep3AcvmType<F>>,
         Vec<Vec<Rep3AcvmType<F>>>,
-        Vec<Rep3PrimeFieldShare<F>>,
-        Vec<Rep3PrimeFieldShare<F>>,
+        BitDecomps<Vec<Rep3PrimeFieldShare<F>>>,
     )> {
         // let my_id = PartyID::try_from(net0.id())?;
 
@@ -193,18 +336,19 @@ where
         traces.insert(0, plain_traces[0].clone());
         traces.insert(2, plain_traces[1].clone());
 
-        // Elements are public, so we do not need bit decomposition witnesses
-        let decomp_amount = vec![];
-        let decomp_sender = vec![];
+        // Only the new receiver balance of the policy check is secret, all other elements are public, so we do not need bit decomposition witnesses for them
+        let mut decomps = BitDecomps::new();
+        decomps.push(
+            NUM_WITHDRAW_NEW_BITS,
+            rep3::arithmetic::sub_public_by_shared(
+                limits.max_balance(),
+                receiver_new.amount,
+                rep3_state.id,
+            ),
+        );
+        let decomps = decomps.decompose_compose(net0, rep3_state)?;
 
-        Ok((
-            sender_new,
-            receiver_new,
-            inputs,
-            traces,
-            decomp_amount,
-            decomp_sender,
-        ))
+        Ok((sender_new, receiver_new, inputs, traces, decomps))
     }
 
     fn get_withdraw_input_public_amount(
@@ -213,49 +357,18 @@ where
         amount_blinding: F,
         sender_new_blinding: Rep3PrimeFieldShare<F>,
     ) -> Vec<Rep3AcvmType<F>> {
-        vec![
-            Rep3AcvmType::from(sender_old.amount),
-            Rep3AcvmType::from(sender_old.blinding),
-            Rep3AcvmType::from(F::zero()),
-            Rep3AcvmType::from(F::zero()),
-            Rep3AcvmType::from(amount),
-            Rep3AcvmType::from(amount_blinding),
-            Rep3AcvmType::from(sender_new_blinding),
-            Rep3AcvmType::from(F::zero()),
-        ]
-    }
-
-    #[expect(clippy::assertions_on_constants)]
-    pub(super) fn decompose_compose_for_withdraw<N: Network>(
-        sender_new: Rep3PrimeFieldShare<F>,
-        net0: &N,
-        rep3_state: &mut Rep3State,
-    ) -> eyre::Result<Vec<Rep3PrimeFieldShare<F>>> {
-        let a2b_sender = rep3::conversion::a2y2b(sender_new, net0, rep3_state)?;
-
-        let mut to_compose = Vec::with_capacity(NUM_WITHDRAW_NEW_BITS);
-        assert!(NUM_WITHDRAW_NEW_BITS <= 128);
-        assert!(NUM_WITHDRAW_NEW_BITS > 64);
-        let a2b_sender_a = a2b_sender.a.to_u64_digits();
-        let a2b_sender_b = a2b_sender.b.to_u64_digits();
-        let mut a2b_sender_a = ((a2b_sender_a[1] as u128) << 64) | a2b_sender_a[0] as u128;
-        let mut a2b_sender_b = ((a2b_sender_b[1] as u128) << 64) | a2b_sender_b[0] as u128;
-        for _ in 0..NUM_WITHDRAW_NEW_BITS {
-            let bit = Rep3RingShare::new(
-                Bit::new((a2b_sender_a & 1) == 1),
-                Bit::new((a2b_sender_b & 1) == 1),
-            );
-            to_compose.push(bit);
-            a2b_sender_a >>= 1;
-            a2b_sender_b >>= 1;
+        // The receiver is a public balance which gets amount
+        TransactionWitnessInput::<Rep3AcvmType<F>> {
+            sender_old_balance: sender_old.amount.into(),
+            sender_old_r: sender_old.blinding.into(),
+            receiver_old_balance: F::zero().into(),
+            receiver_old_r: F::zero().into(),
+            amount: amount.into(),
+            amount_r: amount_blinding.into(),
+            sender_new_r: sender_new_blinding.into(),
+            receiver_new_r: F::zero().into(),
         }
-        let composed = rep3_ring::conversion::bit_inject_from_bits_to_field_many(
-            &to_compose,
-            net0,
-            rep3_state,
-        )?;
-
-        Ok(composed)
+        .into_noir()
     }
 
     #[expect(clippy::type_complexity)]
@@ -270,8 +383,7 @@ where
         DepositValueShare<F>,
         Vec<Rep3AcvmType<F>>,
         Vec<Vec<Rep3AcvmType<F>>>,
-        Vec<Rep3PrimeFieldShare<F>>,
-        Vec<Rep3PrimeFieldShare<F>>,
+        BitDecomps<Vec<Rep3PrimeFieldShare<F>>>,
     )> {
         let my_id = PartyID::try_from(net0.id())?;
 
@@ -308,19 +420,12 @@ where
         traces.push(plain_traces[1].clone());
         traces.push(plain_traces[0].clone());
 
-        // The bit decomposition
-        let decomp_amount = vec![]; // Amount is public, so we do not need bit decomposition witnesses
-        let decomp_sender =
-            Self::decompose_compose_for_withdraw(sender_new.amount, net0, rep3_state)?;
+        // The bit decomposition, amount is public, so we do not need bit decomposition witnesses for it (also for the policy checks)
+        let mut decomps = BitDecomps::new();
+        decomps.push(NUM_WITHDRAW_NEW_BITS, sender_new.amount);
+        let decomps = decomps.decompose_compose(net0, rep3_state)?;
 
-        Ok((
-            sender_new,
-            receiver_new,
-            inputs,
-            traces,
-            decomp_amount,
-            decomp_sender,
-        ))
+        Ok((sender_new, receiver_new, inputs, traces, decomps))
     }
 
     #[expect(clippy::type_complexity)]
@@ -329,8 +434,7 @@ where
         DepositValueShare<F>,
         Vec<Rep3AcvmType<F>>,
         Vec<Vec<Rep3AcvmType<F>>>,
-        Vec<Rep3PrimeFieldShare<F>>,
-        Vec<Rep3PrimeFieldShare<F>>,
+        BitDecomps<Vec<Rep3PrimeFieldShare<F>>>,
     )> {
         let mut plain_traces =
             super::poseidon2_plain_commitment_helper::<1, _, _>([F::zero(), F::zero()]);
@@ -346,53 +450,111 @@ where
             );
 
         // Elements are public, so we do not need bit decomposition witnesses
-        let decomp_amount = vec![];
-        let decomp_sender = vec![];
+        let decomps = BitDecomps::new();
 
-        let inputs = vec![Rep3AcvmType::from(F::zero()); 8];
+        let inputs = vec![Rep3AcvmType::from(F::zero()); TRANSACTION_INPUTS.len()];
 
-        Ok((
-            zero.clone(),
-            zero,
-            inputs,
-            plain_traces,
-            decomp_amount,
-            decomp_sender,
-        ))
+        Ok((zero.clone(), zero, inputs, plain_traces, decomps))
     }
 
+    // Records the current values of all keys the queue touches, such that a failed batch can be rolled back
+    pub(super) fn journal(&self, queue: &[Action<K>]) -> Vec<(K, Option<DepositValueShare<F>>)> {
+        let mut journal = Vec::with_capacity(queue.len() * 2);
+        for action in queue {
+            match action {
+                Action::Deposit(key, _)
+                | Action::Withdraw(key, _)
+                | Action::PrivateWithdraw(key, _, _) => {
+                    journal.push((key.to_owned(), self.get(key).cloned()));
+                }
+                Action::Transfer(sender, receiver, _, _) => {
+                    journal.push((sender.to_owned(), self.get(sender).cloned()));
+                    journal.push((receiver.to_owned(), self.get(receiver).cloned()));
+                }
+                Action::Dummy | Action::Invalid => {}
+            }
+        }
+        journal
+    }
+
+    // Restores the values recorded in the journal. Keys which are recorded multiple times get the value of their first record.
+    pub(super) fn rollback(&mut self, journal: Vec<(K, Option<DepositValueShare<F>>)>) {
+        for (key, value) in journal.into_iter().rev() {
+            match value {
+                Some(value) => self.insert(key, value),
+                None => self.remove(&key),
+            };
+        }
+    }
+
+    // The batch is processed atomically: If any party fails (e.g., because of a network error), the map is rolled back to its state before the batch, such that the batch can be retried. Each action computes its Poseidon2 precomputation online on its own networks, since the actions need different numbers of permutations. The packed modes take it from a PrecomputationPool instead.
     #[expect(clippy::type_complexity)]
     pub fn process_queue_with_r1cs_witness<N: Network>(
         &mut self,
         queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
         proof_schema: &NoirProofScheme<F>,
         nets: &[N; NUM_TRANSACTIONS * 2],
         rep3_states: &mut [Rep3State; NUM_TRANSACTIONS],
-    ) -> eyre::Result<(
-        Vec<DepositValueShare<F>>,
-        Vec<DepositValueShare<F>>,
-        Rep3SharedWitness<F>,
-    )> {
-        assert_eq!(queue.len(), NUM_TRANSACTIONS);
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            Rep3SharedWitness<F>,
+        ),
+        ActionQueueError<K>,
+    > {
+        let journal = self.journal(&queue);
+        let result = self.process_queue_with_r1cs_witness_inner(
+            queue,
+            limits,
+            proof_schema,
+            nets,
+            rep3_states,
+        );
+        if result.is_err() {
+            self.rollback(journal);
+        }
+        result
+    }
+
+    #[expect(clippy::type_complexity)]
+    fn process_queue_with_r1cs_witness_inner<N: Network>(
+        &mut self,
+        queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
+        proof_schema: &NoirProofScheme<F>,
+        nets: &[N; NUM_TRANSACTIONS * 2],
+        rep3_states: &mut [Rep3State; NUM_TRANSACTIONS],
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            Rep3SharedWitness<F>,
+        ),
+        ActionQueueError<K>,
+    > {
+        if queue.len() != NUM_TRANSACTIONS {
+            return Err(ActionQueueError::InvalidLength(queue.len()));
+        }
         let mut sender_new = Vec::with_capacity(NUM_TRANSACTIONS);
         let mut receiver_new = Vec::with_capacity(NUM_TRANSACTIONS);
-        let mut proof_inputs = Vec::with_capacity(NUM_TRANSACTIONS * 8);
+        let mut proof_inputs = Vec::with_capacity(NUM_TRANSACTIONS * 8 + NUM_POLICY_INPUTS);
         let mut traces = Vec::with_capacity(NUM_COMMITMENTS);
-        // The compiler groups bitdecomps by bits, so we have to store both used ones separately first
-        let mut bitdecomps1 = Vec::with_capacity(NUM_TRANSACTIONS * 2);
-        let mut bitdecomps2 = Vec::with_capacity(NUM_TRANSACTIONS);
+        let mut bitdecomps = BitDecomps::new();
 
-        let my_id = PartyID::try_from(nets[0].id())?;
+        let my_id = rep3_states[0].id;
 
         let result = thread::scope(|scope| {
             let mut handles = Vec::with_capacity(3);
-            for (action, nets, rep3_state) in
-                izip!(queue, nets.chunks_exact(2), rep3_states.iter_mut())
+            for (index, (action, nets, rep3_state)) in
+                izip!(queue, nets.chunks_exact(2), rep3_states.iter_mut()).enumerate()
             {
                 match action {
                     Action::Transfer(sender, receiver, amount, amount_blinding) => {
-                        let (sender_old, sender_new, receiver_old, receiver_new) =
-                            self.transaction(sender, receiver, amount, rep3_state)?;
+                        let (sender_old, sender_new, receiver_old, receiver_new) = self
+                            .transaction(sender, receiver, amount, rep3_state)
+                            .map_err(ActionQueueError::at(index))?;
                         let handle = scope.spawn(move || {
                             Self::process_transaction(
                                 sender_old,
@@ -401,6 +563,7 @@ where
                                 receiver_new,
                                 amount,
                                 amount_blinding,
+                                limits,
                                 &nets[0],
                                 &nets[1],
                                 rep3_state,
@@ -418,6 +581,7 @@ where
                                 receiver_old,
                                 receiver_new,
                                 amount,
+                                limits,
                                 &nets[0],
                                 rep3_state,
                             )
@@ -427,8 +591,9 @@ where
                     Action::Withdraw(sender, amount) => {
                         let amount_shared =
                             rep3::arithmetic::promote_to_trivial_share(my_id, amount);
-                        let (sender_old, sender_new) =
-                            self.withdraw(sender, amount_shared, rep3_state)?;
+                        let (sender_old, sender_new) = self
+                            .withdraw(sender, amount_shared, rep3_state)
+                            .map_err(ActionQueueError::at(index))?;
                         let handle = scope.spawn(move || {
                             Self::process_withdraw(
                                 sender_old, sender_new, amount, &nets[0], rep3_state,
@@ -436,181 +601,805 @@ where
                         });
                         handles.push(handle);
                     }
+                    Action::PrivateWithdraw(sender, amount, amount_blinding) => {
+                        let (sender_old, sender_new) = self
+                            .withdraw(sender, amount, rep3_state)
+                            .map_err(ActionQueueError::at(index))?;
+                        // The amount is moved to a fresh balance with commitment commit(amount, 0), such that the smart contract can check the amount which is opened for the payout
+                        let receiver_new =
+                            DepositValueShare::new(amount, Rep3PrimeFieldShare::zero_share());
+                        let handle = scope.spawn(move || {
+                            Self::process_transaction(
+                                sender_old,
+                                None,
+                                sender_new,
+                                receiver_new,
+                                amount,
+                                amount_blinding,
+                                limits,
+                                &nets[0],
+                                &nets[1],
+                                rep3_state,
+                            )
+                        });
+                        handles.push(handle);
+                    }
                     Action::Dummy => {
                         let handle = scope.spawn(move || Self::process_dummy());
                         handles.push(handle);
                     }
-                    _ => eyre::bail!("Unsupported action in batched transaction processing"),
+                    Action::Invalid => {
+                        return Err(ActionQueueError::UnsupportedAction { index });
+                    }
                 }
             }
 
             for handle in handles {
-                let (sender_new_, receiver_new_, inputs_, traces_, decomps1, decomps2) =
-                    handle.join().map_err(|_| {
-                        eyre::eyre!("A thread panicked while processing a transaction")
-                    })??;
+                let (sender_new_, receiver_new_, inputs_, traces_, decomps) =
+                    handle.join().map_err(|_| ProofError::Panicked)??;
                 sender_new.push(sender_new_);
                 receiver_new.push(receiver_new_);
                 proof_inputs.extend(inputs_);
                 traces.extend(traces_);
-                bitdecomps1.extend(decomps1);
-                bitdecomps2.extend(decomps2);
+                bitdecomps.append(decomps);
             }
-            Result::<_, eyre::Report>::Ok(())
+            Result::<_, ActionQueueError<K>>::Ok(())
         });
         result?;
-
-        bitdecomps1.extend(bitdecomps2);
-        let bitdecomps = bitdecomps1;
+        proof_inputs.extend(limits.inputs());
 
         let r1cs = r1cs::trace_to_r1cs_witness_with_bitdecomp_witness(
             proof_inputs,
             traces,
-            bitdecomps,
+            bitdecomps
+                .into_witness(&batched_range_checks())
+                .map_err(|err| ProofError::InvalidCircuit(format!("{err:#}")))?,
             proof_schema,
             &nets[0],
             &nets[1],
             &mut rep3_states[0],
         )
-        .context("while translating witness to R1CS")?;
+        .context("while translating witness to R1CS")
+        .map_err(ProofError::WitnessExtension)?;
 
         let witness = r1cs::r1cs_witness_to_cogroth16(proof_schema, r1cs, rep3_states[0].id);
 
         Ok((sender_new, receiver_new, witness))
     }
 
+    // Opens the amounts of the private withdraws in the queue, given the new receiver values returned by processing the queue. Should only be called once the proof was created, since the amounts are only revealed for the payout.
+    pub fn open_payouts<N: Network>(
+        queue: &[Action<K>],
+        receiver_new: &[DepositValueShare<F>],
+        net: &N,
+    ) -> eyre::Result<Vec<F>> {
+        if queue.len() != receiver_new.len() {
+            eyre::bail!("Invalid queue or receiver length");
+        }
+        let amounts = queue
+            .iter()
+            .zip(receiver_new)
+            .filter(|(action, _)| matches!(action, Action::PrivateWithdraw(_, _, _)))
+            .map(|(_, receiver_new)| receiver_new.amount)
+            .collect::<Vec<_>>();
+        rep3::arithmetic::open_vec(&amounts, net)
+    }
+
+    // Collects the additive shares of the values disclosed to an auditor, given the new sender and receiver values returned by processing the queue. The shares are not opened, each party encrypts its own shares to the auditor.
+    pub fn audit_shares(
+        queue: &[Action<K>],
+        sender_new: &[DepositValueShare<F>],
+        receiver_new: &[DepositValueShare<F>],
+    ) -> eyre::Result<Vec<AuditShare<K>>> {
+        if queue.len() != sender_new.len() || queue.len() != receiver_new.len() {
+            eyre::bail!("Invalid queue or sender/receiver length");
+        }
+        let mut shares = Vec::with_capacity(queue.len() * 3);
+        for (index, (action, sender_new, receiver_new)) in
+            izip!(queue, sender_new, receiver_new).enumerate()
+        {
+            match action {
+                Action::Deposit(receiver, _) => {
+                    shares.push(AuditShare::receiver(index, receiver.clone(), receiver_new));
+                }
+                Action::Withdraw(sender, _) => {
+                    shares.push(AuditShare::sender(index, sender.clone(), sender_new));
+                }
+                Action::Transfer(sender, receiver, amount, amount_blinding) => {
+                    shares.push(AuditShare::sender(index, sender.clone(), sender_new));
+                    shares.push(AuditShare::receiver(index, receiver.clone(), receiver_new));
+                    shares.push(AuditShare::TransferAmount(
+                        index,
+                        amount.a,
+                        amount_blinding.a,
+                    ));
+                }
+                Action::PrivateWithdraw(sender, amount, amount_blinding) => {
+                    shares.push(AuditShare::sender(index, sender.clone(), sender_new));
+                    shares.push(AuditShare::TransferAmount(
+                        index,
+                        amount.a,
+                        amount_blinding.a,
+                    ));
+                }
+                Action::Dummy => {}
+                Action::Invalid => eyre::bail!("Invalid action in queue"),
+            }
+        }
+        Ok(shares)
+    }
+
+    // Same as process_queue_with_r1cs_witness, the map is rolled back if witness generation or proving fails
     #[expect(clippy::type_complexity)]
     pub fn process_queue_with_groth16_proof<N: Network>(
         &mut self,
         queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
         proof_schema: &NoirProofScheme<F>,
         cs: &ConstraintMatrices<F>,
         pk: &ProvingKey<Curve>,
         nets: &[N; NUM_TRANSACTIONS * 2],
         rep3_states: &mut [Rep3State; NUM_TRANSACTIONS],
-    ) -> eyre::Result<(
-        Vec<DepositValueShare<F>>,
-        Vec<DepositValueShare<F>>,
-        Proof<Curve>,
-        Vec<F>,
-        Duration,
-    )> {
-        let (sender_new, receiver_new, witness) =
-            self.process_queue_with_r1cs_witness(queue, proof_schema, nets, rep3_states)?;
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            Proof<Curve>,
+            Vec<F>,
+            Duration,
+        ),
+        ActionQueueError<K>,
+    > {
+        let journal = self.journal(&queue);
+        let result = self
+            .process_queue_with_r1cs_witness_inner(queue, limits, proof_schema, nets, rep3_states)
+            .and_then(|(sender_new, receiver_new, witness)| {
+                let start = Instant::now();
+                let (proof, public_inputs) = r1cs::prove(cs, pk, witness, &nets[0], &nets[1])
+                    .context("while generating Groth16 proof")
+                    .map_err(ProofError::Prover)?;
+                super::check_public_inputs_agree(&public_inputs, &nets[0])?;
+                let duration = start.elapsed();
+                Ok((sender_new, receiver_new, proof, public_inputs, duration))
+            });
+        if result.is_err() {
+            self.rollback(journal);
+        }
+        result
+    }
 
-        let start = Instant::now();
-        let (proof, public_inputs) = r1cs::prove(cs, pk, witness, &nets[0], &nets[1])
-            .context("while generating Groth16 proof")?;
-        let duration = start.elapsed();
+    // Applies the queue to the map and collects the inputs of the packed witness generation: The proof inputs, the inputs of the commitments of the whole queue, and the values to decompose for the range checks
+    #[expect(clippy::type_complexity)]
+    fn packed_queue_inputs(
+        &mut self,
+        queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
+        rep3_state: &mut Rep3State,
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            Vec<Rep3AcvmType<F>>,
+            [Rep3PrimeFieldShare<F>; NUM_COMMITMENTS * 2],
+            BitDecomps<Rep3PrimeFieldShare<F>>,
+        ),
+        ActionQueueError<K>,
+    > {
+        if queue.len() != NUM_TRANSACTIONS {
+            return Err(ActionQueueError::InvalidLength(queue.len()));
+        }
+        let mut sender_new = Vec::with_capacity(NUM_TRANSACTIONS);
+        let mut receiver_new = Vec::with_capacity(NUM_TRANSACTIONS);
+        let mut proof_inputs = Vec::with_capacity(NUM_TRANSACTIONS * 8 + NUM_POLICY_INPUTS);
+        let mut commitment_inputs = [Rep3PrimeFieldShare::zero_share(); NUM_COMMITMENTS * 2]; // each commitment needs 2 inputs
+        let mut decomps = BitDecomps::new();
+
+        let my_id = rep3_state.id;
+        let zero = Rep3PrimeFieldShare::zero_share();
+
+        for (index, (action, commitments)) in queue
+            .into_iter()
+            .zip(commitment_inputs.chunks_exact_mut(NUM_TRANSACTION_COMMITMENTS * 2))
+            .enumerate()
+        {
+            let (sender_old, sender_new_, receiver_old, receiver_new_, amount, amount_blinding) =
+                match action {
+                    Action::Transfer(sender, receiver, amount, amount_blinding) => {
+                        let (sender_old, sender_new_, receiver_old, receiver_new_) = self
+                            .transaction(sender, receiver, amount, rep3_state)
+                            .map_err(ActionQueueError::at(index))?;
+                        (
+                            sender_old,
+                            sender_new_,
+                            receiver_old,
+                            receiver_new_,
+                            amount,
+                            amount_blinding,
+                        )
+                    }
+                    Action::PrivateWithdraw(sender, amount, amount_blinding) => {
+                        let (sender_old, sender_new_) = self
+                            .withdraw(sender, amount, rep3_state)
+                            .map_err(ActionQueueError::at(index))?;
+                        // The same as in process_queue_with_r1cs_witness
+                        let receiver_new_ = DepositValueShare::new(amount, zero);
+                        (
+                            sender_old,
+                            sender_new_,
+                            None,
+                            receiver_new_,
+                            amount,
+                            amount_blinding,
+                        )
+                    }
+                    Action::Deposit(receiver, amount) => {
+                        let amount_shared =
+                            rep3::arithmetic::promote_to_trivial_share(my_id, amount);
+                        let (receiver_old, receiver_new_) =
+                            self.deposit(receiver, amount_shared, rep3_state);
+                        let (inputs, receiver_old_amount, receiver_old_blinding) =
+                            Self::get_deposit_input_public_amount(
+                                receiver_old,
+                                amount,
+                                F::zero(),
+                                receiver_new_.blinding,
+                            );
+                        proof_inputs.extend(inputs);
+                        commitments.copy_from_slice(&[
+                            amount_shared,
+                            zero,
+                            amount_shared,
+                            zero,
+                            zero,
+                            zero,
+                            receiver_old_amount,
+                            receiver_old_blinding,
+                            receiver_new_.amount,
+                            receiver_new_.blinding,
+                        ]);
+                        decomps.push(
+                            NUM_WITHDRAW_NEW_BITS,
+                            rep3::arithmetic::sub_public_by_shared(
+                                limits.max_balance(),
+                                receiver_new_.amount,
+                                my_id,
+                            ),
+                        );
+                        sender_new.push(DepositValueShare::new(zero, zero));
+                        receiver_new.push(receiver_new_);
+                        continue;
+                    }
+                    Action::Withdraw(sender, amount) => {
+                        let amount_shared =
+                            rep3::arithmetic::promote_to_trivial_share(my_id, amount);
+                        let (sender_old, sender_new_) = self
+                            .withdraw(sender, amount_shared, rep3_state)
+                            .map_err(ActionQueueError::at(index))?;
+                        proof_inputs.extend(Self::get_withdraw_input_public_amount(
+                            sender_old.to_owned(),
+                            amount,
+                            F::zero(),
+                            sender_new_.blinding,
+                        ));
+                        commitments.copy_from_slice(&[
+                            amount_shared,
+                            zero,
+                            sender_old.amount,
+                            sender_old.blinding,
+                            sender_new_.amount,
+                            sender_new_.blinding,
+                            zero,
+                            zero,
+                            amount_shared,
+                            zero,
+                        ]);
+                        decomps.push(NUM_WITHDRAW_NEW_BITS, sender_new_.amount);
+                        sender_new.push(sender_new_);
+                        receiver_new.push(DepositValueShare::new(amount_shared, zero));
+                        continue;
+                    }
+                    Action::Dummy => {
+                        proof_inputs.extend(vec![
+                            Rep3AcvmType::from(F::zero());
+                            TRANSACTION_INPUTS.len()
+                        ]);
+                        sender_new.push(DepositValueShare::new(zero, zero));
+                        receiver_new.push(DepositValueShare::new(zero, zero));
+                        continue;
+                    }
+                    Action::Invalid => {
+                        return Err(ActionQueueError::UnsupportedAction { index });
+                    }
+                };
 
-        Ok((sender_new, receiver_new, proof, public_inputs, duration))
+            // Transfers and private withdraws
+            let (inputs, receiver_old_amount, receiver_old_blinding) = Self::get_transaction_input(
+                sender_old.to_owned(),
+                receiver_old,
+                amount,
+                amount_blinding,
+                sender_new_.blinding,
+                receiver_new_.blinding,
+            );
+            proof_inputs.extend(inputs);
+            commitments.copy_from_slice(&[
+                amount,
+                amount_blinding,
+                sender_old.amount,
+                sender_old.blinding,
+                sender_new_.amount,
+                sender_new_.blinding,
+                receiver_old_amount,
+                receiver_old_blinding,
+                receiver_new_.amount,
+                receiver_new_.blinding,
+            ]);
+            decomps.push(NUM_AMOUNT_BITS, amount);
+            decomps.push(NUM_WITHDRAW_NEW_BITS, sender_new_.amount);
+            decomps.push(
+                NUM_AMOUNT_BITS,
+                rep3::arithmetic::sub_public_by_shared(limits.max_transfer(), amount, my_id),
+            );
+            decomps.push(
+                NUM_WITHDRAW_NEW_BITS,
+                rep3::arithmetic::sub_public_by_shared(
+                    limits.max_balance(),
+                    receiver_new_.amount,
+                    my_id,
+                ),
+            );
+            sender_new.push(sender_new_);
+            receiver_new.push(receiver_new_);
+        }
+        proof_inputs.extend(limits.inputs());
+
+        Ok((
+            sender_new,
+            receiver_new,
+            proof_inputs,
+            commitment_inputs,
+            decomps,
+        ))
+    }
+
+    // The throughput mode of process_queue_with_r1cs_witness: Instead of processing each action in its own thread with its own Poseidon2 precomputation and bit decompositions, the commitments and bit decompositions of the whole queue are computed in one packed call each. This only needs one pair of networks and far fewer rounds, at the cost of also computing the commitments of public values in MPC. If a pool is given, the Poseidon2 precomputation of the commitments is taken from it instead of being computed online.
+    #[expect(clippy::type_complexity, clippy::too_many_arguments)]
+    pub fn process_queue_packed_with_r1cs_witness<N: Network>(
+        &mut self,
+        queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
+        proof_schema: &NoirProofScheme<F>,
+        pool: Option<&PrecomputationPool>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            Rep3SharedWitness<F>,
+        ),
+        ActionQueueError<K>,
+    > {
+        let journal = self.journal(&queue);
+        let result = self.process_queue_packed_with_r1cs_witness_inner(
+            queue,
+            limits,
+            proof_schema,
+            pool,
+            net0,
+            net1,
+            rep3_state,
+        );
+        if result.is_err() {
+            self.rollback(journal);
+        }
+        result
+    }
+
+    #[expect(clippy::type_complexity, clippy::too_many_arguments)]
+    fn process_queue_packed_with_r1cs_witness_inner<N: Network>(
+        &mut self,
+        queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
+        proof_schema: &NoirProofScheme<F>,
+        pool: Option<&PrecomputationPool>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            Rep3SharedWitness<F>,
+        ),
+        ActionQueueError<K>,
+    > {
+        let (sender_new, receiver_new, proof_inputs, commitment_inputs, decomps) =
+            self.packed_queue_inputs(queue, limits, rep3_state)?;
+
+        // One packed Poseidon2 trace generation and one packed bit decomposition for the whole queue
+        let traces = Self::packed_commitment_traces(commitment_inputs, pool, net0, rep3_state)?;
+        let bitdecomps = decomps
+            .decompose_compose(net0, rep3_state)?
+            .into_witness(&batched_range_checks())
+            .map_err(|err| ProofError::InvalidCircuit(format!("{err:#}")))?;
+
+        let r1cs = r1cs::trace_to_r1cs_witness_with_bitdecomp_witness(
+            proof_inputs,
+            traces,
+            bitdecomps,
+            proof_schema,
+            net0,
+            net1,
+            rep3_state,
+        )
+        .context("while translating witness to R1CS")
+        .map_err(ProofError::WitnessExtension)?;
+
+        let witness = r1cs::r1cs_witness_to_cogroth16(proof_schema, r1cs, rep3_state.id);
+
+        Ok((sender_new, receiver_new, witness))
+    }
+
+    // The Poseidon2 traces of the commitments of the whole queue. The precomputation is taken from the pool if one is given, otherwise it is computed online.
+    fn packed_commitment_traces<N: Network>(
+        commitment_inputs: [Rep3PrimeFieldShare<F>; NUM_COMMITMENTS * 2],
+        pool: Option<&PrecomputationPool>,
+        net0: &N,
+        rep3_state: &mut Rep3State,
+    ) -> eyre::Result<Vec<Vec<Rep3AcvmType<F>>>> {
+        match pool {
+            Some(pool) => {
+                super::poseidon2_commitment_helper_with_precomputation::<NUM_COMMITMENTS, _, _, _>(
+                    commitment_inputs,
+                    pool.take(NUM_COMMITMENTS)?,
+                    net0,
+                    rep3_state,
+                )
+            }
+            None => super::poseidon2_commitment_helper::<NUM_COMMITMENTS, _, _, _>(
+                commitment_inputs,
+                net0,
+                rep3_state,
+            ),
+        }
+    }
+
+    // Same as process_queue_with_groth16_proof, but with the packed witness generation
+    #[expect(clippy::type_complexity, clippy::too_many_arguments)]
+    pub fn process_queue_packed_with_groth16_proof<N: Network>(
+        &mut self,
+        queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
+        proof_schema: &NoirProofScheme<F>,
+        cs: &ConstraintMatrices<F>,
+        pk: &ProvingKey<Curve>,
+        pool: Option<&PrecomputationPool>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            Proof<Curve>,
+            Vec<F>,
+            Duration,
+        ),
+        ActionQueueError<K>,
+    > {
+        let journal = self.journal(&queue);
+        let result = self
+            .process_queue_packed_with_r1cs_witness_inner(
+                queue,
+                limits,
+                proof_schema,
+                pool,
+                net0,
+                net1,
+                rep3_state,
+            )
+            .and_then(|(sender_new, receiver_new, witness)| {
+                let start = Instant::now();
+                let (proof, public_inputs) = r1cs::prove(cs, pk, witness, net0, net1)
+                    .context("while generating Groth16 proof")
+                    .map_err(ProofError::Prover)?;
+                super::check_public_inputs_agree(&public_inputs, net0)?;
+                let duration = start.elapsed();
+                Ok((sender_new, receiver_new, proof, public_inputs, duration))
+            });
+        if result.is_err() {
+            self.rollback(journal);
+        }
+        result
+    }
+
+    // The action queue with an UltraHonk proof instead of Groth16, i.e., without a circuit-specific trusted setup at the cost of a larger proof and higher verification gas. The witness is generated as in the packed mode, the bit decompositions are part of the UltraHonk witness extension. The public inputs have the same layout as the ones of the Groth16 proof. As in the packed mode, the Poseidon2 precomputation is taken from the pool if one is given.
+    #[expect(clippy::too_many_arguments, clippy::type_complexity)]
+    pub fn process_queue_with_ultrahonk_proof<N: Network>(
+        &mut self,
+        queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
+        program_artifact: ProgramArtifact,
+        constraint_system: &AcirFormat<F>,
+        prover_crs: &ProverCrs<ark_bn254::G1Projective>,
+        verifying_key: &VerifyingKeyBarretenberg<ark_bn254::G1Projective>,
+        pool: Option<&PrecomputationPool>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            HonkProof<U256>,
+            Vec<U256>,
+            Duration,
+        ),
+        ActionQueueError<K>,
+    > {
+        let journal = self.journal(&queue);
+        let result = self
+            .packed_queue_inputs(queue, limits, rep3_state)
+            .and_then(
+                |(sender_new, receiver_new, proof_inputs, commitment_inputs, _)| {
+                    let traces =
+                        Self::packed_commitment_traces(commitment_inputs, pool, net0, rep3_state)?;
+                    let witness_stack = ultrahonk::r1cs_witness_extension_with_helper(
+                        proof_inputs,
+                        traces,
+                        program_artifact,
+                        net0,
+                        net1,
+                    )
+                    .context("while running witness extension")
+                    .map_err(ProofError::WitnessExtension)?;
+                    let witness = co_noir::witness_stack_to_vec_rep3(witness_stack);
+
+                    let start = Instant::now();
+                    let (proof, public_inputs) = ultrahonk::prove(
+                        constraint_system,
+                        witness,
+                        prover_crs,
+                        verifying_key,
+                        net0,
+                        net1,
+                    )
+                    .context("while generating UltraHonk proof")
+                    .map_err(ProofError::Prover)?;
+                    super::check_honk_public_inputs_agree(&public_inputs, net0)?;
+                    let duration = start.elapsed();
+                    Ok((sender_new, receiver_new, proof, public_inputs, duration))
+                },
+            );
+        if result.is_err() {
+            self.rollback(journal);
+        }
+        result
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{data_structure::DepositValue, proof::TestConfig};
+    use crate::{
+        data_structure::{DepositValue, DepositValuePlain},
+        faulty_network::{Fault, FaultInjector, FaultyNetwork},
+        key::MapKey,
+        merkle_tree::CommitmentTree,
+        proof::{NUM_AMOUNT_BITS, TestConfig, plain_commitment},
+        three_party::ThreeParty,
+    };
     use ark_ff::UniformRand;
-    use mpc_core::protocols::rep3::conversion::A2BType;
-    use mpc_net::local::LocalNetwork;
+    use proptest::prelude::*;
+    use proptest::test_runner::{Config as ProptestConfig, TestCaseError, TestRunner};
     use rand::Rng;
-    use std::sync::Arc;
+    use rand_chacha::{ChaCha12Rng, rand_core::SeedableRng};
+    use std::collections::HashMap;
+
+    // The amounts of the tests fit into u128, also for wider amounts
+    const MAX_AMOUNT: u128 = u128::MAX >> 128usize.saturating_sub(NUM_AMOUNT_BITS);
+    const NUM_EXISTING_KEYS: usize = 8;
+    const NUM_NEW_KEYS: usize = 4;
+
+    // Keys are indices into the existing keys followed by the new keys
+    #[derive(Debug, Clone)]
+    enum PlainAction {
+        Deposit(usize, u128),
+        Withdraw(usize, u128),
+        Transfer(usize, usize, u128),
+        PrivateWithdraw(usize, u128),
+        Dummy,
+    }
+
+    // The view of the smart contract on a queued action, i.e., the ActionQuery struct
+    enum ContractAction {
+        Deposit(F, F),         // Receiver, amount
+        Withdraw(F, F),        // Sender, amount
+        Transfer(F, F, F),     // Sender, receiver, amount commitment
+        PrivateWithdraw(F, F), // Sender, amount commitment
+        Dummy,
+    }
+
+    fn amount_strategy() -> impl Strategy<Value = u128> {
+        prop_oneof![Just(0), Just(MAX_AMOUNT), 0..1000u128, 0..=MAX_AMOUNT]
+    }
+
+    fn action_strategy() -> impl Strategy<Value = PlainAction> {
+        let key = 0..NUM_EXISTING_KEYS + NUM_NEW_KEYS;
+        prop_oneof![
+            (key.clone(), amount_strategy()).prop_map(|(k, a)| PlainAction::Deposit(k, a)),
+            (key.clone(), amount_strategy()).prop_map(|(k, a)| PlainAction::Withdraw(k, a)),
+            (key.clone(), key.clone(), amount_strategy())
+                .prop_map(|(s, r, a)| PlainAction::Transfer(s, r, a)),
+            (key.clone(), amount_strategy()).prop_map(|(k, a)| PlainAction::Transfer(k, k, a)),
+            (key, amount_strategy()).prop_map(|(k, a)| PlainAction::PrivateWithdraw(k, a)),
+            Just(PlainAction::Dummy),
+        ]
+    }
+
+    // Mirrors processMPC of the smart contract: Assembles the public inputs of the proof from the on-chain commitments, the commitments posted by the MPC network, and the stored policy limits, and updates the on-chain commitments
+    fn contract_process_mpc(
+        actions: &[ContractAction],
+        commitments: &[F],
+        onchain: &mut HashMap<F, F>,
+    ) -> Result<Vec<F>, TestCaseError> {
+        let zero = PrivateDeposit::<F, DepositValueShare<F>>::zero_commitment();
+        let mut public_inputs = Vec::with_capacity(NUM_COMMITMENTS + NUM_POLICY_INPUTS);
+        for (action, commitments) in actions.iter().zip(commitments.chunks_exact(2)) {
+            let (sender_new, receiver_new) = (commitments[0], commitments[1]);
+            match action {
+                ContractAction::Deposit(receiver, amount) => {
+                    prop_assert!(sender_new.is_zero());
+                    let amount_commitment = plain_commitment(*amount, F::zero());
+                    let receiver_old = onchain.get(receiver).copied().unwrap_or(zero);
+                    onchain.insert(*receiver, receiver_new);
+                    public_inputs.extend([
+                        amount_commitment,
+                        zero,
+                        receiver_old,
+                        receiver_new,
+                        amount_commitment,
+                    ]);
+                }
+                ContractAction::Withdraw(sender, amount) => {
+                    prop_assert!(receiver_new.is_zero());
+                    let amount_commitment = plain_commitment(*amount, F::zero());
+                    let sender_old = onchain.insert(*sender, sender_new).unwrap_or_default();
+                    public_inputs.extend([
+                        sender_old,
+                        sender_new,
+                        zero,
+                        amount_commitment,
+                        amount_commitment,
+                    ]);
+                }
+                ContractAction::Transfer(sender, receiver, amount_commitment) => {
+                    let sender_old = onchain.get(sender).copied().unwrap_or_default();
+                    let receiver_old = onchain.get(receiver).copied().unwrap_or(zero);
+                    onchain.insert(*sender, sender_new);
+                    onchain.insert(*receiver, receiver_new);
+                    public_inputs.extend([
+                        sender_old,
+                        sender_new,
+                        receiver_old,
+                        receiver_new,
+                        *amount_commitment,
+                    ]);
+                }
+                ContractAction::PrivateWithdraw(sender, amount_commitment) => {
+                    // The MPC network posts the opened amount instead of the receiver commitment
+                    let payout = receiver_new;
+                    prop_assert!(payout <= F::from(MAX_AMOUNT));
+                    let sender_old = onchain.insert(*sender, sender_new).unwrap_or_default();
+                    public_inputs.extend([
+                        sender_old,
+                        sender_new,
+                        zero,
+                        plain_commitment(payout, F::zero()),
+                        *amount_commitment,
+                    ]);
+                }
+                ContractAction::Dummy => {
+                    prop_assert!(sender_new.is_zero());
+                    prop_assert!(receiver_new.is_zero());
+                    public_inputs.extend([zero; NUM_TRANSACTION_COMMITMENTS]);
+                }
+            }
+        }
+        public_inputs.extend(PolicyLimits::default().public_inputs());
+        Ok(public_inputs)
+    }
+
+    #[test]
+    fn zero_commitment_test() {
+        assert_eq!(
+            PrivateDeposit::<F, DepositValueShare<F>>::zero_commitment(),
+            plain_commitment(F::zero(), F::zero())
+        );
+    }
+
+    // Proves the queues of the three parties with the batched Groth16 circuit. Returns the proof, the public inputs, and the opened payouts of private withdraws.
+    fn prove_queues<K: MapKey>(
+        parties: &mut ThreeParty,
+        map_shares: &mut [PrivateDeposit<K, DepositValueShare<F>>; 3],
+        queues: [Vec<Action<K>>; 3],
+        proof_schema: &NoirProofScheme<F>,
+        cs: &ConstraintMatrices<F>,
+        pk: &ProvingKey<Curve>,
+    ) -> eyre::Result<(Proof<Curve>, Vec<F>, Vec<F>)> {
+        let [map0, map1, map2] = map_shares.each_mut();
+        let [queue0, queue1, queue2] = queues;
+        parties.run_public(
+            NUM_TRANSACTIONS,
+            [(map0, queue0), (map1, queue1), (map2, queue2)],
+            |(map, queue), nets, rep3_states| {
+                let (_sender_new, receiver_new, proof, public_inputs, _proof_duration) = map
+                    .process_queue_with_groth16_proof(
+                        queue.clone(),
+                        &PolicyLimits::default(),
+                        proof_schema,
+                        cs,
+                        pk,
+                        nets.try_into()?,
+                        rep3_states.try_into()?,
+                    )?;
+                let payouts = PrivateDeposit::open_payouts(&queue, &receiver_new, &nets[0])?;
+                Ok((proof, public_inputs, payouts))
+            },
+        )
+    }
 
     #[test]
     fn actionqueue_test() {
         // TestConfig::install_tracing();
+        let mut parties = ThreeParty::new(NUM_TRANSACTIONS * 2, TestConfig::SEED);
 
         // Init Groth16
         // Read constraint system
         let pa = TestConfig::get_transaction_batched_program_artifact().unwrap();
 
         // Get the R1CS proof schema
-        let mut rng = rand::thread_rng();
-        let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, &mut rng).unwrap();
-        let proof_schema: Arc<
-            NoirProofScheme<ark_ff::Fp<ark_ff::MontBackend<ark_bn254::FrConfig, 4>, 4>>,
-        > = Arc::new(proof_schema);
-        let pk = Arc::new(pk);
-        let cs = Arc::new(cs);
+        let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, parties.rng()).unwrap();
         let size = proof_schema.size();
         println!(
             "R1CS size: constraints = {}, witnesses = {}",
             size.0, size.1
         );
 
-        // Init networks
-        let mut test_networks0 = Vec::with_capacity(NUM_TRANSACTIONS * 2);
-        let mut test_networks1 = Vec::with_capacity(NUM_TRANSACTIONS * 2);
-        let mut test_networks2 = Vec::with_capacity(NUM_TRANSACTIONS);
-        for _ in 0..(NUM_TRANSACTIONS * 2) {
-            let [net0, net1, net2] = LocalNetwork::new(3).try_into().unwrap();
-            test_networks0.push(net0);
-            test_networks1.push(net1);
-            test_networks2.push(net2);
-        }
-
         // Get a random map and its shares
-        let mut rng = rand::thread_rng();
         let mut plain_map =
-            TestConfig::get_random_plain_map::<F, _>(TestConfig::NUM_ITEMS, &mut rng);
-        let mut map_shares = plain_map.share(&mut rng);
+            TestConfig::get_random_plain_map::<F, _>(TestConfig::NUM_ITEMS, parties.rng());
+        let mut map_shares = parties.share_map(&plain_map);
 
         // The actual testcase
         // We test a batch with 3 transactions deposit to first new key, transfer between first and second new key, withdraw from second new key
         for _ in 0..TestConfig::TEST_RUNS {
             // Get two new random keys
-            let key1 = TestConfig::get_random_new_key(&plain_map, &mut rng);
-            let key2 = TestConfig::get_random_new_key(&plain_map, &mut rng);
-            let amount = F::from(rng.r#gen::<u64>());
-            let amount_blinding = F::rand(&mut rng);
+            let key1 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+            let key2 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+            let amount = F::from(parties.rng().r#gen::<u64>());
+            let amount_blinding = F::rand(parties.rng());
 
             // Share the amount and the blinding
-            let amount_share = rep3::share_field_element(amount, &mut rng);
-            let amount_blinding_share = rep3::share_field_element(amount_blinding, &mut rng);
+            let amount_share = parties.share_field_element(amount);
+            let amount_blinding_share = parties.share_field_element(amount_blinding);
 
             // Action queue per party
-            let mut action_queue_0 = Vec::with_capacity(NUM_TRANSACTIONS);
-            let mut action_queue_1 = Vec::with_capacity(NUM_TRANSACTIONS);
-            let mut action_queue_2 = Vec::with_capacity(NUM_TRANSACTIONS);
-
-            // Deposit to key1
-            action_queue_0.push(Action::Deposit(key1, amount));
-            action_queue_1.push(Action::Deposit(key1, amount));
-            action_queue_2.push(Action::Deposit(key1, amount));
-
-            // Transfer from key1 to key2
-            action_queue_0.push(Action::Transfer(
-                key1,
-                key2,
-                amount_share[0],
-                amount_blinding_share[0],
-            ));
-            action_queue_1.push(Action::Transfer(
-                key1,
-                key2,
-                amount_share[1],
-                amount_blinding_share[1],
-            ));
-            action_queue_2.push(Action::Transfer(
-                key1,
-                key2,
-                amount_share[2],
-                amount_blinding_share[2],
-            ));
-
-            // Withdraw from key2
-            action_queue_0.push(Action::Withdraw(key2, amount));
-            action_queue_1.push(Action::Withdraw(key2, amount));
-            action_queue_2.push(Action::Withdraw(key2, amount));
-
-            // Batch queues
-            debug_assert_eq!(action_queue_0.len(), action_queue_1.len());
-            debug_assert_eq!(action_queue_0.len(), action_queue_2.len());
-            for _ in action_queue_0.len()..NUM_TRANSACTIONS {
-                action_queue_0.push(Action::Dummy);
-                action_queue_1.push(Action::Dummy);
-                action_queue_2.push(Action::Dummy);
+            let mut action_queues: [Vec<Action<F>>; 3] = Default::default();
+            for (queue, amount_share, amount_blinding_share) in
+                izip!(&mut action_queues, amount_share, amount_blinding_share)
+            {
+                queue.push(Action::Deposit(key1, amount));
+                queue.push(Action::Transfer(
+                    key1,
+                    key2,
+                    amount_share,
+                    amount_blinding_share,
+                ));
+                queue.push(Action::Withdraw(key2, amount));
+                queue.resize(NUM_TRANSACTIONS, Action::Dummy);
             }
 
             // Update plain map (just amount, ignore blinding)
@@ -618,66 +1407,684 @@ mod tests {
             plain_map.insert(key2, DepositValue::new(F::zero(), F::zero()));
 
             // Do the MPC work
-            let (proof, public_inputs) = thread::scope(|scope| {
-                let mut handles = Vec::with_capacity(3);
-                for (nets, map, transaction) in izip!(
-                    [
-                        &mut test_networks0,
-                        &mut test_networks1,
-                        &mut test_networks2
-                    ],
-                    &mut map_shares,
-                    [action_queue_0, action_queue_1, action_queue_2]
-                ) {
-                    let proof_schema = proof_schema.clone();
-                    let cs = cs.clone();
-                    let pk = pk.clone();
-                    let handle = scope.spawn(move || {
-                        let mut rep3_states = Vec::with_capacity(nets.len() / 2);
-                        for net in nets.iter().take(nets.len() / 2) {
-                            rep3_states.push(Rep3State::new(net, A2BType::default()).unwrap());
-                        }
-
-                        let (_sender_read, _receiver_read, proof, public_inputs, _proof_duration) =
-                            map.process_queue_with_groth16_proof(
-                                transaction,
-                                &proof_schema,
-                                &cs,
-                                &pk,
-                                nets.as_slice().try_into().unwrap(),
-                                rep3_states.as_mut_slice().try_into().unwrap(),
-                            )
-                            .unwrap();
-
-                        (proof, public_inputs)
-                    });
-                    handles.push(handle);
-                }
-
-                let (proof0, public_inputs0) = handles.remove(0).join().unwrap();
-                for handle in handles {
-                    let (proof, public_inputs) = handle.join().unwrap();
-                    assert_eq!(proof, proof0);
-                    assert_eq!(public_inputs, public_inputs0);
-                }
-                (proof0, public_inputs0)
-            });
+            let (proof, public_inputs, _payouts) = prove_queues(
+                &mut parties,
+                &mut map_shares,
+                action_queues,
+                &proof_schema,
+                &cs,
+                &pk,
+            )
+            .unwrap();
 
             // Verifiy the results
             assert!(r1cs::verify(&pk.vk, &proof, &public_inputs).unwrap());
         }
 
         // Finally, compare the maps
+        let result = PrivateDeposit::reconstruct(map_shares).unwrap();
+        assert_eq!(result.len(), plain_map.len());
+        for (key, plain_value) in plain_map.into_iter() {
+            assert_eq!(result.get(&key).unwrap().amount, plain_value.amount);
+        }
+    }
+
+    // Same as prove_queues, but the networks of the parties inject the given faults. Returns the result of each party.
+    #[expect(clippy::type_complexity)]
+    fn prove_queues_with_faults(
+        parties: &mut ThreeParty,
+        map_shares: &mut [PrivateDeposit<F, DepositValueShare<F>>; 3],
+        queues: [Vec<Action<F>>; 3],
+        faults: [Option<Fault>; 3],
+        proof_schema: &NoirProofScheme<F>,
+        cs: &ConstraintMatrices<F>,
+        pk: &ProvingKey<Curve>,
+    ) -> [Result<(Proof<Curve>, Vec<F>), Recovery>; 3] {
+        let [map0, map1, map2] = map_shares.each_mut();
+        let [queue0, queue1, queue2] = queues;
+        let [injector0, injector1, injector2] =
+            faults.map(|fault| fault.map_or_else(FaultInjector::healthy, FaultInjector::new));
+        parties
+            .run(
+                NUM_TRANSACTIONS,
+                [
+                    (map0, queue0, injector0),
+                    (map1, queue1, injector1),
+                    (map2, queue2, injector2),
+                ],
+                |(map, queue, injector), nets, rep3_states| {
+                    let nets = FaultyNetwork::wrap_all(nets, injector);
+                    let result = map.process_queue_with_groth16_proof(
+                        queue,
+                        &PolicyLimits::default(),
+                        proof_schema,
+                        cs,
+                        pk,
+                        nets.as_slice().try_into()?,
+                        rep3_states.try_into()?,
+                    );
+                    if result.is_err() {
+                        FaultyNetwork::abort_all(&nets);
+                    }
+                    Ok(result
+                        .map(|(_, _, proof, public_inputs, _)| (proof, public_inputs))
+                        .map_err(|error| error.recovery()))
+                },
+            )
+            .unwrap()
+    }
+
+    // Deposit to the first key, transfer from the first to the second key, withdraw from the second key
+    fn deposit_transfer_withdraw_queues<K: MapKey>(
+        parties: &mut ThreeParty,
+        key1: K,
+        key2: K,
+        amount: F,
+    ) -> [Vec<Action<K>>; 3] {
+        let amount_blinding = F::rand(parties.rng());
+        let amount_share = parties.share_field_element(amount);
+        let amount_blinding_share = parties.share_field_element(amount_blinding);
+
+        let mut action_queues: [Vec<Action<K>>; 3] = Default::default();
+        for (queue, amount_share, amount_blinding_share) in
+            izip!(&mut action_queues, amount_share, amount_blinding_share)
+        {
+            queue.push(Action::Deposit(key1.to_owned(), amount));
+            queue.push(Action::Transfer(
+                key1.to_owned(),
+                key2.to_owned(),
+                amount_share,
+                amount_blinding_share,
+            ));
+            queue.push(Action::Withdraw(key2.to_owned(), amount));
+            queue.resize(NUM_TRANSACTIONS, Action::Dummy);
+        }
+        action_queues
+    }
+
+    fn assert_maps_equal<K: MapKey>(
+        map_shares: &[PrivateDeposit<K, DepositValueShare<F>>; 3],
+        plain_map: &PrivateDeposit<K, DepositValuePlain<F>>,
+    ) {
+        let result = PrivateDeposit::reconstruct(map_shares.to_owned()).unwrap();
+        assert_eq!(result.len(), plain_map.len());
+        for (key, plain_value) in plain_map.iter() {
+            let value = result.get(key).unwrap();
+            assert_eq!(value.amount, plain_value.amount);
+            assert_eq!(value.blinding, plain_value.blinding);
+        }
+    }
+
+    // The packed mode proves the same statement as the per-action mode, it only needs one pair of networks
+    #[test]
+    fn actionqueue_packed_test() {
+        let mut parties = ThreeParty::new(2, TestConfig::SEED);
+
+        // Init Groth16
+        let pa = TestConfig::get_transaction_batched_program_artifact().unwrap();
+        let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, parties.rng()).unwrap();
+
+        // Get a random map and its shares
+        let mut plain_map =
+            TestConfig::get_random_plain_map::<F, _>(TestConfig::NUM_ITEMS, parties.rng());
+        let mut map_shares = parties.share_map(&plain_map);
+
+        let key1 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        let key2 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        let amount = F::from(parties.rng().r#gen::<u64>());
+        let queues = deposit_transfer_withdraw_queues(&mut parties, key1, key2, amount);
+
+        let [map0, map1, map2] = map_shares.each_mut();
+        let [queue0, queue1, queue2] = queues;
+        let (proof, public_inputs) = parties
+            .run_public(
+                1,
+                [(map0, queue0), (map1, queue1), (map2, queue2)],
+                |(map, queue), nets, rep3_states| {
+                    let (_, _, proof, public_inputs, _) = map
+                        .process_queue_packed_with_groth16_proof(
+                            queue,
+                            &PolicyLimits::default(),
+                            &proof_schema,
+                            &cs,
+                            &pk,
+                            None,
+                            &nets[0],
+                            &nets[1],
+                            &mut rep3_states[0],
+                        )?;
+                    Ok((proof, public_inputs))
+                },
+            )
+            .unwrap();
+        assert!(r1cs::verify(&pk.vk, &proof, &public_inputs).unwrap());
+        assert_eq!(public_inputs.len(), NUM_COMMITMENTS + NUM_POLICY_INPUTS);
+
+        let result = PrivateDeposit::reconstruct(map_shares.to_owned()).unwrap();
+        for key in [key1, key2] {
+            let value = result.get(&key).unwrap().to_owned();
+            assert!(value.amount.is_zero());
+            plain_map.insert(key, value);
+        }
+        assert_maps_equal(&map_shares, &plain_map);
+    }
+
+    #[test]
+    #[ignore = "UltraHonk tests are ignored at the moment"]
+    fn actionqueue_ultrahonk_test() {
+        let mut parties = ThreeParty::new(2, TestConfig::SEED);
+
+        // Init Ultrahonk
+        let pa = TestConfig::get_transaction_batched_program_artifact().unwrap();
+        let constraint_system = ultrahonk::get_constraint_system_from_artifact(&pa);
+        let prover_crs = TestConfig::get_prover_crs(&constraint_system).unwrap();
+        let verifier_crs = TestConfig::get_verifier_crs().unwrap();
+        let vk_barretenberg =
+            ultrahonk::generate_vk_barretenberg(&constraint_system, prover_crs.clone()).unwrap();
+        let vk = ultrahonk::get_vk(vk_barretenberg.to_owned(), verifier_crs);
+
+        // Get a random map and its shares
+        let mut plain_map =
+            TestConfig::get_random_plain_map::<F, _>(TestConfig::NUM_ITEMS, parties.rng());
+        let mut map_shares = parties.share_map(&plain_map);
+
+        let key1 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        let key2 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        let amount = F::from(parties.rng().r#gen::<u64>());
+        let queues = deposit_transfer_withdraw_queues(&mut parties, key1, key2, amount);
+
+        let [map0, map1, map2] = map_shares.each_mut();
+        let [queue0, queue1, queue2] = queues;
+        let (proof, public_inputs) = parties
+            .run_public(
+                1,
+                [(map0, queue0), (map1, queue1), (map2, queue2)],
+                |(map, queue), nets, rep3_states| {
+                    let (_, _, proof, public_inputs, _) = map.process_queue_with_ultrahonk_proof(
+                        queue,
+                        &PolicyLimits::default(),
+                        pa.clone(),
+                        &constraint_system,
+                        &prover_crs,
+                        &vk_barretenberg,
+                        None,
+                        &nets[0],
+                        &nets[1],
+                        &mut rep3_states[0],
+                    )?;
+                    Ok((proof, public_inputs))
+                },
+            )
+            .unwrap();
+        assert_eq!(public_inputs.len(), NUM_COMMITMENTS + NUM_POLICY_INPUTS);
+        assert!(ultrahonk::verify(proof, &public_inputs, &vk).unwrap());
+
+        let result = PrivateDeposit::reconstruct(map_shares.to_owned()).unwrap();
+        for key in [key1, key2] {
+            let value = result.get(&key).unwrap().to_owned();
+            assert!(value.amount.is_zero());
+            plain_map.insert(key, value);
+        }
+        assert_maps_equal(&map_shares, &plain_map);
+
+        // A diverged share is noticed by all parties, as with Groth16
+        let sender = TestConfig::get_random_map_key(&plain_map, parties.rng());
+        map_shares[0].inner_mut().get_mut(&sender).unwrap().amount.a += F::from(1u64);
+        let amount = plain_map.get(&sender).unwrap().amount;
+        let queues = deposit_transfer_withdraw_queues(&mut parties, sender, key2, amount);
+        let [map0, map1, map2] = map_shares.each_mut();
+        let [queue0, queue1, queue2] = queues;
+        let results = parties
+            .run(
+                1,
+                [(map0, queue0), (map1, queue1), (map2, queue2)],
+                |(map, queue), nets, rep3_states| {
+                    Ok(map
+                        .process_queue_with_ultrahonk_proof(
+                            queue,
+                            &PolicyLimits::default(),
+                            pa.clone(),
+                            &constraint_system,
+                            &prover_crs,
+                            &vk_barretenberg,
+                            None,
+                            &nets[0],
+                            &nets[1],
+                            &mut rep3_states[0],
+                        )
+                        .map(|_| ())
+                        .map_err(|error| error.recovery()))
+                },
+            )
+            .unwrap();
+        assert!(
+            results
+                .iter()
+                .all(|result| matches!(result, Err(Recovery::Halt))),
+            "the diverged share was not noticed by all parties: {results:?}"
+        );
+    }
+
+    // The map is keyed by addresses like the smart contract, the keys only enter the Merkle tree over the commitments
+    #[test]
+    fn actionqueue_address_test() {
+        let mut parties = ThreeParty::new(NUM_TRANSACTIONS * 2, TestConfig::SEED);
+
+        // Init Groth16
+        let pa = TestConfig::get_transaction_batched_program_artifact().unwrap();
+        let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, parties.rng()).unwrap();
+
+        // Get a random map and its shares
+        let mut plain_map =
+            TestConfig::get_random_plain_address_map::<F, _>(TestConfig::NUM_ITEMS, parties.rng());
+        let mut map_shares = parties.share_map(&plain_map);
+        let mut tree = CommitmentTree::new();
+        for (key, value) in plain_map.iter() {
+            tree.insert(
+                key.to_field(),
+                plain_commitment(value.amount, value.blinding),
+            )
+            .unwrap();
+        }
+
+        let key1 = TestConfig::get_random_new_address(&plain_map, parties.rng());
+        let key2 = TestConfig::get_random_new_address(&plain_map, parties.rng());
+        let amount = F::from(parties.rng().r#gen::<u64>());
+        let queues = deposit_transfer_withdraw_queues(&mut parties, key1, key2, amount);
+        let queue = queues[0].to_owned();
+        let (proof, public_inputs, _payouts) = prove_queues(
+            &mut parties,
+            &mut map_shares,
+            queues,
+            &proof_schema,
+            &cs,
+            &pk,
+        )
+        .unwrap();
+        assert!(r1cs::verify(&pk.vk, &proof, &public_inputs).unwrap());
+
+        let result = PrivateDeposit::reconstruct(map_shares.to_owned()).unwrap();
+        for key in [key1, key2] {
+            let value = result.get(&key).unwrap().to_owned();
+            assert!(value.amount.is_zero());
+            plain_map.insert(key, value);
+        }
+        assert_maps_equal(&map_shares, &plain_map);
+
+        // The tree contains the new commitments of both addresses
+        let root = tree.apply_batch(&queue, &public_inputs).unwrap();
+        for key in [key1, key2] {
+            let proof = tree.proof(key.to_field()).unwrap();
+            let value = plain_map.get(&key).unwrap();
+            assert_eq!(
+                proof.commitment,
+                Some(plain_commitment(value.amount, value.blinding))
+            );
+            assert!(proof.verify(root));
+        }
+    }
+
+    // A party that crashes or loses a message mid-batch leads to clean errors at all parties, the maps are rolled back, and the batch can be retried
+    #[test]
+    fn actionqueue_fault_recovery_test() {
+        // A lost message is only noticed when the receive times out
+        let mut parties = ThreeParty::new(NUM_TRANSACTIONS * 2, TestConfig::SEED)
+            .with_recv_timeout(Duration::from_secs(30));
+
+        // Init Groth16
+        let pa = TestConfig::get_transaction_batched_program_artifact().unwrap();
+        let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, parties.rng()).unwrap();
+
+        // Get a random map and its shares
+        let mut plain_map =
+            TestConfig::get_random_plain_map::<F, _>(TestConfig::NUM_ITEMS, parties.rng());
+        let mut map_shares = parties.share_map(&plain_map);
+
+        let key1 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        let key2 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        let amount = F::from(parties.rng().r#gen::<u64>());
+
+        for fault in [Fault::Drop(50), Fault::Disconnect(100)] {
+            let queues = deposit_transfer_withdraw_queues(&mut parties, key1, key2, amount);
+            let results = prove_queues_with_faults(
+                &mut parties,
+                &mut map_shares,
+                queues,
+                [None, Some(fault), None],
+                &proof_schema,
+                &cs,
+                &pk,
+            );
+            assert!(
+                results
+                    .iter()
+                    .all(|result| matches!(result, Err(Recovery::Retry))),
+                "{fault:?} did not fail at all parties"
+            );
+
+            // Nothing happened
+            assert_maps_equal(&map_shares, &plain_map);
+
+            // The aborted run might have left messages in the queues
+            parties.reconnect();
+        }
+
+        // A withdraw from a key without balance or an invalid action is reported with its index, such that the batch can be processed without it
+        let key3 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        for (index, action) in [(2, Action::Withdraw(key3, amount)), (1, Action::Invalid)] {
+            let mut queues = deposit_transfer_withdraw_queues(&mut parties, key1, key2, amount);
+            for queue in queues.iter_mut() {
+                queue[index] = action.to_owned();
+            }
+            let results = prove_queues_with_faults(
+                &mut parties,
+                &mut map_shares,
+                queues,
+                [None, None, None],
+                &proof_schema,
+                &cs,
+                &pk,
+            );
+            assert!(
+                results
+                    .iter()
+                    .all(|result| matches!(result, Err(Recovery::DropAction(i)) if *i == index))
+            );
+            assert_maps_equal(&map_shares, &plain_map);
+        }
+
+        // Retry with a slow party
+        let queues = deposit_transfer_withdraw_queues(&mut parties, key1, key2, amount);
+        let [result0, result1, result2] = prove_queues_with_faults(
+            &mut parties,
+            &mut map_shares,
+            queues,
+            [Some(Fault::Delay(Duration::from_micros(10))), None, None],
+            &proof_schema,
+            &cs,
+            &pk,
+        );
+        let (proof, public_inputs) = result0.unwrap();
+        assert_eq!(result1.unwrap(), (proof.clone(), public_inputs.clone()));
+        assert_eq!(result2.unwrap(), (proof.clone(), public_inputs.clone()));
+        assert!(r1cs::verify(&pk.vk, &proof, &public_inputs).unwrap());
+
+        let result = PrivateDeposit::reconstruct(map_shares).unwrap();
+        plain_map.insert(key1, result.get(&key1).unwrap().to_owned());
+        plain_map.insert(key2, result.get(&key2).unwrap().to_owned());
+        assert_eq!(result.len(), plain_map.len());
+        assert!(result.get(&key1).unwrap().amount.is_zero());
+        assert!(result.get(&key2).unwrap().amount.is_zero());
         for (key, plain_value) in plain_map.into_iter() {
-            let amount = plain_value.amount;
-            let share0 = map_shares[0].remove(&key).unwrap().amount;
-            let share1 = map_shares[1].remove(&key).unwrap().amount;
-            let share2 = map_shares[2].remove(&key).unwrap().amount;
-            let combined = rep3::combine_field_element(share0, share1, share2);
-            assert_eq!(amount, combined);
+            assert_eq!(result.get(&key).unwrap().amount, plain_value.amount);
+        }
+    }
+
+    // If the share of one party diverges, the parties notice it when comparing the public inputs. All of them fail and roll back, such that no proof is posted.
+    #[test]
+    fn actionqueue_diverged_share_test() {
+        let mut parties = ThreeParty::new(NUM_TRANSACTIONS * 2, TestConfig::SEED);
+
+        // Init Groth16
+        let pa = TestConfig::get_transaction_batched_program_artifact().unwrap();
+        let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, parties.rng()).unwrap();
+
+        // Get a random map and its shares
+        let mut plain_map =
+            TestConfig::get_random_plain_map::<F, _>(TestConfig::NUM_ITEMS, parties.rng());
+        let mut map_shares = parties.share_map(&plain_map);
+
+        // Party 0 diverges on the balance of the sender
+        let sender = TestConfig::get_random_map_key(&plain_map, parties.rng());
+        let receiver = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        map_shares[0].inner_mut().get_mut(&sender).unwrap().amount.a += F::from(1u64);
+
+        // Transfer the whole balance
+        let amount = plain_map.get(&sender).unwrap().amount;
+        let amount_blinding = F::rand(parties.rng());
+        let amount_share = parties.share_field_element(amount);
+        let amount_blinding_share = parties.share_field_element(amount_blinding);
+        let mut queues: [Vec<Action<F>>; 3] = Default::default();
+        for (queue, amount_share, amount_blinding_share) in
+            izip!(&mut queues, amount_share, amount_blinding_share)
+        {
+            queue.push(Action::Transfer(
+                sender,
+                receiver,
+                amount_share,
+                amount_blinding_share,
+            ));
+            queue.resize(NUM_TRANSACTIONS, Action::Dummy);
         }
-        assert!(map_shares[0].is_empty());
-        assert!(map_shares[1].is_empty());
-        assert!(map_shares[2].is_empty());
+
+        let results = prove_queues_with_faults(
+            &mut parties,
+            &mut map_shares,
+            queues,
+            [None, None, None],
+            &proof_schema,
+            &cs,
+            &pk,
+        );
+        assert!(
+            results
+                .iter()
+                .all(|result| matches!(result, Err(Recovery::Halt))),
+            "the diverged share was not noticed by all parties: {results:?}"
+        );
+
+        // Nothing happened, apart from the diverged share itself
+        let diverged = plain_map.get(&sender).unwrap().to_owned();
+        plain_map.insert(
+            sender,
+            DepositValue::new(diverged.amount + F::from(1u64), diverged.blinding),
+        );
+        assert_maps_equal(&map_shares, &plain_map);
+    }
+
+    // Random mixes of actions are checked against a plaintext model of the balances, against the Groth16 verifier, and against the public inputs the smart contract assembles from the posted commitments
+    #[test]
+    fn actionqueue_proptest() {
+        // Init Groth16
+        let pa = TestConfig::get_transaction_batched_program_artifact().unwrap();
+        let (proof_schema, pk, cs) =
+            r1cs::setup_r1cs(pa, &mut ChaCha12Rng::seed_from_u64(0)).unwrap();
+
+        // Proving is expensive, so we run few cases and limit shrinking
+        let mut runner = TestRunner::new(ProptestConfig {
+            cases: TestConfig::TEST_RUNS as u32,
+            max_shrink_iters: 16,
+            failure_persistence: None,
+            ..ProptestConfig::default()
+        });
+        // The existing balances reach the largest amount, such that withdraws and transfers are not clamped below it
+        let strategy = (
+            prop::collection::vec(action_strategy(), 0..=NUM_TRANSACTIONS),
+            prop::collection::vec(amount_strategy(), NUM_EXISTING_KEYS),
+            any::<u64>(),
+        );
+
+        runner
+            .run(&strategy, |(plain_actions, existing_balances, seed)| {
+                let mut parties = ThreeParty::new(NUM_TRANSACTIONS * 2, seed);
+
+                // A small map, such that keys repeat often
+                let mut balances = HashMap::with_capacity(NUM_EXISTING_KEYS + NUM_NEW_KEYS);
+                let mut plain_map = PrivateDeposit::with_capacity(NUM_EXISTING_KEYS);
+                let mut keys = Vec::with_capacity(NUM_EXISTING_KEYS + NUM_NEW_KEYS);
+                for amount in existing_balances {
+                    let key = TestConfig::get_random_new_key(&plain_map, parties.rng());
+                    plain_map.insert(
+                        key,
+                        DepositValuePlain::new(F::from(amount), F::rand(parties.rng())),
+                    );
+                    balances.insert(key, amount);
+                    keys.push(key);
+                }
+                for _ in 0..NUM_NEW_KEYS {
+                    let mut key = TestConfig::get_random_new_key(&plain_map, parties.rng());
+                    while keys.contains(&key) {
+                        key = TestConfig::get_random_new_key(&plain_map, parties.rng());
+                    }
+                    keys.push(key);
+                }
+                let mut map_shares = parties.share_map(&plain_map);
+                let mut onchain = plain_map
+                    .iter()
+                    .map(|(key, value)| (*key, plain_commitment(value.amount, value.blinding)))
+                    .collect::<HashMap<_, _>>();
+
+                // Drop the actions the smart contract rejects (zero deposits/withdraws and self transfers) and the ones spending from unknown keys. Spent amounts are clamped to the available balance.
+                let mut queues = [
+                    Vec::with_capacity(NUM_TRANSACTIONS),
+                    Vec::with_capacity(NUM_TRANSACTIONS),
+                    Vec::with_capacity(NUM_TRANSACTIONS),
+                ];
+                let mut contract_actions = Vec::with_capacity(NUM_TRANSACTIONS);
+                let mut expected_payouts = Vec::new();
+                for action in plain_actions {
+                    match action {
+                        PlainAction::Deposit(receiver, amount) => {
+                            if amount == 0 {
+                                continue;
+                            }
+                            let receiver = keys[receiver];
+                            *balances.entry(receiver).or_insert(0) += amount;
+                            for queue in queues.iter_mut() {
+                                queue.push(Action::Deposit(receiver, F::from(amount)));
+                            }
+                            contract_actions
+                                .push(ContractAction::Deposit(receiver, F::from(amount)));
+                        }
+                        PlainAction::Withdraw(sender, amount) => {
+                            let sender = keys[sender];
+                            let Some(balance) = balances.get_mut(&sender) else {
+                                continue;
+                            };
+                            let amount = amount.min(*balance);
+                            if amount == 0 {
+                                continue;
+                            }
+                            *balance -= amount;
+                            for queue in queues.iter_mut() {
+                                queue.push(Action::Withdraw(sender, F::from(amount)));
+                            }
+                            contract_actions
+                                .push(ContractAction::Withdraw(sender, F::from(amount)));
+                        }
+                        PlainAction::Transfer(sender, receiver, amount) => {
+                            let (sender, receiver) = (keys[sender], keys[receiver]);
+                            if sender == receiver {
+                                continue;
+                            }
+                            let Some(balance) = balances.get_mut(&sender) else {
+                                continue;
+                            };
+                            let amount = amount.min(*balance);
+                            *balance -= amount;
+                            *balances.entry(receiver).or_insert(0) += amount;
+
+                            let amount = F::from(amount);
+                            let amount_blinding = F::rand(parties.rng());
+                            let amount_share = parties.share_field_element(amount);
+                            let amount_blinding_share =
+                                parties.share_field_element(amount_blinding);
+                            for (queue, amount, amount_blinding) in
+                                izip!(queues.iter_mut(), amount_share, amount_blinding_share)
+                            {
+                                queue.push(Action::Transfer(
+                                    sender,
+                                    receiver,
+                                    amount,
+                                    amount_blinding,
+                                ));
+                            }
+                            contract_actions.push(ContractAction::Transfer(
+                                sender,
+                                receiver,
+                                plain_commitment(amount, amount_blinding),
+                            ));
+                        }
+                        PlainAction::PrivateWithdraw(sender, amount) => {
+                            // The contract cannot reject zero amounts, since it only sees the commitment
+                            let sender = keys[sender];
+                            let Some(balance) = balances.get_mut(&sender) else {
+                                continue;
+                            };
+                            let amount = amount.min(*balance);
+                            *balance -= amount;
+                            expected_payouts.push(F::from(amount));
+
+                            let amount = F::from(amount);
+                            let amount_blinding = F::rand(parties.rng());
+                            let amount_share = parties.share_field_element(amount);
+                            let amount_blinding_share =
+                                parties.share_field_element(amount_blinding);
+                            for (queue, amount, amount_blinding) in
+                                izip!(queues.iter_mut(), amount_share, amount_blinding_share)
+                            {
+                                queue.push(Action::PrivateWithdraw(
+                                    sender,
+                                    amount,
+                                    amount_blinding,
+                                ));
+                            }
+                            contract_actions.push(ContractAction::PrivateWithdraw(
+                                sender,
+                                plain_commitment(amount, amount_blinding),
+                            ));
+                        }
+                        PlainAction::Dummy => {
+                            for queue in queues.iter_mut() {
+                                queue.push(Action::Dummy);
+                            }
+                            contract_actions.push(ContractAction::Dummy);
+                        }
+                    }
+                }
+                for _ in contract_actions.len()..NUM_TRANSACTIONS {
+                    for queue in queues.iter_mut() {
+                        queue.push(Action::Dummy);
+                    }
+                    contract_actions.push(ContractAction::Dummy);
+                }
+                let queue = queues[0].clone();
+
+                // Do the MPC work
+                let (proof, public_inputs, payouts) = prove_queues(
+                    &mut parties,
+                    &mut map_shares,
+                    queues,
+                    &proof_schema,
+                    &cs,
+                    &pk,
+                )
+                .map_err(|e| TestCaseError::fail(e.to_string()))?;
+
+                prop_assert!(r1cs::verify(&pk.vk, &proof, &public_inputs).unwrap());
+                prop_assert_eq!(&payouts, &expected_payouts);
+
+                // The smart contract has to arrive at the same public inputs
+                let commitments =
+                    public_inputs_to_contract_commitments(&queue, &public_inputs, &payouts)
+                        .map_err(|e| TestCaseError::fail(e.to_string()))?;
+                let contract_public_inputs =
+                    contract_process_mpc(&contract_actions, &commitments, &mut onchain)?;
+                prop_assert_eq!(&contract_public_inputs, &public_inputs);
+
+                // Compare the balances with the plaintext model and the on-chain commitments
+                prop_assert_eq!(map_shares[0].len(), balances.len());
+                for (key, balance) in balances {
+                    let [v0, v1, v2] =
+                        [0, 1, 2].map(|i| map_shares[i].get(&key).unwrap().to_owned());
+                    let amount = rep3::combine_field_element(v0.amount, v1.amount, v2.amount);
+                    let blinding =
+                        rep3::combine_field_element(v0.blinding, v1.blinding, v2.blinding);
+                    prop_assert_eq!(amount, F::from(balance));
+                    prop_assert_eq!(onchain[&key], plain_commitment(amount, blinding));
+                }
+                Ok(())
+            })
+            .unwrap();
     }
 }
@@ -0,0 +1,279 @@
+use ark_ff::PrimeField;
+use co_noir::AcirFormat;
+use co_noir_to_r1cs::noir::ultrahonk;
+use mpc_core::protocols::{
+    rep3::{self, Rep3PrimeFieldShare, Rep3State},
+    rep3_ring::{self, Rep3RingShare, ring::bit::Bit},
+};
+use mpc_net::Network;
+use noirc_artifacts::program::ProgramArtifact;
+use std::collections::BTreeMap;
+
+use super::F;
+
+// The bit decompositions of the range checks (assert_max_bit_size) of a Noir
+// circuit, in the order of the circuit. Range checks of public values have no
+// decomposition in the witness, thus they may be skipped. The layout of the
+// witness is built from the range checks of the circuit, see into_witness.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitDecomps<T> {
+    values: Vec<(usize, T)>,
+}
+
+impl<T> Default for BitDecomps<T> {
+    fn default() -> Self {
+        Self { values: Vec::new() }
+    }
+}
+
+impl<T> BitDecomps<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds the value of the next range check with num_bits bits
+    pub fn push(&mut self, num_bits: usize, value: T) {
+        self.values.push((num_bits, value));
+    }
+
+    // Appends the decompositions of a later part of the circuit, e.g., of the
+    // next transaction of a batch
+    pub fn append(&mut self, other: Self) {
+        self.values.extend(other.values);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    // Orders the values in the layout of the witness of the circuit with the
+    // given range checks (see witness_positions). Fails if the values do not
+    // follow the range checks, i.e., if their sizes are not a subsequence of the
+    // range checks of the circuit.
+    fn witness_order(self, range_checks: &[usize]) -> eyre::Result<Vec<T>> {
+        let positions = witness_positions(range_checks);
+        let mut checks = range_checks.iter().zip(positions);
+        let mut ordered = Vec::with_capacity(self.values.len());
+        for (num_bits, value) in self.values {
+            let Some((_, position)) = checks.find(|(check, _)| **check == num_bits) else {
+                eyre::bail!(
+                    "The bit decompositions do not follow the range checks {:?} (bits: count) of the circuit",
+                    bit_layout(range_checks)
+                );
+            };
+            ordered.push((position, value));
+        }
+        ordered.sort_by_key(|(position, _)| *position);
+        Ok(ordered.into_iter().map(|(_, value)| value).collect())
+    }
+}
+
+impl<T> BitDecomps<Vec<T>> {
+    // Flattens the decomposed values in the layout of the witness of the circuit
+    // with the given range checks
+    pub fn into_witness(self, range_checks: &[usize]) -> eyre::Result<Vec<T>> {
+        Ok(self
+            .witness_order(range_checks)?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+}
+
+impl BitDecomps<Rep3PrimeFieldShare<F>> {
+    // Decomposes all values at once, such that all range checks only need the
+    // rounds of a single decomposition. The result contains the bits of each
+    // value, starting with the least significant one, in the same order. Works
+    // for any bit size up to the size of the field.
+    pub fn decompose_compose<N: Network>(
+        self,
+        net: &N,
+        rep3_state: &mut Rep3State,
+    ) -> eyre::Result<BitDecomps<Vec<Rep3PrimeFieldShare<F>>>> {
+        if let Some((num_bits, _)) = self
+            .values
+            .iter()
+            .find(|(num_bits, _)| *num_bits == 0 || *num_bits > F::MODULUS_BIT_SIZE as usize)
+        {
+            eyre::bail!("Invalid bit size {num_bits} for a bit decomposition");
+        }
+
+        let (sizes, values): (Vec<_>, Vec<_>) = self.values.into_iter().unzip();
+        let a2b = rep3::conversion::a2y2b_many(&values, net, rep3_state)?;
+
+        let mut to_compose = Vec::with_capacity(sizes.iter().sum());
+        for (num_bits, a2b) in sizes.iter().zip(a2b.iter()) {
+            for bit in 0..*num_bits as u64 {
+                to_compose.push(Rep3RingShare::new(
+                    Bit::new(a2b.a.bit(bit)),
+                    Bit::new(a2b.b.bit(bit)),
+                ));
+            }
+        }
+        let mut composed = rep3_ring::conversion::bit_inject_from_bits_to_field_many(
+            &to_compose,
+            net,
+            rep3_state,
+        )?
+        .into_iter();
+
+        let mut result = BitDecomps::new();
+        for num_bits in sizes {
+            result.push(num_bits, composed.by_ref().take(num_bits).collect());
+        }
+        Ok(result)
+    }
+}
+
+// The bit sizes of the range checks of a circuit, in the order of the circuit
+pub fn range_check_bit_sizes(constraint_system: &AcirFormat<F>) -> Vec<usize> {
+    constraint_system
+        .range_constraints
+        .iter()
+        .map(|constraint| constraint.num_bits as usize)
+        .collect()
+}
+
+// The layout of the bit decomposition witness for the given range checks: the number of range checks of each bit size, in ascending order of the sizes
+pub fn bit_layout(range_checks: &[usize]) -> BTreeMap<usize, usize> {
+    let mut layout = BTreeMap::new();
+    for num_bits in range_checks {
+        *layout.entry(*num_bits).or_default() += 1;
+    }
+    layout
+}
+
+// The position of each range check in the bit decomposition witness. The R1CS
+// translation groups the decompositions by bit size in ascending order, and
+// within one size in the order of the circuit.
+pub fn witness_positions(range_checks: &[usize]) -> Vec<usize> {
+    let mut order = (0..range_checks.len()).collect::<Vec<_>>();
+    order.sort_by_key(|index| range_checks[*index]);
+    let mut positions = vec![0; range_checks.len()];
+    for (position, index) in order.into_iter().enumerate() {
+        positions[index] = position;
+    }
+    positions
+}
+
+// Checks that the circuit range checks exactly the sizes the witness generation decomposes (expected), in the same order. Thus, the number of range checks of each size and the order within a size, i.e., the layout of the witness, match as well. Changing a range check in the circuit fails when the circuit is loaded instead of producing an invalid witness.
+pub fn check_range_checks(
+    program_artifact: &ProgramArtifact,
+    expected: &[usize],
+) -> eyre::Result<()> {
+    let constraint_system = ultrahonk::get_constraint_system_from_artifact(program_artifact);
+    let range_checks = range_check_bit_sizes(&constraint_system);
+    if range_checks != expected {
+        eyre::bail!(
+            "The circuit range checks {:?} (bits: count), but the witness generation decomposes {:?}",
+            bit_layout(&range_checks),
+            bit_layout(expected)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof::{
+        NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS, TestConfig,
+        deposit::DEPOSIT_RANGE_CHECKS,
+        transaction::TRANSACTION_RANGE_CHECKS,
+        transaction_batched::{NUM_TRANSACTIONS, batched_range_checks},
+    };
+    use crate::three_party::ThreeParty;
+    use ark_ff::{BigInteger, UniformRand};
+
+    #[test]
+    fn bitdecomp_test() {
+        let mut parties = ThreeParty::new(1, TestConfig::SEED);
+
+        // The values are pushed out of order of their sizes, including sizes above 128 bits
+        let sizes = [100, 80, 254, 80, 200];
+        let values = sizes.map(|num_bits| {
+            let value = F::rand(parties.rng());
+            let mut bits = value.into_bigint().to_bits_le();
+            bits.truncate(num_bits);
+            F::from_bigint(BigInteger::from_bits_le(&bits)).unwrap()
+        });
+        let shares = values.map(|value| parties.share_field_element(value));
+        let inputs: [_; 3] = std::array::from_fn(|party| {
+            let mut decomps = BitDecomps::new();
+            for (num_bits, share) in sizes.iter().zip(shares.iter()) {
+                decomps.push(*num_bits, share[party]);
+            }
+            decomps
+        });
+
+        let bits = parties
+            .run_public(1, inputs, |decomps, nets, rep3_states| {
+                let bits = decomps
+                    .decompose_compose(&nets[0], &mut rep3_states[0])?
+                    .into_witness(&sizes)?;
+                rep3::arithmetic::open_vec(&bits, &nets[0])
+            })
+            .unwrap();
+
+        // Grouped by size in ascending order, in the order of the circuit within a size
+        let mut expected = Vec::new();
+        for i in [1, 3, 0, 4, 2] {
+            let value_bits = values[i].into_bigint().to_bits_le();
+            expected.extend(value_bits[..sizes[i]].iter().map(|bit| F::from(*bit)));
+        }
+        for bits in bits {
+            assert_eq!(bits, expected);
+        }
+
+        // The layout is built from the range checks of the circuit, range checks
+        // of public values may be skipped
+        assert_eq!(witness_positions(&sizes), [2, 0, 4, 1, 3]);
+        let mut decomps = BitDecomps::new();
+        decomps.push(80, vec![1]);
+        decomps.push(200, vec![2]);
+        decomps.push(80, vec![3]);
+        assert_eq!(
+            decomps
+                .clone()
+                .into_witness(&[100, 80, 254, 200, 80])
+                .unwrap(),
+            [1, 3, 2]
+        );
+        // Values which do not follow the range checks are rejected
+        assert!(decomps.clone().into_witness(&sizes).is_err());
+        assert!(decomps.into_witness(&[80, 200]).is_err());
+
+        // Invalid sizes are rejected
+        let mut decomps = BitDecomps::<Rep3PrimeFieldShare<F>>::new();
+        decomps.push(255, Rep3PrimeFieldShare::zero_share());
+        let result = parties.run_public(
+            1,
+            [0, 1, 2].map(|_| decomps.clone()),
+            |decomps, nets, rep3_states| decomps.decompose_compose(&nets[0], &mut rep3_states[0]),
+        );
+        assert!(result.is_err());
+
+        // The range checks of the circuits, including their number and order
+        let pa = TestConfig::get_deposit_program_artifact().unwrap();
+        check_range_checks(&pa, &DEPOSIT_RANGE_CHECKS).unwrap();
+        assert!(check_range_checks(&pa, &[NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS]).is_err());
+        let pa = TestConfig::get_transaction_program_artifact().unwrap();
+        check_range_checks(&pa, &TRANSACTION_RANGE_CHECKS).unwrap();
+        // Same sizes, but in a different order
+        assert!(check_range_checks(&pa, &[NUM_WITHDRAW_NEW_BITS, NUM_AMOUNT_BITS]).is_err());
+        // Same sizes, but a missing range check
+        let pa = TestConfig::get_transaction_batched_program_artifact().unwrap();
+        assert!(check_range_checks(&pa, &TRANSACTION_RANGE_CHECKS).is_err());
+        assert_eq!(
+            bit_layout(&batched_range_checks()),
+            BTreeMap::from([
+                (NUM_AMOUNT_BITS, 2 * NUM_TRANSACTIONS),
+                (NUM_WITHDRAW_NEW_BITS, 2 * NUM_TRANSACTIONS)
+            ])
+        );
+    }
+}
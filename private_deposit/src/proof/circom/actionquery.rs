@@ -0,0 +1,353 @@
+use crate::{
+    data_structure::{DepositValueShare, PrivateDeposit},
+    proof::{
+        Curve, F, ProofError,
+        actionquery::{Action, ActionQueueError},
+        policy::{NUM_POLICY_INPUTS, PolicyLimits},
+        transaction_batched::{NUM_COMMITMENTS, NUM_TRANSACTIONS},
+        witness_input::{POLICY_INPUTS, TransactionWitnessInput},
+    },
+};
+use ark_ff::Zero;
+use ark_groth16::Proof;
+use circom_mpc_vm::{Rep3VmType, mpc_vm::Rep3WitnessExtension};
+use co_circom::{CoCircomCompilerParsed, Rep3SharedWitness, VMConfig};
+use co_noir_to_r1cs::{circom::proof_schema::CircomProofSchema, noir::r1cs};
+use eyre::Context;
+use mpc_core::protocols::rep3::{self, Rep3PrimeFieldShare, Rep3State};
+use mpc_net::Network;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+impl<K> PrivateDeposit<K, DepositValueShare<F>>
+where
+    K: std::hash::Hash + Eq + Clone + Send + Sync + std::fmt::Debug + 'static,
+{
+    // The co-circom version of process_queue_with_r1cs_witness. The witness is the one of the ActionQueue template, whose public inputs have the same layout as the ones of the Noir circuit, thus the proof can be verified by processMPC with a verifier for the circom circuit. As in the Noir version, public amounts stay public in the witness generation.
+    #[expect(clippy::type_complexity)]
+    pub fn process_queue_with_cocircom_witext<N: Network>(
+        &mut self,
+        queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
+        circuit: &CoCircomCompilerParsed<F>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            Rep3SharedWitness<F>,
+        ),
+        ActionQueueError<K>,
+    > {
+        let journal = self.journal(&queue);
+        let result = self.process_queue_with_cocircom_witext_inner(
+            queue, limits, circuit, net0, net1, rep3_state,
+        );
+        if result.is_err() {
+            self.rollback(journal);
+        }
+        result
+    }
+
+    #[expect(clippy::type_complexity)]
+    fn process_queue_with_cocircom_witext_inner<N: Network>(
+        &mut self,
+        queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
+        circuit: &CoCircomCompilerParsed<F>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            Rep3SharedWitness<F>,
+        ),
+        ActionQueueError<K>,
+    > {
+        if queue.len() != NUM_TRANSACTIONS {
+            return Err(ActionQueueError::InvalidLength(queue.len()));
+        }
+        let mut sender_new = Vec::with_capacity(NUM_TRANSACTIONS);
+        let mut receiver_new = Vec::with_capacity(NUM_TRANSACTIONS);
+        let mut proof_inputs = BTreeMap::new();
+
+        let my_id = rep3_state.id;
+        let zero = Rep3PrimeFieldShare::zero_share();
+        let public_zero = || Rep3VmType::from(F::zero());
+
+        for (i, action) in queue.into_iter().enumerate() {
+            let input: TransactionWitnessInput<Rep3VmType<F>> = match action {
+                Action::Transfer(sender, receiver, amount, amount_blinding) => {
+                    let (sender_old, sender_new_, receiver_old, receiver_new_) = self
+                        .transaction(sender, receiver, amount, rep3_state)
+                        .map_err(ActionQueueError::at(i))?;
+                    let receiver_old =
+                        receiver_old.unwrap_or_else(|| DepositValueShare::new(zero, zero));
+                    let input = TransactionWitnessInput {
+                        sender_old_balance: sender_old.amount.into(),
+                        sender_old_r: sender_old.blinding.into(),
+                        receiver_old_balance: receiver_old.amount.into(),
+                        receiver_old_r: receiver_old.blinding.into(),
+                        amount: amount.into(),
+                        amount_r: amount_blinding.into(),
+                        sender_new_r: sender_new_.blinding.into(),
+                        receiver_new_r: receiver_new_.blinding.into(),
+                    };
+                    sender_new.push(sender_new_);
+                    receiver_new.push(receiver_new_);
+                    input
+                }
+                Action::PrivateWithdraw(sender, amount, amount_blinding) => {
+                    let (sender_old, sender_new_) = self
+                        .withdraw(sender, amount, rep3_state)
+                        .map_err(ActionQueueError::at(i))?;
+                    // The same as in process_queue_with_r1cs_witness
+                    let input = TransactionWitnessInput {
+                        sender_old_balance: sender_old.amount.into(),
+                        sender_old_r: sender_old.blinding.into(),
+                        receiver_old_balance: public_zero(),
+                        receiver_old_r: public_zero(),
+                        amount: amount.into(),
+                        amount_r: amount_blinding.into(),
+                        sender_new_r: sender_new_.blinding.into(),
+                        receiver_new_r: public_zero(),
+                    };
+                    sender_new.push(sender_new_);
+                    receiver_new.push(DepositValueShare::new(amount, zero));
+                    input
+                }
+                Action::Deposit(receiver, amount) => {
+                    let amount_shared = rep3::arithmetic::promote_to_trivial_share(my_id, amount);
+                    let (receiver_old, receiver_new_) =
+                        self.deposit(receiver, amount_shared, rep3_state);
+                    let (receiver_old_balance, receiver_old_r) = match receiver_old {
+                        Some(old) => (old.amount.into(), old.blinding.into()),
+                        None => (public_zero(), public_zero()),
+                    };
+                    // The sender is a public balance of amount which is deposited entirely
+                    let input = TransactionWitnessInput {
+                        sender_old_balance: amount.into(),
+                        sender_old_r: public_zero(),
+                        receiver_old_balance,
+                        receiver_old_r,
+                        amount: amount.into(),
+                        amount_r: public_zero(),
+                        sender_new_r: public_zero(),
+                        receiver_new_r: receiver_new_.blinding.into(),
+                    };
+                    sender_new.push(DepositValueShare::new(zero, zero));
+                    receiver_new.push(receiver_new_);
+                    input
+                }
+                Action::Withdraw(sender, amount) => {
+                    let amount_shared = rep3::arithmetic::promote_to_trivial_share(my_id, amount);
+                    let (sender_old, sender_new_) = self
+                        .withdraw(sender, amount_shared, rep3_state)
+                        .map_err(ActionQueueError::at(i))?;
+                    let input = TransactionWitnessInput {
+                        sender_old_balance: sender_old.amount.into(),
+                        sender_old_r: sender_old.blinding.into(),
+                        receiver_old_balance: public_zero(),
+                        receiver_old_r: public_zero(),
+                        amount: amount.into(),
+                        amount_r: public_zero(),
+                        sender_new_r: sender_new_.blinding.into(),
+                        receiver_new_r: public_zero(),
+                    };
+                    sender_new.push(sender_new_);
+                    receiver_new.push(DepositValueShare::new(amount_shared, zero));
+                    input
+                }
+                Action::Dummy => {
+                    sender_new.push(DepositValueShare::new(zero, zero));
+                    receiver_new.push(DepositValueShare::new(zero, zero));
+                    TransactionWitnessInput {
+                        sender_old_balance: public_zero(),
+                        sender_old_r: public_zero(),
+                        receiver_old_balance: public_zero(),
+                        receiver_old_r: public_zero(),
+                        amount: public_zero(),
+                        amount_r: public_zero(),
+                        sender_new_r: public_zero(),
+                        receiver_new_r: public_zero(),
+                    }
+                }
+                Action::Invalid => {
+                    return Err(ActionQueueError::UnsupportedAction { index: i });
+                }
+            };
+            input.insert_circom(i, &mut proof_inputs);
+        }
+        for (name, limit) in POLICY_INPUTS.iter().zip(limits.public_inputs()) {
+            proof_inputs.insert(name.to_string(), Rep3VmType::from(limit));
+        }
+
+        // init MPC protocol
+        let rep3_vm = Rep3WitnessExtension::new(net0, net1, circuit, VMConfig::default())
+            .context("while constructing MPC VM")?;
+
+        // execute witness generation in MPC
+        let witness = rep3_vm
+            .run(proof_inputs, circuit.public_inputs().len())
+            .context("while running witness generation")
+            .map_err(ProofError::WitnessExtension)?
+            .into_shared_witness();
+
+        Ok((sender_new, receiver_new, witness))
+    }
+
+    // Same as process_queue_with_groth16_proof, but with the co-circom backend. The public inputs are the commitments of the queue followed by the limits, see public_inputs_to_contract_commitments.
+    #[expect(clippy::type_complexity, clippy::too_many_arguments)]
+    pub fn process_queue_with_cocircom_proof<N: Network>(
+        &mut self,
+        queue: Vec<Action<K>>,
+        limits: &PolicyLimits,
+        circuit: &CoCircomCompilerParsed<F>,
+        proof_schema: &CircomProofSchema<Curve>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> Result<
+        (
+            Vec<DepositValueShare<F>>,
+            Vec<DepositValueShare<F>>,
+            Proof<Curve>,
+            Vec<F>,
+            Duration,
+        ),
+        ActionQueueError<K>,
+    > {
+        let journal = self.journal(&queue);
+        let result = self
+            .process_queue_with_cocircom_witext_inner(
+                queue, limits, circuit, net0, net1, rep3_state,
+            )
+            .and_then(|(sender_new, receiver_new, witness)| {
+                let start = Instant::now();
+                let (proof, public_inputs) = r1cs::prove(
+                    &proof_schema.matrices,
+                    &proof_schema.pk,
+                    witness,
+                    net0,
+                    net1,
+                )
+                .context("while generating Groth16 proof")
+                .map_err(ProofError::Prover)?;
+                crate::proof::check_public_inputs_agree(&public_inputs, net0)?;
+                let duration = start.elapsed();
+                if public_inputs.len() != NUM_COMMITMENTS + NUM_POLICY_INPUTS {
+                    return Err(ProofError::InvalidCircuit(
+                        "The circuit does not have the public inputs of processMPC".to_owned(),
+                    )
+                    .into());
+                }
+                Ok((sender_new, receiver_new, proof, public_inputs, duration))
+            });
+        if result.is_err() {
+            self.rollback(journal);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        proof::{TestConfig, plain_commitment, transaction::NUM_TRANSACTION_COMMITMENTS},
+        three_party::ThreeParty,
+    };
+    use ark_ff::UniformRand;
+    use itertools::izip;
+    use rand::Rng;
+
+    #[test]
+    fn actionqueue_cocircom_test() {
+        let mut parties = ThreeParty::new(2, TestConfig::SEED);
+
+        // Init Groth16
+        let circuit = TestConfig::get_action_queue_circom().unwrap();
+        let proof_schema = TestConfig::get_action_queue_proof_schema(parties.rng()).unwrap();
+
+        // Get a random map and its shares
+        let plain_map =
+            TestConfig::get_random_plain_map::<F, _>(TestConfig::NUM_ITEMS, parties.rng());
+        let mut map_shares = parties.share_map(&plain_map);
+
+        // Deposit to key1, transfer from key1 to key2, withdraw from key2
+        let key1 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        let key2 = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        let amount = F::from(parties.rng().r#gen::<u64>());
+        let amount_blinding = F::rand(parties.rng());
+        let amount_share = parties.share_field_element(amount);
+        let amount_blinding_share = parties.share_field_element(amount_blinding);
+        let mut queues: [Vec<Action<F>>; 3] = Default::default();
+        for (queue, amount_share, amount_blinding_share) in
+            izip!(&mut queues, amount_share, amount_blinding_share)
+        {
+            queue.push(Action::Deposit(key1, amount));
+            queue.push(Action::Transfer(
+                key1,
+                key2,
+                amount_share,
+                amount_blinding_share,
+            ));
+            queue.push(Action::Withdraw(key2, amount));
+            queue.resize(NUM_TRANSACTIONS, Action::Dummy);
+        }
+
+        let limits = PolicyLimits::default();
+        let [map0, map1, map2] = map_shares.each_mut();
+        let [queue0, queue1, queue2] = queues;
+        let (proof, public_inputs) = parties
+            .run_public(
+                1,
+                [(map0, queue0), (map1, queue1), (map2, queue2)],
+                |(map, queue), nets, rep3_states| {
+                    let (_, _, proof, public_inputs, _) = map.process_queue_with_cocircom_proof(
+                        queue,
+                        &limits,
+                        &circuit,
+                        &proof_schema,
+                        &nets[0],
+                        &nets[1],
+                        &mut rep3_states[0],
+                    )?;
+                    Ok((proof, public_inputs))
+                },
+            )
+            .unwrap();
+        assert!(r1cs::verify(&proof_schema.pk.vk, &proof, &public_inputs).unwrap());
+
+        // The public inputs have the layout of the Noir circuit
+        let result = PrivateDeposit::reconstruct(map_shares).unwrap();
+        let commitment = |key| {
+            let value = result.get(&key).unwrap();
+            assert!(value.amount.is_zero());
+            plain_commitment(value.amount, value.blinding)
+        };
+        let amount_commitment = plain_commitment(amount, F::zero());
+        let zero_commitment = PrivateDeposit::<F, DepositValueShare<F>>::zero_commitment();
+        assert_eq!(public_inputs[0], amount_commitment);
+        assert_eq!(public_inputs[1], zero_commitment);
+        assert_eq!(public_inputs[4], amount_commitment);
+        assert_eq!(
+            public_inputs[NUM_TRANSACTION_COMMITMENTS + 1],
+            commitment(key1)
+        );
+        assert_eq!(
+            public_inputs[2 * NUM_TRANSACTION_COMMITMENTS + 1],
+            commitment(key2)
+        );
+        assert!(
+            public_inputs[3 * NUM_TRANSACTION_COMMITMENTS..NUM_COMMITMENTS]
+                .iter()
+                .all(|c| *c == zero_commitment)
+        );
+        assert_eq!(public_inputs[NUM_COMMITMENTS..], limits.public_inputs());
+    }
+}
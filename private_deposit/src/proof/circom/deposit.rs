@@ -1,6 +1,6 @@
 use crate::{
     data_structure::{DepositValueShare, PrivateDeposit},
-    proof::{Curve, F},
+    proof::{Curve, F, witness_input::BalanceUpdateInput},
 };
 use ark_ff::Zero;
 use ark_groth16::Proof;
@@ -22,18 +22,19 @@ where
         amount_blinding: Rep3PrimeFieldShare<F>,
         new_blinding: Rep3PrimeFieldShare<F>,
     ) -> BTreeMap<String, Rep3VmType<F>> {
-        let mut inputs = BTreeMap::new();
-        if let Some(old) = old {
-            inputs.insert("old_balance".to_string(), Rep3VmType::from(old.amount));
-            inputs.insert("old_r".to_string(), Rep3VmType::from(old.blinding));
+        let (old_balance, old_r) = if let Some(old) = old {
+            (old.amount.into(), old.blinding.into())
         } else {
-            inputs.insert("old_balance".to_string(), Rep3VmType::from(F::zero()));
-            inputs.insert("old_r".to_string(), Rep3VmType::from(F::zero()));
+            (Rep3VmType::from(F::zero()), Rep3VmType::from(F::zero()))
         };
-        inputs.insert("amount".to_string(), Rep3VmType::from(amount));
-        inputs.insert("amount_r".to_string(), Rep3VmType::from(amount_blinding));
-        inputs.insert("new_r".to_string(), Rep3VmType::from(new_blinding));
-        inputs
+        BalanceUpdateInput {
+            old_balance,
+            old_r,
+            amount: amount.into(),
+            amount_r: amount_blinding.into(),
+            new_r: new_blinding.into(),
+        }
+        .into_circom()
     }
 
     #[expect(clippy::too_many_arguments)]
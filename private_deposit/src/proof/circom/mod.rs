@@ -1,9 +1,10 @@
+pub mod actionquery;
 pub mod deposit;
 pub mod transaction;
 pub mod transaction_batched;
 pub mod withdraw;
 
-use crate::proof::TestConfig;
+use crate::proof::{TestConfig, witness_input};
 use co_circom::CoCircomCompilerParsed;
 use co_noir::Bn254;
 use co_noir_to_r1cs::circom::proof_schema::CircomProofSchema;
@@ -11,24 +12,61 @@ use eyre::Context;
 use rand::{CryptoRng, Rng};
 use std::path::PathBuf;
 
+// The circom circuits of the MPC nodes. Loading a circuit through them checks that the input signals of its main template are the ones the witness extension produces, for circuits at any path and not only for the ones of the tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircomCircuit {
+    Deposit,
+    Withdraw,
+    Transaction,
+    TransactionBatched,
+    ActionQueue,
+}
+
+impl CircomCircuit {
+    pub fn check(self, signals: &[witness_input::CircomSignal]) -> eyre::Result<()> {
+        match self {
+            Self::Deposit | Self::Withdraw => witness_input::check_balance_update_signals(signals),
+            Self::Transaction => witness_input::check_transaction_signals(signals),
+            Self::TransactionBatched => witness_input::check_transaction_batched_signals(signals),
+            Self::ActionQueue => witness_input::check_action_queue_signals(signals),
+        }
+    }
+
+    pub fn load(
+        self,
+        circuit: PathBuf,
+        lib: PathBuf,
+    ) -> eyre::Result<CoCircomCompilerParsed<ark_bn254::Fr>> {
+        let signals = witness_input::circom_input_signals(&circuit, &lib)?;
+        self.check(&signals)
+            .with_context(|| format!("while checking the {self:?} circuit"))?;
+        CircomProofSchema::<Bn254>::read_circuit_co_circom(circuit, lib)
+    }
+}
+
 impl TestConfig {
     const CIRCOM_LIB: &str = "/../circom";
     const DEPOSIT_CIRCOM: &str = "/../circom/main/deposit.circom";
     const WITHDRAW_CIRCOM: &str = "/../circom/main/withdraw.circom";
     const TRANSACTION_CIRCOM: &str = "/../circom/main/transaction.circom";
     const TRANSACTION_BATCHED_CIRCOM: &str = "/../circom/main/transaction_batched.circom";
+    const ACTION_QUEUE_CIRCOM: &str = "/../circom/main/action_queue.circom";
     const DEPOSIT_R1CS: &str = "/../circom/main/deposit.r1cs";
     const WITHDRAW_R1CS: &str = "/../circom/main/withdraw.r1cs";
     const TRANSACTION_R1CS: &str = "/../circom/main/transaction.r1cs";
     const TRANSACTION_BATCHED_R1CS: &str = "/../circom/main/transaction_batched.r1cs";
+    const ACTION_QUEUE_R1CS: &str = "/../circom/main/action_queue.r1cs";
 
-    pub fn get_deposit_circom() -> eyre::Result<CoCircomCompilerParsed<ark_bn254::Fr>> {
-        let lib = format!("{}{}", Self::ROOT, Self::CIRCOM_LIB);
+    // The paths of the circuit and of the library directory
+    pub fn deposit_circom_paths() -> (PathBuf, PathBuf) {
         let circuit = format!("{}{}", Self::ROOT, Self::DEPOSIT_CIRCOM);
-        CircomProofSchema::<Bn254>::read_circuit_co_circom(
-            PathBuf::from(circuit),
-            PathBuf::from(lib),
-        )
+        let lib = format!("{}{}", Self::ROOT, Self::CIRCOM_LIB);
+        (PathBuf::from(circuit), PathBuf::from(lib))
+    }
+
+    pub fn get_deposit_circom() -> eyre::Result<CoCircomCompilerParsed<ark_bn254::Fr>> {
+        let (circuit, lib) = Self::deposit_circom_paths();
+        CircomCircuit::Deposit.load(circuit, lib)
     }
 
     pub fn get_deposit_proof_schema<R: Rng + CryptoRng>(
@@ -39,13 +77,15 @@ impl TestConfig {
             .context("while reading r1cs file")
     }
 
-    pub fn get_withdraw_circom() -> eyre::Result<CoCircomCompilerParsed<ark_bn254::Fr>> {
-        let lib = format!("{}{}", Self::ROOT, Self::CIRCOM_LIB);
+    pub fn withdraw_circom_paths() -> (PathBuf, PathBuf) {
         let circuit = format!("{}{}", Self::ROOT, Self::WITHDRAW_CIRCOM);
-        CircomProofSchema::<Bn254>::read_circuit_co_circom(
-            PathBuf::from(circuit),
-            PathBuf::from(lib),
-        )
+        let lib = format!("{}{}", Self::ROOT, Self::CIRCOM_LIB);
+        (PathBuf::from(circuit), PathBuf::from(lib))
+    }
+
+    pub fn get_withdraw_circom() -> eyre::Result<CoCircomCompilerParsed<ark_bn254::Fr>> {
+        let (circuit, lib) = Self::withdraw_circom_paths();
+        CircomCircuit::Withdraw.load(circuit, lib)
     }
 
     pub fn get_withdraw_proof_schema<R: Rng + CryptoRng>(
@@ -56,13 +96,15 @@ impl TestConfig {
             .context("while reading r1cs file")
     }
 
-    pub fn get_transaction_circom() -> eyre::Result<CoCircomCompilerParsed<ark_bn254::Fr>> {
-        let lib = format!("{}{}", Self::ROOT, Self::CIRCOM_LIB);
+    pub fn transaction_circom_paths() -> (PathBuf, PathBuf) {
         let circuit = format!("{}{}", Self::ROOT, Self::TRANSACTION_CIRCOM);
-        CircomProofSchema::<Bn254>::read_circuit_co_circom(
-            PathBuf::from(circuit),
-            PathBuf::from(lib),
-        )
+        let lib = format!("{}{}", Self::ROOT, Self::CIRCOM_LIB);
+        (PathBuf::from(circuit), PathBuf::from(lib))
+    }
+
+    pub fn get_transaction_circom() -> eyre::Result<CoCircomCompilerParsed<ark_bn254::Fr>> {
+        let (circuit, lib) = Self::transaction_circom_paths();
+        CircomCircuit::Transaction.load(circuit, lib)
     }
 
     pub fn get_transaction_proof_schema<R: Rng + CryptoRng>(
@@ -73,13 +115,15 @@ impl TestConfig {
             .context("while reading r1cs file")
     }
 
-    pub fn get_transaction_batched_circom() -> eyre::Result<CoCircomCompilerParsed<ark_bn254::Fr>> {
-        let lib = format!("{}{}", Self::ROOT, Self::CIRCOM_LIB);
+    pub fn transaction_batched_circom_paths() -> (PathBuf, PathBuf) {
         let circuit = format!("{}{}", Self::ROOT, Self::TRANSACTION_BATCHED_CIRCOM);
-        CircomProofSchema::<Bn254>::read_circuit_co_circom(
-            PathBuf::from(circuit),
-            PathBuf::from(lib),
-        )
+        let lib = format!("{}{}", Self::ROOT, Self::CIRCOM_LIB);
+        (PathBuf::from(circuit), PathBuf::from(lib))
+    }
+
+    pub fn get_transaction_batched_circom() -> eyre::Result<CoCircomCompilerParsed<ark_bn254::Fr>> {
+        let (circuit, lib) = Self::transaction_batched_circom_paths();
+        CircomCircuit::TransactionBatched.load(circuit, lib)
     }
 
     pub fn get_transaction_batched_proof_schema<R: Rng + CryptoRng>(
@@ -89,4 +133,23 @@ impl TestConfig {
         CircomProofSchema::from_r1cs_file(PathBuf::from(r1cs), rng)
             .context("while reading r1cs file")
     }
+
+    pub fn action_queue_circom_paths() -> (PathBuf, PathBuf) {
+        let circuit = format!("{}{}", Self::ROOT, Self::ACTION_QUEUE_CIRCOM);
+        let lib = format!("{}{}", Self::ROOT, Self::CIRCOM_LIB);
+        (PathBuf::from(circuit), PathBuf::from(lib))
+    }
+
+    pub fn get_action_queue_circom() -> eyre::Result<CoCircomCompilerParsed<ark_bn254::Fr>> {
+        let (circuit, lib) = Self::action_queue_circom_paths();
+        CircomCircuit::ActionQueue.load(circuit, lib)
+    }
+
+    pub fn get_action_queue_proof_schema<R: Rng + CryptoRng>(
+        rng: &mut R,
+    ) -> eyre::Result<CircomProofSchema<Bn254>> {
+        let r1cs = format!("{}{}", Self::ROOT, Self::ACTION_QUEUE_R1CS);
+        CircomProofSchema::from_r1cs_file(PathBuf::from(r1cs), rng)
+            .context("while reading r1cs file")
+    }
 }
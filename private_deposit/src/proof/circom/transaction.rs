@@ -1,6 +1,6 @@
 use crate::{
     data_structure::{DepositValueShare, PrivateDeposit},
-    proof::{Curve, F},
+    proof::{Curve, F, witness_input::TransactionWitnessInput},
 };
 use ark_ff::Zero;
 use ark_groth16::Proof;
@@ -14,7 +14,7 @@ use std::collections::BTreeMap;
 
 impl<K> PrivateDeposit<K, DepositValueShare<F>>
 where
-    K: std::hash::Hash + Eq,
+    K: std::hash::Hash + Eq + Send + Sync + std::fmt::Debug + 'static,
 {
     fn get_transaction_circom_input(
         sender_old: DepositValueShare<F>,
@@ -24,39 +24,22 @@ where
         sender_new_blinding: Rep3PrimeFieldShare<F>,
         receiver_new_blinding: Rep3PrimeFieldShare<F>,
     ) -> BTreeMap<String, Rep3VmType<F>> {
-        let mut inputs = BTreeMap::new();
-        inputs.insert(
-            "sender_old_balance".to_string(),
-            Rep3VmType::from(sender_old.amount),
-        );
-        inputs.insert(
-            "sender_old_r".to_string(),
-            Rep3VmType::from(sender_old.blinding),
-        );
-        if let Some(old) = receiver_old {
-            inputs.insert(
-                "receiver_old_balance".to_string(),
-                Rep3VmType::from(old.amount),
-            );
-            inputs.insert("receiver_old_r".to_string(), Rep3VmType::from(old.blinding));
+        let (receiver_old_balance, receiver_old_r) = if let Some(old) = receiver_old {
+            (old.amount.into(), old.blinding.into())
         } else {
-            inputs.insert(
-                "receiver_old_balance".to_string(),
-                Rep3VmType::from(F::zero()),
-            );
-            inputs.insert("receiver_old_r".to_string(), Rep3VmType::from(F::zero()));
+            (Rep3VmType::from(F::zero()), Rep3VmType::from(F::zero()))
         };
-        inputs.insert("amount".to_string(), Rep3VmType::from(amount));
-        inputs.insert("amount_r".to_string(), Rep3VmType::from(amount_blinding));
-        inputs.insert(
-            "sender_new_r".to_string(),
-            Rep3VmType::from(sender_new_blinding),
-        );
-        inputs.insert(
-            "receiver_new_r".to_string(),
-            Rep3VmType::from(receiver_new_blinding),
-        );
-        inputs
+        TransactionWitnessInput {
+            sender_old_balance: sender_old.amount.into(),
+            sender_old_r: sender_old.blinding.into(),
+            receiver_old_balance,
+            receiver_old_r,
+            amount: amount.into(),
+            amount_r: amount_blinding.into(),
+            sender_new_r: sender_new_blinding.into(),
+            receiver_new_r: receiver_new_blinding.into(),
+        }
+        .into_circom()
     }
 
     #[expect(clippy::too_many_arguments)]
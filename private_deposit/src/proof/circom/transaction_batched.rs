@@ -3,6 +3,7 @@ use crate::{
     proof::{
         Curve, F,
         transaction_batched::{NUM_TRANSACTIONS, TransactionInput},
+        witness_input::TransactionWitnessInput,
     },
 };
 use ark_ff::Zero;
@@ -17,7 +18,7 @@ use std::collections::BTreeMap;
 
 impl<K> PrivateDeposit<K, DepositValueShare<F>>
 where
-    K: std::hash::Hash + Eq + Clone,
+    K: std::hash::Hash + Eq + Clone + Send + Sync + std::fmt::Debug + 'static,
 {
     #[expect(clippy::too_many_arguments)]
     fn add_to_circom_tranasction_input(
@@ -30,46 +31,22 @@ where
         sender_new_blinding: Rep3PrimeFieldShare<F>,
         receiver_new_blinding: Rep3PrimeFieldShare<F>,
     ) {
-        inputs.insert(
-            format!("sender_old_balance[{i}]").to_string(),
-            Rep3VmType::from(sender_old.amount),
-        );
-        inputs.insert(
-            format!("sender_old_r[{i}]").to_string(),
-            Rep3VmType::from(sender_old.blinding),
-        );
-        if let Some(old) = receiver_old {
-            inputs.insert(
-                format!("receiver_old_balance[{i}]").to_string(),
-                Rep3VmType::from(old.amount),
-            );
-            inputs.insert(
-                format!("receiver_old_r[{i}]").to_string(),
-                Rep3VmType::from(old.blinding),
-            );
+        let (receiver_old_balance, receiver_old_r) = if let Some(old) = receiver_old {
+            (old.amount.into(), old.blinding.into())
         } else {
-            inputs.insert(
-                format!("receiver_old_balance[{i}]").to_string(),
-                Rep3VmType::from(F::zero()),
-            );
-            inputs.insert(
-                format!("receiver_old_r[{i}]").to_string(),
-                Rep3VmType::from(F::zero()),
-            );
+            (Rep3VmType::from(F::zero()), Rep3VmType::from(F::zero()))
         };
-        inputs.insert(format!("amount[{i}]").to_string(), Rep3VmType::from(amount));
-        inputs.insert(
-            format!("amount_r[{i}]").to_string(),
-            Rep3VmType::from(amount_blinding),
-        );
-        inputs.insert(
-            format!("sender_new_r[{i}]").to_string(),
-            Rep3VmType::from(sender_new_blinding),
-        );
-        inputs.insert(
-            format!("receiver_new_r[{i}]").to_string(),
-            Rep3VmType::from(receiver_new_blinding),
-        );
+        TransactionWitnessInput {
+            sender_old_balance: sender_old.amount.into(),
+            sender_old_r: sender_old.blinding.into(),
+            receiver_old_balance,
+            receiver_old_r,
+            amount: amount.into(),
+            amount_r: amount_blinding.into(),
+            sender_new_r: sender_new_blinding.into(),
+            receiver_new_r: receiver_new_blinding.into(),
+        }
+        .insert_circom(i, inputs);
     }
 
     #[expect(clippy::type_complexity)]
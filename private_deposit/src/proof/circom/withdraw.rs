@@ -1,6 +1,6 @@
 use crate::{
     data_structure::{DepositValueShare, PrivateDeposit},
-    proof::{Curve, F},
+    proof::{Curve, F, witness_input::BalanceUpdateInput},
 };
 use ark_groth16::Proof;
 use circom_mpc_vm::{Rep3VmType, mpc_vm::Rep3WitnessExtension};
@@ -13,7 +13,7 @@ use std::collections::BTreeMap;
 
 impl<K> PrivateDeposit<K, DepositValueShare<F>>
 where
-    K: std::hash::Hash + Eq,
+    K: std::hash::Hash + Eq + Send + Sync + std::fmt::Debug + 'static,
 {
     fn get_withdraw_circom_input(
         old: DepositValueShare<F>,
@@ -21,13 +21,14 @@ where
         amount_blinding: Rep3PrimeFieldShare<F>,
         new_blinding: Rep3PrimeFieldShare<F>,
     ) -> BTreeMap<String, Rep3VmType<F>> {
-        let mut inputs = BTreeMap::new();
-        inputs.insert("old_balance".to_string(), Rep3VmType::from(old.amount));
-        inputs.insert("old_r".to_string(), Rep3VmType::from(old.blinding));
-        inputs.insert("amount".to_string(), Rep3VmType::from(amount));
-        inputs.insert("amount_r".to_string(), Rep3VmType::from(amount_blinding));
-        inputs.insert("new_r".to_string(), Rep3VmType::from(new_blinding));
-        inputs
+        BalanceUpdateInput {
+            old_balance: old.amount,
+            old_r: old.blinding,
+            amount,
+            amount_r: amount_blinding,
+            new_r: new_blinding,
+        }
+        .into_circom()
     }
 
     #[expect(clippy::too_many_arguments)]
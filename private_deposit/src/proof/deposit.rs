@@ -16,8 +16,13 @@ use noirc_artifacts::program::ProgramArtifact;
 
 use super::Curve;
 use super::F;
+use super::NUM_AMOUNT_BITS;
+use super::bitdecomp::BitDecomps;
+use super::witness_input::BalanceUpdateInput;
 
 pub(super) const NUM_DEPOSIT_COMMITMENTS: usize = 3;
+// The range checks of the circuit in their order, see bitdecomp::check_range_checks
+pub const DEPOSIT_RANGE_CHECKS: [usize; 1] = [NUM_AMOUNT_BITS];
 
 impl<K> PrivateDeposit<K, DepositValueShare<F>>
 where
@@ -33,20 +38,29 @@ where
         Rep3PrimeFieldShare<F>,
         Rep3PrimeFieldShare<F>,
     ) {
-        let mut inputs = Vec::with_capacity(5);
-        let (old_amount, old_blinding) = if let Some(old) = old {
-            inputs.push(Rep3AcvmType::from(old.amount));
-            inputs.push(Rep3AcvmType::from(old.blinding));
-            (old.amount, old.blinding)
+        let (old_balance, old_r, old_amount, old_blinding) = if let Some(old) = old {
+            (
+                old.amount.into(),
+                old.blinding.into(),
+                old.amount,
+                old.blinding,
+            )
         } else {
-            inputs.push(Rep3AcvmType::from(F::zero()));
-            inputs.push(Rep3AcvmType::from(F::zero()));
-            (Rep3PrimeFieldShare::zero(), Rep3PrimeFieldShare::zero())
+            (
+                Rep3AcvmType::from(F::zero()),
+                Rep3AcvmType::from(F::zero()),
+                Rep3PrimeFieldShare::zero(),
+                Rep3PrimeFieldShare::zero(),
+            )
+        };
+        let inputs = BalanceUpdateInput {
+            old_balance,
+            old_r,
+            amount: amount.into(),
+            amount_r: amount_blinding.into(),
+            new_r: new_blinding.into(),
         };
-        inputs.push(Rep3AcvmType::from(amount));
-        inputs.push(Rep3AcvmType::from(amount_blinding));
-        inputs.push(Rep3AcvmType::from(new_blinding));
-        (inputs, old_amount, old_blinding)
+        (inputs.into_noir(), old_amount, old_blinding)
     }
 
     pub fn deposit_with_commitments<N: Network>(
@@ -164,12 +178,14 @@ where
         )?;
 
         // The bit decomposition
-        let decomp_amount = super::decompose_compose_for_deposit(amount, net0, rep3_state)?;
+        let mut decomps = BitDecomps::new();
+        decomps.push(NUM_AMOUNT_BITS, amount);
+        let decomps = decomps.decompose_compose(net0, rep3_state)?;
 
         let r1cs = r1cs::trace_to_r1cs_witness_with_bitdecomp_witness(
             inputs,
             traces,
-            decomp_amount,
+            decomps.into_witness(&DEPOSIT_RANGE_CHECKS)?,
             proof_schema,
             net0,
             net1,
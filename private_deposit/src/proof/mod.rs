@@ -1,33 +1,39 @@
 pub mod actionquery;
+pub mod bitdecomp;
 pub mod circom;
 pub mod deposit;
+pub mod policy;
+pub mod precompute;
+pub mod solvency;
+pub mod threshold;
 pub mod transaction;
 pub mod transaction_batched;
+pub mod widths;
 pub mod withdraw;
+pub mod witness_input;
 
-use crate::data_structure::{DepositValuePlain, PrivateDeposit};
-use ark_ff::PrimeField;
+use crate::data_structure::{DepositValuePlain, DepositValueShare, PrivateDeposit};
+use alloy::primitives::Address;
+use ark_ff::{BigInteger, Field, One, PrimeField};
 use co_noir::{AcirFormat, Bn254, Rep3AcvmType};
 use co_noir_common::crs::ProverCrs;
 use co_noir_to_r1cs::{
     noir::ultrahonk,
     trace::{MpcTraceHasher, TraceHasher},
 };
+use eyre::Context;
 use mpc_core::{
-    gadgets::poseidon2::Poseidon2,
-    protocols::{
-        rep3::{self, Rep3PrimeFieldShare, Rep3State},
-        rep3_ring::{self, Rep3RingShare, ring::bit::Bit},
-    },
+    gadgets::poseidon2::{Poseidon2, Poseidon2Precomputations},
+    protocols::rep3::{self, Rep3PrimeFieldShare, Rep3State},
 };
 use mpc_net::Network;
+use noir_types::U256;
 use noirc_artifacts::program::ProgramArtifact;
 use rand::{CryptoRng, Rng};
 use std::{collections::BTreeMap, sync::Arc};
 
 // From the Noir circuits
-const NUM_AMOUNT_BITS: usize = 80;
-const NUM_WITHDRAW_NEW_BITS: usize = 100;
+use widths::{NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS};
 const DOMAIN_SEPARATOR: u64 = 0xDEADBEEFu64;
 
 pub const NUM_BATCHED_TRANSACTIONS: usize = transaction_batched::NUM_TRANSACTIONS;
@@ -35,15 +41,96 @@ pub const NUM_BATCHED_TRANSACTIONS: usize = transaction_batched::NUM_TRANSACTION
 pub(super) type F = ark_bn254::Fr;
 pub(super) type Curve = Bn254;
 
+// The failures of creating a proof in MPC, by the step which failed. The reports of co-snarks do not have a structure, thus failures of the MPC protocols themselves (e.g., of the network) end up in Mpc.
+#[derive(Debug, thiserror::Error)]
+pub enum ProofError {
+    #[error("MPC failure: {0:#}")]
+    Mpc(eyre::Report),
+    #[error("Witness extension failed: {0:#}")]
+    WitnessExtension(eyre::Report),
+    #[error("Proof generation failed: {0:#}")]
+    Prover(eyre::Report),
+    #[error("Invalid circuit: {0}")]
+    InvalidCircuit(String),
+    #[error("A thread panicked while generating the witness")]
+    Panicked,
+    #[error("The parties disagree on the public inputs, the shares have diverged")]
+    Diverged,
+}
+
+// Such that the MPC protocols can be called with ?
+impl From<eyre::Report> for ProofError {
+    fn from(err: eyre::Report) -> Self {
+        Self::Mpc(err)
+    }
+}
+
+// Each party sends the opened public inputs to the other parties and compares them with its own. The opened values of a party whose shares diverged from the others differ from theirs, thus all parties fail instead of posting a proof the smart contract would reject.
+pub(crate) fn check_public_inputs_agree<N: Network>(
+    public_inputs: &[F],
+    net: &N,
+) -> Result<(), ProofError> {
+    let bytes = public_inputs
+        .iter()
+        .flat_map(|input| input.into_bigint().to_bytes_le())
+        .collect::<Vec<_>>();
+    check_opened_agree(&bytes, net)
+}
+
+// Same as check_public_inputs_agree for the public inputs of an UltraHonk proof. They are compared in their printed form, which is unique for each value.
+pub(crate) fn check_honk_public_inputs_agree<N: Network>(
+    public_inputs: &[U256],
+    net: &N,
+) -> Result<(), ProofError> {
+    check_opened_agree(format!("{public_inputs:?}").as_bytes(), net)
+}
+
+fn check_opened_agree<N: Network>(bytes: &[u8], net: &N) -> Result<(), ProofError> {
+    let id = net.id();
+    let peers = (0..3).filter(|peer| *peer != id);
+    for peer in peers.clone() {
+        net.send(peer, bytes).map_err(ProofError::Mpc)?;
+    }
+    let mut agree = true;
+    for peer in peers {
+        agree &= net.recv(peer).map_err(ProofError::Mpc)? == bytes;
+    }
+    if !agree {
+        return Err(ProofError::Diverged);
+    }
+    Ok(())
+}
+
 fn poseidon2_commitment_helper<const I: usize, const I2: usize, F: PrimeField, N: Network>(
+    input: [Rep3PrimeFieldShare<F>; I2],
+    net: &N,
+    rep3_state: &mut Rep3State,
+) -> eyre::Result<Vec<Vec<Rep3AcvmType<F>>>> {
+    let hasher = Poseidon2::<F, 2, 5>::default();
+    let hasher_precomp = hasher.precompute_rep3(I, net, rep3_state)?;
+    poseidon2_commitment_helper_with_precomputation::<I, I2, _, _>(
+        input,
+        hasher_precomp,
+        net,
+        rep3_state,
+    )
+}
+
+// Same as poseidon2_commitment_helper, but with precomputed material for I permutations, e.g., from a PrecomputationPool
+fn poseidon2_commitment_helper_with_precomputation<
+    const I: usize,
+    const I2: usize,
+    F: PrimeField,
+    N: Network,
+>(
     mut input: [Rep3PrimeFieldShare<F>; I2],
+    mut hasher_precomp: Poseidon2Precomputations<Rep3PrimeFieldShare<F>>,
     net: &N,
     rep3_state: &mut Rep3State,
 ) -> eyre::Result<Vec<Vec<Rep3AcvmType<F>>>> {
     assert_eq!(2 * I, I2);
     let domain_separator = F::from(DOMAIN_SEPARATOR);
     let hasher = Poseidon2::<F, 2, 5>::default();
-    let mut hasher_precomp = hasher.precompute_rep3(I, net, rep3_state)?;
     for input in input.iter_mut().step_by(2) {
         rep3::arithmetic::add_assign_public(input, domain_separator, rep3_state.id);
     }
@@ -82,14 +169,30 @@ fn poseidon2_plain_commitment_helper<const I: usize, const I2: usize, F: PrimeFi
 }
 
 fn poseidon2_commitments<const I: usize, const I2: usize, F: PrimeField, N: Network>(
+    input: [Rep3PrimeFieldShare<F>; I2],
+    net: &N,
+    rep3_state: &mut Rep3State,
+) -> eyre::Result<Vec<Rep3PrimeFieldShare<F>>> {
+    let hasher = Poseidon2::<F, 2, 5>::default();
+    let hasher_precomp = hasher.precompute_rep3(I, net, rep3_state)?;
+    poseidon2_commitments_with_precomputation::<I, I2, _, _>(input, hasher_precomp, net, rep3_state)
+}
+
+// Same as poseidon2_commitments, but with precomputed material for I permutations
+fn poseidon2_commitments_with_precomputation<
+    const I: usize,
+    const I2: usize,
+    F: PrimeField,
+    N: Network,
+>(
     mut input: [Rep3PrimeFieldShare<F>; I2],
+    mut hasher_precomp: Poseidon2Precomputations<Rep3PrimeFieldShare<F>>,
     net: &N,
     rep3_state: &mut Rep3State,
 ) -> eyre::Result<Vec<Rep3PrimeFieldShare<F>>> {
     assert_eq!(2 * I, I2);
     let domain_separator = F::from(DOMAIN_SEPARATOR);
     let hasher = Poseidon2::<F, 2, 5>::default();
-    let mut hasher_precomp = hasher.precompute_rep3(I, net, rep3_state)?;
     let mut result = Vec::with_capacity(I);
     for input in input.iter_mut().step_by(2) {
         result.push(input.to_owned());
@@ -107,86 +210,92 @@ fn poseidon2_commitments<const I: usize, const I2: usize, F: PrimeField, N: Netw
     Ok(result)
 }
 
-#[expect(clippy::assertions_on_constants, clippy::type_complexity)]
-pub(super) fn decompose_compose_for_transaction<N: Network>(
-    amount: Rep3PrimeFieldShare<F>,
-    sender_new: Rep3PrimeFieldShare<F>,
-    net0: &N,
-    net1: &N,
+// Jointly computes and opens the commitments of the given values, e.g., to compare them against the commitments on chain
+pub(crate) fn open_commitments<N: Network>(
+    values: &[DepositValueShare<F>],
+    net: &N,
     rep3_state: &mut Rep3State,
-) -> eyre::Result<(Vec<Rep3PrimeFieldShare<F>>, Vec<Rep3PrimeFieldShare<F>>)> {
-    let a2b_amount = rep3::conversion::a2y2b(amount, net0, rep3_state)?;
-    let a2b_sender = rep3::conversion::a2y2b(sender_new, net1, rep3_state)?;
-
-    let mut to_compose = Vec::with_capacity(NUM_AMOUNT_BITS + NUM_WITHDRAW_NEW_BITS);
-
-    assert!(NUM_AMOUNT_BITS <= 128);
-    assert!(NUM_AMOUNT_BITS > 64);
-    let a2b_amount_a = a2b_amount.a.to_u64_digits();
-    let a2b_amount_b = a2b_amount.b.to_u64_digits();
-    let mut a2b_amount_a = ((a2b_amount_a[1] as u128) << 64) | a2b_amount_a[0] as u128;
-    let mut a2b_amount_b = ((a2b_amount_b[1] as u128) << 64) | a2b_amount_b[0] as u128;
-    for _ in 0..NUM_AMOUNT_BITS {
-        let bit = Rep3RingShare::new(
-            Bit::new((a2b_amount_a & 1) == 1),
-            Bit::new((a2b_amount_b & 1) == 1),
-        );
-        to_compose.push(bit);
-        a2b_amount_a >>= 1;
-        a2b_amount_b >>= 1;
+) -> eyre::Result<Vec<F>> {
+    const CHUNK_SIZE: usize = 64;
+
+    let mut commitments = Vec::with_capacity(values.len());
+    for chunk in values.chunks(CHUNK_SIZE) {
+        // The last chunk is padded with zeros
+        let mut inputs = [Rep3PrimeFieldShare::zero_share(); CHUNK_SIZE * 2];
+        for (inputs, value) in inputs.chunks_exact_mut(2).zip(chunk) {
+            inputs[0] = value.amount;
+            inputs[1] = value.blinding;
+        }
+        let shares =
+            poseidon2_commitments::<CHUNK_SIZE, { CHUNK_SIZE * 2 }, _, _>(inputs, net, rep3_state)?;
+        commitments.extend(rep3::arithmetic::open_vec(&shares[..chunk.len()], net)?);
     }
+    Ok(commitments)
+}
 
-    assert!(NUM_WITHDRAW_NEW_BITS <= 128);
-    assert!(NUM_WITHDRAW_NEW_BITS > 64);
-    let a2b_sender_a = a2b_sender.a.to_u64_digits();
-    let a2b_sender_b = a2b_sender.b.to_u64_digits();
-    let mut a2b_sender_a = ((a2b_sender_a[1] as u128) << 64) | a2b_sender_a[0] as u128;
-    let mut a2b_sender_b = ((a2b_sender_b[1] as u128) << 64) | a2b_sender_b[0] as u128;
-
-    for _ in 0..NUM_WITHDRAW_NEW_BITS {
-        let bit = Rep3RingShare::new(
-            Bit::new((a2b_sender_a & 1) == 1),
-            Bit::new((a2b_sender_b & 1) == 1),
-        );
-        to_compose.push(bit);
-        a2b_sender_a >>= 1;
-        a2b_sender_b >>= 1;
-    }
-    let mut composed =
-        rep3_ring::conversion::bit_inject_from_bits_to_field_many(&to_compose, net0, rep3_state)?;
+// The largest value of num_bits bits, e.g., the default policy limits. Works for widths above 128 bits.
+pub fn max_value(num_bits: usize) -> F {
+    F::from(2u64).pow([num_bits as u64]) - F::one()
+}
+
+// Computes commit(amount, blinding) in plain, i.e., the value the smart contract stores for a balance
+pub fn plain_commitment(amount: F, blinding: F) -> F {
+    let hasher = Poseidon2::<F, 2, 5>::default();
+    let permuted = hasher.permutation(&[amount + F::from(DOMAIN_SEPARATOR), blinding]);
+    permuted[0] + amount
+}
 
-    let decomp_sender = composed.split_off(NUM_AMOUNT_BITS);
-    Ok((composed, decomp_sender))
+// The Noir circuits of the MPC nodes. Loading a program artifact through them checks that its inputs and range checks are the ones the witness extension produces, for artifacts at any path and not only for the ones of the tests. The solvency circuit takes the whole map, thus it has no fixed inputs to check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoirCircuit {
+    Deposit,
+    Withdraw,
+    Transaction,
+    TransactionBatched,
+    BalanceThreshold,
+    Solvency,
 }
 
-// Same as decompose_compose_for_transaction but without range check on the result_amount
-#[expect(clippy::assertions_on_constants)]
-pub(super) fn decompose_compose_for_deposit<N: Network>(
-    amount: Rep3PrimeFieldShare<F>,
-    net0: &N,
-    rep3_state: &mut Rep3State,
-) -> eyre::Result<Vec<Rep3PrimeFieldShare<F>>> {
-    let a2b_amount = rep3::conversion::a2y2b(amount, net0, rep3_state)?;
-
-    let mut to_compose = Vec::with_capacity(NUM_AMOUNT_BITS);
-
-    assert!(NUM_AMOUNT_BITS <= 128);
-    assert!(NUM_AMOUNT_BITS > 64);
-    let a2b_amount_a = a2b_amount.a.to_u64_digits();
-    let a2b_amount_b = a2b_amount.b.to_u64_digits();
-    let mut a2b_amount_a = ((a2b_amount_a[1] as u128) << 64) | a2b_amount_a[0] as u128;
-    let mut a2b_amount_b = ((a2b_amount_b[1] as u128) << 64) | a2b_amount_b[0] as u128;
-    for _ in 0..NUM_AMOUNT_BITS {
-        let bit = Rep3RingShare::new(
-            Bit::new((a2b_amount_a & 1) == 1),
-            Bit::new((a2b_amount_b & 1) == 1),
-        );
-        to_compose.push(bit);
-        a2b_amount_a >>= 1;
-        a2b_amount_b >>= 1;
+impl NoirCircuit {
+    pub fn check(self, program_artifact: &ProgramArtifact) -> eyre::Result<()> {
+        let abi = &program_artifact.abi;
+        match self {
+            Self::Deposit => {
+                witness_input::check_balance_update_abi(abi)?;
+                bitdecomp::check_range_checks(program_artifact, &deposit::DEPOSIT_RANGE_CHECKS)
+            }
+            Self::Withdraw => {
+                witness_input::check_balance_update_abi(abi)?;
+                bitdecomp::check_range_checks(program_artifact, &withdraw::WITHDRAW_RANGE_CHECKS)
+            }
+            Self::Transaction => {
+                witness_input::check_transaction_abi(abi)?;
+                bitdecomp::check_range_checks(
+                    program_artifact,
+                    &transaction::TRANSACTION_RANGE_CHECKS,
+                )
+            }
+            Self::TransactionBatched => {
+                witness_input::check_transaction_batched_abi(abi)?;
+                bitdecomp::check_range_checks(
+                    program_artifact,
+                    &transaction_batched::batched_range_checks(),
+                )
+            }
+            Self::BalanceThreshold => {
+                witness_input::check_balance_threshold_abi(abi)?;
+                bitdecomp::check_range_checks(program_artifact, &threshold::THRESHOLD_RANGE_CHECKS)
+            }
+            Self::Solvency => Ok(()),
+        }
     }
 
-    rep3_ring::conversion::bit_inject_from_bits_to_field_many(&to_compose, net0, rep3_state)
+    pub fn load(self, cs_path: String) -> eyre::Result<ProgramArtifact> {
+        let program_artifact = ultrahonk::get_program_artifact(cs_path)?;
+        self.check(&program_artifact)
+            .with_context(|| format!("while checking the {self:?} circuit"))?;
+        Ok(program_artifact)
+    }
 }
 
 pub struct TestConfig {}
@@ -199,11 +308,16 @@ impl TestConfig {
     const WITHDRAW_CIRCUIT: &str = "/data/private_withdraw.json";
     const TRANSACTION_CIRCUIT: &str = "/data/private_transaction.json";
     const TRANSACTION_BATCHED_CIRCUIT: &str = "/data/private_transaction_batched.json";
+    const BALANCE_THRESHOLD_CIRCUIT: &str = "/data/private_balance_threshold.json";
+    const SOLVENCY_CIRCUIT: &str = "/data/private_solvency.json";
 
     #[cfg(test)]
-    const NUM_ITEMS: usize = 1000;
+    pub(crate) const NUM_ITEMS: usize = 1000;
     #[cfg(test)]
     const TEST_RUNS: usize = 5;
+    // The seed of the ThreeParty simulator in the tests, such that failures are reproducible
+    #[cfg(test)]
+    pub(crate) const SEED: u64 = 0x5eed_dead_beef;
 
     pub fn install_tracing() {
         use tracing_subscriber::fmt::format::FmtSpan;
@@ -226,22 +340,32 @@ impl TestConfig {
 
     pub fn get_deposit_program_artifact() -> eyre::Result<ProgramArtifact> {
         let cs_path = format!("{}{}", Self::ROOT, Self::DEPOSIT_CIRCUIT);
-        ultrahonk::get_program_artifact(cs_path)
+        NoirCircuit::Deposit.load(cs_path)
     }
 
     pub fn get_withdraw_program_artifact() -> eyre::Result<ProgramArtifact> {
         let cs_path = format!("{}{}", Self::ROOT, Self::WITHDRAW_CIRCUIT);
-        ultrahonk::get_program_artifact(cs_path)
+        NoirCircuit::Withdraw.load(cs_path)
     }
 
     pub fn get_transaction_program_artifact() -> eyre::Result<ProgramArtifact> {
         let cs_path = format!("{}{}", Self::ROOT, Self::TRANSACTION_CIRCUIT);
-        ultrahonk::get_program_artifact(cs_path)
+        NoirCircuit::Transaction.load(cs_path)
     }
 
     pub fn get_transaction_batched_program_artifact() -> eyre::Result<ProgramArtifact> {
         let cs_path = format!("{}{}", Self::ROOT, Self::TRANSACTION_BATCHED_CIRCUIT);
-        ultrahonk::get_program_artifact(cs_path)
+        NoirCircuit::TransactionBatched.load(cs_path)
+    }
+
+    pub fn get_balance_threshold_program_artifact() -> eyre::Result<ProgramArtifact> {
+        let cs_path = format!("{}{}", Self::ROOT, Self::BALANCE_THRESHOLD_CIRCUIT);
+        NoirCircuit::BalanceThreshold.load(cs_path)
+    }
+
+    pub fn get_solvency_program_artifact() -> eyre::Result<ProgramArtifact> {
+        let cs_path = format!("{}{}", Self::ROOT, Self::SOLVENCY_CIRCUIT);
+        NoirCircuit::Solvency.load(cs_path)
     }
 
     pub fn get_prover_crs(
@@ -274,6 +398,21 @@ impl TestConfig {
         map
     }
 
+    // The same as get_random_plain_map, but keyed by addresses like the smart contract
+    pub fn get_random_plain_address_map<F: PrimeField, R: Rng + CryptoRng>(
+        num_items: usize,
+        rng: &mut R,
+    ) -> PrivateDeposit<Address, DepositValuePlain<F>> {
+        let mut map = PrivateDeposit::with_capacity(num_items);
+        for _ in 0..num_items {
+            let key = Self::get_random_new_address(&map, rng);
+            let amount = F::from(rng.gen_range(0..u32::MAX));
+            let blinding = F::rand(rng);
+            map.insert(key, DepositValuePlain::new(amount, blinding));
+        }
+        map
+    }
+
     // Hashmap is not ordered, so we need to convert it to an ordered map first to be consistent among multiple runs
     pub fn get_random_map_key<K, V, R: Rng>(map: &PrivateDeposit<K, V>, rng: &mut R) -> K
     where
@@ -296,4 +435,15 @@ impl TestConfig {
         }
         key
     }
+
+    pub fn get_random_new_address<V, R: Rng + CryptoRng>(
+        map: &PrivateDeposit<Address, V>,
+        rng: &mut R,
+    ) -> Address {
+        let mut key = Address::from(rng.r#gen::<[u8; 20]>());
+        while map.contains_key(&key) {
+            key = Address::from(rng.r#gen::<[u8; 20]>());
+        }
+        key
+    }
 }
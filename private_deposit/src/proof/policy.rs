@@ -0,0 +1,433 @@
+use crate::data_structure::{DepositValueShare, PrivateDeposit};
+use crate::proof::actionquery::Action;
+use ark_ff::{One, Zero};
+use co_noir::Rep3AcvmType;
+use mpc_core::protocols::rep3::{self, Rep3PrimeFieldShare, Rep3State};
+use mpc_core::serde_compat::{ark_de, ark_se};
+use mpc_net::Network;
+use std::collections::HashMap;
+
+use super::F;
+use super::{NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS};
+
+// The limits are the last public inputs of the batched circuit
+pub const NUM_POLICY_INPUTS: usize = 2;
+
+// The limits every transaction of a batch has to satisfy. They are part of the batched proof, such that the smart contract can bind the proof to the limits it stores. The default limits are the largest ones the circuit supports, i.e., no additional policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PolicyLimits {
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    max_transfer: F,
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    max_balance: F,
+}
+
+impl Default for PolicyLimits {
+    fn default() -> Self {
+        Self {
+            max_transfer: super::max_value(NUM_AMOUNT_BITS),
+            max_balance: super::max_value(NUM_WITHDRAW_NEW_BITS),
+        }
+    }
+}
+
+impl PolicyLimits {
+    // The transfer limit also applies to withdraws, whose payout the circuit treats as the new balance of a receiver. Thus, it must not exceed the balance limit.
+    pub fn new(max_transfer: F, max_balance: F) -> eyre::Result<Self> {
+        let bounds = Self::default();
+        if max_transfer > bounds.max_transfer || max_balance > bounds.max_balance {
+            eyre::bail!("Policy limits exceed the range of the circuit");
+        }
+        if max_transfer > max_balance {
+            eyre::bail!("The transfer limit must not exceed the balance limit");
+        }
+        Ok(Self {
+            max_transfer,
+            max_balance,
+        })
+    }
+
+    pub fn max_transfer(&self) -> F {
+        self.max_transfer
+    }
+
+    pub fn max_balance(&self) -> F {
+        self.max_balance
+    }
+
+    pub fn public_inputs(&self) -> [F; NUM_POLICY_INPUTS] {
+        [self.max_transfer, self.max_balance]
+    }
+
+    pub(super) fn inputs(&self) -> [Rep3AcvmType<F>; NUM_POLICY_INPUTS] {
+        self.public_inputs().map(Rep3AcvmType::from)
+    }
+}
+
+// Limits the outgoing volume (transfers and withdraws) of each account per epoch.
+// The spent volume is kept secret-shared by the MPC parties and is not part of the
+// proof, since it is not stored on chain. Thus, the limit is a policy of the MPC
+// network which the contract cannot verify. The spent volumes are persisted in
+// the snapshots of the parties, see export_snapshot.
+#[derive(Debug, Clone)]
+pub struct VelocityLimit<K> {
+    max_volume: F,
+    epoch: u64,
+    spent: HashMap<K, Rep3PrimeFieldShare<F>>,
+}
+
+impl<K> VelocityLimit<K>
+where
+    K: std::hash::Hash + Eq + Clone,
+{
+    pub fn new(max_volume: F, epoch: u64) -> Self {
+        Self {
+            max_volume,
+            epoch,
+            spent: HashMap::new(),
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn max_volume(&self) -> F {
+        self.max_volume
+    }
+
+    // Resets the spent volumes if a new epoch starts
+    pub fn set_epoch(&mut self, epoch: u64) {
+        if epoch != self.epoch {
+            self.epoch = epoch;
+            self.spent.clear();
+        }
+    }
+
+    // Restores a limit from the spent volumes of a snapshot
+    pub fn with_spent(
+        max_volume: F,
+        epoch: u64,
+        spent: HashMap<K, Rep3PrimeFieldShare<F>>,
+    ) -> Self {
+        Self {
+            max_volume,
+            epoch,
+            spent,
+        }
+    }
+
+    // The shares of the spent volumes of this party, by account
+    pub fn spent_volumes(&self) -> &HashMap<K, Rep3PrimeFieldShare<F>> {
+        &self.spent
+    }
+
+    fn spent(&self, key: &K) -> Rep3PrimeFieldShare<F> {
+        self.spent
+            .get(key)
+            .copied()
+            .unwrap_or_else(Rep3PrimeFieldShare::zero_share)
+    }
+
+    // Returns a share of 1 if the account can still spend the amount in this epoch, a share of 0 otherwise
+    fn check<N: Network>(
+        &self,
+        key: &K,
+        amount: Rep3PrimeFieldShare<F>,
+        net: &N,
+        rep3_state: &mut Rep3State,
+    ) -> eyre::Result<Rep3PrimeFieldShare<F>> {
+        let volume = rep3::arithmetic::add(self.spent(key), amount);
+        rep3::arithmetic::le_public(volume, self.max_volume, net, rep3_state)
+    }
+
+    fn spend(&mut self, key: &K, amount: Rep3PrimeFieldShare<F>) {
+        let volume = rep3::arithmetic::add(self.spent(key), amount);
+        self.spent.insert(key.to_owned(), volume);
+    }
+}
+
+impl<K> PrivateDeposit<K, DepositValueShare<F>>
+where
+    K: std::hash::Hash + Eq + Clone,
+{
+    fn policy_balance(
+        &self,
+        balances: &HashMap<K, Rep3PrimeFieldShare<F>>,
+        key: &K,
+    ) -> Rep3PrimeFieldShare<F> {
+        balances
+            .get(key)
+            .copied()
+            .or_else(|| self.get(key).map(|value| value.amount))
+            .unwrap_or_else(Rep3PrimeFieldShare::zero_share)
+    }
+
+    // Checks the policy for the actions of the queue in order, without changing the map. Each check is computed on the shares and only whether the action is accepted is opened, not which limit it violates. Rejected actions do not count towards the balances and volumes of later actions. Only the volumes of accepted actions are recorded in the velocity limit.
+    pub fn check_policy<N: Network>(
+        &self,
+        queue: &[Action<K>],
+        limits: &PolicyLimits,
+        mut velocity: Option<&mut VelocityLimit<K>>,
+        net: &N,
+        rep3_state: &mut Rep3State,
+    ) -> eyre::Result<Vec<bool>> {
+        let mut balances = HashMap::new();
+        let mut accepted = Vec::with_capacity(queue.len());
+
+        for action in queue {
+            let ok = match action {
+                Action::Deposit(receiver, amount) => {
+                    // The amount is public
+                    if *amount > limits.max_transfer {
+                        false
+                    } else {
+                        let amount =
+                            rep3::arithmetic::promote_to_trivial_share(rep3_state.id, *amount);
+                        let receiver_new =
+                            rep3::arithmetic::add(self.policy_balance(&balances, receiver), amount);
+                        let ok = rep3::arithmetic::le_public(
+                            receiver_new,
+                            limits.max_balance,
+                            net,
+                            rep3_state,
+                        )?;
+                        let ok = Self::open_bit(ok, net)?;
+                        if ok {
+                            balances.insert(receiver.to_owned(), receiver_new);
+                        }
+                        ok
+                    }
+                }
+                Action::Withdraw(sender, amount) => {
+                    // The amount is public
+                    if *amount > limits.max_transfer {
+                        false
+                    } else {
+                        let amount =
+                            rep3::arithmetic::promote_to_trivial_share(rep3_state.id, *amount);
+                        let ok = match velocity.as_deref() {
+                            Some(velocity) => Self::open_bit(
+                                velocity.check(sender, amount, net, rep3_state)?,
+                                net,
+                            )?,
+                            None => true,
+                        };
+                        if ok {
+                            self.policy_spend(
+                                &mut balances,
+                                velocity.as_deref_mut(),
+                                sender,
+                                amount,
+                            );
+                        }
+                        ok
+                    }
+                }
+                Action::Transfer(sender, receiver, amount, _) => {
+                    let transfer_ok =
+                        rep3::arithmetic::le_public(*amount, limits.max_transfer, net, rep3_state)?;
+                    let receiver_new =
+                        rep3::arithmetic::add(self.policy_balance(&balances, receiver), *amount);
+                    let balance_ok = rep3::arithmetic::le_public(
+                        receiver_new,
+                        limits.max_balance,
+                        net,
+                        rep3_state,
+                    )?;
+                    let mut ok = rep3::arithmetic::mul(transfer_ok, balance_ok, net, rep3_state)?;
+                    if let Some(velocity) = velocity.as_deref() {
+                        let velocity_ok = velocity.check(sender, *amount, net, rep3_state)?;
+                        ok = rep3::arithmetic::mul(ok, velocity_ok, net, rep3_state)?;
+                    }
+                    let ok = Self::open_bit(ok, net)?;
+                    if ok {
+                        self.policy_spend(&mut balances, velocity.as_deref_mut(), sender, *amount);
+                        let receiver_new = rep3::arithmetic::add(
+                            self.policy_balance(&balances, receiver),
+                            *amount,
+                        );
+                        balances.insert(receiver.to_owned(), receiver_new);
+                    }
+                    ok
+                }
+                Action::PrivateWithdraw(sender, amount, _) => {
+                    let mut ok =
+                        rep3::arithmetic::le_public(*amount, limits.max_transfer, net, rep3_state)?;
+                    if let Some(velocity) = velocity.as_deref() {
+                        let velocity_ok = velocity.check(sender, *amount, net, rep3_state)?;
+                        ok = rep3::arithmetic::mul(ok, velocity_ok, net, rep3_state)?;
+                    }
+                    let ok = Self::open_bit(ok, net)?;
+                    if ok {
+                        self.policy_spend(&mut balances, velocity.as_deref_mut(), sender, *amount);
+                    }
+                    ok
+                }
+                Action::Dummy => true,
+                Action::Invalid => eyre::bail!("Invalid action in queue"),
+            };
+            accepted.push(ok);
+        }
+
+        Ok(accepted)
+    }
+
+    fn policy_spend(
+        &self,
+        balances: &mut HashMap<K, Rep3PrimeFieldShare<F>>,
+        velocity: Option<&mut VelocityLimit<K>>,
+        sender: &K,
+        amount: Rep3PrimeFieldShare<F>,
+    ) {
+        let sender_new = rep3::arithmetic::sub(self.policy_balance(balances, sender), amount);
+        balances.insert(sender.to_owned(), sender_new);
+        if let Some(velocity) = velocity {
+            velocity.spend(sender, amount);
+        }
+    }
+
+    fn open_bit<N: Network>(bit: Rep3PrimeFieldShare<F>, net: &N) -> eyre::Result<bool> {
+        let bit = rep3::arithmetic::open(bit, net)?;
+        if bit.is_one() {
+            Ok(true)
+        } else if bit.is_zero() {
+            Ok(false)
+        } else {
+            eyre::bail!("Policy check did not result in a bit")
+        }
+    }
+}
+
+// Replaces the rejected actions with dummies, such that the batch can be proven with the policy limits. The rejected actions have to be removed from the queue of the smart contract with rejectActions, which refunds rejected deposits.
+pub fn apply_rejections<K>(
+    queue: Vec<Action<K>>,
+    accepted: &[bool],
+) -> eyre::Result<Vec<Action<K>>> {
+    if queue.len() != accepted.len() {
+        eyre::bail!("Invalid queue or accepted length");
+    }
+    Ok(queue
+        .into_iter()
+        .zip(accepted)
+        .map(|(action, accepted)| if *accepted { action } else { Action::Dummy })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data_structure::DepositValuePlain,
+        proof::{
+            TestConfig,
+            actionquery::public_inputs_to_contract_commitments,
+            transaction_batched::{NUM_COMMITMENTS, NUM_TRANSACTIONS},
+        },
+        three_party::ThreeParty,
+    };
+    use ark_ff::UniformRand;
+    use co_noir_to_r1cs::noir::r1cs;
+    use itertools::izip;
+
+    #[test]
+    fn policy_limits_test() {
+        assert!(PolicyLimits::new(F::from(100u64), F::from(1000u64)).is_ok());
+        assert!(PolicyLimits::new(F::from(1001u64), F::from(1000u64)).is_err());
+        let max = PolicyLimits::default();
+        assert!(PolicyLimits::new(max.max_transfer(), max.max_balance()).is_ok());
+        assert!(PolicyLimits::new(max.max_transfer() + F::one(), max.max_balance()).is_err());
+        assert!(PolicyLimits::new(max.max_transfer(), max.max_balance() + F::one()).is_err());
+    }
+
+    #[test]
+    fn policy_groth16_test() {
+        let mut parties = ThreeParty::new(NUM_TRANSACTIONS * 2, TestConfig::SEED);
+
+        // Init Groth16
+        let pa = TestConfig::get_transaction_batched_program_artifact().unwrap();
+        let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, parties.rng()).unwrap();
+
+        let limits = PolicyLimits::new(F::from(1000u64), F::from(6000u64)).unwrap();
+        let mut plain_map = PrivateDeposit::new();
+        let [alice, bob, carol] = [0u64, 1, 2].map(F::from);
+        plain_map.insert(
+            alice,
+            DepositValuePlain::new(F::from(3000u64), F::rand(parties.rng())),
+        );
+        plain_map.insert(
+            bob,
+            DepositValuePlain::new(F::from(5700u64), F::rand(parties.rng())),
+        );
+        let mut map_shares = parties.share_map(&plain_map);
+
+        // (sender, receiver, amount, accepted)
+        let transfers = [
+            (alice, carol, 1001u64, false), // Above the transfer limit
+            (alice, bob, 500, false),       // Bob would be above the balance limit
+            (alice, bob, 200, true),
+            (alice, carol, 1000, true),
+            (alice, carol, 100, false), // Above the velocity limit
+        ];
+        let mut queues: [Vec<Action<F>>; 3] = Default::default();
+        for (sender, receiver, amount, _) in transfers {
+            let amount_share = parties.share_field_element(F::from(amount));
+            let amount_blinding_share = parties.share_field_element(F::rand(parties.rng()));
+            for (queue, amount, amount_blinding) in
+                izip!(queues.iter_mut(), amount_share, amount_blinding_share)
+            {
+                queue.push(Action::Transfer(sender, receiver, amount, amount_blinding));
+            }
+        }
+        for queue in queues.iter_mut() {
+            queue.push(Action::Deposit(carol, F::from(2000u64))); // Above the transfer limit
+            queue.push(Action::Deposit(bob, F::from(100u64)));
+            queue.resize(NUM_TRANSACTIONS, Action::Dummy);
+        }
+        let mut expected = transfers.map(|(_, _, _, accepted)| accepted).to_vec();
+        expected.extend([false, true]);
+        expected.resize(NUM_TRANSACTIONS, true);
+
+        let [map0, map1, map2] = map_shares.each_mut();
+        let [queue0, queue1, queue2] = queues;
+        let (accepted, queue, public_inputs) = parties
+            .run_public(
+                NUM_TRANSACTIONS,
+                [(map0, queue0), (map1, queue1), (map2, queue2)],
+                |(map, queue), nets, rep3_states| {
+                    let mut velocity = VelocityLimit::new(F::from(1250u64), 1);
+                    let accepted = map.check_policy(
+                        &queue,
+                        &limits,
+                        Some(&mut velocity),
+                        &nets[0],
+                        &mut rep3_states[0],
+                    )?;
+                    let queue = apply_rejections(queue, &accepted)?;
+                    let (_, _, proof, public_inputs, _) = map.process_queue_with_groth16_proof(
+                        queue.clone(),
+                        &limits,
+                        &proof_schema,
+                        &cs,
+                        &pk,
+                        nets.try_into()?,
+                        rep3_states.try_into()?,
+                    )?;
+                    if !r1cs::verify(&pk.vk, &proof, &public_inputs)? {
+                        eyre::bail!("Invalid proof");
+                    }
+                    Ok((accepted, queue, public_inputs))
+                },
+            )
+            .unwrap();
+        assert_eq!(accepted, expected);
+        assert_eq!(public_inputs[NUM_COMMITMENTS..], limits.public_inputs());
+        assert!(public_inputs_to_contract_commitments(&queue, &public_inputs, &[]).is_ok());
+
+        // Only the accepted actions were applied
+        let result = PrivateDeposit::reconstruct(map_shares).unwrap();
+        assert_eq!(result.get(&alice).unwrap().amount, F::from(1800u64));
+        assert_eq!(result.get(&bob).unwrap().amount, F::from(6000u64));
+        assert_eq!(result.get(&carol).unwrap().amount, F::from(1000u64));
+    }
+}
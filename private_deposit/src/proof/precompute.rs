@@ -0,0 +1,179 @@
+//! The offline phase of the action queue. Only the Poseidon2 precomputations
+//! are pooled: the conversions (a2y2b, bit injection) draw their correlated
+//! randomness locally from the seeded RNGs of the Rep3State, they have no
+//! interactive preprocessing which could be moved offline. The pool serves the
+//! packed modes (process_queue_packed_with_r1cs_witness and
+//! process_queue_with_ultrahonk_proof), which need NUM_COMMITMENTS permutations
+//! per batch. The per-action mode of process_queue_with_r1cs_witness needs a
+//! different number of permutations for each action and computes its
+//! precomputations online.
+
+use mpc_core::gadgets::poseidon2::{Poseidon2, Poseidon2Precomputations};
+use mpc_core::protocols::rep3::{Rep3PrimeFieldShare, Rep3State};
+use mpc_net::Network;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+use super::F;
+
+pub type Precomputation = Poseidon2Precomputations<Rep3PrimeFieldShare<F>>;
+
+// A background thread precomputes the randomness of the Poseidon2 permutations
+// of whole batches over its own network while the system is idle, the online
+// phase then only consumes from the pool.
+//
+// All three parties have to spawn their pools at the same time with the same
+// parameters and consume the same number of entries. The entries are consumed
+// in the order they are produced, thus the parties always use matching
+// precomputations. If the pool is empty, taking blocks until the next entry is
+// ready instead of computing one inline, which would use a different network
+// than the other parties.
+pub struct PrecomputationPool {
+    num_permutations: usize,
+    receiver: Mutex<mpsc::Receiver<eyre::Result<Precomputation>>>,
+    available: Arc<AtomicUsize>,
+}
+
+impl PrecomputationPool {
+    // Spawns the background thread, which keeps up to capacity entries of num_permutations permutations each. The network must not be used by anything else. The thread stops once the pool is dropped.
+    pub fn spawn<N: Network + Send + 'static>(
+        num_permutations: usize,
+        capacity: usize,
+        net: N,
+        mut rep3_state: Rep3State,
+    ) -> eyre::Result<Self> {
+        if num_permutations == 0 || capacity == 0 {
+            eyre::bail!("Invalid parameters for the precomputation pool");
+        }
+        // The channel holds capacity entries, the thread can precompute one more before it blocks
+        let (sender, receiver) = mpsc::sync_channel(capacity - 1);
+        let available = Arc::new(AtomicUsize::new(0));
+        let available_ = available.clone();
+
+        thread::spawn(move || {
+            let hasher = Poseidon2::<F, 2, 5>::default();
+            loop {
+                let result = hasher.precompute_rep3(num_permutations, &net, &mut rep3_state);
+                let failed = result.is_err();
+                available_.fetch_add(1, Ordering::SeqCst);
+                if sender.send(result).is_err() || failed {
+                    break;
+                }
+            }
+            tracing::debug!("Stopped precomputation thread");
+        });
+
+        Ok(Self {
+            num_permutations,
+            receiver: Mutex::new(receiver),
+            available,
+        })
+    }
+
+    pub fn num_permutations(&self) -> usize {
+        self.num_permutations
+    }
+
+    // The number of entries which are ready
+    pub fn available(&self) -> usize {
+        self.available.load(Ordering::SeqCst)
+    }
+
+    // Takes the next entry, blocks if the background thread has not finished it yet
+    pub fn take(&self, num_permutations: usize) -> eyre::Result<Precomputation> {
+        if num_permutations != self.num_permutations {
+            eyre::bail!(
+                "The pool precomputes {} permutations, but {num_permutations} are required",
+                self.num_permutations
+            );
+        }
+        let receiver = self
+            .receiver
+            .lock()
+            .map_err(|_| eyre::eyre!("Precomputation pool is poisoned"))?;
+        let result = receiver
+            .recv()
+            .map_err(|_| eyre::eyre!("Precomputation thread stopped"))?;
+        self.available.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof::{TestConfig, plain_commitment, poseidon2_commitments_with_precomputation};
+    use crate::three_party::ThreeParty;
+    use ark_ff::UniformRand;
+    use mpc_core::protocols::rep3::{self, conversion::A2BType};
+    use mpc_net::local::LocalNetwork;
+    use std::time::Duration;
+
+    #[test]
+    fn precomputation_pool_test() {
+        let mut parties = ThreeParty::new(1, TestConfig::SEED);
+
+        // Each party spawns its pool with its own network
+        let handles = LocalNetwork::new(3)
+            .into_iter()
+            .map(|net| {
+                thread::spawn(move || {
+                    let rep3_state = Rep3State::new(&net, A2BType::default())?;
+                    PrecomputationPool::spawn(2, 3, net, rep3_state)
+                })
+            })
+            .collect::<Vec<_>>();
+        let pools = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap().unwrap())
+            .collect::<Vec<_>>();
+
+        // The background threads fill the pools
+        while pools.iter().any(|pool| pool.available() < 3) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(pools[0].take(1).is_err());
+
+        // Commit to two values with the precomputations
+        let values = [0, 1].map(|_| (F::rand(parties.rng()), F::rand(parties.rng())));
+        let shares = values.map(|(amount, blinding)| {
+            [
+                parties.share_field_element(amount),
+                parties.share_field_element(blinding),
+            ]
+        });
+        let inputs: [_; 3] = std::array::from_fn(|party| {
+            (
+                &pools[party],
+                [
+                    shares[0][0][party],
+                    shares[0][1][party],
+                    shares[1][0][party],
+                    shares[1][1][party],
+                ],
+            )
+        });
+        // Consume more entries than the capacity, such that the pools have to refill
+        let commitments = parties
+            .run_public(1, inputs, |(pool, input), nets, rep3_states| {
+                let mut commitments = Vec::with_capacity(5);
+                for _ in 0..5 {
+                    let commitment = poseidon2_commitments_with_precomputation::<2, 4, _, _>(
+                        input,
+                        pool.take(2)?,
+                        &nets[0],
+                        &mut rep3_states[0],
+                    )?;
+                    commitments.push(rep3::arithmetic::open_vec(&commitment, &nets[0])?);
+                }
+                Ok(commitments)
+            })
+            .unwrap();
+
+        let expected = values.map(|(amount, blinding)| plain_commitment(amount, blinding));
+        for commitments in commitments {
+            assert_eq!(commitments, expected);
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use crate::data_structure::{DepositValueShare, PrivateDeposit};
+use crate::key::MapKey;
+use crate::merkle_tree::CommitmentTree;
+use ark_ff::Zero;
+use ark_groth16::{Proof, VerifyingKey};
+use co_circom::{ConstraintMatrices, ProvingKey, Rep3SharedWitness};
+use co_noir::Rep3AcvmType;
+use co_noir_to_r1cs::{noir::r1cs, r1cs::noir_proof_schema::NoirProofScheme};
+use eyre::Context;
+use mpc_core::protocols::rep3::{self, Rep3PrimeFieldShare, Rep3State};
+use mpc_net::Network;
+
+use super::Curve;
+use super::F;
+
+// From the Noir circuit
+pub const NUM_SOLVENCY_BALANCES: usize = 50;
+// The previous total, the balances, and the new total
+const NUM_SOLVENCY_COMMITMENTS: usize = NUM_SOLVENCY_BALANCES + 2;
+
+// The result of a solvency run. The keys are sorted and split into chunks of NUM_SOLVENCY_BALANCES, where each chunk has its own proof. The proofs are chained by the commitments to the running total: The first proof starts from commit(0, 0), each following one from the total commitment of its predecessor. Only the final total is opened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolvencyProof<K> {
+    pub keys: Vec<K>,
+    pub proofs: Vec<(Proof<Curve>, Vec<F>)>,
+    pub total: F,
+    pub total_blinding: F,
+}
+
+impl<K> PrivateDeposit<K, DepositValueShare<F>>
+where
+    K: std::hash::Hash + Eq + Clone + Ord,
+{
+    fn get_solvency_input(
+        previous: &DepositValueShare<F>,
+        values: &[DepositValueShare<F>],
+        new_blinding: Rep3PrimeFieldShare<F>,
+    ) -> Vec<Rep3AcvmType<F>> {
+        let mut inputs = Vec::with_capacity(NUM_SOLVENCY_BALANCES * 2 + 3);
+        inputs.push(Rep3AcvmType::from(previous.amount));
+        inputs.push(Rep3AcvmType::from(previous.blinding));
+        for value in values {
+            inputs.push(Rep3AcvmType::from(value.amount));
+            inputs.push(Rep3AcvmType::from(value.blinding));
+        }
+        inputs.push(Rep3AcvmType::from(new_blinding));
+        inputs
+    }
+
+    // Adds the balances of the keys (at most NUM_SOLVENCY_BALANCES) to the running total. Returns the new running total and the witness. Missing slots are filled with zero balances.
+    pub fn solvency_chunk_with_r1cs_witext<N: Network>(
+        &self,
+        keys: &[K],
+        previous: &DepositValueShare<F>,
+        proof_schema: &NoirProofScheme<F>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> eyre::Result<(DepositValueShare<F>, Rep3SharedWitness<F>)> {
+        if keys.len() > NUM_SOLVENCY_BALANCES {
+            eyre::bail!("Too many keys for a solvency chunk");
+        }
+
+        let mut values = Vec::with_capacity(NUM_SOLVENCY_BALANCES);
+        for key in keys {
+            let value = self
+                .get(key)
+                .ok_or_else(|| eyre::eyre!("Key not found in HashMap"))?;
+            values.push(value.to_owned());
+        }
+        values.resize(
+            NUM_SOLVENCY_BALANCES,
+            DepositValueShare::new(Rep3PrimeFieldShare::zero(), Rep3PrimeFieldShare::zero()),
+        );
+
+        // The sum is a local operation on the shares
+        let mut total = previous.amount;
+        for value in values.iter() {
+            total = rep3::arithmetic::add(total, value.amount);
+        }
+        let new_total = DepositValueShare::new(total, rep3::arithmetic::rand(rep3_state));
+
+        let inputs = Self::get_solvency_input(previous, &values, new_total.blinding);
+
+        let mut commitment_inputs = [Rep3PrimeFieldShare::zero(); NUM_SOLVENCY_COMMITMENTS * 2];
+        commitment_inputs[0] = previous.amount;
+        commitment_inputs[1] = previous.blinding;
+        for (commitment, value) in commitment_inputs[2..]
+            .chunks_exact_mut(2)
+            .zip(values.iter())
+        {
+            commitment[0] = value.amount;
+            commitment[1] = value.blinding;
+        }
+        commitment_inputs[NUM_SOLVENCY_COMMITMENTS * 2 - 2] = new_total.amount;
+        commitment_inputs[NUM_SOLVENCY_COMMITMENTS * 2 - 1] = new_total.blinding;
+
+        let traces = super::poseidon2_commitment_helper::<NUM_SOLVENCY_COMMITMENTS, _, _, _>(
+            commitment_inputs,
+            net0,
+            rep3_state,
+        )?;
+
+        let r1cs =
+            r1cs::trace_to_r1cs_witness(inputs, traces, proof_schema, net0, net1, rep3_state)
+                .context("while translating witness to R1CS")?;
+
+        let witness = r1cs::r1cs_witness_to_cogroth16(proof_schema, r1cs, rep3_state.id);
+
+        Ok((new_total, witness))
+    }
+
+    // Proves that the sum of all balances in the map is the opened total. Meant to
+    // be run periodically, such that everyone can compare the total against the
+    // token balance of the smart contract (see `verify_solvency`).
+    pub fn solvency_with_groth16_proof<N: Network>(
+        &self,
+        proof_schema: &NoirProofScheme<F>,
+        cs: &ConstraintMatrices<F>,
+        pk: &ProvingKey<Curve>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> eyre::Result<SolvencyProof<K>> {
+        // The parties have to agree on the order of the keys
+        let mut keys = self.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+
+        let mut total =
+            DepositValueShare::new(Rep3PrimeFieldShare::zero(), Rep3PrimeFieldShare::zero());
+        let mut proofs = Vec::with_capacity(keys.len().div_ceil(NUM_SOLVENCY_BALANCES));
+        for chunk in keys.chunks(NUM_SOLVENCY_BALANCES) {
+            let (new_total, witness) = self.solvency_chunk_with_r1cs_witext(
+                chunk,
+                &total,
+                proof_schema,
+                net0,
+                net1,
+                rep3_state,
+            )?;
+            let proof = r1cs::prove(cs, pk, witness, net0, net1)
+                .context("while generating Groth16 proof")?;
+            proofs.push(proof);
+            total = new_total;
+        }
+
+        let opened = rep3::arithmetic::open_vec(&[total.amount, total.blinding], net0)?;
+
+        Ok(SolvencyProof {
+            keys,
+            proofs,
+            total: opened[0],
+            total_blinding: opened[1],
+        })
+    }
+}
+
+// The reasons a solvency proof is rejected
+#[derive(Debug, thiserror::Error)]
+pub enum SolvencyError {
+    #[error("Expected {expected} balance commitments, got {actual}")]
+    CommitmentLength { expected: usize, actual: usize },
+    #[error("The keys are not sorted or contain duplicates")]
+    UnsortedKeys,
+    #[error("Invalid key: {0:#}")]
+    InvalidKey(eyre::Report),
+    #[error("The keys and commitments do not match the balance root")]
+    RootMismatch,
+    #[error("Expected {expected} chunk proofs, got {actual}")]
+    ProofCount { expected: usize, actual: usize },
+    #[error("The public inputs of chunk {0} do not match the balance commitments")]
+    CommitmentMismatch(usize),
+    #[error("The proof of chunk {0} is invalid")]
+    InvalidProof(usize),
+    #[error("Verifying the proof of chunk {chunk} failed: {error:#}")]
+    Verifier { chunk: usize, error: eyre::Report },
+    #[error("The opened total does not match the last running total")]
+    TotalMismatch,
+    #[error("The total {total} differs from the reserve {reserve}")]
+    ReserveMismatch { total: F, reserve: F },
+}
+
+// Verifies a solvency proof. The balance commitments are the ones stored on
+// chain for the keys of the proof (in the same order), the balance root is the
+// one of the contract. Rebuilding the Merkle tree from the keys and commitments
+// binds them to the root, thus a proof which leaves out a user is rejected. The
+// reserve is the token balance of the contract minus the deposits which are not
+// yet processed.
+pub fn verify_solvency<K: MapKey>(
+    vk: &VerifyingKey<Curve>,
+    proof: &SolvencyProof<K>,
+    balance_commitments: &[F],
+    balance_root: F,
+    reserve: F,
+) -> Result<(), SolvencyError> {
+    if balance_commitments.len() != proof.keys.len() {
+        return Err(SolvencyError::CommitmentLength {
+            expected: proof.keys.len(),
+            actual: balance_commitments.len(),
+        });
+    }
+    // Sorted and without duplicates
+    if proof.keys.windows(2).any(|keys| keys[0] >= keys[1]) {
+        return Err(SolvencyError::UnsortedKeys);
+    }
+
+    let mut tree = CommitmentTree::new();
+    for (key, commitment) in proof.keys.iter().zip(balance_commitments) {
+        tree.insert(key.to_field(), *commitment)
+            .map_err(SolvencyError::InvalidKey)?;
+    }
+    if tree.root() != balance_root {
+        return Err(SolvencyError::RootMismatch);
+    }
+
+    let expected = proof.keys.len().div_ceil(NUM_SOLVENCY_BALANCES);
+    if proof.proofs.len() != expected {
+        return Err(SolvencyError::ProofCount {
+            expected,
+            actual: proof.proofs.len(),
+        });
+    }
+
+    let zero_commitment = super::plain_commitment(F::zero(), F::zero());
+    let mut previous = zero_commitment;
+    for (chunk, ((groth16_proof, public_inputs), expected)) in proof
+        .proofs
+        .iter()
+        .zip(balance_commitments.chunks(NUM_SOLVENCY_BALANCES))
+        .enumerate()
+    {
+        if public_inputs.len() != NUM_SOLVENCY_COMMITMENTS || public_inputs[0] != previous {
+            return Err(SolvencyError::CommitmentMismatch(chunk));
+        }
+        let (commitments, padding) =
+            public_inputs[1..=NUM_SOLVENCY_BALANCES].split_at(expected.len());
+        if commitments != expected || padding.iter().any(|c| *c != zero_commitment) {
+            return Err(SolvencyError::CommitmentMismatch(chunk));
+        }
+        if !r1cs::verify(vk, groth16_proof, public_inputs)
+            .map_err(|error| SolvencyError::Verifier { chunk, error })?
+        {
+            return Err(SolvencyError::InvalidProof(chunk));
+        }
+        previous = public_inputs[NUM_SOLVENCY_COMMITMENTS - 1];
+    }
+
+    if previous != super::plain_commitment(proof.total, proof.total_blinding) {
+        return Err(SolvencyError::TotalMismatch);
+    }
+    if proof.total != reserve {
+        return Err(SolvencyError::ReserveMismatch {
+            total: proof.total,
+            reserve,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        proof::{TestConfig, plain_commitment},
+        three_party::ThreeParty,
+    };
+
+    #[test]
+    fn solvency_groth16_test() {
+        let mut parties = ThreeParty::new(2, TestConfig::SEED);
+
+        // Init Groth16
+        let pa = TestConfig::get_solvency_program_artifact().unwrap();
+        let (proof_schema, pk, cs) = r1cs::setup_r1cs(pa, parties.rng()).unwrap();
+
+        // Two full chunks and a partial one
+        let plain_map = TestConfig::get_random_plain_address_map::<F, _>(
+            2 * NUM_SOLVENCY_BALANCES + 7,
+            parties.rng(),
+        );
+        let [map0, map1, map2] = parties.share_map(&plain_map);
+
+        let proof = parties
+            .run_public(1, [map0, map1, map2], |map, nets, rep3_states| {
+                map.solvency_with_groth16_proof(
+                    &proof_schema,
+                    &cs,
+                    &pk,
+                    &nets[0],
+                    &nets[1],
+                    &mut rep3_states[0],
+                )
+            })
+            .unwrap();
+        assert_eq!(proof.proofs.len(), 3);
+
+        let reserve = plain_map
+            .values()
+            .fold(F::zero(), |acc, value| acc + value.amount);
+        let mut tree = CommitmentTree::new();
+        let mut commitments = proof
+            .keys
+            .iter()
+            .map(|key| {
+                let value = plain_map.get(key).unwrap();
+                let commitment = plain_commitment(value.amount, value.blinding);
+                tree.insert(key.to_field(), commitment).unwrap();
+                commitment
+            })
+            .collect::<Vec<_>>();
+        let root = tree.root();
+
+        assert_eq!(proof.total, reserve);
+        verify_solvency(&pk.vk, &proof, &commitments, root, reserve).unwrap();
+
+        // Drift between the reserve and the balances
+        assert!(matches!(
+            verify_solvency(&pk.vk, &proof, &commitments, root, reserve + F::from(1u64)),
+            Err(SolvencyError::ReserveMismatch { .. })
+        ));
+
+        // A user which is not covered by the proof changes the root
+        let mut missing = tree.clone();
+        missing
+            .insert(
+                TestConfig::get_random_new_address(&plain_map, parties.rng()).to_field(),
+                plain_commitment(F::from(1u64), F::from(2u64)),
+            )
+            .unwrap();
+        assert!(matches!(
+            verify_solvency(&pk.vk, &proof, &commitments, missing.root(), reserve),
+            Err(SolvencyError::RootMismatch)
+        ));
+
+        // The proof is bound to the commitments on chain
+        let mut changed = tree.clone();
+        commitments[NUM_SOLVENCY_BALANCES] += F::from(1u64);
+        changed
+            .insert(
+                proof.keys[NUM_SOLVENCY_BALANCES].to_field(),
+                commitments[NUM_SOLVENCY_BALANCES],
+            )
+            .unwrap();
+        assert!(matches!(
+            verify_solvency(&pk.vk, &proof, &commitments, changed.root(), reserve),
+            Err(SolvencyError::CommitmentMismatch(1))
+        ));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::data_structure::{DataStructureError, DepositValueShare, PrivateDeposit};
+use ark_groth16::{Proof, VerifyingKey};
+use co_circom::{ConstraintMatrices, ProvingKey, Rep3SharedWitness};
+use co_noir::Rep3AcvmType;
+use co_noir_to_r1cs::{noir::r1cs, r1cs::noir_proof_schema::NoirProofScheme};
+use eyre::Context;
+use mpc_core::protocols::rep3::{self, Rep3State};
+use mpc_net::Network;
+use noirc_artifacts::program::ProgramArtifact;
+use rand_chacha::{ChaCha12Rng, rand_core::SeedableRng};
+
+use super::Curve;
+use super::F;
+use super::NUM_WITHDRAW_NEW_BITS;
+use super::bitdecomp::BitDecomps;
+use super::max_value;
+
+const NUM_THRESHOLD_COMMITMENTS: usize = 1;
+// The range checks of the circuit in their order: the distances to min and to max
+pub const THRESHOLD_RANGE_CHECKS: [usize; 2] = [NUM_WITHDRAW_NEW_BITS; 2];
+// The seed of the Groth16 setup which create_solidity renders into balance_threshold_verifier.sol. The keys are derived from a seed instead of a ceremony, thus insecure, but the MPC nodes recreate the proving key matching the deployed verifier from it.
+pub const THRESHOLD_SETUP_SEED: [u8; 32] = *b"SOLIDITY_BALANCE_THRESHOLD\0\0\0\0\0\0";
+
+// The Groth16 setup of the balance threshold circuit. It draws from its own RNG, such that the keys do not depend on what else is set up before.
+pub fn balance_threshold_setup(
+    program_artifact: ProgramArtifact,
+) -> eyre::Result<(NoirProofScheme<F>, ProvingKey<Curve>, ConstraintMatrices<F>)> {
+    let mut rng = ChaCha12Rng::from_seed(THRESHOLD_SETUP_SEED);
+    r1cs::setup_r1cs(program_artifact, &mut rng)
+}
+
+// The circuit range checks balance - min and max - balance to the bits of a balance. Bounds outside of [0, 2^BALANCE_BITS) could wrap around the field, thus they are rejected by the prover and the verifier.
+pub fn check_threshold_bounds(min: F, max: F) -> eyre::Result<()> {
+    if min > max {
+        eyre::bail!("The lower bound {min} is above the upper bound {max}");
+    }
+    if max > max_value(NUM_WITHDRAW_NEW_BITS) {
+        eyre::bail!("The upper bound {max} does not fit into {NUM_WITHDRAW_NEW_BITS} bits");
+    }
+    Ok(())
+}
+
+impl<K> PrivateDeposit<K, DepositValueShare<F>>
+where
+    K: std::hash::Hash + Eq + Clone + Send + Sync + std::fmt::Debug + 'static,
+{
+    fn get_balance_threshold_input(
+        value: &DepositValueShare<F>,
+        min: F,
+        max: F,
+    ) -> Vec<Rep3AcvmType<F>> {
+        vec![
+            Rep3AcvmType::from(value.amount),
+            Rep3AcvmType::from(value.blinding),
+            Rep3AcvmType::from(min),
+            Rep3AcvmType::from(max),
+        ]
+    }
+
+    // Creates the witness for proving that the balance of the key lies in [min, max]. If it does not, the witness does not satisfy the circuit and the resulting proof does not verify.
+    #[expect(clippy::too_many_arguments)]
+    pub fn balance_threshold_with_r1cs_witext<N: Network>(
+        &self,
+        key: &K,
+        min: F,
+        max: F,
+        proof_schema: &NoirProofScheme<F>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> eyre::Result<Rep3SharedWitness<F>> {
+        check_threshold_bounds(min, max)?;
+        let value = self
+            .get(key)
+            .ok_or_else(|| DataStructureError::KeyNotFound(key.to_owned()))?;
+
+        let inputs = Self::get_balance_threshold_input(value, min, max);
+
+        let traces = super::poseidon2_commitment_helper::<NUM_THRESHOLD_COMMITMENTS, _, _, _>(
+            [value.amount, value.blinding],
+            net0,
+            rep3_state,
+        )?;
+
+        // The bit decompositions of balance - min and max - balance
+        let above_min = rep3::arithmetic::sub_shared_by_public(value.amount, min, rep3_state.id);
+        let below_max = rep3::arithmetic::sub_public_by_shared(max, value.amount, rep3_state.id);
+        let mut decomps = BitDecomps::new();
+        decomps.push(NUM_WITHDRAW_NEW_BITS, above_min);
+        decomps.push(NUM_WITHDRAW_NEW_BITS, below_max);
+        let decomps = decomps.decompose_compose(net0, rep3_state)?;
+
+        let r1cs = r1cs::trace_to_r1cs_witness_with_bitdecomp_witness(
+            inputs,
+            traces,
+            decomps.into_witness(&THRESHOLD_RANGE_CHECKS)?,
+            proof_schema,
+            net0,
+            net1,
+            rep3_state,
+        )
+        .context("while translating witness to R1CS")?;
+
+        let witness = r1cs::r1cs_witness_to_cogroth16(proof_schema, r1cs, rep3_state.id);
+
+        Ok(witness)
+    }
+
+    // Proves that the balance of the key lies in [min, max]. The public inputs are [min, max, commitment], where the commitment is the one stored on chain for the key.
+    #[expect(clippy::too_many_arguments)]
+    pub fn balance_threshold_with_groth16_proof<N: Network>(
+        &self,
+        key: &K,
+        min: F,
+        max: F,
+        proof_schema: &NoirProofScheme<F>,
+        cs: &ConstraintMatrices<F>,
+        pk: &ProvingKey<Curve>,
+        net0: &N,
+        net1: &N,
+        rep3_state: &mut Rep3State,
+    ) -> eyre::Result<(Proof<Curve>, Vec<F>)> {
+        let witness = self.balance_threshold_with_r1cs_witext(
+            key,
+            min,
+            max,
+            proof_schema,
+            net0,
+            net1,
+            rep3_state,
+        )?;
+
+        r1cs::prove(cs, pk, witness, net0, net1).context("while generating Groth16 proof")
+    }
+}
+
+// Verifies a balance threshold proof against a commitment, e.g., read from `balanceCommitments` of the smart contract
+pub fn verify_balance_threshold(
+    vk: &VerifyingKey<Curve>,
+    proof: &Proof<Curve>,
+    commitment: F,
+    min: F,
+    max: F,
+) -> eyre::Result<bool> {
+    check_threshold_bounds(min, max)?;
+    r1cs::verify(vk, proof, &[min, max, commitment])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        proof::{NUM_WITHDRAW_NEW_BITS, TestConfig, plain_commitment},
+        three_party::ThreeParty,
+    };
+    use ark_ff::Zero;
+
+    #[test]
+    fn balance_threshold_groth16_test() {
+        let mut parties = ThreeParty::new(2, TestConfig::SEED);
+
+        // Init Groth16
+        let pa = TestConfig::get_balance_threshold_program_artifact().unwrap();
+        let (proof_schema, pk, cs) = balance_threshold_setup(pa).unwrap();
+
+        // Get a random map and its shares
+        let plain_map =
+            TestConfig::get_random_plain_map::<F, _>(TestConfig::NUM_ITEMS, parties.rng());
+        let map_shares = parties.share_map(&plain_map);
+
+        let max_balance = max_value(NUM_WITHDRAW_NEW_BITS);
+        for _ in 0..TestConfig::TEST_RUNS {
+            let key = TestConfig::get_random_map_key(&plain_map, parties.rng());
+            let value = plain_map.get(&key).unwrap();
+            let commitment = plain_commitment(value.amount, value.blinding);
+            let one = F::from(1u64);
+
+            // (min, max, holds)
+            let ranges = [
+                (F::zero(), max_balance, true),
+                (value.amount, max_balance, true),
+                (F::zero(), value.amount, true),
+                (value.amount + one, max_balance, false),
+                (F::zero(), value.amount - one, false),
+            ];
+            for (min, max, holds) in ranges {
+                if value.amount.is_zero() && !holds {
+                    // value.amount - 1 wraps around
+                    continue;
+                }
+                let [map0, map1, map2] = map_shares.each_ref();
+                let result = parties.run_public(1, [map0, map1, map2], |map, nets, rep3_states| {
+                    map.balance_threshold_with_groth16_proof(
+                        &key,
+                        min,
+                        max,
+                        &proof_schema,
+                        &cs,
+                        &pk,
+                        &nets[0],
+                        &nets[1],
+                        &mut rep3_states[0],
+                    )
+                });
+
+                match result {
+                    Ok((proof, public_inputs)) => {
+                        assert_eq!(public_inputs, [min, max, commitment]);
+                        assert_eq!(
+                            verify_balance_threshold(&pk.vk, &proof, commitment, min, max).unwrap(),
+                            holds
+                        );
+                        // The proof is bound to the commitment
+                        if holds {
+                            assert!(
+                                !verify_balance_threshold(
+                                    &pk.vk,
+                                    &proof,
+                                    commitment + one,
+                                    min,
+                                    max
+                                )
+                                .unwrap()
+                            );
+                        }
+                    }
+                    Err(_) => {
+                        assert!(!holds);
+                        parties.reconnect();
+                    }
+                }
+            }
+
+            // Bounds which are swapped or do not fit into a balance are rejected by the prover and the verifier
+            let ranges = [
+                (value.amount + one, value.amount),
+                (F::zero(), max_balance + one),
+            ];
+            for (min, max) in ranges {
+                let [map0, map1, map2] = map_shares.each_ref();
+                let result = parties.run_public(1, [map0, map1, map2], |map, nets, rep3_states| {
+                    map.balance_threshold_with_groth16_proof(
+                        &key,
+                        min,
+                        max,
+                        &proof_schema,
+                        &cs,
+                        &pk,
+                        &nets[0],
+                        &nets[1],
+                        &mut rep3_states[0],
+                    )
+                });
+                assert!(result.is_err());
+                assert!(check_threshold_bounds(min, max).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn balance_threshold_setup_test() {
+        // The setup is reproducible, such that the proving key matches balance_threshold_verifier.sol
+        let pa = TestConfig::get_balance_threshold_program_artifact().unwrap();
+        let (_, pk0, _) = balance_threshold_setup(pa.clone()).unwrap();
+        let (_, pk1, _) = balance_threshold_setup(pa).unwrap();
+        assert_eq!(pk0.vk, pk1.vk);
+
+        assert!(check_threshold_bounds(F::zero(), F::zero()).is_ok());
+        assert!(check_threshold_bounds(F::zero(), max_value(NUM_WITHDRAW_NEW_BITS)).is_ok());
+        assert!(check_threshold_bounds(F::from(2u64), F::from(1u64)).is_err());
+        assert!(check_threshold_bounds(F::zero(), -F::from(1u64)).is_err());
+    }
+}
@@ -16,12 +16,17 @@ use noirc_artifacts::program::ProgramArtifact;
 
 use super::Curve;
 use super::F;
+use super::bitdecomp::BitDecomps;
+use super::witness_input::TransactionWitnessInput;
+use super::{NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS};
 
 pub const NUM_TRANSACTION_COMMITMENTS: usize = 5;
+// The range checks of the circuit in their order: the amount and the new balance of the sender
+pub const TRANSACTION_RANGE_CHECKS: [usize; 2] = [NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS];
 
 impl<K> PrivateDeposit<K, DepositValueShare<F>>
 where
-    K: std::hash::Hash + Eq,
+    K: std::hash::Hash + Eq + Send + Sync + std::fmt::Debug + 'static,
 {
     pub(super) fn get_transaction_input(
         sender_old: DepositValueShare<F>,
@@ -35,22 +40,61 @@ where
         Rep3PrimeFieldShare<F>,
         Rep3PrimeFieldShare<F>,
     ) {
-        let mut inputs = Vec::with_capacity(8);
-        inputs.push(Rep3AcvmType::from(sender_old.amount));
-        inputs.push(Rep3AcvmType::from(sender_old.blinding));
-        let (reciever_old_amount, reciever_old_blinding) = if let Some(old) = receiver_old {
-            inputs.push(Rep3AcvmType::from(old.amount));
-            inputs.push(Rep3AcvmType::from(old.blinding));
-            (old.amount, old.blinding)
-        } else {
-            inputs.push(Rep3AcvmType::from(F::zero()));
-            inputs.push(Rep3AcvmType::from(F::zero()));
-            (Rep3PrimeFieldShare::zero(), Rep3PrimeFieldShare::zero())
+        let (inputs, reciever_old_amount, reciever_old_blinding) =
+            Self::get_transaction_witness_input(
+                sender_old,
+                receiver_old,
+                amount,
+                amount_blinding,
+                sender_new_blinding,
+                receiver_new_blinding,
+            );
+        (
+            inputs.into_noir(),
+            reciever_old_amount,
+            reciever_old_blinding,
+        )
+    }
+
+    // The typed version of get_transaction_input. A new receiver has a public zero balance and blinding.
+    pub(super) fn get_transaction_witness_input(
+        sender_old: DepositValueShare<F>,
+        receiver_old: Option<DepositValueShare<F>>,
+        amount: Rep3PrimeFieldShare<F>,
+        amount_blinding: Rep3PrimeFieldShare<F>,
+        sender_new_blinding: Rep3PrimeFieldShare<F>,
+        receiver_new_blinding: Rep3PrimeFieldShare<F>,
+    ) -> (
+        TransactionWitnessInput<Rep3AcvmType<F>>,
+        Rep3PrimeFieldShare<F>,
+        Rep3PrimeFieldShare<F>,
+    ) {
+        let (receiver_old_balance, receiver_old_r, reciever_old_amount, reciever_old_blinding) =
+            if let Some(old) = receiver_old {
+                (
+                    old.amount.into(),
+                    old.blinding.into(),
+                    old.amount,
+                    old.blinding,
+                )
+            } else {
+                (
+                    Rep3AcvmType::from(F::zero()),
+                    Rep3AcvmType::from(F::zero()),
+                    Rep3PrimeFieldShare::zero(),
+                    Rep3PrimeFieldShare::zero(),
+                )
+            };
+        let inputs = TransactionWitnessInput {
+            sender_old_balance: sender_old.amount.into(),
+            sender_old_r: sender_old.blinding.into(),
+            receiver_old_balance,
+            receiver_old_r,
+            amount: amount.into(),
+            amount_r: amount_blinding.into(),
+            sender_new_r: sender_new_blinding.into(),
+            receiver_new_r: receiver_new_blinding.into(),
         };
-        inputs.push(Rep3AcvmType::from(amount));
-        inputs.push(Rep3AcvmType::from(amount_blinding));
-        inputs.push(Rep3AcvmType::from(sender_new_blinding));
-        inputs.push(Rep3AcvmType::from(receiver_new_blinding));
         (inputs, reciever_old_amount, reciever_old_blinding)
     }
 
@@ -213,19 +257,15 @@ where
         )?;
 
         // The bit decompositions
-        let (mut decomp_amount, decomp_sender) = super::decompose_compose_for_transaction(
-            amount,
-            sender_new.amount,
-            net0,
-            net1,
-            rep3_state,
-        )?;
-        decomp_amount.extend(decomp_sender);
+        let mut decomps = BitDecomps::new();
+        decomps.push(NUM_AMOUNT_BITS, amount);
+        decomps.push(NUM_WITHDRAW_NEW_BITS, sender_new.amount);
+        let decomps = decomps.decompose_compose(net0, rep3_state)?;
 
         let r1cs = r1cs::trace_to_r1cs_witness_with_bitdecomp_witness(
             inputs,
             traces,
-            decomp_amount,
+            decomps.into_witness(&TRANSACTION_RANGE_CHECKS)?,
             proof_schema,
             net0,
             net1,
@@ -1,6 +1,11 @@
 use crate::{
     data_structure::{DepositValueShare, PrivateDeposit},
-    proof::transaction::NUM_TRANSACTION_COMMITMENTS,
+    proof::{
+        NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS,
+        bitdecomp::BitDecomps,
+        policy::{NUM_POLICY_INPUTS, PolicyLimits},
+        transaction::{NUM_TRANSACTION_COMMITMENTS, TRANSACTION_RANGE_CHECKS},
+    },
 };
 use ark_ff::{PrimeField, Zero};
 use ark_groth16::Proof;
@@ -13,7 +18,7 @@ use co_noir_to_r1cs::{
 };
 use eyre::Context;
 use itertools::izip;
-use mpc_core::protocols::rep3::{Rep3PrimeFieldShare, Rep3State};
+use mpc_core::protocols::rep3::{self, Rep3PrimeFieldShare, Rep3State};
 use mpc_net::Network;
 use noir_types::U256;
 use noirc_artifacts::program::ProgramArtifact;
@@ -26,6 +31,18 @@ use super::F;
 // From the Noir circuits
 pub const NUM_TRANSACTIONS: usize = 50;
 pub const NUM_COMMITMENTS: usize = NUM_TRANSACTIONS * NUM_TRANSACTION_COMMITMENTS;
+// The range checks of each transaction in their order: the ones of the transaction circuit, then the transfer and the balance headroom of the policy check
+const POLICY_RANGE_CHECKS: [usize; 2] = [NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS];
+
+// The range checks of the circuit in their order, see bitdecomp::check_range_checks
+pub fn batched_range_checks() -> Vec<usize> {
+    TRANSACTION_RANGE_CHECKS
+        .into_iter()
+        .chain(POLICY_RANGE_CHECKS)
+        .cycle()
+        .take(NUM_TRANSACTIONS * (TRANSACTION_RANGE_CHECKS.len() + POLICY_RANGE_CHECKS.len()))
+        .collect()
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct TransactionInput<K, F> {
@@ -71,7 +88,7 @@ impl<K, F: PrimeField> TransactionInput<K, F> {
 
 impl<K> PrivateDeposit<K, DepositValueShare<F>>
 where
-    K: std::hash::Hash + Eq + Clone + Send + Sync,
+    K: std::hash::Hash + Eq + Clone + Send + Sync + std::fmt::Debug + 'static,
 {
     fn add_to_tranasction_input(
         inputs: &mut Vec<Rep3AcvmType<F>>,
@@ -82,21 +99,16 @@ where
         sender_new_blinding: Rep3PrimeFieldShare<F>,
         receiver_new_blinding: Rep3PrimeFieldShare<F>,
     ) -> (Rep3PrimeFieldShare<F>, Rep3PrimeFieldShare<F>) {
-        inputs.push(Rep3AcvmType::from(sender_old.amount));
-        inputs.push(Rep3AcvmType::from(sender_old.blinding));
-        let (reciever_old_amount, reciever_old_blinding) = if let Some(old) = receiver_old {
-            inputs.push(Rep3AcvmType::from(old.amount));
-            inputs.push(Rep3AcvmType::from(old.blinding));
-            (old.amount, old.blinding)
-        } else {
-            inputs.push(Rep3AcvmType::from(F::zero()));
-            inputs.push(Rep3AcvmType::from(F::zero()));
-            (Rep3PrimeFieldShare::zero(), Rep3PrimeFieldShare::zero())
-        };
-        inputs.push(Rep3AcvmType::from(amount));
-        inputs.push(Rep3AcvmType::from(amount_blinding));
-        inputs.push(Rep3AcvmType::from(sender_new_blinding));
-        inputs.push(Rep3AcvmType::from(receiver_new_blinding));
+        let (input, reciever_old_amount, reciever_old_blinding) =
+            Self::get_transaction_witness_input(
+                sender_old,
+                receiver_old,
+                amount,
+                amount_blinding,
+                sender_new_blinding,
+                receiver_new_blinding,
+            );
+        input.extend_noir(inputs);
         (reciever_old_amount, reciever_old_blinding)
     }
 
@@ -248,7 +260,7 @@ where
         let mut sender_new = Vec::with_capacity(NUM_TRANSACTIONS);
         let mut receiver_new = Vec::with_capacity(NUM_TRANSACTIONS);
         let mut commitment_inputs = [Rep3PrimeFieldShare::zero(); NUM_COMMITMENTS * 2]; // each commitment needs 2 inputs
-        let mut proof_inputs = Vec::with_capacity(NUM_TRANSACTIONS * 8);
+        let mut proof_inputs = Vec::with_capacity(NUM_TRANSACTIONS * 8 + NUM_POLICY_INPUTS);
 
         for (input, commitments) in inputs
             .iter()
@@ -285,6 +297,8 @@ where
             sender_new.push(sender_new_);
             receiver_new.push(receiver_new_);
         }
+        // Plain transactions are not subject to policy limits
+        proof_inputs.extend(PolicyLimits::default().inputs());
 
         // let witness_stack =
         //     ultrahonk::conoir_witness_extension(proof_inputs, program_artifact, net0, net1)?;
@@ -330,7 +344,7 @@ where
         let mut sender_new = Vec::with_capacity(NUM_TRANSACTIONS);
         let mut receiver_new = Vec::with_capacity(NUM_TRANSACTIONS);
         let mut commitment_inputs = [Rep3PrimeFieldShare::zero(); NUM_COMMITMENTS * 2]; // each commitment needs 2 inputs
-        let mut proof_inputs = Vec::with_capacity(NUM_TRANSACTIONS * 8);
+        let mut proof_inputs = Vec::with_capacity(NUM_TRANSACTIONS * 8 + NUM_POLICY_INPUTS);
 
         for (input, commitments) in inputs
             .iter()
@@ -367,6 +381,8 @@ where
             sender_new.push(sender_new_);
             receiver_new.push(receiver_new_);
         }
+        // Plain transactions are not subject to policy limits
+        proof_inputs.extend(PolicyLimits::default().inputs());
 
         let traces = super::poseidon2_commitment_helper::<NUM_COMMITMENTS, _, _, _>(
             commitment_inputs,
@@ -427,11 +443,9 @@ where
     )> {
         let mut sender_new = Vec::with_capacity(NUM_TRANSACTIONS);
         let mut receiver_new = Vec::with_capacity(NUM_TRANSACTIONS);
-        let mut proof_inputs = Vec::with_capacity(NUM_TRANSACTIONS * 8);
+        let mut proof_inputs = Vec::with_capacity(NUM_TRANSACTIONS * 8 + NUM_POLICY_INPUTS);
         let mut traces = Vec::with_capacity(NUM_COMMITMENTS);
-        // The compiler groups bitdecomps by bits, so we have to store both used ones separately first
-        let mut bitdecomps1 = Vec::with_capacity(NUM_TRANSACTIONS * 2);
-        let mut bitdecomps2 = Vec::with_capacity(NUM_TRANSACTIONS);
+        let mut bitdecomps = BitDecomps::new();
 
         let result = thread::scope(|scope| {
             let mut handles = Vec::with_capacity(3);
@@ -475,28 +489,41 @@ where
                             rep3_state,
                         )?;
 
-                    // The bit decompositions
-                    let (decomp_amount, decomp_sender) = super::decompose_compose_for_transaction(
-                        input.amount,
-                        sender_new.amount,
-                        &nets[0],
-                        &nets[1],
-                        rep3_state,
-                    )?;
+                    // The bit decompositions, followed by the ones of the policy checks with the default limits
+                    let limits = PolicyLimits::default();
+                    let mut decomps = BitDecomps::new();
+                    decomps.push(NUM_AMOUNT_BITS, input.amount);
+                    decomps.push(NUM_WITHDRAW_NEW_BITS, sender_new.amount);
+                    decomps.push(
+                        NUM_AMOUNT_BITS,
+                        rep3::arithmetic::sub_public_by_shared(
+                            limits.max_transfer(),
+                            input.amount,
+                            rep3_state.id,
+                        ),
+                    );
+                    decomps.push(
+                        NUM_WITHDRAW_NEW_BITS,
+                        rep3::arithmetic::sub_public_by_shared(
+                            limits.max_balance(),
+                            receiver_new.amount,
+                            rep3_state.id,
+                        ),
+                    );
+                    let decomps = decomps.decompose_compose(&nets[1], rep3_state)?;
 
                     Result::<_, eyre::Report>::Ok((
                         sender_new,
                         receiver_new,
                         inputs,
                         traces,
-                        decomp_amount,
-                        decomp_sender,
+                        decomps,
                     ))
                 });
                 handles.push(handle);
             }
             for handle in handles {
-                let (sender_new_, receiver_new_, inputs_, traces_, decomps1, decomps2) =
+                let (sender_new_, receiver_new_, inputs_, traces_, decomps) =
                     handle.join().map_err(|_| {
                         eyre::eyre!("A thread panicked while processing a transaction")
                     })??;
@@ -504,20 +531,17 @@ where
                 receiver_new.push(receiver_new_);
                 proof_inputs.extend(inputs_);
                 traces.extend(traces_);
-                bitdecomps1.extend(decomps1);
-                bitdecomps2.extend(decomps2);
+                bitdecomps.append(decomps);
             }
             Result::<_, eyre::Report>::Ok(())
         });
         result?;
-
-        bitdecomps1.extend(bitdecomps2);
-        let bitdecomps = bitdecomps1;
+        proof_inputs.extend(PolicyLimits::default().inputs());
 
         let r1cs = r1cs::trace_to_r1cs_witness_with_bitdecomp_witness(
             proof_inputs,
             traces,
-            bitdecomps,
+            bitdecomps.into_witness(&batched_range_checks())?,
             proof_schema,
             &nets[0],
             &nets[1],
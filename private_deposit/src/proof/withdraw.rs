@@ -15,12 +15,17 @@ use noirc_artifacts::program::ProgramArtifact;
 
 use super::Curve;
 use super::F;
+use super::bitdecomp::BitDecomps;
+use super::witness_input::BalanceUpdateInput;
+use super::{NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS};
 
 const NUM_WITHDRAW_COMMITMENTS: usize = 3;
+// The range checks of the circuit in their order: the amount and the new balance
+pub const WITHDRAW_RANGE_CHECKS: [usize; 2] = [NUM_AMOUNT_BITS, NUM_WITHDRAW_NEW_BITS];
 
 impl<K> PrivateDeposit<K, DepositValueShare<F>>
 where
-    K: std::hash::Hash + Eq,
+    K: std::hash::Hash + Eq + Send + Sync + std::fmt::Debug + 'static,
 {
     fn get_withdraw_input(
         old: DepositValueShare<F>,
@@ -28,13 +33,14 @@ where
         amount_blinding: Rep3PrimeFieldShare<F>,
         new_blinding: Rep3PrimeFieldShare<F>,
     ) -> Vec<Rep3AcvmType<F>> {
-        vec![
-            Rep3AcvmType::from(old.amount),
-            Rep3AcvmType::from(old.blinding),
-            Rep3AcvmType::from(amount),
-            Rep3AcvmType::from(amount_blinding),
-            Rep3AcvmType::from(new_blinding),
-        ]
+        BalanceUpdateInput {
+            old_balance: old.amount,
+            old_r: old.blinding,
+            amount,
+            amount_r: amount_blinding,
+            new_r: new_blinding,
+        }
+        .into_noir()
     }
 
     pub fn withdraw_with_commitments<N: Network>(
@@ -146,14 +152,15 @@ where
         )?;
 
         // The bit decompositions
-        let (mut decomp_amount, decomp_sender) =
-            super::decompose_compose_for_transaction(amount, new.amount, net0, net1, rep3_state)?;
-        decomp_amount.extend(decomp_sender);
+        let mut decomps = BitDecomps::new();
+        decomps.push(NUM_AMOUNT_BITS, amount);
+        decomps.push(NUM_WITHDRAW_NEW_BITS, new.amount);
+        let decomps = decomps.decompose_compose(net0, rep3_state)?;
 
         let r1cs = r1cs::trace_to_r1cs_witness_with_bitdecomp_witness(
             inputs,
             traces,
-            decomp_amount,
+            decomps.into_witness(&WITHDRAW_RANGE_CHECKS)?,
             proof_schema,
             net0,
             net1,
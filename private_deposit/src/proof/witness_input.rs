@@ -0,0 +1,433 @@
+use circom_mpc_vm::Rep3VmType;
+use co_noir::Rep3AcvmType;
+use eyre::Context;
+use noirc_abi::{Abi, AbiType};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::F;
+use super::transaction_batched::NUM_TRANSACTIONS;
+
+// The inputs of the circuits, named like the parameters of the Noir circuits and
+// the signals of the circom templates. Noir takes them as a flat vector in the
+// order of the ABI, which is checked when a program artifact is loaded.
+
+// The inputs of the deposit and the withdraw circuit
+pub const BALANCE_UPDATE_INPUTS: [&str; 5] =
+    ["old_balance", "old_r", "amount", "amount_r", "new_r"];
+
+// The inputs of the transaction circuit and of each transaction of the batched circuit
+pub const TRANSACTION_INPUTS: [&str; 8] = [
+    "sender_old_balance",
+    "sender_old_r",
+    "receiver_old_balance",
+    "receiver_old_r",
+    "amount",
+    "amount_r",
+    "sender_new_r",
+    "receiver_new_r",
+];
+
+// The inputs of the batched circuit which follow the transactions
+pub const POLICY_INPUTS: [&str; 2] = ["max_transfer", "max_balance"];
+
+// The inputs of the balance threshold circuit, see get_balance_threshold_input
+pub const BALANCE_THRESHOLD_INPUTS: [&str; 4] = ["balance", "r", "min", "max"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceUpdateInput<T> {
+    pub old_balance: T,
+    pub old_r: T,
+    pub amount: T,
+    pub amount_r: T,
+    pub new_r: T,
+}
+
+impl<T> BalanceUpdateInput<T> {
+    // In the order of BALANCE_UPDATE_INPUTS
+    pub fn into_array(self) -> [T; 5] {
+        [
+            self.old_balance,
+            self.old_r,
+            self.amount,
+            self.amount_r,
+            self.new_r,
+        ]
+    }
+}
+
+impl<T: Into<Rep3AcvmType<F>>> BalanceUpdateInput<T> {
+    pub fn into_noir(self) -> Vec<Rep3AcvmType<F>> {
+        self.into_array().into_iter().map(Into::into).collect()
+    }
+}
+
+impl<T: Into<Rep3VmType<F>>> BalanceUpdateInput<T> {
+    pub fn into_circom(self) -> BTreeMap<String, Rep3VmType<F>> {
+        BALANCE_UPDATE_INPUTS
+            .iter()
+            .zip(self.into_array())
+            .map(|(name, value)| (name.to_string(), value.into()))
+            .collect()
+    }
+}
+
+// Deposits and withdraws in an action queue are transactions with public values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionWitnessInput<T> {
+    pub sender_old_balance: T,
+    pub sender_old_r: T,
+    pub receiver_old_balance: T,
+    pub receiver_old_r: T,
+    pub amount: T,
+    pub amount_r: T,
+    pub sender_new_r: T,
+    pub receiver_new_r: T,
+}
+
+impl<T> TransactionWitnessInput<T> {
+    // In the order of TRANSACTION_INPUTS
+    pub fn into_array(self) -> [T; 8] {
+        [
+            self.sender_old_balance,
+            self.sender_old_r,
+            self.receiver_old_balance,
+            self.receiver_old_r,
+            self.amount,
+            self.amount_r,
+            self.sender_new_r,
+            self.receiver_new_r,
+        ]
+    }
+}
+
+impl<T: Into<Rep3AcvmType<F>>> TransactionWitnessInput<T> {
+    pub fn into_noir(self) -> Vec<Rep3AcvmType<F>> {
+        self.into_array().into_iter().map(Into::into).collect()
+    }
+
+    // Appends the inputs to the ones of the previous transactions of a batch
+    pub fn extend_noir(self, inputs: &mut Vec<Rep3AcvmType<F>>) {
+        inputs.extend(self.into_array().into_iter().map(Into::into));
+    }
+}
+
+impl<T: Into<Rep3VmType<F>>> TransactionWitnessInput<T> {
+    pub fn into_circom(self) -> BTreeMap<String, Rep3VmType<F>> {
+        TRANSACTION_INPUTS
+            .iter()
+            .zip(self.into_array())
+            .map(|(name, value)| (name.to_string(), value.into()))
+            .collect()
+    }
+
+    // Inserts the inputs of the i-th transaction of a batched template
+    pub fn insert_circom(self, i: usize, inputs: &mut BTreeMap<String, Rep3VmType<F>>) {
+        for (name, value) in TRANSACTION_INPUTS.iter().zip(self.into_array()) {
+            inputs.insert(format!("{name}[{i}]"), value.into());
+        }
+    }
+}
+
+// Flattens a parameter of an ABI to the names of its field elements, e.g.,
+// transactions[0].amount
+fn flatten_abi_type(name: String, typ: &AbiType, names: &mut Vec<String>) {
+    match typ {
+        AbiType::Array { length, typ } => {
+            for i in 0..*length {
+                flatten_abi_type(format!("{name}[{i}]"), typ, names);
+            }
+        }
+        AbiType::Struct { fields, .. } => {
+            for (field, typ) in fields {
+                flatten_abi_type(format!("{name}.{field}"), typ, names);
+            }
+        }
+        AbiType::Tuple { fields } => {
+            for (i, typ) in fields.iter().enumerate() {
+                flatten_abi_type(format!("{name}.{i}"), typ, names);
+            }
+        }
+        AbiType::String { length } => {
+            for i in 0..*length {
+                names.push(format!("{name}[{i}]"));
+            }
+        }
+        _ => names.push(name),
+    }
+}
+
+pub fn abi_input_names(abi: &Abi) -> Vec<String> {
+    let mut names = Vec::new();
+    for parameter in abi.parameters.iter() {
+        flatten_abi_type(parameter.name.to_owned(), &parameter.typ, &mut names);
+    }
+    names
+}
+
+fn check_abi(abi: &Abi, expected: &[String]) -> eyre::Result<()> {
+    let names = abi_input_names(abi);
+    if names.len() != expected.len() {
+        eyre::bail!(
+            "The circuit has {} inputs, but {} are expected",
+            names.len(),
+            expected.len()
+        );
+    }
+    if let Some((name, expected)) = names.iter().zip(expected).find(|(a, b)| a != b) {
+        eyre::bail!("The circuit has the input {name} where {expected} is expected");
+    }
+    Ok(())
+}
+
+pub fn check_balance_update_abi(abi: &Abi) -> eyre::Result<()> {
+    let expected = BALANCE_UPDATE_INPUTS.map(str::to_string);
+    check_abi(abi, &expected)
+}
+
+pub fn check_transaction_abi(abi: &Abi) -> eyre::Result<()> {
+    let expected = TRANSACTION_INPUTS.map(str::to_string);
+    check_abi(abi, &expected)
+}
+
+pub fn check_transaction_batched_abi(abi: &Abi) -> eyre::Result<()> {
+    let mut expected = Vec::with_capacity(NUM_TRANSACTIONS * TRANSACTION_INPUTS.len() + 2);
+    for i in 0..NUM_TRANSACTIONS {
+        for name in TRANSACTION_INPUTS {
+            expected.push(format!("transactions[{i}].{name}"));
+        }
+    }
+    expected.extend(POLICY_INPUTS.map(str::to_string));
+    check_abi(abi, &expected)
+}
+
+pub fn check_balance_threshold_abi(abi: &Abi) -> eyre::Result<()> {
+    let expected = BALANCE_THRESHOLD_INPUTS.map(str::to_string);
+    check_abi(abi, &expected)
+}
+
+// An input signal of a circom template, arrays have one entry per transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircomSignal {
+    pub name: String,
+    pub is_array: bool,
+}
+
+impl CircomSignal {
+    fn new(name: &str, is_array: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            is_array,
+        }
+    }
+}
+
+// The name of the template of `component main ... = Template(...);`
+fn circom_main_template(source: &str) -> Option<&str> {
+    let line = source
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("component main"))?;
+    let (_, template) = line.split_once('=')?;
+    let (template, _) = template.split_once('(')?;
+    Some(template.trim())
+}
+
+fn circom_includes(source: &str) -> impl Iterator<Item = &str> {
+    source.lines().filter_map(|line| {
+        let line = line.trim().strip_prefix("include")?;
+        line.trim().strip_prefix('"')?.split('"').next()
+    })
+}
+
+// The input signals declared in the body of the template, in their order.
+// Expects one declaration per line.
+fn circom_template_inputs(source: &str, template: &str) -> Option<Vec<CircomSignal>> {
+    let start = source.find(&format!("template {template}("))?;
+    let mut depth = 0;
+    let mut inputs = Vec::new();
+    for line in source[start..].lines() {
+        let line = line.trim();
+        if let Some(declaration) = line.strip_prefix("signal input") {
+            for signal in declaration.trim_end_matches(';').split(',') {
+                let signal = signal.trim();
+                match signal.split_once('[') {
+                    Some((name, _)) => inputs.push(CircomSignal::new(name.trim(), true)),
+                    None => inputs.push(CircomSignal::new(signal, false)),
+                }
+            }
+        }
+        depth += line.matches('{').count();
+        depth -= line.matches('}').count();
+        if depth == 0 && line.contains('}') {
+            return Some(inputs);
+        }
+    }
+    None
+}
+
+// Searches the template in the file and, depth-first, in its includes. Includes
+// are resolved like the circom compiler does.
+fn find_circom_template(
+    file: &Path,
+    lib: &Path,
+    template: &str,
+    visited: &mut Vec<PathBuf>,
+) -> eyre::Result<Option<Vec<CircomSignal>>> {
+    if visited.iter().any(|path| path == file) {
+        return Ok(None);
+    }
+    visited.push(file.to_path_buf());
+    let source = std::fs::read_to_string(file)
+        .with_context(|| format!("while reading {}", file.display()))?;
+    if let Some(inputs) = circom_template_inputs(&source, template) {
+        return Ok(Some(inputs));
+    }
+    let dir = file.parent().unwrap_or(Path::new("."));
+    for include in circom_includes(&source) {
+        let Some(path) = [dir.join(include), lib.join(include)]
+            .into_iter()
+            .find(|path| path.is_file())
+        else {
+            eyre::bail!("Cannot resolve the include {include} of {}", file.display());
+        };
+        if let Some(inputs) = find_circom_template(&path, lib, template, visited)? {
+            return Ok(Some(inputs));
+        }
+    }
+    Ok(None)
+}
+
+// The input signals of the main component of a circom circuit
+pub fn circom_input_signals(circuit: &Path, lib: &Path) -> eyre::Result<Vec<CircomSignal>> {
+    let source = std::fs::read_to_string(circuit)
+        .with_context(|| format!("while reading {}", circuit.display()))?;
+    let template = circom_main_template(&source)
+        .ok_or_else(|| eyre::eyre!("{} has no main component", circuit.display()))?;
+    find_circom_template(circuit, lib, template, &mut Vec::new())?
+        .ok_or_else(|| eyre::eyre!("Cannot find the template {template}"))
+}
+
+fn check_circom_signals(signals: &[CircomSignal], expected: &[CircomSignal]) -> eyre::Result<()> {
+    // circom takes the inputs by name, thus only the set of names matters
+    for signal in expected {
+        match signals.iter().find(|s| s.name == signal.name) {
+            None => eyre::bail!("The circuit has no input signal {}", signal.name),
+            Some(s) if s.is_array != signal.is_array => {
+                eyre::bail!(
+                    "The input signal {} is expected to be {}an array",
+                    signal.name,
+                    if signal.is_array { "" } else { "not " }
+                )
+            }
+            Some(_) => {}
+        }
+    }
+    if let Some(signal) = signals.iter().find(|s| !expected.contains(s)) {
+        eyre::bail!(
+            "The circuit has the unexpected input signal {}",
+            signal.name
+        );
+    }
+    Ok(())
+}
+
+pub fn check_balance_update_signals(signals: &[CircomSignal]) -> eyre::Result<()> {
+    let expected = BALANCE_UPDATE_INPUTS.map(|name| CircomSignal::new(name, false));
+    check_circom_signals(signals, &expected)
+}
+
+pub fn check_transaction_signals(signals: &[CircomSignal]) -> eyre::Result<()> {
+    let expected = TRANSACTION_INPUTS.map(|name| CircomSignal::new(name, false));
+    check_circom_signals(signals, &expected)
+}
+
+// The batched templates take the transactions as arrays, see insert_circom
+pub fn check_transaction_batched_signals(signals: &[CircomSignal]) -> eyre::Result<()> {
+    let expected = TRANSACTION_INPUTS.map(|name| CircomSignal::new(name, true));
+    check_circom_signals(signals, &expected)
+}
+
+pub fn check_action_queue_signals(signals: &[CircomSignal]) -> eyre::Result<()> {
+    let mut expected = TRANSACTION_INPUTS
+        .map(|name| CircomSignal::new(name, true))
+        .to_vec();
+    expected.extend(POLICY_INPUTS.map(|name| CircomSignal::new(name, false)));
+    check_circom_signals(signals, &expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof::TestConfig;
+
+    #[test]
+    fn witness_input_order_test() {
+        // The arrays have the order of the names
+        let input = BalanceUpdateInput {
+            old_balance: "old_balance",
+            old_r: "old_r",
+            amount: "amount",
+            amount_r: "amount_r",
+            new_r: "new_r",
+        };
+        assert_eq!(input.into_array(), BALANCE_UPDATE_INPUTS);
+        let input = TransactionWitnessInput {
+            sender_old_balance: "sender_old_balance",
+            sender_old_r: "sender_old_r",
+            receiver_old_balance: "receiver_old_balance",
+            receiver_old_r: "receiver_old_r",
+            amount: "amount",
+            amount_r: "amount_r",
+            sender_new_r: "sender_new_r",
+            receiver_new_r: "receiver_new_r",
+        };
+        assert_eq!(input.into_array(), TRANSACTION_INPUTS);
+
+        // The names match the ABIs of the circuits
+        let abi = TestConfig::get_deposit_program_artifact().unwrap().abi;
+        check_balance_update_abi(&abi).unwrap();
+        let abi = TestConfig::get_withdraw_program_artifact().unwrap().abi;
+        check_balance_update_abi(&abi).unwrap();
+        let abi = TestConfig::get_transaction_program_artifact().unwrap().abi;
+        check_transaction_abi(&abi).unwrap();
+        assert!(check_balance_update_abi(&abi).is_err());
+
+        // A different order is detected
+        let mut abi = TestConfig::get_deposit_program_artifact().unwrap().abi;
+        abi.parameters.swap(0, 2);
+        assert!(check_balance_update_abi(&abi).is_err());
+    }
+
+    #[test]
+    fn circom_input_signals_test() {
+        // The names match the signals of the templates
+        let (circuit, lib) = TestConfig::deposit_circom_paths();
+        let signals = circom_input_signals(&circuit, &lib).unwrap();
+        check_balance_update_signals(&signals).unwrap();
+        let (circuit, lib) = TestConfig::withdraw_circom_paths();
+        check_balance_update_signals(&circom_input_signals(&circuit, &lib).unwrap()).unwrap();
+        let (circuit, lib) = TestConfig::transaction_circom_paths();
+        let signals = circom_input_signals(&circuit, &lib).unwrap();
+        check_transaction_signals(&signals).unwrap();
+        assert!(check_transaction_batched_signals(&signals).is_err());
+        let (circuit, lib) = TestConfig::transaction_batched_circom_paths();
+        let signals = circom_input_signals(&circuit, &lib).unwrap();
+        check_transaction_batched_signals(&signals).unwrap();
+        assert!(check_action_queue_signals(&signals).is_err());
+        let (circuit, lib) = TestConfig::action_queue_circom_paths();
+        let signals = circom_input_signals(&circuit, &lib).unwrap();
+        check_action_queue_signals(&signals).unwrap();
+        assert!(check_transaction_batched_signals(&signals).is_err());
+
+        // A renamed signal is detected
+        let source = "template T() {\n    signal input old_balance, old_r;\n    signal input amount;\n    signal input amount_r;\n    signal input new_rr;\n}\n";
+        let signals = circom_template_inputs(source, "T").unwrap();
+        assert_eq!(signals[1], CircomSignal::new("old_r", false));
+        assert!(check_balance_update_signals(&signals).is_err());
+        assert_eq!(
+            circom_main_template("component main {public [a]} = T(50);"),
+            Some("T")
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use crate::data_structure::{DepositValueShare, PrivateDeposit};
+use mpc_core::protocols::rep3::{self, Rep3PrimeFieldShare};
+use mpc_core::serde_compat::{ark_de, ark_se};
+use rand::{CryptoRng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+type F = ark_bn254::Fr;
+
+// The additive shares (i.e., the decrypted part of a `Ciphertext` of a party) of the amount and the amount blinding of a transfer which is not yet processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingAdditiveShare {
+    pub action_index: usize,
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    pub amount: F,
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    pub amount_blinding: F,
+}
+
+// The replicated shares of a pending transfer for the new committee. They replace the ciphertext of the action, which the new committee cannot decrypt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingShare {
+    pub action_index: usize,
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    pub amount: Rep3PrimeFieldShare<F>,
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    pub amount_blinding: Rep3PrimeFieldShare<F>,
+}
+
+// What an old party sends to a new party. It contains fresh replicated shares of the additive shares of the old party, thus it has to be sent over a confidential channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReshareMessage<K> {
+    pub from: usize,
+    pub to: usize,
+    // The index of the last action that is included in the balances
+    pub action_index: u64,
+    pub keys: Vec<K>,
+    // The amount and the blinding of each key
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    pub balances: Vec<Rep3PrimeFieldShare<F>>,
+    pub pending_indices: Vec<usize>,
+    // The amount and the amount blinding of each pending transfer
+    #[serde(serialize_with = "ark_se", deserialize_with = "ark_de")]
+    pub pending: Vec<Rep3PrimeFieldShare<F>>,
+}
+
+// Shares the additive share of a value freshly to the three new parties
+fn reshare_additive<R: Rng + CryptoRng>(
+    value: F,
+    shares: &mut [Vec<Rep3PrimeFieldShare<F>>; 3],
+    rng: &mut R,
+) {
+    for (shares, share) in shares.iter_mut().zip(rep3::share_field_element(value, rng)) {
+        shares.push(share);
+    }
+}
+
+// Moves the shared state from an old committee to a new committee of three parties. Each old party shares its additive share of every value freshly to the new parties, each new party adds up what it receives. The resulting shares are independent of the old ones, thus a new party learns nothing even if it colludes with one old party.
+//
+// Pending transfers are encrypted to the BabyJubJub keys of the old committee. Thus, the MPC keys in the smart contract are rotated first (setMpcKeys), such that no new ciphertexts for the old keys are queued. Then, each old party decrypts its part of all pending ciphertexts (see `decrypt_share` of contract-rs) and reshares it together with the balances. The old keys can be deleted afterwards.
+impl<K> PrivateDeposit<K, DepositValueShare<F>>
+where
+    K: std::hash::Hash + Eq + Clone + Ord,
+{
+    // Run by each party of the old committee, returns the message for each new party
+    pub fn reshare<R: Rng + CryptoRng>(
+        &self,
+        party: usize,
+        action_index: u64,
+        pending: &[PendingAdditiveShare],
+        rng: &mut R,
+    ) -> eyre::Result<[ReshareMessage<K>; 3]> {
+        if party >= 3 {
+            eyre::bail!("Invalid party index for resharing");
+        }
+
+        // The parties have to agree on the order of the keys
+        let ordered = BTreeMap::from_iter(self.iter());
+        let keys = ordered
+            .keys()
+            .map(|key| (*key).to_owned())
+            .collect::<Vec<_>>();
+
+        let mut balances = std::array::from_fn(|_| Vec::with_capacity(self.len() * 2));
+        for value in ordered.values() {
+            reshare_additive(value.amount.a, &mut balances, rng);
+            reshare_additive(value.blinding.a, &mut balances, rng);
+        }
+        let mut pending_shares = std::array::from_fn(|_| Vec::with_capacity(pending.len() * 2));
+        for pending in pending {
+            reshare_additive(pending.amount, &mut pending_shares, rng);
+            reshare_additive(pending.amount_blinding, &mut pending_shares, rng);
+        }
+        let pending_indices = pending
+            .iter()
+            .map(|pending| pending.action_index)
+            .collect::<Vec<_>>();
+
+        let [balances0, balances1, balances2] = balances;
+        let [pending0, pending1, pending2] = pending_shares;
+        let message = |to, balances, pending| ReshareMessage {
+            from: party,
+            to,
+            action_index,
+            keys: keys.clone(),
+            balances,
+            pending_indices: pending_indices.clone(),
+            pending,
+        };
+        Ok([
+            message(0, balances0, pending0),
+            message(1, balances1, pending1),
+            message(2, balances2, pending2),
+        ])
+    }
+
+    // Run by each party of the new committee with the messages of the three old parties. Returns the shares of the balances, the shares of the pending transfers, and the action index up to which the balances are processed. When processing a pending transfer, the new committee looks up its shares by the action index instead of decrypting the ciphertext.
+    pub fn from_reshare(
+        party: usize,
+        messages: [ReshareMessage<K>; 3],
+    ) -> eyre::Result<(Self, Vec<PendingShare>, u64)> {
+        let [first, ..] = &messages;
+        for (from, message) in messages.iter().enumerate() {
+            if message.from != from || message.to != party {
+                eyre::bail!("Reshare messages are not ordered by party");
+            }
+            if message.action_index != first.action_index
+                || message.keys != first.keys
+                || message.pending_indices != first.pending_indices
+            {
+                eyre::bail!("The old parties reshared different states");
+            }
+            if message.balances.len() != message.keys.len() * 2
+                || message.pending.len() != message.pending_indices.len() * 2
+            {
+                eyre::bail!("Invalid reshare message length");
+            }
+        }
+
+        let sum = |index: usize, select: fn(&ReshareMessage<K>) -> &[Rep3PrimeFieldShare<F>]| {
+            messages
+                .iter()
+                .fold(Rep3PrimeFieldShare::zero_share(), |acc, message| {
+                    rep3::arithmetic::add(acc, select(message)[index])
+                })
+        };
+
+        let mut map = Self::with_capacity(first.keys.len());
+        for (i, key) in first.keys.iter().enumerate() {
+            let value = DepositValueShare::new(
+                sum(2 * i, |message| message.balances.as_slice()),
+                sum(2 * i + 1, |message| message.balances.as_slice()),
+            );
+            if map.insert(key.to_owned(), value).is_some() {
+                eyre::bail!("Duplicate key in reshare message");
+            }
+        }
+
+        let pending = first
+            .pending_indices
+            .iter()
+            .enumerate()
+            .map(|(i, action_index)| PendingShare {
+                action_index: *action_index,
+                amount: sum(2 * i, |message| message.pending.as_slice()),
+                amount_blinding: sum(2 * i + 1, |message| message.pending.as_slice()),
+            })
+            .collect();
+
+        Ok((map, pending, first.action_index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof::TestConfig;
+    use ark_ff::UniformRand;
+
+    #[test]
+    fn reshare_test() {
+        let mut rng = rand::thread_rng();
+        let plain_map = TestConfig::get_random_plain_map::<F, _>(100, &mut rng);
+        let old = plain_map.share(&mut rng);
+
+        // Two pending transfers, each old party decrypted its additive shares
+        let pending_plain = [(3, F::from(10u64)), (7, F::from(20u64))]
+            .map(|(index, amount)| (index, amount, F::rand(&mut rng)));
+        let mut pending: [Vec<PendingAdditiveShare>; 3] = Default::default();
+        for (action_index, amount, amount_blinding) in pending_plain {
+            let amount = rep3::share_field_element(amount, &mut rng);
+            let amount_blinding = rep3::share_field_element(amount_blinding, &mut rng);
+            for (party, pending) in pending.iter_mut().enumerate() {
+                pending.push(PendingAdditiveShare {
+                    action_index,
+                    amount: amount[party].a,
+                    amount_blinding: amount_blinding[party].a,
+                });
+            }
+        }
+
+        // Each old party sends one message to each new party
+        let mut messages: [Vec<ReshareMessage<F>>; 3] = Default::default();
+        for (party, (map, pending)) in old.iter().zip(pending.iter()).enumerate() {
+            let sent = map.reshare(party, 42, pending, &mut rng).unwrap();
+            for (messages, message) in messages.iter_mut().zip(sent) {
+                messages.push(message);
+            }
+        }
+
+        let mut new = Vec::with_capacity(3);
+        let mut new_pending = Vec::with_capacity(3);
+        for (party, messages) in messages.iter().enumerate() {
+            let messages: [_; 3] = messages.to_owned().try_into().unwrap();
+            let (map, pending, action_index) =
+                PrivateDeposit::from_reshare(party, messages).unwrap();
+            assert_eq!(action_index, 42);
+            new.push(map);
+            new_pending.push(pending);
+        }
+
+        // The shares are fresh
+        let key = plain_map.keys().next().unwrap();
+        assert_ne!(
+            old[0].get(key).unwrap().amount,
+            new[0].get(key).unwrap().amount
+        );
+
+        let new: [_; 3] = new.try_into().unwrap();
+        let result = PrivateDeposit::reconstruct(new).unwrap();
+        assert_eq!(result.len(), plain_map.len());
+        for (key, plain_value) in plain_map.iter() {
+            let value = result.get(key).unwrap();
+            assert_eq!(value.amount, plain_value.amount);
+            assert_eq!(value.blinding, plain_value.blinding);
+        }
+
+        for (i, (action_index, amount, amount_blinding)) in pending_plain.into_iter().enumerate() {
+            assert!(
+                new_pending
+                    .iter()
+                    .all(|p| p[i].action_index == action_index)
+            );
+            let amounts = [0, 1, 2].map(|party| new_pending[party][i].amount);
+            let blindings = [0, 1, 2].map(|party| new_pending[party][i].amount_blinding);
+            assert_eq!(
+                rep3::combine_field_element(amounts[0], amounts[1], amounts[2]),
+                amount
+            );
+            assert_eq!(
+                rep3::combine_field_element(blindings[0], blindings[1], blindings[2]),
+                amount_blinding
+            );
+        }
+
+        // Messages of different states are rejected
+        let mut messages: [_; 3] = messages[0].to_owned().try_into().unwrap();
+        messages[1].action_index += 1;
+        assert!(PrivateDeposit::<F, DepositValueShare<F>>::from_reshare(0, messages).is_err());
+    }
+}
@@ -0,0 +1,532 @@
+use crate::data_structure::{DepositValueShare, PrivateDeposit};
+use crate::key::MapKey;
+use crate::proof::policy::VelocityLimit;
+use ark_ff::{PrimeField, UniformRand, Zero};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use mpc_core::gadgets::poseidon2::Poseidon2;
+use mpc_core::protocols::rep3::{Rep3PrimeFieldShare, Rep3State};
+use mpc_net::Network;
+use rand::{CryptoRng, Rng};
+use std::collections::{BTreeMap, HashMap};
+
+type F = ark_bn254::Fr;
+
+pub const SNAPSHOT_VERSION: u8 = 2;
+const MAGIC: &[u8; 6] = b"PDSNAP";
+const FLAG_ENCRYPTED: u8 = 1;
+const FLAG_VELOCITY: u8 = 2;
+// Magic, version, flags, party, action index, number of entries, number of spent volumes, nonce
+const HEADER_SIZE: usize = 6 + 1 + 1 + 1 + 8 + 8 + 8 + FIELD_SIZE;
+const FIELD_SIZE: usize = 32;
+// The key, and the two shares of the amount and the blinding
+const ELEMENTS_PER_ENTRY: usize = 5;
+// The maximum volume and the epoch of the velocity limit
+const VELOCITY_ELEMENTS: usize = 2;
+// The key, and the two shares of the spent volume
+const ELEMENTS_PER_SPENT: usize = 3;
+
+const MAC_DOMAIN_SEPARATOR: u64 = 0x534E4150; // "SNAP"
+const ENCRYPTION_DOMAIN_SEPARATOR: u64 = 0x454E4352; // "ENCR"
+const ENCRYPTION_KEY_DOMAIN_SEPARATOR: u64 = 0x4B454E43; // "KENC"
+const MAC_KEY_DOMAIN_SEPARATOR: u64 = 0x4B4D4143; // "KMAC"
+
+// The key an operator uses to encrypt and authenticate the snapshots of its party at rest
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OperatorKey(F);
+
+impl OperatorKey {
+    pub fn new(key: F) -> Self {
+        Self(key)
+    }
+
+    pub fn random<R: Rng + CryptoRng>(rng: &mut R) -> Self {
+        Self(F::rand(rng))
+    }
+
+    // Separate keys for the encryption and the MAC
+    fn derive(&self, domain_separator: u64) -> F {
+        let hasher = Poseidon2::<F, 2, 5>::default();
+        hasher.permutation(&[F::from(domain_separator), self.0])[0]
+    }
+
+    fn encryption_key(&self) -> F {
+        self.derive(ENCRYPTION_KEY_DOMAIN_SEPARATOR)
+    }
+
+    fn mac_key(&self) -> F {
+        self.derive(MAC_KEY_DOMAIN_SEPARATOR)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub version: u8,
+    pub encrypted: bool,
+    pub party: usize,
+    // The index of the last action of the queue that is included in the snapshot
+    pub action_index: u64,
+    pub num_entries: usize,
+    // Whether the snapshot contains the state of a velocity limit
+    pub velocity: bool,
+    pub num_spent: usize,
+}
+
+impl SnapshotHeader {
+    fn elements(&self) -> [F; 7] {
+        [
+            F::from(self.version),
+            F::from(self.encrypted),
+            F::from(self.party as u64),
+            F::from(self.action_index),
+            F::from(self.num_entries as u64),
+            F::from(self.velocity),
+            F::from(self.num_spent as u64),
+        ]
+    }
+
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.encrypted {
+            flags |= FLAG_ENCRYPTED;
+        }
+        if self.velocity {
+            flags |= FLAG_VELOCITY;
+        }
+        flags
+    }
+
+    fn num_elements(&self) -> Option<usize> {
+        let entries = self.num_entries.checked_mul(ELEMENTS_PER_ENTRY)?;
+        if !self.velocity {
+            return (self.num_spent == 0).then_some(entries);
+        }
+        let spent = self.num_spent.checked_mul(ELEMENTS_PER_SPENT)?;
+        entries.checked_add(spent)?.checked_add(VELOCITY_ELEMENTS)
+    }
+}
+
+// Checks that the imported snapshots of the three parties form a consistent backup, i.e., they are taken at the same action index, contain the balances of the same keys, and the same number of spent volumes. Returns the action index.
+pub fn check_backup<K: MapKey>(
+    snapshots: [(&SnapshotHeader, &PrivateDeposit<K, DepositValueShare<F>>); 3],
+) -> eyre::Result<u64> {
+    let (first, first_map) = snapshots[0];
+    for (party, (header, map)) in snapshots.iter().enumerate() {
+        if header.party != party {
+            eyre::bail!("Snapshots are not ordered by party");
+        }
+        if header.action_index != first.action_index {
+            eyre::bail!("Snapshots are taken at different action indices");
+        }
+        if header.num_entries != first.num_entries || map.len() != header.num_entries {
+            eyre::bail!("Snapshots contain a different number of balances");
+        }
+        if header.velocity != first.velocity || header.num_spent != first.num_spent {
+            eyre::bail!("Snapshots contain a different velocity limit state");
+        }
+        // With the same number of keys, containing the keys of the first party means having the same keys
+        if let Some(key) = map.keys().find(|key| !first_map.contains_key(key)) {
+            eyre::bail!("Only the snapshot of party {party} contains a balance of {key:?}");
+        }
+    }
+    Ok(first.action_index)
+}
+
+// A keyed Poseidon2 sponge over the nonce, the header, and the encrypted
+// elements, with a rate of two elements. Plain snapshots have no key, thus they
+// use the zero key and their MAC is only a checksum against corruption.
+fn mac(mac_key: F, nonce: F, header: &SnapshotHeader, elements: &[F]) -> F {
+    let hasher = Poseidon2::<F, 3, 5>::default();
+    let mut state = hasher.permutation(&[F::from(MAC_DOMAIN_SEPARATOR), mac_key, nonce]);
+    for block in header.elements().chunks(2).chain(elements.chunks(2)) {
+        for (state, element) in state[1..].iter_mut().zip(block) {
+            *state += element;
+        }
+        state = hasher.permutation(&state);
+    }
+    state[1]
+}
+
+// A Poseidon2 duplex sponge with a rate of two elements: each block is masked
+// with the rate of the state, then the encrypted block overwrites the rate
+// before the next permutation. The number of blocks is fixed by the header,
+// which the MAC covers.
+fn apply_keystream(encryption_key: F, nonce: F, elements: &mut [F], decrypt: bool) {
+    let hasher = Poseidon2::<F, 3, 5>::default();
+    let mut state =
+        hasher.permutation(&[F::from(ENCRYPTION_DOMAIN_SEPARATOR), encryption_key, nonce]);
+    for block in elements.chunks_mut(2) {
+        for (element, state) in block.iter_mut().zip(state[1..].iter_mut()) {
+            if decrypt {
+                let encrypted = *element;
+                *element -= *state;
+                *state = encrypted;
+            } else {
+                *element += *state;
+                *state = *element;
+            }
+        }
+        state = hasher.permutation(&state);
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().expect("Slice has 8 bytes"))
+}
+
+fn read_field(bytes: &[u8]) -> eyre::Result<F> {
+    F::deserialize_compressed(bytes).map_err(|_| eyre::eyre!("Invalid field element in snapshot"))
+}
+
+impl<K: MapKey> PrivateDeposit<K, DepositValueShare<F>> {
+    // Exports the shares of this party in a versioned binary format. The action index is the watermark of the snapshot, i.e., the last processed action. The spent volumes of the velocity limit are not stored on chain, thus they are exported as well if the party enforces one. If an operator key is given, the entries are encrypted and authenticated with a MAC.
+    pub fn export_snapshot<R: Rng + CryptoRng>(
+        &self,
+        party: usize,
+        action_index: u64,
+        velocity: Option<&VelocityLimit<K>>,
+        key: Option<&OperatorKey>,
+        rng: &mut R,
+    ) -> eyre::Result<Vec<u8>> {
+        if party >= 3 {
+            eyre::bail!("Invalid party index for snapshot");
+        }
+        let header = SnapshotHeader {
+            version: SNAPSHOT_VERSION,
+            encrypted: key.is_some(),
+            party,
+            action_index,
+            num_entries: self.len(),
+            velocity: velocity.is_some(),
+            num_spent: velocity.map_or(0, |velocity| velocity.spent_volumes().len()),
+        };
+
+        // Sorted, such that the snapshots of the parties have the same order
+        let ordered = BTreeMap::from_iter(self.iter());
+        let mut elements = Vec::with_capacity(self.len() * ELEMENTS_PER_ENTRY);
+        for (key, value) in ordered {
+            elements.extend([
+                key.to_field(),
+                value.amount.a,
+                value.amount.b,
+                value.blinding.a,
+                value.blinding.b,
+            ]);
+        }
+        if let Some(velocity) = velocity {
+            elements.extend([velocity.max_volume(), F::from(velocity.epoch())]);
+            let spent = BTreeMap::from_iter(velocity.spent_volumes());
+            for (key, volume) in spent {
+                elements.extend([key.to_field(), volume.a, volume.b]);
+            }
+        }
+        // Encrypt-then-MAC
+        let (nonce, mac_key) = match key {
+            Some(key) => {
+                let nonce = F::rand(rng);
+                apply_keystream(key.encryption_key(), nonce, &mut elements, false);
+                (nonce, key.mac_key())
+            }
+            None => (F::zero(), F::zero()),
+        };
+        let tag = mac(mac_key, nonce, &header, &elements);
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + (elements.len() + 1) * FIELD_SIZE);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(header.version);
+        bytes.push(header.flags());
+        bytes.push(party as u8);
+        bytes.extend_from_slice(&action_index.to_le_bytes());
+        bytes.extend_from_slice(&(header.num_entries as u64).to_le_bytes());
+        bytes.extend_from_slice(&(header.num_spent as u64).to_le_bytes());
+        nonce.serialize_compressed(&mut bytes)?;
+        for element in elements.iter().chain([&tag]) {
+            element.serialize_compressed(&mut bytes)?;
+        }
+        Ok(bytes)
+    }
+
+    // Imports a snapshot of a party, together with the velocity limit if the snapshot contains one. The key has to be given for encrypted snapshots. The imported shares should be checked against the commitments on chain with `verify_snapshot`.
+    #[expect(clippy::type_complexity)]
+    pub fn import_snapshot(
+        bytes: &[u8],
+        key: Option<&OperatorKey>,
+    ) -> eyre::Result<(SnapshotHeader, Self, Option<VelocityLimit<K>>)> {
+        if bytes.len() < HEADER_SIZE + FIELD_SIZE || &bytes[..6] != MAGIC {
+            eyre::bail!("Not a snapshot");
+        }
+        let version = bytes[6];
+        if version != SNAPSHOT_VERSION {
+            eyre::bail!("Unsupported snapshot version {version}");
+        }
+        let flags = bytes[7];
+        if flags & !(FLAG_ENCRYPTED | FLAG_VELOCITY) != 0 {
+            eyre::bail!("Invalid snapshot flags");
+        }
+        let party = bytes[8] as usize;
+        if party >= 3 {
+            eyre::bail!("Invalid party index in snapshot");
+        }
+        let header = SnapshotHeader {
+            version,
+            encrypted: flags & FLAG_ENCRYPTED != 0,
+            party,
+            action_index: read_u64(&bytes[9..17]),
+            num_entries: usize::try_from(read_u64(&bytes[17..25]))?,
+            velocity: flags & FLAG_VELOCITY != 0,
+            num_spent: usize::try_from(read_u64(&bytes[25..33]))?,
+        };
+        let nonce = read_field(&bytes[33..HEADER_SIZE])?;
+
+        let num_elements = header
+            .num_elements()
+            .ok_or_else(|| eyre::eyre!("Invalid number of entries in snapshot"))?;
+        if (bytes.len() - HEADER_SIZE) / FIELD_SIZE != num_elements + 1
+            || (bytes.len() - HEADER_SIZE) % FIELD_SIZE != 0
+        {
+            eyre::bail!("Invalid snapshot length");
+        }
+        let mut elements = bytes[HEADER_SIZE..]
+            .chunks_exact(FIELD_SIZE)
+            .map(read_field)
+            .collect::<eyre::Result<Vec<_>>>()?;
+        let expected = elements.pop().expect("Length was checked");
+
+        // The MAC is checked before decrypting
+        let key = match (header.encrypted, key) {
+            (true, Some(key)) => Some(key),
+            (true, None) => eyre::bail!("Snapshot is encrypted, but no key was given"),
+            (false, _) => None,
+        };
+        let mac_key = key.map_or_else(F::zero, OperatorKey::mac_key);
+        if mac(mac_key, nonce, &header, &elements) != expected {
+            eyre::bail!("Snapshot authentication failed");
+        }
+        if let Some(key) = key {
+            apply_keystream(key.encryption_key(), nonce, &mut elements, true);
+        }
+
+        let (entries, velocity) = elements.split_at(header.num_entries * ELEMENTS_PER_ENTRY);
+        let mut map = Self::with_capacity(header.num_entries);
+        for entry in entries.chunks_exact(ELEMENTS_PER_ENTRY) {
+            let value = DepositValueShare::new(
+                Rep3PrimeFieldShare::new(entry[1], entry[2]),
+                Rep3PrimeFieldShare::new(entry[3], entry[4]),
+            );
+            if map.insert(K::from_field(entry[0])?, value).is_some() {
+                eyre::bail!("Duplicate key in snapshot");
+            }
+        }
+
+        let velocity = if header.velocity {
+            let (limit, spent_volumes) = velocity.split_at(VELOCITY_ELEMENTS);
+            let epoch = limit[1].into_bigint().0[0];
+            if F::from(epoch) != limit[1] {
+                eyre::bail!("Invalid velocity epoch in snapshot");
+            }
+            let mut spent = HashMap::with_capacity(header.num_spent);
+            for entry in spent_volumes.chunks_exact(ELEMENTS_PER_SPENT) {
+                let volume = Rep3PrimeFieldShare::new(entry[1], entry[2]);
+                if spent.insert(K::from_field(entry[0])?, volume).is_some() {
+                    eyre::bail!("Duplicate spent volume in snapshot");
+                }
+            }
+            Some(VelocityLimit::with_spent(limit[0], epoch, spent))
+        } else {
+            None
+        };
+        Ok((header, map, velocity))
+    }
+
+    // Jointly recomputes the commitments of all balances and compares them against the commitments on chain. Returns the keys whose commitment does not match, followed by the keys which have a commitment on chain but no balance in the snapshot, each in the order of the keys. All parties have to import their snapshots of the same action index first.
+    pub fn verify_snapshot<N: Network>(
+        &self,
+        onchain: &HashMap<K, F>,
+        net: &N,
+        rep3_state: &mut Rep3State,
+    ) -> eyre::Result<Vec<K>> {
+        let ordered = BTreeMap::from_iter(self.iter());
+        let values = ordered
+            .values()
+            .map(|v| (*v).to_owned())
+            .collect::<Vec<_>>();
+        let commitments = crate::proof::open_commitments(&values, net, rep3_state)?;
+
+        let mut mismatches = ordered
+            .keys()
+            .zip(commitments)
+            .filter(|(key, commitment)| onchain.get(*key) != Some(commitment))
+            .map(|(key, _)| (*key).to_owned())
+            .collect::<Vec<_>>();
+        let mut missing = onchain
+            .keys()
+            .filter(|key| !ordered.contains_key(key))
+            .cloned()
+            .collect::<Vec<_>>();
+        missing.sort();
+        mismatches.extend(missing);
+        Ok(mismatches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        proof::{TestConfig, plain_commitment},
+        three_party::ThreeParty,
+    };
+    use alloy::primitives::Address;
+    use ark_ff::One;
+
+    type ShareMap = PrivateDeposit<Address, DepositValueShare<F>>;
+
+    #[test]
+    fn snapshot_test() {
+        let mut parties = ThreeParty::new(1, TestConfig::SEED);
+        let plain_map = TestConfig::get_random_plain_address_map::<F, _>(100, parties.rng());
+        let map_shares = parties.share_map(&plain_map);
+        let keys = map_shares
+            .each_ref()
+            .map(|_| OperatorKey::random(parties.rng()));
+        // Spent volumes of a velocity limit, in the current epoch
+        let spent_keys = plain_map.keys().take(10).copied().collect::<Vec<_>>();
+        let spent_shares = spent_keys
+            .iter()
+            .map(|_| parties.share_field_element(F::from(7u64)))
+            .collect::<Vec<_>>();
+        let velocities = [0, 1, 2].map(|party| {
+            let spent = spent_keys
+                .iter()
+                .zip(&spent_shares)
+                .map(|(key, shares)| (*key, shares[party]))
+                .collect();
+            VelocityLimit::with_spent(F::from(1000u64), 3, spent)
+        });
+
+        let mut snapshots = Vec::with_capacity(3);
+        for (party, (map, key)) in map_shares.iter().zip(keys.iter()).enumerate() {
+            snapshots.push(
+                map.export_snapshot(
+                    party,
+                    42,
+                    Some(&velocities[party]),
+                    Some(key),
+                    parties.rng(),
+                )
+                .unwrap(),
+            );
+        }
+
+        // Roundtrip
+        let mut imported = Vec::with_capacity(3);
+        for (snapshot, key, map, velocity) in
+            itertools::izip!(&snapshots, &keys, &map_shares, &velocities)
+        {
+            let (header, import, import_velocity) =
+                ShareMap::import_snapshot(snapshot, Some(key)).unwrap();
+            assert!(header.encrypted);
+            assert_eq!(header.action_index, 42);
+            assert_eq!(import.len(), map.len());
+            for (key, value) in map.iter() {
+                let imported = import.get(key).unwrap();
+                assert_eq!(imported.amount, value.amount);
+                assert_eq!(imported.blinding, value.blinding);
+            }
+            let import_velocity = import_velocity.unwrap();
+            assert_eq!(import_velocity.epoch(), 3);
+            assert_eq!(import_velocity.max_volume(), F::from(1000u64));
+            assert_eq!(import_velocity.spent_volumes(), velocity.spent_volumes());
+            imported.push((header, import));
+        }
+        let backup = [0, 1, 2].map(|i| (&imported[i].0, &imported[i].1));
+        assert_eq!(check_backup(backup).unwrap(), 42);
+
+        // Plain snapshots
+        let plain = map_shares[0]
+            .export_snapshot(0, 42, Some(&velocities[0]), None, parties.rng())
+            .unwrap();
+        let (header, plain, _) = ShareMap::import_snapshot(&plain, None).unwrap();
+        assert!(!header.encrypted);
+        assert!(
+            check_backup([
+                (&header, &plain),
+                (&imported[0].0, &imported[0].1),
+                (&imported[2].0, &imported[2].1)
+            ])
+            .is_err()
+        );
+
+        // A snapshot of party 1 with the same number of balances, but one of another key
+        let mut other_keys = map_shares[1].clone();
+        let removed = *other_keys.keys().next().unwrap();
+        let value = other_keys.remove(&removed).unwrap();
+        other_keys.insert(Address::repeat_byte(0xff), value);
+        let other_keys = other_keys
+            .export_snapshot(1, 42, Some(&velocities[1]), Some(&keys[1]), parties.rng())
+            .unwrap();
+        let (header, other_keys, _) =
+            ShareMap::import_snapshot(&other_keys, Some(&keys[1])).unwrap();
+        assert!(
+            check_backup([
+                (&imported[0].0, &imported[0].1),
+                (&header, &other_keys),
+                (&imported[2].0, &imported[2].1)
+            ])
+            .is_err()
+        );
+
+        // A snapshot of party 2 without the velocity limit
+        let no_velocity = map_shares[2]
+            .export_snapshot(2, 42, None, Some(&keys[2]), parties.rng())
+            .unwrap();
+        let (header, no_velocity, velocity) =
+            ShareMap::import_snapshot(&no_velocity, Some(&keys[2])).unwrap();
+        assert!(velocity.is_none());
+        assert!(
+            check_backup([
+                (&imported[0].0, &imported[0].1),
+                (&imported[1].0, &imported[1].1),
+                (&header, &no_velocity)
+            ])
+            .is_err()
+        );
+
+        // Wrong key, missing key, and corruption
+        assert!(ShareMap::import_snapshot(&snapshots[0], Some(&keys[1])).is_err());
+        assert!(ShareMap::import_snapshot(&snapshots[0], None).is_err());
+        let mut corrupted = snapshots[0].clone();
+        let last = corrupted.len() - FIELD_SIZE - 1;
+        corrupted[last] ^= 1;
+        assert!(ShareMap::import_snapshot(&corrupted, Some(&keys[0])).is_err());
+        let mut watermark = snapshots[0].clone();
+        watermark[9] += 1;
+        assert!(ShareMap::import_snapshot(&watermark, Some(&keys[0])).is_err());
+        let mut nonce = snapshots[0].clone();
+        nonce[33] ^= 1;
+        assert!(ShareMap::import_snapshot(&nonce, Some(&keys[0])).is_err());
+
+        // Verify against the commitments on chain, one of which differs
+        let mut onchain = plain_map
+            .iter()
+            .map(|(key, value)| (*key, plain_commitment(value.amount, value.blinding)))
+            .collect::<HashMap<_, _>>();
+        let changed = *onchain.keys().next().unwrap();
+        *onchain.get_mut(&changed).unwrap() += F::one();
+        // A balance on chain which is not in the snapshot
+        let missing = Address::repeat_byte(0xff);
+        onchain.insert(missing, F::one());
+
+        let [map0, map1, map2]: [_; 3] = imported
+            .into_iter()
+            .map(|(_, map)| map)
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+        let mismatches = parties
+            .run_public(1, [map0, map1, map2], |map, nets, rep3_states| {
+                map.verify_snapshot(&onchain, &nets[0], &mut rep3_states[0])
+            })
+            .unwrap();
+        assert_eq!(mismatches, vec![changed, missing]);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::data_structure::{DepositValuePlain, DepositValueShare, PrivateDeposit};
+use ark_ff::PrimeField;
+use mpc_core::protocols::rep3::{self, Rep3PrimeFieldShare, Rep3State, conversion::A2BType};
+use mpc_net::local::LocalNetwork;
+use rand_chacha::{ChaCha12Rng, rand_core::SeedableRng};
+use std::{fmt::Debug, thread, time::Duration};
+
+// An in-process simulator of the three MPC parties for tests and tooling. All randomness used for sharing is drawn from one seeded RNG, such that runs are reproducible. The parties communicate over local networks which are kept alive between runs.
+pub struct ThreeParty {
+    rng: ChaCha12Rng,
+    networks: [Vec<LocalNetwork>; 3],
+    // The receive timeout of the networks, the default of mpc-net if None
+    recv_timeout: Option<Duration>,
+}
+
+impl ThreeParty {
+    pub fn new(num_networks: usize, seed: u64) -> Self {
+        Self::with_rng(num_networks, ChaCha12Rng::seed_from_u64(seed))
+    }
+
+    pub fn from_seed(num_networks: usize, seed: [u8; 32]) -> Self {
+        Self::with_rng(num_networks, ChaCha12Rng::from_seed(seed))
+    }
+
+    fn with_rng(num_networks: usize, rng: ChaCha12Rng) -> Self {
+        Self {
+            rng,
+            networks: Self::local_networks(num_networks, None),
+            recv_timeout: None,
+        }
+    }
+
+    // Replaces the networks by ones whose receives fail after the given timeout, e.g., for tests in which a message is lost
+    pub fn with_recv_timeout(mut self, recv_timeout: Duration) -> Self {
+        self.recv_timeout = Some(recv_timeout);
+        self.reconnect();
+        self
+    }
+
+    fn local_networks(
+        num_networks: usize,
+        recv_timeout: Option<Duration>,
+    ) -> [Vec<LocalNetwork>; 3] {
+        let mut networks = [
+            Vec::with_capacity(num_networks),
+            Vec::with_capacity(num_networks),
+            Vec::with_capacity(num_networks),
+        ];
+        for _ in 0..num_networks {
+            let nets = match recv_timeout {
+                Some(recv_timeout) => LocalNetwork::new_with_timeout(3, recv_timeout),
+                None => LocalNetwork::new(3),
+            };
+            let [net0, net1, net2] = nets.try_into().expect("We created three networks");
+            networks[0].push(net0);
+            networks[1].push(net1);
+            networks[2].push(net2);
+        }
+        networks
+    }
+
+    // Replaces all networks, e.g., after an aborted run which might have left messages in the queues
+    pub fn reconnect(&mut self) {
+        self.networks = Self::local_networks(self.num_networks(), self.recv_timeout);
+    }
+
+    pub fn rng(&mut self) -> &mut ChaCha12Rng {
+        &mut self.rng
+    }
+
+    pub fn num_networks(&self) -> usize {
+        self.networks[0].len()
+    }
+
+    pub fn share_map<K, F: PrimeField>(
+        &mut self,
+        map: &PrivateDeposit<K, DepositValuePlain<F>>,
+    ) -> [PrivateDeposit<K, DepositValueShare<F>>; 3]
+    where
+        K: std::hash::Hash + Eq + Clone + Ord,
+    {
+        map.share(&mut self.rng)
+    }
+
+    pub fn share_field_element<F: PrimeField>(&mut self, value: F) -> [Rep3PrimeFieldShare<F>; 3] {
+        rep3::share_field_element(value, &mut self.rng)
+    }
+
+    // Runs `party` for each party on its own thread. Each party gets its input, all of its networks, and `num_states` Rep3States which are created on the first `num_states` networks. The outputs are returned in the order of the party ids.
+    pub fn run<I, O, P>(
+        &mut self,
+        num_states: usize,
+        inputs: [I; 3],
+        party: P,
+    ) -> eyre::Result<[O; 3]>
+    where
+        I: Send,
+        O: Send,
+        P: Fn(I, &[LocalNetwork], &mut [Rep3State]) -> eyre::Result<O> + Sync,
+    {
+        if num_states > self.num_networks() {
+            eyre::bail!("Cannot create more Rep3States than networks");
+        }
+
+        let party = &party;
+        let results = thread::scope(|scope| {
+            let mut handles = Vec::with_capacity(3);
+            for (nets, input) in self.networks.iter_mut().zip(inputs) {
+                let handle = scope.spawn(move || {
+                    let mut rep3_states = Vec::with_capacity(num_states);
+                    for net in nets.iter().take(num_states) {
+                        rep3_states.push(Rep3State::new(net, A2BType::default())?);
+                    }
+                    party(input, nets, &mut rep3_states)
+                });
+                handles.push(handle);
+            }
+
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .map_err(|_| eyre::eyre!("A party panicked during the simulation"))?
+                })
+                .collect::<eyre::Result<Vec<_>>>()
+        })?;
+
+        Ok(results
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("We spawned three parties")))
+    }
+
+    // Same as `run`, but for outputs which are public and thus have to be the same for all parties
+    pub fn run_public<I, O, P>(
+        &mut self,
+        num_states: usize,
+        inputs: [I; 3],
+        party: P,
+    ) -> eyre::Result<O>
+    where
+        I: Send,
+        O: Send + PartialEq + Debug,
+        P: Fn(I, &[LocalNetwork], &mut [Rep3State]) -> eyre::Result<O> + Sync,
+    {
+        let [output0, output1, output2] = self.run(num_states, inputs, party)?;
+        if output0 != output1 || output0 != output2 {
+            eyre::bail!(
+                "Parties disagree on the public output: {output0:?}, {output1:?}, {output2:?}"
+            );
+        }
+        Ok(output0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof::TestConfig;
+    use rand::Rng;
+
+    type F = ark_bn254::Fr;
+
+    #[test]
+    fn three_party_test() {
+        let mut parties = ThreeParty::new(1, TestConfig::SEED);
+
+        // Get a random map and its shares
+        let mut plain_map =
+            TestConfig::get_random_plain_map::<F, _>(TestConfig::NUM_ITEMS, parties.rng());
+        let [map0, map1, map2] = parties.share_map(&plain_map);
+
+        let key = TestConfig::get_random_map_key(&plain_map, parties.rng());
+        let new_key = TestConfig::get_random_new_key(&plain_map, parties.rng());
+        let amount = F::from(parties.rng().r#gen::<u64>());
+        let amount_share = parties.share_field_element(amount);
+        let [amount0, amount1, amount2] = amount_share;
+
+        // Deposit to an existing and to a new key
+        let map_shares = parties
+            .run(
+                1,
+                [(map0, amount0), (map1, amount1), (map2, amount2)],
+                |(mut map, amount), _, rep3_states| {
+                    map.deposit(key, amount, &mut rep3_states[0]);
+                    map.deposit(new_key, amount, &mut rep3_states[0]);
+                    Ok(map)
+                },
+            )
+            .unwrap();
+        let old_amount = plain_map.get(&key).unwrap().amount;
+        plain_map.insert(
+            key,
+            DepositValuePlain::new(old_amount + amount, F::from(0u64)),
+        );
+        plain_map.insert(new_key, DepositValuePlain::new(amount, F::from(0u64)));
+
+        // Compare the maps
+        let result = PrivateDeposit::reconstruct(map_shares).unwrap();
+        assert_eq!(result.len(), plain_map.len());
+        for (key, plain_value) in plain_map.into_iter() {
+            assert_eq!(result.get(&key).unwrap().amount, plain_value.amount);
+        }
+
+        // Public outputs have to agree
+        let opened = parties
+            .run_public(1, amount_share, |amount, nets, _| {
+                rep3::arithmetic::open(amount, &nets[0])
+            })
+            .unwrap();
+        assert_eq!(opened, amount);
+    }
+}